@@ -0,0 +1,62 @@
+use crate::telegram::telegram_get;
+use anyhow::Result;
+use beancount_core::secret::redact;
+use chrono::Utc;
+use repository::error::StoreError;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+/// Answers the admin endpoint `name` with JSON, `None` if there's no such
+/// endpoint: `webhook-info` and `me` proxy the Bot API's `getWebhookInfo` and
+/// `getMe`, `errors` lists the errors this instance ran into recently.
+pub async fn admin_report(name: &str) -> Result<Option<String>> {
+    let report = match name {
+        "webhook-info" => telegram_get("getWebhookInfo").await?,
+        "me" => telegram_get("getMe").await?,
+        "errors" => error_stats().to_string(),
+        _ => return Ok(None),
+    };
+    Ok(Some(report))
+}
+
+/// Errors this instance has handled updates with, since it started.
+struct ErrorStats {
+    by_kind: BTreeMap<&'static str, u64>,
+    recent: VecDeque<serde_json::Value>,
+}
+
+/// Errors kept in full for the `errors` admin endpoint; older ones are only
+/// counted.
+const RECENT_ERRORS: usize = 20;
+
+static ERROR_STATS: Mutex<ErrorStats> = Mutex::new(ErrorStats {
+    by_kind: BTreeMap::new(),
+    recent: VecDeque::new(),
+});
+
+/// Counts an update that failed, by the kind of store error behind it.
+pub(crate) fn record_error(e: &anyhow::Error) {
+    let kind = e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<StoreError>())
+        .map_or("other", StoreError::kind);
+    let mut stats = ERROR_STATS.lock().unwrap();
+    *stats.by_kind.entry(kind).or_default() += 1;
+    if stats.recent.len() == RECENT_ERRORS {
+        stats.recent.pop_front();
+    }
+    stats.recent.push_back(serde_json::json!({
+        "at": Utc::now().to_rfc3339(),
+        "kind": kind,
+        "error": redact(&e.to_string()),
+    }));
+}
+
+fn error_stats() -> serde_json::Value {
+    let stats = ERROR_STATS.lock().unwrap();
+    serde_json::json!({
+        "total": stats.by_kind.values().sum::<u64>(),
+        "by_kind": stats.by_kind,
+        "recent": stats.recent,
+    })
+}
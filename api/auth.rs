@@ -0,0 +1,62 @@
+use std::env;
+use subtle::ConstantTimeEq;
+
+/// Whether a request to run a job carries `Authorization: Bearer <CRON_SECRET>`,
+/// which Vercel sends with its cron requests. Without `CRON_SECRET` jobs can't be
+/// triggered over HTTP at all.
+pub fn is_cron_authorized(authorization: Option<&str>) -> bool {
+    is_bearer(authorization, "CRON_SECRET")
+}
+
+/// Whether a request to an admin endpoint carries
+/// `Authorization: Bearer <ADMIN_TOKEN>`. Without `ADMIN_TOKEN` they're off.
+pub fn is_admin_authorized(authorization: Option<&str>) -> bool {
+    is_bearer(authorization, "ADMIN_TOKEN")
+}
+
+/// Whether a request to the dashboard API carries
+/// `Authorization: Bearer <API_TOKEN>`. Without `API_TOKEN` it's off.
+pub fn is_api_authorized(authorization: Option<&str>) -> bool {
+    is_bearer(authorization, "API_TOKEN")
+}
+
+/// Whether a webhook request carries the `TELEGRAM_WEBHOOK_SECRET`, or none is
+/// configured.
+pub(crate) fn is_webhook_authorized(secret_token: Option<&str>) -> bool {
+    match env::var("TELEGRAM_WEBHOOK_SECRET") {
+        Ok(secret) if !secret.is_empty() => {
+            secret_token.is_some_and(|token| secret_eq(token, &secret))
+        }
+        _ => true,
+    }
+}
+
+fn is_bearer(authorization: Option<&str>, key: &str) -> bool {
+    match (env::var(key), authorization) {
+        (Ok(secret), Some(authorization)) if !secret.is_empty() => authorization
+            .strip_prefix("Bearer ")
+            .is_some_and(|token| secret_eq(token, &secret)),
+        _ => false,
+    }
+}
+
+/// Compares a token sent with a request to the secret in constant time, so
+/// response times don't give away how much of it matched.
+fn secret_eq(token: &str, secret: &str) -> bool {
+    token.as_bytes().ct_eq(secret.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_bearer_tokens() {
+        env::set_var("BEARER_TEST_TOKEN", "s3cret");
+        assert!(is_bearer(Some("Bearer s3cret"), "BEARER_TEST_TOKEN"));
+        assert!(!is_bearer(Some("Bearer s3cre"), "BEARER_TEST_TOKEN"));
+        assert!(!is_bearer(Some("s3cret"), "BEARER_TEST_TOKEN"));
+        assert!(!is_bearer(None, "BEARER_TEST_TOKEN"));
+        assert!(!is_bearer(Some("Bearer "), "BEARER_UNSET_TOKEN"));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::load_settings;
+use crate::dashboard::ApiError;
+use crate::stores::create_store;
+use crate::telegram::{call_telegram, send_message};
+use anyhow::{anyhow, Result};
+use beancount_core::bank_feed::{book, BankTransaction};
+use beancount_core::reply::Reply;
+use beancount_core::secret::Secret;
+use beancount_core::settings::{BankFeedSettings, Settings};
+use log::{info, warn};
+use metrics::counter;
+use repository::bank_feed::{self, UpClient, UpEvent};
+use repository::Store;
+
+/// Books a transaction pushed by a bank to `/webhooks/<provider>`, signed with
+/// the provider's secret:
+///
+/// - `up`: Up webhook events, signed with `UP_WEBHOOK_SECRET`; the transaction
+///   is fetched with `UP_API_TOKEN`
+/// - `generic`: a [`BankTransaction`] as JSON, signed with `BANK_FEED_SECRET`
+///
+/// `signature` is the hex HMAC-SHA256 of the body.
+pub async fn bank_webhook(
+    provider: &str,
+    signature: Option<&str>,
+    body: &[u8],
+) -> Result<String, ApiError> {
+    let secret = match provider {
+        "up" => "UP_WEBHOOK_SECRET",
+        "generic" => "BANK_FEED_SECRET",
+        _ => {
+            return Err(ApiError::BadRequest(format!(
+                "unknown bank feed provider {}",
+                provider
+            )))
+        }
+    };
+    let secret = Secret::from_env(secret)?;
+    if !signature
+        .is_some_and(|signature| bank_feed::verify_signature(secret.expose(), body, signature))
+    {
+        warn!("Rejected {} webhook with a bad signature", provider);
+        return Err(ApiError::Unauthorized);
+    }
+    let bank = match provider {
+        "up" => {
+            let body = std::str::from_utf8(body)
+                .map_err(|_| ApiError::BadRequest("the body isn't UTF-8".into()))?;
+            match bank_feed::up_event(body).map_err(|e| ApiError::BadRequest(e.to_string()))? {
+                UpEvent::Ping => return Ok("pong".into()),
+                UpEvent::Other(event_type) => return Ok(format!("ignored {}", event_type)),
+                UpEvent::TransactionCreated(id) => UpClient::from_env()?.transaction(&id).await?,
+            }
+        }
+        _ => serde_json::from_slice(body).map_err(|e| ApiError::BadRequest(e.to_string()))?,
+    };
+
+    let settings = load_settings().await?;
+    let feed = settings
+        .bank_feed
+        .clone()
+        .ok_or_else(|| ApiError::BadRequest("[bank_feed] isn't configured".into()))?;
+    let store = create_store(Some(&settings))?;
+    let state_store = create_store(None)?;
+    let booked = book_bank_transaction(
+        store.as_ref(),
+        state_store.as_ref(),
+        &settings,
+        &feed,
+        &bank,
+    )
+    .await?;
+    match booked {
+        BankFeedOutcome::Known => Ok(format!("{} was already booked", bank.id)),
+        BankFeedOutcome::Committed(entry) => {
+            if let Some(chat_id) = feed.chat_id {
+                send_message(
+                    chat_id,
+                    Reply::plain(format!("Booked from the bank feed:\n{}", entry)),
+                )
+                .await?;
+            }
+            Ok(format!("booked {}", bank.id))
+        }
+        BankFeedOutcome::Prompted(pending) => {
+            let token = Secret::from_env("TELEGRAM_BOT_TOKEN")?;
+            call_telegram(
+                &token,
+                "sendMessage",
+                &serde_json::json!({
+                    "chat_id": pending.chat_id,
+                    "text": format!("New bank transaction:\n{}", String::from(pending.transaction.clone())),
+                    "reply_markup": { "inline_keyboard": [[
+                        { "text": "Save", "callback_data": format!("bank:save:{}", bank.id) },
+                        { "text": "Skip", "callback_data": format!("bank:skip:{}", bank.id) },
+                    ]] },
+                }),
+            )
+            .await?;
+            Ok(format!("asked chat {} about {}", pending.chat_id, bank.id))
+        }
+    }
+}
+
+/// What became of a bank transaction.
+#[derive(Debug)]
+enum BankFeedOutcome {
+    /// Booked or awaiting confirmation already, banks deliver webhooks again
+    /// when unsure they arrived.
+    Known,
+    /// Saved to the ledger, as this entry.
+    Committed(String),
+    /// Kept in the state store until the chat answers.
+    Prompted(Box<bank_feed::Pending>),
+}
+
+async fn book_bank_transaction(
+    store: &dyn Store,
+    state_store: &dyn Store,
+    settings: &Settings,
+    feed: &BankFeedSettings,
+    bank: &BankTransaction,
+) -> Result<BankFeedOutcome> {
+    let transaction = book(settings, feed, bank)?;
+    let ledger = store
+        .read(&transaction.ledger_path(settings.ledger_path.as_deref()))
+        .await?;
+    if ledger.is_some_and(|content| content.contains(&bank.marker()))
+        || bank_feed::load_pending(state_store, &bank.id)
+            .await?
+            .is_some()
+    {
+        info!("bank transaction {} is already known", bank.id);
+        return Ok(BankFeedOutcome::Known);
+    }
+    match (feed.auto_commit, feed.chat_id) {
+        (true, _) => {
+            let entry = String::from(transaction.clone());
+            store.save(transaction).await?;
+            counter!("beancount_transactions_saved_total").increment(1);
+            Ok(BankFeedOutcome::Committed(entry))
+        }
+        (false, Some(chat_id)) => {
+            let pending = bank_feed::Pending {
+                bank_id: bank.id.clone(),
+                chat_id,
+                transaction,
+            };
+            bank_feed::save_pending(state_store, &pending).await?;
+            Ok(BankFeedOutcome::Prompted(Box::new(pending)))
+        }
+        (false, None) => Err(anyhow!("[bank_feed] needs a chat_id to confirm with")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use repository::memory_store::MemoryStore;
+    use std::collections::HashMap;
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn bank_transactions_are_booked_once() {
+        let settings = settings();
+        let bank = BankTransaction {
+            id: "tx-1".into(),
+            account_id: "acc-1".into(),
+            date: "2021-09-08".into(),
+            description: "KFC".into(),
+            raw_text: None,
+            amount: -12.4,
+            currency: "AUD".into(),
+            category: None,
+        };
+        let mut feed = BankFeedSettings {
+            accounts: HashMap::from([("acc-1".to_string(), "cash".to_string())]),
+            default_account: Some("food".into()),
+            chat_id: Some(42),
+            ..BankFeedSettings::default()
+        };
+
+        let store = MemoryStore::new();
+        let state = MemoryStore::new();
+        let booked = book_bank_transaction(&store, &state, &settings, &feed, &bank).await;
+        assert!(matches!(booked.unwrap(), BankFeedOutcome::Prompted(p) if p.chat_id == 42));
+        let booked = book_bank_transaction(&store, &state, &settings, &feed, &bank).await;
+        assert!(matches!(booked.unwrap(), BankFeedOutcome::Known));
+        assert!(store.file("2021.bean").is_none());
+
+        feed.auto_commit = true;
+        let store = MemoryStore::new();
+        let state = MemoryStore::new();
+        let booked = book_bank_transaction(&store, &state, &settings, &feed, &bank).await;
+        assert!(
+            matches!(booked.unwrap(), BankFeedOutcome::Committed(entry) if entry.contains("bank_id: \"tx-1\""))
+        );
+        let booked = book_bank_transaction(&store, &state, &settings, &feed, &bank).await;
+        assert!(matches!(booked.unwrap(), BankFeedOutcome::Known));
+        assert!(store.file("2021.bean").unwrap().contains("Expenses:Food"));
+    }
+}
@@ -1,90 +1,179 @@
+#[cfg(feature = "vercel")]
 use anyhow::Result;
-use beancount_core::{parser::BeancountParser, settings::Settings};
-use bot_message::telegram::{ResponseBody, Update};
+#[cfg(feature = "vercel")]
 use http::StatusCode;
-use log::{error, info, warn};
-use repository::github_store::GithubStore;
-use repository::Store;
+#[cfg(feature = "vercel")]
+use log::warn;
+#[cfg(feature = "vercel")]
+use std::future::Future;
+#[cfg(feature = "vercel")]
 use vercel_lambda::{error::VercelError, lambda, IntoResponse, Request, Response};
 
+mod admin;
+mod auth;
+#[cfg(feature = "bank-feed")]
+mod bank_feed;
+#[cfg(any(feature = "slack", feature = "discord"))]
+mod channels;
+mod commands;
+mod config;
+mod dashboard;
+mod imports;
+mod jobs;
+mod reports;
+mod saves;
+mod stores;
+mod telegram;
+mod updates;
+mod webhook;
+
+pub use admin::admin_report;
+pub use auth::{is_admin_authorized, is_api_authorized, is_cron_authorized};
+#[cfg(feature = "bank-feed")]
+pub use bank_feed::bank_webhook;
+#[cfg(feature = "discord")]
+pub use channels::discord::{discord_request, DISCORD_SIGNATURE_HEADER, DISCORD_TIMESTAMP_HEADER};
+#[cfg(feature = "slack")]
+pub use channels::slack::{
+    slack_request, SLACK_RETRY_HEADER, SLACK_SIGNATURE_HEADER, SLACK_TIMESTAMP_HEADER,
+};
+pub use config::load_settings;
+pub use dashboard::{dashboard_api, ApiError};
+pub use imports::{import_profile, import_statement};
+pub use jobs::{run_due_jobs, run_job, run_recurring};
+pub use reports::{
+    account_balance, budgets_on, net_worth_on, period_export, period_report, search,
+    year_duplicates,
+};
+pub use saves::{flush_state, saves_in_flight, PendingSave};
+pub use stores::create_store;
+pub use telegram::check_ready;
+pub use webhook::{
+    handle_bot_update, handle_serverless_update, handle_update, handle_update_deferred,
+    validate_request, Handled, Rejection, MAX_BODY_BYTES, SECRET_TOKEN_HEADER,
+};
+
+#[cfg(feature = "vercel")]
 #[allow(dead_code)]
 fn main() -> Result<()> {
     env_logger::init();
-    Ok(lambda!(handler))
+    lambda!(handler);
+    Ok(())
+}
+
+/// The value of the `name` parameter of the request's query string, decoded.
+#[cfg(feature = "vercel")]
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(request.uri().query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
 }
 
+#[cfg(feature = "vercel")]
 #[allow(dead_code)]
 fn handler(request: Request) -> Result<impl IntoResponse, VercelError> {
-    let body = String::from_utf8_lossy(request.body());
-    info!("request body is {}", body);
-
-    let update: Update = match serde_json::from_str(&body) {
-        Ok(v) => v,
-        Err(_) => {
-            warn!("Failed to deserialize request body: {}", body);
+    // Vercel cron jobs call `GET /api/beancount?job=<name>`.
+    if let Some(job) = query_param(&request, "job") {
+        let authorization = request
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok());
+        if !is_cron_authorized(authorization) {
             return Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "application/json")
-                .body("Failed to deserialize request body".into())?);
+                .status(StatusCode::UNAUTHORIZED)
+                .header("Content-Type", "text/plain")
+                .body("unauthorized".to_string())?);
         }
-    };
-
-    let message = match update.message {
-        Some(v) => v,
-        None => match update.edited_message {
-            Some(v) => v,
-            None => {
-                warn!("Could not get message or edited_message from request");
-                return Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", "application/json")
-                    .body("Could not get message or edited_message from request".into())?);
+        let outcome = block_on(run_job(&job)).map_err(|e| VercelError::new(&e.to_string()))?;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain")
+            .body(outcome)?);
+    }
+    if let Some(name) = query_param(&request, "admin") {
+        let authorization = request
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok());
+        let (status, content_type, body) = if !is_admin_authorized(authorization) {
+            (
+                StatusCode::UNAUTHORIZED,
+                "text/plain",
+                "unauthorized".into(),
+            )
+        } else {
+            match block_on(admin_report(&name)) {
+                Ok(Some(report)) => (StatusCode::OK, "application/json", report),
+                Ok(None) => (StatusCode::NOT_FOUND, "text/plain", "not found".into()),
+                Err(e) => (StatusCode::BAD_GATEWAY, "text/plain", e.to_string()),
             }
-        },
-    };
-
-    let settings =
-        Settings::load_from_env().map_err(|e| VercelError::new(e.to_string().as_str()))?;
-    let parser = BeancountParser::new(settings);
-
-    let ok_response = |text| {
-        let response_body = ResponseBody {
-            method: "sendMessage".into(),
-            chat_id: message.chat.id,
-            text,
-            reply_to_message_id: message.message_id,
         };
-
-        Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", "application/json")
-            .body(serde_json::to_string(&response_body).unwrap())?)
-    };
-
-    let transaction = match parser.parse(&message.text) {
-        Ok(transaction) => transaction,
-        Err(e) => {
-            error!("Failed to parse input: {}", e.to_string());
-            return ok_response(format!(
-                "⚠️\n==============================\nFailed to parse input: {}",
-                e
-            ));
+        return Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", content_type)
+            .body(body)?);
+    }
+    let content_type = request
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok());
+    let secret_token = request
+        .headers()
+        .get(SECRET_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let body = match validate_request(content_type, secret_token, request.body()) {
+        Ok(body) => body,
+        Err(rejection) => {
+            warn!("Rejected request: {}", rejection);
+            return Ok(Response::builder()
+                .status(rejection.status())
+                .header("Content-Type", "text/plain")
+                .body(rejection.to_string())?);
         }
     };
+    // Tenants with their own bot set their webhook to `/api/beancount?bot=<bot id>`.
+    let bot_id = query_param(&request, "bot");
+    let response = block_on(handle_serverless_update(bot_id.as_deref(), body))
+        .map_err(|e| VercelError::new(&e.to_string()))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(response)?)
+}
 
-    info!("parsed transaction is {:?}", transaction);
+/// Runs `future` to completion on a runtime kept for the life of the function,
+/// since Vercel calls handlers synchronously.
+#[cfg(feature = "vercel")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("the tokio runtime starts")
+        })
+        .block_on(future)
+}
 
-    let store = GithubStore::new()
-        .map_err(|e| VercelError::new(format!("Failed to create github store: {}", e).as_str()))?;
+#[cfg(all(test, feature = "vercel"))]
+mod tests {
+    use super::*;
 
-    match store.save(transaction) {
-        Ok(text) => {
-            info!("Successfully saved transaction!");
-            ok_response(text)
-        }
-        Err(e) => {
-            error!("Failed to save transaction: {}", e.to_string());
-            Err(VercelError::new(&e.to_string()))
-        }
+    #[test]
+    fn it_reads_query_params_in_any_order() {
+        let request = |uri: &str| {
+            http::Request::builder()
+                .uri(uri)
+                .body(vercel_lambda::Body::from(()))
+                .unwrap()
+        };
+        let request = request("/api/beancount?_vercel=1&job=daily%20digest&bot=acme");
+        assert_eq!(
+            query_param(&request, "job").as_deref(),
+            Some("daily digest")
+        );
+        assert_eq!(query_param(&request, "bot").as_deref(), Some("acme"));
+        assert_eq!(query_param(&request, "admin"), None);
     }
 }
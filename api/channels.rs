@@ -0,0 +1,66 @@
+use crate::commands::{handle_command, CommandContext};
+use crate::config::load_settings;
+use crate::saves::{convert_currency, parse_failure_text, PendingSave};
+use crate::stores::create_store;
+use anyhow::{anyhow, Result};
+use beancount_core::parser::BeancountParser;
+use log::error;
+
+#[cfg(feature = "discord")]
+pub mod discord;
+#[cfg(feature = "slack")]
+pub mod slack;
+
+/// Runs a command or saves a transaction sent from Slack or Discord and returns
+/// the reply, or what went wrong.
+pub(crate) async fn chat_reply(text: &str) -> String {
+    match run_chat_text(text).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            error!("Failed to handle message: {}", e);
+            format!("⚠️\n==============================\n{}", e)
+        }
+    }
+}
+
+async fn run_chat_text(text: &str) -> Result<String> {
+    let mut settings = load_settings().await?;
+    // Neither shows Telegram's MarkdownV2 code blocks without their escapes.
+    settings.reply.code_block = false;
+    let store = create_store(Some(&settings))?;
+    if text.starts_with('/') {
+        let state_store = create_store(None)?;
+        let context = CommandContext {
+            store: store.as_ref(),
+            state_store: state_store.as_ref(),
+            tenant: None,
+            settings: &settings,
+            user_id: 0,
+            chat_id: 0,
+        };
+        return handle_command(&context, text)
+            .await
+            .map_err(|e| anyhow!("Failed to run command: {}", e));
+    }
+
+    let mut transaction = BeancountParser::new(settings.clone())
+        .parse(text)
+        .map_err(|e| anyhow!(parse_failure_text(&e, &settings)))?;
+    let rate = convert_currency(&settings, &mut transaction)
+        .await
+        .map_err(|e| anyhow!("Failed to convert {}: {}", transaction.currency(), e))?;
+    let pending = PendingSave {
+        update_id: 0,
+        tenant: None,
+        // There are no buttons to save a likely duplicate anyway with.
+        duplicate_lines: 0,
+        settings,
+        transaction,
+        rate,
+        chat_id: 0,
+        message_id: 0,
+        ack_message_id: None,
+        body: String::new(),
+    };
+    Ok(pending.save().await?.text)
+}
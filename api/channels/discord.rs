@@ -0,0 +1,92 @@
+use crate::channels::chat_reply;
+use crate::dashboard::ApiError;
+use anyhow::{anyhow, Result};
+use bot_message::discord::{self, Interaction, InteractionResponse};
+use log::{info, warn};
+use std::env;
+
+/// The header Discord signs interactions in, see [`discord_request`].
+pub const DISCORD_SIGNATURE_HEADER: &str = "X-Signature-Ed25519";
+
+/// The header with the timestamp that is signed along with the body.
+pub const DISCORD_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+/// Handles an interaction of a Discord application, signed with the key in
+/// `DISCORD_PUBLIC_KEY`: the ping Discord sends when the endpoint is set, then
+/// slash commands such as `/beancount text:@KFC 12.4 cba > food`. The text goes
+/// through the same commands and parser as a Telegram message, with the
+/// top-level settings, and is answered to the user who ran it only. Returns
+/// the JSON to answer with.
+pub async fn discord_request(
+    signature: Option<&str>,
+    timestamp: Option<&str>,
+    body: &[u8],
+) -> Result<String, ApiError> {
+    let public_key =
+        env::var("DISCORD_PUBLIC_KEY").map_err(|_| anyhow!("DISCORD_PUBLIC_KEY isn't set"))?;
+    let signed = match (signature, timestamp) {
+        (Some(signature), Some(timestamp)) => {
+            discord::verify_signature(public_key.trim(), signature, timestamp, body)
+        }
+        _ => false,
+    };
+    if !signed {
+        // Discord checks that unsigned requests are refused before saving the
+        // endpoint.
+        warn!("Rejected Discord interaction with a bad signature");
+        return Err(ApiError::Unauthorized);
+    }
+
+    let interaction: Interaction =
+        serde_json::from_slice(body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let response = match interaction.kind {
+        Interaction::PING => InteractionResponse::pong(),
+        Interaction::APPLICATION_COMMAND => {
+            info!(
+                "Discord interaction {} from {}",
+                interaction.id,
+                interaction.user_id().unwrap_or("unknown user")
+            );
+            let reply = match interaction.text() {
+                Some(text) => chat_reply(text.trim()).await,
+                None => "Type a transaction or a command after the command name".into(),
+            };
+            InteractionResponse::ephemeral(reply)
+        }
+        kind => {
+            return Err(ApiError::BadRequest(format!(
+                "unsupported interaction type {}",
+                kind
+            )))
+        }
+    };
+    Ok(serde_json::to_string(&response).map_err(anyhow::Error::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_answers_signed_discord_pings_only() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let body = b"{\"type\":1,\"id\":\"786008729715212338\"}";
+        let mut message = b"1700000000".to_vec();
+        message.extend_from_slice(body);
+        let signature = hex::encode(key.sign(&message).to_bytes());
+
+        env::set_var(
+            "DISCORD_PUBLIC_KEY",
+            hex::encode(key.verifying_key().to_bytes()),
+        );
+        let pong = discord_request(Some(&signature), Some("1700000000"), body).await;
+        let replayed = discord_request(Some(&signature), Some("1700000001"), body).await;
+        let unsigned = discord_request(None, None, body).await;
+        env::remove_var("DISCORD_PUBLIC_KEY");
+        assert_eq!(pong.unwrap(), "{\"type\":1}");
+        assert!(matches!(replayed, Err(ApiError::Unauthorized)));
+        assert!(matches!(unsigned, Err(ApiError::Unauthorized)));
+    }
+}
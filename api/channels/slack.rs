@@ -0,0 +1,234 @@
+use crate::channels::chat_reply;
+use crate::dashboard::ApiError;
+use anyhow::{anyhow, Result};
+use beancount_core::secret::Secret;
+use bot_message::slack::{
+    CommandResponse, Event as SlackEvent, EventPayload, PostMessage, SlashCommand,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha2::Sha256;
+use std::env;
+
+/// The header Slack signs requests in, see [`slack_request`].
+pub const SLACK_SIGNATURE_HEADER: &str = "X-Slack-Signature";
+
+/// The header with the time a Slack request was signed at, in seconds.
+pub const SLACK_TIMESTAMP_HEADER: &str = "X-Slack-Request-Timestamp";
+
+/// Set on events Slack delivers again because the first answer took too long.
+pub const SLACK_RETRY_HEADER: &str = "X-Slack-Retry-Num";
+
+/// Slack requests signed longer ago are refused, so they can't be replayed.
+const SLACK_MAX_AGE_SECS: i64 = 5 * 60;
+
+const SLACK_API_URL: &str = "https://slack.com/api";
+
+/// Handles a request from a Slack app, signed with `SLACK_SIGNING_SECRET`:
+///
+/// - Events API: the check of the request URL, then direct messages to the app
+///   and mentions of it, answered in a thread with `chat.postMessage` as
+///   `SLACK_BOT_TOKEN`
+/// - slash commands, e.g. `/beancount @KFC 12.4 cba > food`, answered to their
+///   sender only
+///
+/// The text goes through the same commands and parser as a Telegram message,
+/// with the top-level settings. `retry` is whether Slack delivered the event
+/// before, which is then skipped. Returns the JSON to answer with.
+pub async fn slack_request(
+    signature: Option<&str>,
+    timestamp: Option<&str>,
+    retry: bool,
+    body: &[u8],
+) -> Result<String, ApiError> {
+    let secret = Secret::from_env("SLACK_SIGNING_SECRET")?;
+    if !is_slack_signed(
+        secret.expose(),
+        signature,
+        timestamp,
+        body,
+        Utc::now().timestamp(),
+    ) {
+        warn!("Rejected Slack request with a bad signature");
+        return Err(ApiError::Unauthorized);
+    }
+    let body = std::str::from_utf8(body)
+        .map_err(|_| ApiError::BadRequest("the body isn't UTF-8".into()))?;
+
+    if !body.trim_start().starts_with('{') {
+        let command =
+            SlashCommand::from_form(body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        info!("Slack command {} from {}", command.command, command.user_id);
+        let response = CommandResponse::ephemeral(chat_reply(&command.text).await);
+        return Ok(serde_json::to_string(&response).map_err(anyhow::Error::from)?);
+    }
+    let payload: EventPayload =
+        serde_json::from_str(body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    match payload {
+        EventPayload::UrlVerification { challenge } => {
+            Ok(serde_json::json!({ "challenge": challenge }).to_string())
+        }
+        EventPayload::EventCallback { event_id, event } => {
+            if retry {
+                info!("Slack delivered {} again, skipping it", event_id);
+                return Ok("{}".into());
+            }
+            if let Some((channel, text)) = slack_message(&event) {
+                post_slack_message(&PostMessage {
+                    channel: channel.into(),
+                    text: chat_reply(&text).await,
+                    thread_ts: event.ts.clone(),
+                })
+                .await?;
+            }
+            Ok("{}".into())
+        }
+        EventPayload::Other => Ok("{}".into()),
+    }
+}
+
+/// Whether `signature` is `v0=` and the hex HMAC-SHA256 of `v0:<timestamp>:<body>`
+/// with `secret`, signed at most [`SLACK_MAX_AGE_SECS`] from `now`.
+fn is_slack_signed(
+    secret: &str,
+    signature: Option<&str>,
+    timestamp: Option<&str>,
+    body: &[u8],
+    now: i64,
+) -> bool {
+    let (hex, timestamp) = match (signature.and_then(|s| s.strip_prefix("v0=")), timestamp) {
+        (Some(hex), Some(timestamp)) => (hex, timestamp),
+        _ => return false,
+    };
+    if !timestamp
+        .parse::<i64>()
+        .is_ok_and(|signed| (now - signed).abs() <= SLACK_MAX_AGE_SECS)
+    {
+        return false;
+    }
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return false;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect();
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+    bytes.is_some_and(|bytes| mac.verify_slice(&bytes).is_ok())
+}
+
+/// The channel and text of an event to answer: a direct message to the app or
+/// a mention of it, without the mention. Bots' messages, the app's own replies
+/// among them, and edits are left alone.
+fn slack_message(event: &SlackEvent) -> Option<(&str, String)> {
+    if event.bot_id.is_some() || event.subtype.is_some() {
+        return None;
+    }
+    let channel = event.channel.as_deref()?;
+    let text = event.text.trim();
+    match (event.event_type.as_str(), event.channel_type.as_deref()) {
+        ("message", Some("im")) => Some((channel, text.to_string())),
+        ("app_mention", _) => {
+            let text = match text
+                .strip_prefix("<@")
+                .and_then(|rest| rest.split_once('>'))
+            {
+                Some((_, rest)) => rest.trim(),
+                None => text,
+            };
+            Some((channel, text.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Sends `message` with `SLACK_BOT_TOKEN`; `SLACK_API_URL` replaces Slack's API.
+async fn post_slack_message(message: &PostMessage) -> Result<()> {
+    let token = Secret::from_env("SLACK_BOT_TOKEN")?;
+    let url = format!(
+        "{}/chat.postMessage",
+        env::var("SLACK_API_URL").unwrap_or_else(|_| SLACK_API_URL.into())
+    );
+    let response: serde_json::Value = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(token.expose())
+        .json(message)
+        .send()
+        .await?
+        .json()
+        .await?;
+    // Slack answers errors with 200 and `"ok": false`.
+    if response["ok"].as_bool() != Some(true) {
+        return Err(anyhow!(
+            "Failed to call chat.postMessage: {}",
+            response["error"]
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_slack_signatures() {
+        let body = b"command=%2Fbeancount&text=%40KFC+12.4+cba+%3E+food";
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"signing-secret").unwrap();
+        mac.update(b"v0:1531420618:");
+        mac.update(body);
+        let hex: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let signature = format!("v0={}", hex);
+        let signed = |signature: &str, timestamp: &str, now: i64| {
+            is_slack_signed(
+                "signing-secret",
+                Some(signature),
+                Some(timestamp),
+                body,
+                now,
+            )
+        };
+
+        assert!(signed(&signature, "1531420618", 1531420618 + 60));
+        assert!(!signed(&signature, "1531420618", 1531420618 + 600));
+        assert!(!signed(&signature, "1531420619", 1531420619));
+        assert!(!signed(&hex, "1531420618", 1531420618));
+        assert!(!is_slack_signed(
+            "signing-secret",
+            None,
+            Some("1531420618"),
+            body,
+            1531420618
+        ));
+    }
+
+    #[test]
+    fn it_answers_direct_messages_and_mentions_on_slack() {
+        let event = |json: &str| -> SlackEvent { serde_json::from_str(json).unwrap() };
+        assert_eq!(
+            slack_message(&event("{\"type\":\"message\",\"channel\":\"D024BE91L\",\"user\":\"U2147483697\",\"text\":\"@KFC 12.4 cba > food\",\"channel_type\":\"im\"}")),
+            Some(("D024BE91L", "@KFC 12.4 cba > food".to_string()))
+        );
+        assert_eq!(
+            slack_message(&event("{\"type\":\"app_mention\",\"channel\":\"C2147483705\",\"user\":\"U2147483697\",\"text\":\"<@U0LAN0Z89> /balance cba\"}")),
+            Some(("C2147483705", "/balance cba".to_string()))
+        );
+        assert_eq!(
+            slack_message(&event("{\"type\":\"message\",\"channel\":\"D024BE91L\",\"bot_id\":\"B0001\",\"text\":\"Saved\",\"channel_type\":\"im\"}")),
+            None
+        );
+        assert_eq!(
+            slack_message(&event("{\"type\":\"message\",\"channel\":\"C2147483705\",\"user\":\"U2147483697\",\"text\":\"lunch?\",\"channel_type\":\"channel\"}")),
+            None
+        );
+    }
+}
@@ -0,0 +1,1022 @@
+use crate::config::{load_tenant_settings, reload_settings, ACCOUNT_DISCOVERY, ALIAS_CACHE};
+use crate::jobs::report_links;
+use crate::reports::{
+    account_balance, balance_to_text, budgets_on, history_ledger, net_worth_on, period_export,
+    period_report, search, search_line, year_duplicates,
+};
+use crate::updates::process_update_boxed;
+use crate::webhook::tenant_state;
+use anyhow::{anyhow, Result};
+use beancount_core::accounts::open_directive;
+use beancount_core::budget::budgets_to_text;
+use beancount_core::duplicates::{duplicates_to_text, DEFAULT_WINDOW_DAYS};
+use beancount_core::ledger::{Directive, Entry};
+use beancount_core::query::Query;
+use beancount_core::report::{GroupBy, Period};
+use beancount_core::validation::check_account_name;
+use beancount_core::{
+    settings::{render_entry_path, Settings},
+    tenants::Tenant,
+};
+use chrono::{Datelike, NaiveDate};
+use repository::aliases::add_alias;
+use repository::chat_profiles::set_active_profile;
+use repository::dead_letter;
+use repository::maintenance::archive_year;
+use repository::scheduler::post_recurring;
+use repository::{append_lines, load_ledger, Store};
+
+/// What a command runs against: `store` is the ledger of the chat's active
+/// profile, `state_store` the default ledger repository where bot state lives,
+/// the tenant's own one in a multi-tenant deployment.
+pub(crate) struct CommandContext<'a> {
+    pub(crate) store: &'a dyn Store,
+    pub(crate) state_store: &'a dyn Store,
+    pub(crate) tenant: Option<&'a Tenant>,
+    pub(crate) settings: &'a Settings,
+    pub(crate) user_id: u64,
+    pub(crate) chat_id: u64,
+}
+
+/// Longest CSV `/export` sends as a message, below Telegram's 4096 characters.
+const MAX_INLINE_EXPORT: usize = 3500;
+
+/// Where `/export` saves CSV too long for a message.
+const EXPORTS_DIR: &str = "exports";
+
+/// Matches `/search` lists, the most recent ones.
+const MAX_SEARCH_RESULTS: usize = 20;
+
+/// Appends an `open` or `close` directive to the first `discover_accounts`
+/// file, then scans the files again so aliases follow.
+async fn append_account_directive(
+    context: &CommandContext<'_>,
+    directive: &str,
+    message: &str,
+) -> Result<()> {
+    let file = context.settings.discover_accounts.first().ok_or_else(|| {
+        anyhow!("set discover_accounts to the ledger file accounts are opened in first")
+    })?;
+    let content = context.state_store.read(file).await?.unwrap_or_default();
+    append_lines(
+        context.state_store,
+        file,
+        content,
+        &[directive.to_string()],
+        message,
+    )
+    .await?;
+    forget_aliases(context.tenant).await;
+    Ok(())
+}
+
+/// Makes the next message read stored aliases and discovered accounts again.
+async fn forget_aliases(tenant: Option<&Tenant>) {
+    match tenant {
+        Some(tenant) => {
+            let state = tenant_state(tenant).await;
+            state.accounts.invalidate();
+            state.aliases.invalidate();
+        }
+        None => {
+            ACCOUNT_DISCOVERY.invalidate();
+            ALIAS_CACHE.invalidate();
+        }
+    }
+}
+
+pub(crate) async fn handle_command(context: &CommandContext<'_>, text: &str) -> Result<String> {
+    let (store, settings) = (context.store, context.settings);
+    let mut args = text.split_whitespace();
+    match args.next() {
+        Some("/archive") => {
+            let year = args
+                .next()
+                .ok_or_else(|| anyhow!("usage: /archive <year> [move]"))?
+                .parse::<i32>()?;
+            let move_to_archive = args.next() == Some("move");
+            let paths = archive_year(store, settings, year, move_to_archive).await?;
+            Ok(format!(
+                "Closed year {}, ledger written to {}",
+                year,
+                paths.join(", ")
+            ))
+        }
+        Some("/reload") => {
+            if !settings.is_admin(context.user_id) {
+                return Err(anyhow!("/reload is only available to admins"));
+            }
+            let settings = match context.tenant {
+                Some(tenant) => load_tenant_settings(tenant, true).await?,
+                None => reload_settings().await?,
+            };
+            Ok(format!(
+                "Reloaded settings, {} account aliases configured",
+                settings.accounts.len()
+            ))
+        }
+        Some("/report") => {
+            let usage = || anyhow!("usage: /report [YYYY-MM|YYYY|FROM..TO] [category|account]");
+            let mut period = Period::month_of(settings.today());
+            let mut group_by = GroupBy::Category;
+            for arg in args {
+                match (arg.parse::<GroupBy>(), arg.parse::<Period>()) {
+                    (Ok(v), _) => group_by = v,
+                    (_, Ok(v)) => period = v,
+                    _ => return Err(usage()),
+                }
+            }
+            let report = period_report(store, settings, &period, group_by).await?;
+            let mut text = format!("Report for {}\n{}", report.period, report.to_text());
+            for link in report_links(settings, &period, &report, group_by) {
+                text.push_str(&format!("{}\n", link));
+            }
+            Ok(text)
+        }
+        Some("/budget") => {
+            let date = match args.next() {
+                Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("usage: /budget [YYYY-MM-DD]"))?,
+                None => settings.today(),
+            };
+            let statuses = budgets_on(store, settings, date).await?;
+            Ok(format!(
+                "Budgets on {}\n{}",
+                date,
+                budgets_to_text(&statuses)
+            ))
+        }
+        Some("/export") => {
+            let usage = || anyhow!("usage: /export [YYYY-MM|YYYY|FROM..TO] [query]");
+            let mut args = args.peekable();
+            let period = match args.peek().and_then(|arg| arg.parse::<Period>().ok()) {
+                Some(period) => {
+                    args.next();
+                    period
+                }
+                None => Period::month_of(settings.today()),
+            };
+            let query: Query = args
+                .collect::<Vec<_>>()
+                .join(" ")
+                .parse()
+                .map_err(|e| anyhow!("{}\n{}", e, usage()))?;
+            let csv = period_export(store, settings, &period, &query).await?;
+            if csv.len() <= MAX_INLINE_EXPORT {
+                return Ok(csv);
+            }
+            let path = format!("{}/{}.csv", EXPORTS_DIR, period);
+            store
+                .write(&path, &csv, &format!("exported {}", period))
+                .await?;
+            Ok(format!(
+                "The {} export is too long for a message, it's saved to {}",
+                period, path
+            ))
+        }
+        Some("/search") => {
+            let query: Query = args.collect::<Vec<_>>().join(" ").parse()?;
+            if query.is_empty() {
+                return Err(anyhow!(
+                    "usage: /search account:Expenses:Food date>=2024-01 amount>100"
+                ));
+            }
+            let found = search(store, settings, &query).await?;
+            if found.is_empty() {
+                return Ok(format!("No transactions match {}", query));
+            }
+            let lines: Vec<String> = found
+                .iter()
+                .skip(found.len().saturating_sub(MAX_SEARCH_RESULTS))
+                .map(search_line)
+                .collect();
+            Ok(format!(
+                "{} transactions match {}, the last {}:\n{}\n",
+                found.len(),
+                query,
+                lines.len(),
+                lines.join("\n")
+            ))
+        }
+        Some("/pending") => {
+            let ledger = history_ledger(store, settings, settings.today()).await?;
+            let lines: Vec<String> = ledger
+                .transactions()
+                .filter(|entry| matches!(entry.directive, Directive::Transaction { flag: '!', .. }))
+                .map(search_line)
+                .collect();
+            if lines.is_empty() {
+                return Ok("No pending transactions".into());
+            }
+            Ok(format!(
+                "{} pending transactions:\n{}\n",
+                lines.len(),
+                lines.join("\n")
+            ))
+        }
+        Some("/last") => {
+            let n = match args.next() {
+                None => 5,
+                Some(n) => n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow!("usage: /last [5]"))?,
+            };
+            let year = settings.today().year().to_string();
+            let ledger = load_ledger(store, &settings.year_paths(&year)).await?;
+            let transactions: Vec<&Entry> = ledger.transactions().collect();
+            if transactions.is_empty() {
+                return Ok(format!("No transactions in {}", year));
+            }
+            let lines: Vec<String> = transactions
+                .iter()
+                .skip(transactions.len().saturating_sub(n.min(MAX_SEARCH_RESULTS)))
+                .map(|entry| search_line(entry))
+                .collect();
+            Ok(format!(
+                "The last {} transactions of {}:\n{}\n",
+                lines.len(),
+                year,
+                lines.join("\n")
+            ))
+        }
+        Some("/duplicates") => {
+            let usage = || anyhow!("usage: /duplicates [YYYY] [days]");
+            let year = match args.next() {
+                Some(year) => year.parse().map_err(|_| usage())?,
+                None => settings.today().year(),
+            };
+            let days = match args.next() {
+                Some(days) => days.parse().map_err(|_| usage())?,
+                None => DEFAULT_WINDOW_DAYS,
+            };
+            let groups = year_duplicates(store, settings, year, days).await?;
+            Ok(format!(
+                "Likely duplicates in {}\n{}",
+                year,
+                duplicates_to_text(&groups)
+            ))
+        }
+        Some("/networth") => {
+            let date = match args.next() {
+                Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("usage: /networth [YYYY-MM-DD]"))?,
+                None => settings.today(),
+            };
+            let worth = net_worth_on(store, settings, date).await?;
+            Ok(format!("Net worth on {}\n{}", worth.date, worth.to_text()))
+        }
+        Some("/balance") => {
+            let usage = || anyhow!("usage: /balance <alias> [YYYY-MM-DD]");
+            let alias = args.next().ok_or_else(usage)?;
+            let account = match settings.accounts.get(alias) {
+                Some(entry) => entry.account.clone(),
+                None => return Err(anyhow!("account {} isn't a configured alias", alias)),
+            };
+            let date = match args.next() {
+                Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| usage())?,
+                None => settings.today(),
+            };
+            let balances = account_balance(store, settings, &account, date).await?;
+            Ok(balance_to_text(&account, date, &balances))
+        }
+        Some("/assert") => {
+            let usage = || anyhow!("usage: /assert <alias> <amount> [currency]");
+            let alias = args.next().ok_or_else(usage)?;
+            let account = match settings.accounts.get(alias) {
+                Some(entry) => entry.account.clone(),
+                None => return Err(anyhow!("account {} isn't a configured alias", alias)),
+            };
+            let amount: f64 = args
+                .next()
+                .and_then(|amount| amount.replace(',', "").parse().ok())
+                .ok_or_else(usage)?;
+            let currency = args
+                .next()
+                .unwrap_or_else(|| settings.currency_of(&account));
+            // A balance is checked at the start of its day, so tomorrow's
+            // counts everything up to today.
+            let date = settings.today().succ();
+            let directive = format!("{} balance {} {:.2} {}", date, account, amount, currency);
+            let file = render_entry_path(
+                settings.ledger_path.as_deref(),
+                &date.year().to_string(),
+                &date.format("%m").to_string(),
+                &account,
+            );
+            let content = store.read(&file).await?.unwrap_or_default();
+            append_lines(
+                store,
+                &file,
+                content,
+                std::slice::from_ref(&directive),
+                &format!("added balance of {} on {}", account, date),
+            )
+            .await?;
+            Ok(format!("Added\n{}", directive))
+        }
+        Some("/alias") => match (args.next(), args.next(), args.next()) {
+            (Some("list"), None, _) => {
+                let mut aliases: Vec<(&String, &String)> = settings
+                    .accounts
+                    .iter()
+                    .map(|(alias, entry)| (alias, &entry.account))
+                    .collect();
+                aliases.sort();
+                let lines: Vec<String> = aliases
+                    .iter()
+                    .map(|(alias, account)| format!("{} → {}", alias, account))
+                    .collect();
+                Ok(format!("{} aliases:\n{}\n", lines.len(), lines.join("\n")))
+            }
+            (Some("add"), Some(alias), Some(account)) => {
+                if let Some(message) = check_account_name(account) {
+                    return Err(anyhow!(message));
+                }
+                if let Some(entry) = settings.accounts.get(alias) {
+                    return Err(anyhow!(
+                        "{} is the alias of {} already",
+                        alias,
+                        entry.account
+                    ));
+                }
+                add_alias(context.state_store, alias, account).await?;
+                forget_aliases(context.tenant).await;
+                Ok(format!("Added alias {} for {}", alias, account))
+            }
+            _ => Err(anyhow!("usage: /alias list | /alias add <alias> <account>")),
+        },
+        Some("/open") => {
+            let usage = || anyhow!("usage: /open <account> [alias]");
+            let account = args.next().ok_or_else(usage)?;
+            if let Some(message) = check_account_name(account) {
+                return Err(anyhow!(message));
+            }
+            let alias = args.next();
+            if let Some(alias) = alias {
+                if let Some(entry) = settings.accounts.get(alias) {
+                    return Err(anyhow!(
+                        "{} is the alias of {} already",
+                        alias,
+                        entry.account
+                    ));
+                }
+            }
+            let directive = open_directive(settings.today(), account, alias);
+            append_account_directive(context, &directive, &format!("opened {}", account)).await?;
+            Ok(format!("Added\n{}", directive))
+        }
+        Some("/close") => {
+            let alias = args
+                .next()
+                .ok_or_else(|| anyhow!("usage: /close <alias>"))?;
+            let account = match settings.accounts.get(alias) {
+                Some(entry) => entry.account.clone(),
+                None => return Err(anyhow!("account {} isn't a configured alias", alias)),
+            };
+            let directive = format!("{} close {}", settings.today(), account);
+            append_account_directive(context, &directive, &format!("closed {}", account)).await?;
+            Ok(format!("Added\n{}", directive))
+        }
+        Some("/recurring") => {
+            let date = match args.next() {
+                Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("usage: /recurring [YYYY-MM-DD]"))?,
+                None => settings.today(),
+            };
+            let saved = post_recurring(store, settings, date).await?;
+            if saved.is_empty() {
+                Ok(format!("No recurring transactions due on {}", date))
+            } else {
+                Ok(saved.join("\n"))
+            }
+        }
+        Some("/undo") => {
+            let year = settings.today().year().to_string();
+            for path in settings.year_paths(&year).iter().rev() {
+                if let Some(entry) = store.delete_last(path).await? {
+                    return Ok(format!("Removed\n{}", entry));
+                }
+            }
+            Ok(format!("No transactions in {} to remove", year))
+        }
+        Some("/ledger") => match (args.next(), args.next()) {
+            (None, _) => {
+                let mut names: Vec<&String> = settings.profiles.keys().collect();
+                names.sort();
+                let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                Ok(format!(
+                    "Writing to the {} ledger, profiles: default, {}",
+                    settings.active_profile.as_deref().unwrap_or("default"),
+                    names.join(", ")
+                ))
+            }
+            (Some("use"), Some("default")) => {
+                set_active_profile(context.state_store, context.chat_id, None).await?;
+                Ok("Switched to the default ledger".into())
+            }
+            (Some("use"), Some(name)) => {
+                settings.for_profile(name)?;
+                set_active_profile(context.state_store, context.chat_id, Some(name)).await?;
+                Ok(format!("Switched to the {} ledger", name))
+            }
+            _ => Err(anyhow!("usage: /ledger [use <profile>]")),
+        },
+        Some("/replay") => {
+            let update_id = args
+                .next()
+                .and_then(|id| id.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("usage: /replay <update id>"))?;
+            let letter = dead_letter::load(context.state_store, update_id)
+                .await?
+                .ok_or_else(|| anyhow!("update {} isn't dead-lettered", update_id))?;
+            if letter.chat_id != context.chat_id && !settings.is_admin(context.user_id) {
+                return Err(anyhow!("update {} was sent in another chat", update_id));
+            }
+            let response: serde_json::Value =
+                serde_json::from_str(&process_update_boxed(context.tenant, &letter.body).await?)?;
+            dead_letter::remove(context.state_store, update_id).await?;
+            Ok(format!(
+                "Replayed update {}\n{}",
+                update_id,
+                response["text"].as_str().unwrap_or_default()
+            ))
+        }
+        Some(command) => Err(anyhow!("unknown command {}", command)),
+        None => Err(anyhow!("empty command")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::{BudgetSettings, LedgerProfile, RecurringSettings};
+    use repository::chat_profiles::active_profile;
+    use repository::dead_letter::DeadLetter;
+    use repository::memory_store::{MemoryStore, SimulatedFailure};
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap()
+    }
+
+    async fn run(store: &MemoryStore, settings: &Settings, text: &str) -> Result<String> {
+        let context = CommandContext {
+            store,
+            state_store: store,
+            tenant: None,
+            settings,
+            user_id: 1,
+            chat_id: 42,
+        };
+        handle_command(&context, text).await
+    }
+
+    #[tokio::test]
+    async fn report_command_reads_every_year_of_the_period() {
+        let store = MemoryStore::new()
+            .with_file(
+                "2021.bean",
+                "include \"2021-takeaway.bean\"\n2021-12-20 * \"Coles\" \"\"\n  Assets:Cash  -30.00 AUD\n  Expenses:Food:Groceries\n",
+            )
+            .with_file(
+                "2021-takeaway.bean",
+                "2021-12-24 * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food:Takeaway\n",
+            )
+            .with_file(
+                "2022.bean",
+                "2022-01-02 * \"KFC\" \"\"\n  Assets:Cash  -7.50 AUD\n  Expenses:Food:Takeaway\n",
+            );
+        let reply = run(&store, &settings(), "/report 2021-12..2022-01")
+            .await
+            .unwrap();
+        assert_eq!(
+            reply.lines().nth(2).unwrap(),
+            format!(
+                "{:<40} {:>10.2} {:>10.2} {:>10.2} AUD",
+                "Expenses:Food", 42.5, 7.5, 50.0
+            )
+        );
+
+        let reply = run(&store, &settings(), "/report account 2022-01")
+            .await
+            .unwrap();
+        assert_eq!(
+            reply,
+            format!(
+                "Report for 2022-01\n{:<40} {:>10.2} AUD\n",
+                "Expenses:Food:Takeaway", 7.5
+            )
+        );
+        assert!(run(&store, &settings(), "/report soon").await.is_err());
+
+        let mut linked = settings();
+        linked.fava_url = Some("https://fava.example.com/".into());
+        let reply = run(&store, &linked, "/report account 2022-01")
+            .await
+            .unwrap();
+        assert!(reply.ends_with(
+            "\nhttps://fava.example.com/income_statement/?time=2022-01\n\
+             https://fava.example.com/account/Expenses:Food:Takeaway/?time=2022-01\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn undo_command_removes_the_last_transaction_of_the_year() {
+        let settings = settings();
+        let path = settings.ledger_path(&settings.today().year().to_string());
+        let store = MemoryStore::new().with_file(&path, "option \"title\" \"ledger\"\n");
+        assert_eq!(
+            run(&store, &settings, "/undo").await.unwrap(),
+            format!("No transactions in {} to remove", settings.today().year())
+        );
+
+        let parser = BeancountParser::new(settings.clone());
+        for text in ["@KFC 12.40 cash > food", "@Coles 30 cash > food"] {
+            store.save(parser.parse(text).unwrap()).await.unwrap();
+        }
+        let reply = run(&store, &settings, "/undo").await.unwrap();
+        assert!(reply.starts_with("Removed\n"));
+        assert!(reply.contains("\"Coles\""));
+        let content = store.file(&path).unwrap();
+        assert!(content.contains("\"KFC\""));
+        assert!(!content.contains("Coles"));
+    }
+
+    #[tokio::test]
+    async fn pending_command_lists_flagged_transactions() {
+        let settings = settings();
+        let store = MemoryStore::new();
+        assert_eq!(
+            run(&store, &settings, "/pending").await.unwrap(),
+            "No pending transactions"
+        );
+
+        let parser = BeancountParser::new(settings.clone());
+        for text in ["! @Ikea 230 cash > food", "@Coles 30 cash > food"] {
+            store.save(parser.parse(text).unwrap()).await.unwrap();
+        }
+        assert_eq!(
+            run(&store, &settings, "/pending").await.unwrap(),
+            format!(
+                "1 pending transactions:\n{} Ikea 230.00 AUD\n",
+                settings.today()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn last_command_lists_the_latest_transactions_of_the_year() {
+        let settings = settings();
+        let store = MemoryStore::new();
+        let year = settings.today().year();
+        assert_eq!(
+            run(&store, &settings, "/last").await.unwrap(),
+            format!("No transactions in {}", year)
+        );
+
+        let parser = BeancountParser::new(settings.clone());
+        for text in [
+            "@Coles 30 cash > food",
+            "@KFC 12.5 cash > food",
+            "@Ikea 230 cash > food",
+        ] {
+            store.save(parser.parse(text).unwrap()).await.unwrap();
+        }
+        let today = settings.today();
+        assert_eq!(
+            run(&store, &settings, "/last 2").await.unwrap(),
+            format!(
+                "The last 2 transactions of {}:\n{} KFC 12.50 AUD\n{} Ikea 230.00 AUD\n",
+                year, today, today
+            )
+        );
+        assert!(run(&store, &settings, "/last 10")
+            .await
+            .unwrap()
+            .starts_with(&format!("The last 3 transactions of {}:", year)));
+        assert!(run(&store, &settings, "/last zero").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn networth_command_reads_years_back_until_one_is_missing() {
+        let store = MemoryStore::new()
+            .with_file(
+                "2020.bean",
+                "2020-01-01 price USD 1.50 AUD\n2020-01-01 * \"opening\"\n  Assets:Cash  100.00 AUD\n  Assets:Wise  10.00 USD\n  Equity:Opening\n",
+            )
+            .with_file(
+                "2021.bean",
+                "2021-09-08 * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n",
+            )
+            .with_file(
+                "2018.bean",
+                "2018-01-01 * \"lost\"\n  Assets:Cash  1000.00 AUD\n  Equity:Opening\n",
+            );
+        let reply = run(&store, &settings(), "/networth 2021-12-31")
+            .await
+            .unwrap();
+        assert!(reply.starts_with("Net worth on 2021-12-31\n"));
+        assert!(reply.contains(&format!("{:<40} {:>10.2} AUD\n", "Net worth", 102.5)));
+        assert!(
+            reply.contains("Assets:Wise                                   10.00 USD = 15.00 AUD")
+        );
+    }
+
+    #[tokio::test]
+    async fn balance_command_adds_up_the_account_per_currency() {
+        let store = MemoryStore::new()
+            .with_file(
+                "2020.bean",
+                "2020-01-01 * \"opening\"\n  Assets:Cash  100.00 AUD\n  Assets:Cash:Wallet  10.00 USD\n  Equity:Opening\n",
+            )
+            .with_file(
+                "2021.bean",
+                "2021-09-08 * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n2021-12-30 * \"Coles\" \"\"\n  Assets:Cash  -30.00 AUD\n  Expenses:Food\n",
+            );
+        assert_eq!(
+            run(&store, &settings(), "/balance cash 2021-12-01")
+                .await
+                .unwrap(),
+            format!(
+                "Balance of Assets:Cash on 2021-12-01\n{:>10.2} AUD\n{:>10.2} USD\n",
+                87.5, 10.0
+            )
+        );
+        assert_eq!(
+            run(&store, &settings(), "/balance food 2021-12-31")
+                .await
+                .unwrap(),
+            format!(
+                "Balance of Expenses:Food on 2021-12-31\n{:>10.2} AUD\n",
+                42.5
+            )
+        );
+        assert!(run(&store, &settings(), "/balance").await.is_err());
+        assert!(run(&store, &settings(), "/balance bank").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn balance_command_reads_every_file_of_a_path_template() {
+        let mut settings = settings();
+        settings.ledger_path = Some("ledger/{year}/{month}.bean".into());
+        let store = MemoryStore::new().with_ledger_path(settings.ledger_path.clone());
+        let parser = BeancountParser::new(settings.clone());
+        for text in [
+            "2020-12-31 @KFC 10 cash > food",
+            "2021-01-02 @KFC 12.40 cash > food",
+            "2021-03-04 @Coles 30 cash > food",
+        ] {
+            store.save(parser.parse(text).unwrap()).await.unwrap();
+        }
+        assert!(store.read("ledger/2021/03.bean").await.unwrap().is_some());
+        assert_eq!(
+            run(&store, &settings, "/balance food 2021-12-31")
+                .await
+                .unwrap(),
+            format!(
+                "Balance of Expenses:Food on 2021-12-31\n{:>10.2} AUD\n",
+                52.4
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn budget_command_shows_month_to_date_spend() {
+        let store = MemoryStore::new().with_file(
+            "2021.bean",
+            "2021-09-08 * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n2021-09-20 * \"Coles\" \"\"\n  Assets:Cash  -30.00 AUD\n  Expenses:Food\n",
+        );
+        let budgeted = Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .budget(
+                "Expenses:Food",
+                BudgetSettings {
+                    limit: 50.0,
+                    currency: None,
+                },
+            )
+            .build()
+            .unwrap();
+        let reply = run(&store, &budgeted, "/budget 2021-09-10").await.unwrap();
+        assert_eq!(
+            reply,
+            format!(
+                "Budgets on 2021-09-10\n{:<30} {:>10.2} / 50.00 AUD (25%)\n",
+                "Expenses:Food", 12.5
+            )
+        );
+        assert_eq!(
+            run(&store, &settings(), "/budget").await.unwrap(),
+            format!("Budgets on {}\nNo budgets configured\n", settings().today())
+        );
+    }
+
+    #[tokio::test]
+    async fn export_command_saves_long_exports_to_the_store() {
+        let entry = "2021-09-08 * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n";
+        let store = MemoryStore::new().with_file("2021.bean", entry);
+        assert_eq!(
+            run(&store, &settings(), "/export 2021-09").await.unwrap(),
+            "date,payee,narration,amount,currency,accounts,tags\n2021-09-08,KFC,,12.50,AUD,Assets:Cash > Expenses:Food,\n"
+        );
+
+        assert_eq!(
+            run(&store, &settings(), "/export 2021-09 account:Expenses:Car")
+                .await
+                .unwrap(),
+            "date,payee,narration,amount,currency,accounts,tags\n"
+        );
+
+        let store = MemoryStore::new().with_file("2021.bean", &entry.repeat(100));
+        assert_eq!(
+            run(&store, &settings(), "/export 2021").await.unwrap(),
+            "The 2021 export is too long for a message, it's saved to exports/2021.csv"
+        );
+        assert_eq!(store.file("exports/2021.csv").unwrap().lines().count(), 101);
+        assert!(run(&store, &settings(), "/export soon:ish").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn assert_command_appends_a_balance_for_tomorrow() {
+        let settings = settings();
+        let tomorrow = settings.today().succ();
+        let file = settings.ledger_path(&tomorrow.year().to_string());
+        let store = MemoryStore::new().with_file(&file, "option \"title\" \"ledger\"");
+        assert_eq!(
+            run(&store, &settings, "/assert cash 1,523.4")
+                .await
+                .unwrap(),
+            format!("Added\n{} balance Assets:Cash 1523.40 AUD", tomorrow)
+        );
+        assert_eq!(
+            store.file(&file).unwrap(),
+            format!(
+                "option \"title\" \"ledger\"\n{} balance Assets:Cash 1523.40 AUD\n",
+                tomorrow
+            )
+        );
+        assert!(run(&store, &settings, "/assert cash").await.is_err());
+        assert!(run(&store, &settings, "/assert wallet 10").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn alias_command_adds_aliases_to_the_aliases_file() {
+        let store = MemoryStore::new();
+        assert_eq!(
+            run(
+                &store,
+                &settings(),
+                "/alias add groceries Expenses:Food:Groceries"
+            )
+            .await
+            .unwrap(),
+            "Added alias groceries for Expenses:Food:Groceries"
+        );
+        assert_eq!(
+            store.file("aliases.toml").unwrap(),
+            "groceries = \"Expenses:Food:Groceries\"\n"
+        );
+
+        let settings =
+            settings().with_aliases(repository::aliases::load_aliases(&store).await.unwrap());
+        assert_eq!(
+            run(&store, &settings, "/alias list").await.unwrap(),
+            "3 aliases:\ncash → Assets:Cash\nfood → Expenses:Food\ngroceries → Expenses:Food:Groceries\n"
+        );
+        assert!(run(&store, &settings, "/alias add food Expenses:Dining")
+            .await
+            .is_err());
+        assert!(run(&store, &settings, "/alias add snacks Food:Snacks")
+            .await
+            .is_err());
+        assert!(run(&store, &settings, "/alias").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn open_and_close_commands_append_to_the_accounts_file() {
+        let mut settings = settings();
+        assert!(run(
+            &MemoryStore::new(),
+            &settings,
+            "/open Assets:Bank:NewCard new"
+        )
+        .await
+        .is_err());
+
+        settings.discover_accounts = vec!["accounts.bean".into()];
+        let store = MemoryStore::new().with_file("accounts.bean", "2020-01-01 open Assets:Cash\n");
+        let today = settings.today();
+        assert_eq!(
+            run(&store, &settings, "/open Assets:Bank:NewCard new")
+                .await
+                .unwrap(),
+            format!(
+                "Added\n{} open Assets:Bank:NewCard\n  alias: \"new\"",
+                today
+            )
+        );
+        assert_eq!(
+            run(&store, &settings, "/close cash").await.unwrap(),
+            format!("Added\n{} close Assets:Cash", today)
+        );
+        assert_eq!(
+            store.file("accounts.bean").unwrap(),
+            format!(
+                "2020-01-01 open Assets:Cash\n{} open Assets:Bank:NewCard\n  alias: \"new\"\n{} close Assets:Cash\n",
+                today, today
+            )
+        );
+        assert!(run(&store, &settings, "/open Bank:NewCard").await.is_err());
+        assert!(run(&store, &settings, "/open Assets:Bank:Other food")
+            .await
+            .is_err());
+        assert!(run(&store, &settings, "/close wallet").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn search_command_lists_the_latest_matches() {
+        let store = MemoryStore::new()
+            .with_file(
+                "2020.bean",
+                "2020-12-24 * \"KFC\" \"dinner\"\n  Assets:Cash  -30.00 AUD\n  Expenses:Food\n",
+            )
+            .with_file(
+                "2021.bean",
+                "2021-09-08 * \"KFC\" \"lunch\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n2021-09-09 * \"Shell\" \"fuel\"\n  Assets:Cash  -80.00 AUD\n  Expenses:Car\n",
+            );
+        assert_eq!(
+            run(&store, &settings(), "/search kfc date<=2021-12").await.unwrap(),
+            "2 transactions match kfc date<=2021-12, the last 2:\n2020-12-24 KFC dinner 30.00 AUD\n2021-09-08 KFC lunch 12.50 AUD\n"
+        );
+        assert_eq!(
+            run(
+                &store,
+                &settings(),
+                "/search account:Expenses:Food date=2021 amount>20"
+            )
+            .await
+            .unwrap(),
+            "No transactions match account:Expenses:Food date=2021 amount>20"
+        );
+        assert!(run(&store, &settings(), "/search").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn duplicates_command_lists_likely_double_saves() {
+        let entry = |date: &str| {
+            format!(
+                "{} * \"KFC\" \"\"\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n",
+                date
+            )
+        };
+        let store = MemoryStore::new().with_file(
+            "2021.bean",
+            &[
+                entry("2021-09-08"),
+                entry("2021-09-08"),
+                entry("2021-09-30"),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            run(&store, &settings(), "/duplicates 2021").await.unwrap(),
+            "Likely duplicates in 2021\n2021-09-08 KFC 12.50 AUD: 2021.bean:1, 2021.bean:4\n"
+        );
+        assert_eq!(
+            run(&store, &settings(), "/duplicates 2021 30")
+                .await
+                .unwrap()
+                .lines()
+                .nth(1)
+                .unwrap(),
+            "2021-09-08 KFC 12.50 AUD: 2021.bean:1, 2021.bean:4, 2021.bean:7 (2021-09-30)"
+        );
+        assert!(run(&store, &settings(), "/duplicates soon").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn archive_command_closes_year_in_place() {
+        let store = MemoryStore::new().with_file(
+            "2021.bean",
+            "2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:Cash  -12.40 AUD\n  Expenses:Food\n",
+        );
+        let reply = run(&store, &settings(), "/archive 2021").await.unwrap();
+        assert_eq!(reply, "Closed year 2021, ledger written to 2021.bean");
+        assert!(store
+            .file("2021.bean")
+            .unwrap()
+            .contains("2022-01-01 balance Assets:Cash -12.40 AUD"));
+    }
+
+    #[tokio::test]
+    async fn archive_command_reports_store_failure() {
+        let store = MemoryStore::new();
+        store.fail_next(SimulatedFailure::ServerError);
+        let error = run(&store, &settings(), "/archive 2021").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "simulated failure (500 Internal Server Error)"
+        );
+        assert!(run(&store, &settings(), "/archive").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reload_command_is_admin_only() {
+        let error = run(&MemoryStore::new(), &settings(), "/reload")
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "/reload is only available to admins");
+    }
+
+    #[tokio::test]
+    async fn replay_command_only_replays_own_chat() {
+        let store = MemoryStore::new();
+        let letter = DeadLetter {
+            update_id: 459592837,
+            chat_id: 1,
+            error: "Failed to save transaction!".into(),
+            failed_at: "2021-09-08T10:00:00+00:00".into(),
+            body: "{}".into(),
+        };
+        dead_letter::save(&store, &letter).await.unwrap();
+
+        let error = run(&store, &settings(), "/replay 459592837")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "update 459592837 was sent in another chat"
+        );
+        let error = run(&store, &settings(), "/replay 1").await.unwrap_err();
+        assert_eq!(error.to_string(), "update 1 isn't dead-lettered");
+    }
+
+    #[tokio::test]
+    async fn recurring_command_posts_due_transactions() {
+        let settings = Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("rent", "Expenses:Rent")
+            .recurring(RecurringSettings {
+                name: "rent".into(),
+                schedule: "0 9 1 * *".into(),
+                text: "@Landlord rent 2000 cash > rent".into(),
+            })
+            .build()
+            .unwrap();
+        let store = MemoryStore::new();
+        let reply = run(&store, &settings, "/recurring 2021-09-01")
+            .await
+            .unwrap();
+        assert!(reply.starts_with("2021-09-01 * \"Landlord\" \"rent\""));
+        assert_eq!(
+            run(&store, &settings, "/recurring 2021-09-02")
+                .await
+                .unwrap(),
+            "No recurring transactions due on 2021-09-02"
+        );
+    }
+
+    #[tokio::test]
+    async fn ledger_command_switches_profile_of_chat() {
+        let settings = Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .profile(
+                "business",
+                LedgerProfile {
+                    path: Some("business/{year}.bean".into()),
+                    ..LedgerProfile::default()
+                },
+            )
+            .build()
+            .unwrap();
+        let store = MemoryStore::new();
+        assert_eq!(
+            run(&store, &settings, "/ledger use business")
+                .await
+                .unwrap(),
+            "Switched to the business ledger"
+        );
+        assert_eq!(
+            active_profile(&store, 42).await.unwrap().as_deref(),
+            Some("business")
+        );
+        assert!(run(&store, &settings, "/ledger use personal")
+            .await
+            .is_err());
+
+        let business = settings.for_profile("business").unwrap();
+        assert_eq!(
+            run(&store, &business, "/ledger").await.unwrap(),
+            "Writing to the business ledger, profiles: default, business"
+        );
+        run(&store, &settings, "/ledger use default").await.unwrap();
+        assert_eq!(active_profile(&store, 42).await.unwrap(), None);
+    }
+}
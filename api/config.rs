@@ -0,0 +1,186 @@
+use crate::stores::{create_store, store_for, tenant_store};
+use crate::webhook::tenant_state;
+use anyhow::{anyhow, Result};
+use beancount_core::{
+    settings::{ConfigFormat, Settings},
+    tenants::Tenant,
+};
+use log::warn;
+use repository::account_discovery::AccountDiscovery;
+use repository::aliases::AliasCache;
+use repository::chat_profiles::active_profile;
+use repository::config_source;
+use repository::settings_cache::{SettingsCache, DEFAULT_CONFIG_FILE};
+use repository::Store;
+use std::env;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+
+static SETTINGS_CACHE: SettingsCache = SettingsCache::new();
+pub(crate) static ACCOUNT_DISCOVERY: AccountDiscovery = AccountDiscovery::new();
+pub(crate) static ALIAS_CACHE: AliasCache = AliasCache::new();
+static SECRETS_LOADED: Mutex<bool> = Mutex::new(false);
+
+/// Pulls `CONFIG` and tokens from `CONFIG_SOURCE` into the environment once per
+/// cold start.
+async fn load_secrets() -> Result<()> {
+    if *SECRETS_LOADED.lock().unwrap() {
+        return Ok(());
+    }
+    config_source::export_to_env(config_source::from_env().await?.as_ref(), false).await?;
+    *SECRETS_LOADED.lock().unwrap() = true;
+    Ok(())
+}
+
+/// Settings come from the `CONFIG` env var when set, then from a local
+/// `CONFIG_PATH` file, otherwise from a config file in the ledger repository
+/// which is cached for `CONFIG_TTL_SECONDS`. With both `CONFIG` and
+/// `CONFIG_FILE` set, the file is layered over `CONFIG`.
+pub async fn load_settings() -> Result<Settings> {
+    load_secrets().await?;
+    let settings = base_settings(false).await?;
+    with_discovered_accounts(settings).await
+}
+
+/// Fetches settings from their source again, bypassing the cache TTL. Values
+/// from `CONFIG_SOURCE` replace the ones loaded at cold start.
+pub(crate) async fn reload_settings() -> Result<Settings> {
+    if env::var("CONFIG_SOURCE").is_ok_and(|source| source != "env") {
+        config_source::export_to_env(config_source::from_env().await?.as_ref(), true).await?;
+        *SECRETS_LOADED.lock().unwrap() = true;
+    }
+    let settings = base_settings(true).await?;
+    ACCOUNT_DISCOVERY.invalidate();
+    ALIAS_CACHE.invalidate();
+    with_discovered_accounts(settings).await
+}
+
+async fn base_settings(reload: bool) -> Result<Settings> {
+    let base = match (env::var("CONFIG"), env::var("CONFIG_FILE")) {
+        (Ok(_), Ok(_)) => Some(Settings::env_document()?),
+        (Ok(_), Err(_)) => return Settings::load_from_env(),
+        _ => match env::var("CONFIG_PATH") {
+            Ok(path) => return read_settings_file(&path),
+            Err(_) => None,
+        },
+    };
+    let store = create_store(None)?;
+    if reload {
+        SETTINGS_CACHE
+            .reload_over(store.as_ref(), base.as_ref(), &config_file())
+            .await
+    } else {
+        SETTINGS_CACHE
+            .get_over(store.as_ref(), base.as_ref(), &config_file(), config_ttl())
+            .await
+    }
+}
+
+/// Adds the aliases added with `/alias add`, then accounts opened in the
+/// `discover_accounts` ledger files, both read again after
+/// `CONFIG_TTL_SECONDS`.
+async fn with_discovered_accounts(settings: Settings) -> Result<Settings> {
+    let store = create_store(None)?;
+    let settings = with_stored_aliases(settings, store.as_ref(), &ALIAS_CACHE).await;
+    if settings.discover_accounts.is_empty() {
+        return Ok(settings);
+    }
+    let discovered = ACCOUNT_DISCOVERY
+        .get(store.as_ref(), &settings.discover_accounts, config_ttl())
+        .await?;
+    Ok(settings.with_discovered_accounts(discovered))
+}
+
+/// Adds the aliases of [`repository::aliases::ALIASES_FILE`]; the settings go
+/// on with the config's aliases alone if it can't be read.
+async fn with_stored_aliases(
+    settings: Settings,
+    store: &dyn Store,
+    cache: &AliasCache,
+) -> Settings {
+    match cache.get(store, config_ttl()).await {
+        Ok(aliases) => settings.with_aliases(aliases),
+        Err(e) => {
+            warn!("Failed to read stored aliases: {}", e);
+            settings
+        }
+    }
+}
+
+/// A tenant's settings come from its inline `config`, otherwise from its config
+/// file in its own repository, cached per tenant for `CONFIG_TTL_SECONDS`.
+/// Neither can read the deployment's env vars.
+pub(crate) async fn load_tenant_settings(tenant: &Tenant, reload: bool) -> Result<Settings> {
+    let state = tenant_state(tenant).await;
+    let store = tenant_store(tenant, None)?;
+    let settings = match &tenant.config {
+        Some(config) => Settings::parse_isolated(config, ConfigFormat::Toml)?,
+        None => {
+            let path = tenant.config_file.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
+            if reload {
+                state.settings.reload(store.as_ref(), path).await?
+            } else {
+                state
+                    .settings
+                    .get(store.as_ref(), path, config_ttl())
+                    .await?
+            }
+        }
+    };
+    if reload {
+        state.accounts.invalidate();
+        state.aliases.invalidate();
+    }
+    let settings = with_stored_aliases(settings, store.as_ref(), &state.aliases).await;
+    if settings.discover_accounts.is_empty() {
+        return Ok(settings);
+    }
+    let discovered = state
+        .accounts
+        .get(store.as_ref(), &settings.discover_accounts, config_ttl())
+        .await?;
+    Ok(settings.with_discovered_accounts(discovered))
+}
+
+fn config_ttl() -> Duration {
+    let ttl = env::var("CONFIG_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+    Duration::from_secs(ttl)
+}
+
+fn config_file() -> String {
+    env::var("CONFIG_FILE").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.into())
+}
+
+/// Settings from a file on local disk, e.g. one mounted into a container.
+fn read_settings_file(path: &str) -> Result<Settings> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read config file {}: {}", path, e))?;
+    Settings::parse(
+        &content,
+        ConfigFormat::from_path(path).unwrap_or(ConfigFormat::Toml),
+    )
+}
+
+/// Applies the ledger profile the chat switched to with `/ledger use`.
+pub(crate) async fn with_active_profile(
+    tenant: Option<&Tenant>,
+    settings: Settings,
+    chat_id: u64,
+) -> Result<Settings> {
+    if settings.profiles.is_empty() {
+        return Ok(settings);
+    }
+    let store = store_for(tenant, None)?;
+    match active_profile(store.as_ref(), chat_id).await? {
+        Some(name) if settings.profiles.contains_key(&name) => settings.for_profile(&name),
+        Some(name) => {
+            warn!("ledger profile {} no longer exists, using default", name);
+            Ok(settings)
+        }
+        None => Ok(settings),
+    }
+}
@@ -0,0 +1,207 @@
+use crate::config::load_settings;
+use crate::reports::{history_ledger, period_ledger, period_report};
+use crate::stores::create_store;
+use anyhow::Result;
+use beancount_core::query::Query;
+use beancount_core::report::{GroupBy, Period};
+use beancount_core::settings::Settings;
+use chrono::NaiveDate;
+use repository::Store;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Why a dashboard API request failed.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    Failed(#[from] anyhow::Error),
+}
+
+impl ApiError {
+    /// The HTTP status code to answer with.
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) => 400,
+            ApiError::Unauthorized => 401,
+            ApiError::Failed(_) => 502,
+        }
+    }
+}
+
+/// Answers the read-only dashboard endpoint `name` of the default ledger with
+/// JSON, `None` if there's no such endpoint:
+///
+/// - `entries?from=2021-09-01&to=2021-09-30&q=account:Expenses:Food` lists the
+///   directives dated in the range, the current month by default, or only the
+///   transactions matching the query `q`
+/// - `balances?date=2021-12-31` adds up the postings of every account up to the
+///   date, today by default
+/// - `report/monthly?period=2021-01..2021-06&by=account` is the `/report` of the
+///   period, the current month by category by default
+pub async fn dashboard_api(
+    name: &str,
+    query: &HashMap<String, String>,
+) -> Result<Option<String>, ApiError> {
+    let settings = load_settings().await?;
+    let store = create_store(Some(&settings))?;
+    dashboard_json(store.as_ref(), &settings, name, query).await
+}
+
+async fn dashboard_json(
+    store: &dyn Store,
+    settings: &Settings,
+    name: &str,
+    query: &HashMap<String, String>,
+) -> Result<Option<String>, ApiError> {
+    let param = |key: &str| query.get(key).map(String::as_str);
+    let date = |key: &str| {
+        param(key)
+            .map(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d"))
+            .transpose()
+            .map_err(|_| ApiError::BadRequest(format!("{} must be a YYYY-MM-DD date", key)))
+    };
+    let json = match name {
+        "entries" => {
+            let month = Period::month_of(settings.today());
+            let period = Period {
+                from: date("from")?.unwrap_or(month.from),
+                to: date("to")?.unwrap_or(month.to),
+            };
+            if period.from > period.to {
+                return Err(ApiError::BadRequest("from is after to".into()));
+            }
+            let query: Query = param("q")
+                .unwrap_or("")
+                .parse()
+                .map_err(|e: anyhow::Error| ApiError::BadRequest(e.to_string()))?;
+            let ledger = period_ledger(store, settings, &period).await?;
+            let entries: Vec<_> = ledger
+                .entries
+                .iter()
+                .filter(|entry| period.from <= entry.date && entry.date <= period.to)
+                .filter(|entry| query.is_empty() || query.matches(entry))
+                .collect();
+            serde_json::json!({ "from": period.from, "to": period.to, "entries": entries })
+        }
+        "balances" => {
+            let date = date("date")?.unwrap_or_else(|| settings.today());
+            let ledger = history_ledger(store, settings, date).await?;
+            let balances: Vec<_> = ledger
+                .totals(None, Some(date))
+                .into_iter()
+                .filter(|(_, amount)| amount.abs() >= 0.005)
+                .map(|((account, currency), amount)| {
+                    serde_json::json!({ "account": account, "currency": currency, "amount": amount })
+                })
+                .collect();
+            serde_json::json!({ "date": date, "balances": balances })
+        }
+        "report/monthly" => {
+            let period = match param("period") {
+                Some(v) => v
+                    .parse()
+                    .map_err(|e: anyhow::Error| ApiError::BadRequest(e.to_string()))?,
+                None => Period::month_of(settings.today()),
+            };
+            let group_by = match param("by") {
+                Some(v) => v
+                    .parse()
+                    .map_err(|e: anyhow::Error| ApiError::BadRequest(e.to_string()))?,
+                None => GroupBy::Category,
+            };
+            let report = period_report(store, settings, &period, group_by).await?;
+            serde_json::to_value(report).map_err(anyhow::Error::from)?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(json.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use repository::memory_store::MemoryStore;
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn dashboard_endpoints_answer_with_ledger_json() {
+        let store = MemoryStore::new()
+            .with_file(
+                "2020.bean",
+                "2020-12-31 * \"opening\"\n  Assets:Cash  100.00 AUD\n  Equity:Opening\n",
+            )
+            .with_file(
+                "2021.bean",
+                "2021-09-08 * \"KFC\" \"lunch\" #work\n  Assets:Cash  -12.50 AUD\n  Expenses:Food\n",
+            );
+        let query = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let parse = |json: Result<Option<String>, ApiError>| -> serde_json::Value {
+            serde_json::from_str(&json.unwrap().unwrap()).unwrap()
+        };
+
+        let entries = parse(
+            dashboard_json(
+                &store,
+                &settings(),
+                "entries",
+                &query(&[("from", "2021-09-01"), ("to", "2021-09-30")]),
+            )
+            .await,
+        );
+        assert_eq!(entries["entries"][0]["type"], "transaction");
+        assert_eq!(entries["entries"][0]["date"], "2021-09-08");
+        assert_eq!(entries["entries"][0]["tags"][0], "work");
+        assert_eq!(
+            entries["entries"][0]["postings"][1]["amount"]["number"],
+            12.5
+        );
+
+        let balances = parse(
+            dashboard_json(
+                &store,
+                &settings(),
+                "balances",
+                &query(&[("date", "2021-12-31")]),
+            )
+            .await,
+        );
+        assert_eq!(
+            balances["balances"][0],
+            serde_json::json!({ "account": "Assets:Cash", "currency": "AUD", "amount": 87.5 })
+        );
+
+        let report = parse(
+            dashboard_json(
+                &store,
+                &settings(),
+                "report/monthly",
+                &query(&[("period", "2021-09"), ("by", "account")]),
+            )
+            .await,
+        );
+        assert_eq!(report["rows"][0]["group"], "Expenses:Food");
+
+        let bad = dashboard_json(&store, &settings(), "entries", &query(&[("from", "soon")])).await;
+        assert_eq!(bad.unwrap_err().status(), 400);
+        assert!(dashboard_json(&store, &settings(), "secrets", &query(&[]))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,32 @@
+use beancount::{block_on, discord_request, DISCORD_SIGNATURE_HEADER, DISCORD_TIMESTAMP_HEADER};
+use http::StatusCode;
+use log::warn;
+use vercel_lambda::{error::VercelError, lambda, IntoResponse, Request, Response};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    lambda!(handler);
+    Ok(())
+}
+
+/// `POST /api/discord`, the interactions endpoint of the Discord application.
+fn handler(request: Request) -> Result<impl IntoResponse, VercelError> {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    match block_on(discord_request(
+        header(DISCORD_SIGNATURE_HEADER),
+        header(DISCORD_TIMESTAMP_HEADER),
+        request.body(),
+    )) {
+        Ok(response) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(response)?),
+        Err(e) => {
+            warn!("Discord interaction failed: {}", e);
+            Ok(Response::builder()
+                .status(e.status())
+                .header("Content-Type", "text/plain")
+                .body(e.to_string())?)
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::stores::store_for;
+use crate::telegram::{bot_token, download_file};
+use anyhow::{anyhow, Result};
+use beancount_core::import::{read_statement, StatementFormat};
+use beancount_core::ocr::{book as book_receipt, Receipt};
+use beancount_core::{
+    settings::{ImportProfile, Settings},
+    tenants::Tenant,
+};
+use bot_message::telegram::{
+    Document, InlineKeyboardButton, InlineKeyboardMarkup, Message, ResponseBody,
+};
+use log::info;
+use metrics::counter;
+use repository::importer::commit_statement;
+use repository::ocr::{self, receipt_id, OcrClient};
+use repository::Store;
+use std::io::Read;
+
+/// Statements bigger than this aren't downloaded.
+const MAX_STATEMENT_BYTES: u64 = 1024 * 1024;
+
+/// Skipped rows listed in an import reply, the rest are only counted.
+const MAX_SKIPPED_LINES: usize = 20;
+
+/// Imports a CSV, OFX or QIF statement sent as a document, with the import
+/// profile named by the first word of its caption.
+pub(crate) async fn import_document(
+    tenant: Option<&Tenant>,
+    store: &dyn Store,
+    settings: &Settings,
+    document: &Document,
+    caption: Option<&str>,
+) -> Result<String> {
+    let format = match document.file_name.as_deref() {
+        Some(name) => StatementFormat::from_file_name(name),
+        None if document.mime_type.as_deref() == Some("text/csv") => Some(StatementFormat::Csv),
+        None => None,
+    }
+    .ok_or_else(|| anyhow!("only CSV, OFX and QIF statements can be imported"))?;
+    if document
+        .file_size
+        .is_some_and(|size| size > MAX_STATEMENT_BYTES)
+    {
+        return Err(anyhow!(
+            "statements over {} KB can't be imported",
+            MAX_STATEMENT_BYTES / 1024
+        ));
+    }
+    let name = caption.and_then(|caption| caption.split_whitespace().next());
+    let (name, profile) = import_profile(settings, name)?;
+    let csv = download_file(&bot_token(tenant)?, &document.file_id).await?;
+    let (added, skipped) =
+        import_statement(store, settings, &name, &profile, format, csv.as_slice()).await?;
+
+    let mut text = format!(
+        "Imported {} transactions, skipped {}",
+        added.len(),
+        skipped.len()
+    );
+    for reason in skipped.iter().take(MAX_SKIPPED_LINES) {
+        text.push_str(&format!("\n{}", reason));
+    }
+    if skipped.len() > MAX_SKIPPED_LINES {
+        text.push_str(&format!(
+            "\n… and {} more",
+            skipped.len() - MAX_SKIPPED_LINES
+        ));
+    }
+    Ok(text)
+}
+
+/// Photos bigger than this aren't downloaded.
+const MAX_PHOTO_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Reads a receipt photo with the `[ocr]` API and books it, paid from the
+/// account named by the first word of its caption, then answers with the
+/// transaction and Save and Skip buttons. It waits in the state store until
+/// one is tapped, see `answer_pending_prompt`.
+pub(crate) async fn read_receipt(
+    tenant: Option<&Tenant>,
+    settings: &Settings,
+    message: &Message,
+) -> Result<String> {
+    let ocr_settings = settings
+        .ocr
+        .as_ref()
+        .ok_or_else(|| anyhow!("[ocr] isn't configured, so photos can't be read"))?;
+    // Sizes come smallest first, the largest one small enough reads best.
+    let photo = message
+        .photo
+        .iter()
+        .rev()
+        .find(|photo| photo.file_size.is_none_or(|size| size <= MAX_PHOTO_BYTES))
+        .ok_or_else(|| {
+            anyhow!(
+                "photos over {} MB can't be read",
+                MAX_PHOTO_BYTES / 1024 / 1024
+            )
+        })?;
+    let image = download_file(&bot_token(tenant)?, &photo.file_id).await?;
+    let text = OcrClient::from_settings(ocr_settings)?
+        .recognize(image)
+        .await?;
+    let from = message
+        .caption
+        .as_deref()
+        .and_then(|caption| caption.split_whitespace().next());
+    let transaction = book_receipt(settings, ocr_settings, &Receipt::from_text(&text), from)?;
+    let pending = ocr::Pending {
+        id: receipt_id(message.chat.id, message.message_id),
+        chat_id: message.chat.id,
+        transaction,
+    };
+    ocr::save_pending(store_for(tenant, None)?.as_ref(), &pending).await?;
+    counter!("beancount_receipts_read_total").increment(1);
+    let button = |text: &str, action: &str| InlineKeyboardButton {
+        text: text.into(),
+        callback_data: format!("receipt:{}:{}", action, pending.id),
+    };
+    let response_body = ResponseBody {
+        method: "sendMessage".into(),
+        chat_id: message.chat.id,
+        text: format!(
+            "Receipt read as:\n{}",
+            String::from(pending.transaction.clone())
+        ),
+        reply_to_message_id: message.message_id,
+        parse_mode: None,
+        reply_markup: Some(InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![button("Save", "save"), button("Skip", "skip")]],
+        }),
+    };
+    Ok(serde_json::to_string(&response_body)?)
+}
+
+/// The `[import_profiles]` entry called `name`, or without a name the only one
+/// configured, or the default layout when there are none.
+pub fn import_profile(settings: &Settings, name: Option<&str>) -> Result<(String, ImportProfile)> {
+    let mut names: Vec<&String> = settings.import_profiles.keys().collect();
+    names.sort();
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    match (name, names.as_slice()) {
+        (Some(name), _) => settings
+            .import_profiles
+            .get(name)
+            .map(|profile| (name.to_string(), profile.clone()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "no import profile named {}, configured: {}",
+                    name,
+                    names.join(", ")
+                )
+            }),
+        (None, []) => Ok(("csv".into(), ImportProfile::default())),
+        (None, [name]) => Ok((name.to_string(), settings.import_profiles[*name].clone())),
+        (None, _) => Err(anyhow!(
+            "name the import profile, one of {}",
+            names.join(", ")
+        )),
+    }
+}
+
+/// Books the rows of a statement with `profile` and commits those not in the
+/// ledger yet. Returns the added entries and why the other rows were skipped.
+pub async fn import_statement(
+    store: &dyn Store,
+    settings: &Settings,
+    name: &str,
+    profile: &ImportProfile,
+    format: StatementFormat,
+    input: impl Read,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut statement = read_statement(settings, profile, format, input)?;
+    let added = commit_statement(
+        store,
+        settings,
+        &mut statement,
+        &format!("imported {} statement", name),
+    )
+    .await?;
+    info!(
+        "imported {} transactions with the {} profile, skipped {}",
+        added.len(),
+        name,
+        statement.skipped.len()
+    );
+    Ok((added, statement.skipped))
+}
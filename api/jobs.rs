@@ -0,0 +1,248 @@
+use crate::config::load_settings;
+use crate::reports::{net_worth_on, period_report, year_duplicates};
+use crate::stores::create_store;
+use crate::telegram::send_message;
+use anyhow::{anyhow, Result};
+use beancount_core::duplicates::{duplicates_to_text, DEFAULT_WINDOW_DAYS};
+use beancount_core::fava::Fava;
+use beancount_core::reply::Reply;
+use beancount_core::report::{GroupBy, Period, Report};
+use beancount_core::settings::{JobKind, JobSettings, Settings};
+use chrono::Datelike;
+use log::{error, info};
+use metrics::counter;
+use repository::prices::commit_prices;
+use repository::rates;
+use repository::scheduler::post_recurring;
+use repository::splitwise::{commit_expenses, Splitwise};
+use tracing::instrument;
+
+/// Runs the job `name` from the settings, for platform crons that carry their
+/// own schedule. Returns what it did.
+pub async fn run_job(name: &str) -> Result<String> {
+    let settings = load_settings().await?;
+    let job = settings
+        .job(name)
+        .ok_or_else(|| anyhow!("job {} doesn't exist", name))?;
+    execute_job(&settings, job).await
+}
+
+/// Runs the jobs whose schedule fires this minute in the settings time zone,
+/// for the built-in scheduler and crons that trigger every minute. A failing
+/// job doesn't stop the others; the first error is returned after all ran.
+pub async fn run_due_jobs() -> Result<Vec<String>> {
+    let settings = load_settings().await?;
+    let mut outcomes = Vec::new();
+    let mut failure = None;
+    for job in settings.due_jobs(settings.now()) {
+        match execute_job(&settings, job).await {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => {
+                error!("Job {} failed: {}", job.name, e);
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(outcomes),
+    }
+}
+
+/// Posts the `[[recurring]]` transactions due today, for a platform cron
+/// calling `/api/recurring`. The `recurring` jobs of the settings are run, so
+/// their `chat_id` is told what was added; without any the transactions are
+/// posted all the same.
+pub async fn run_recurring() -> Result<Vec<String>> {
+    let settings = load_settings().await?;
+    let jobs: Vec<&JobSettings> = settings
+        .jobs
+        .iter()
+        .filter(|job| job.kind == JobKind::Recurring)
+        .collect();
+    if jobs.is_empty() {
+        let store = create_store(Some(&settings))?;
+        let saved = post_recurring(store.as_ref(), &settings, settings.today()).await?;
+        return Ok(vec![format!(
+            "posted {} recurring transactions",
+            saved.len()
+        )]);
+    }
+    let mut outcomes = Vec::new();
+    for job in jobs {
+        outcomes.push(execute_job(&settings, job).await?);
+    }
+    Ok(outcomes)
+}
+
+/// What a chat is told after recurring transactions were posted.
+fn recurring_summary(saved: &[String]) -> String {
+    let mut text = format!("Posted {} recurring transactions:", saved.len());
+    for entry in saved.iter() {
+        text.push_str(&format!("\n\n{}", entry.trim_end()));
+    }
+    text
+}
+
+#[instrument(name = "job", skip_all, fields(job = %job.name))]
+async fn execute_job(settings: &Settings, job: &JobSettings) -> Result<String> {
+    let outcome = match job.kind {
+        JobKind::Recurring => {
+            let store = create_store(Some(settings))?;
+            let saved = post_recurring(store.as_ref(), settings, settings.today()).await?;
+            if let (Some(chat_id), false) = (job.chat_id, saved.is_empty()) {
+                send_message(chat_id, Reply::plain(recurring_summary(&saved))).await?;
+            }
+            format!(
+                "{}: posted {} recurring transactions",
+                job.name,
+                saved.len()
+            )
+        }
+        JobKind::Reminder => {
+            let chat_id = job_chat(job)?;
+            let text = job.text.clone().unwrap_or_default();
+            send_message(chat_id, Reply::plain(text)).await?;
+            format!("{}: reminded chat {}", job.name, chat_id)
+        }
+        JobKind::MonthlyReport => {
+            let chat_id = job_chat(job)?;
+            let period = Period::month_before(settings.today());
+            let store = create_store(Some(settings))?;
+            let report = period_report(store.as_ref(), settings, &period, GroupBy::Account).await?;
+            let text = format!("Report for {}\n{}", report.period, report.to_text());
+            let links = report_links(settings, &period, &report, GroupBy::Account);
+            send_message(
+                chat_id,
+                Reply::code_block(text.trim_end()).with_lines(&links),
+            )
+            .await?;
+            format!(
+                "{}: sent the {} report to chat {}",
+                job.name, report.period, chat_id
+            )
+        }
+        JobKind::NetWorth => {
+            let chat_id = job_chat(job)?;
+            let store = create_store(Some(settings))?;
+            let worth = net_worth_on(store.as_ref(), settings, settings.today()).await?;
+            let text = format!("Net worth on {}\n{}", worth.date, worth.to_text());
+            send_message(chat_id, Reply::code_block(text.trim_end())).await?;
+            format!(
+                "{}: sent the net worth on {} to chat {}",
+                job.name, worth.date, chat_id
+            )
+        }
+        JobKind::Duplicates => {
+            let chat_id = job_chat(job)?;
+            let store = create_store(Some(settings))?;
+            let year = settings.today().year();
+            let groups =
+                year_duplicates(store.as_ref(), settings, year, DEFAULT_WINDOW_DAYS).await?;
+            if !groups.is_empty() {
+                let text = format!(
+                    "Likely duplicates in {}\n{}",
+                    year,
+                    duplicates_to_text(&groups)
+                );
+                send_message(chat_id, Reply::code_block(text.trim_end())).await?;
+            }
+            format!(
+                "{}: found {} likely duplicates in {}",
+                job.name,
+                groups.len(),
+                year
+            )
+        }
+        JobKind::Prices => {
+            let (prices, rates) = match (&settings.prices, &settings.rates) {
+                (Some(prices), Some(rates)) => (prices, rates),
+                _ => return Err(anyhow!("{} needs [prices] and [rates]", job.name)),
+            };
+            let source = rates::from_settings(rates)?;
+            let currency = prices.currency.as_deref().unwrap_or(&settings.currency);
+            let store = create_store(Some(settings))?;
+            let added = commit_prices(
+                store.as_ref(),
+                source.as_ref(),
+                prices,
+                currency,
+                settings.today(),
+            )
+            .await?;
+            format!(
+                "{}: committed {} prices to {}",
+                job.name,
+                added.len(),
+                prices.file
+            )
+        }
+        JobKind::Splitwise => {
+            let days = match &settings.splitwise {
+                Some(splitwise) => splitwise.days,
+                None => return Err(anyhow!("{} needs [splitwise]", job.name)),
+            };
+            let splitwise = Splitwise::from_env()?;
+            let user_id = splitwise.current_user().await?;
+            let since = settings.today() - chrono::Duration::days(days.into());
+            let expenses = splitwise.expenses(since).await?;
+            let store = create_store(Some(settings))?;
+            let added = commit_expenses(store.as_ref(), settings, &expenses, user_id).await?;
+            format!(
+                "{}: booked {} of {} Splitwise expenses",
+                job.name,
+                added.len(),
+                expenses.len()
+            )
+        }
+    };
+    info!("{}", outcome);
+    counter!("beancount_jobs_total", "job" => job.name.clone()).increment(1);
+    Ok(outcome)
+}
+
+/// Fava links for a report: its income statement and, by account, each account
+/// over the period. Empty without a `fava_url`.
+pub(crate) fn report_links(
+    settings: &Settings,
+    period: &Period,
+    report: &Report,
+    group_by: GroupBy,
+) -> Vec<String> {
+    let fava = match &settings.fava_url {
+        Some(url) => Fava::new(url),
+        None => return Vec::new(),
+    };
+    let mut links = vec![fava.income_statement(period)];
+    if group_by == GroupBy::Account {
+        links.extend(
+            report
+                .rows
+                .iter()
+                .map(|row| fava.account(&row.group, period)),
+        );
+    }
+    links
+}
+
+fn job_chat(job: &JobSettings) -> Result<u64> {
+    job.chat_id
+        .ok_or_else(|| anyhow!("job {} has no chat_id", job.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recurring_summary_lists_the_posted_entries() {
+        let saved = vec![
+            "2021-09-01 * \"Landlord\" \"rent\"\n  recurring: \"rent\"\n  Assets:Cash        -2000.00 AUD\n  Expenses:Food        2000.00 AUD\n".to_string(),
+            "2021-09-01 * \"Netflix\" \"\"\n  recurring: \"netflix\"\n  Assets:Cash        -16.99 AUD\n  Expenses:Food        16.99 AUD\n".to_string(),
+        ];
+        assert_eq!(
+            recurring_summary(&saved),
+            "Posted 2 recurring transactions:\n\n2021-09-01 * \"Landlord\" \"rent\"\n  recurring: \"rent\"\n  Assets:Cash        -2000.00 AUD\n  Expenses:Food        2000.00 AUD\n\n2021-09-01 * \"Netflix\" \"\"\n  recurring: \"netflix\"\n  Assets:Cash        -16.99 AUD\n  Expenses:Food        16.99 AUD"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use beancount::{block_on, is_cron_authorized, run_recurring};
+use http::StatusCode;
+use log::error;
+use vercel_lambda::{error::VercelError, lambda, IntoResponse, Request, Response};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    lambda!(handler);
+    Ok(())
+}
+
+/// `GET /api/recurring`, for a Vercel cron job that posts the recurring
+/// transactions due each day. Vercel sends `Authorization: Bearer
+/// <CRON_SECRET>` along.
+fn handler(request: Request) -> Result<impl IntoResponse, VercelError> {
+    let authorization = request
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok());
+    if !is_cron_authorized(authorization) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("Content-Type", "text/plain")
+            .body("unauthorized".to_string())?);
+    }
+    let (status, body) = match block_on(run_recurring()) {
+        Ok(outcomes) => (StatusCode::OK, outcomes.join("\n")),
+        Err(e) => {
+            error!("Recurring transactions failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .body(body)?)
+}
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use beancount_core::budget::{budget_status, BudgetStatus};
+use beancount_core::duplicates::{find_duplicates, DuplicateGroup};
+use beancount_core::export::export_csv;
+use beancount_core::ledger::{Directive, Entry, Ledger};
+use beancount_core::networth::{net_worth, NetWorth};
+use beancount_core::query::Query;
+use beancount_core::report::{report, GroupBy, Period, Report};
+use beancount_core::settings::Settings;
+use chrono::{Datelike, NaiveDate};
+use repository::{load_ledger, read_files_from, Store};
+use std::collections::{BTreeMap, HashMap};
+
+/// Expense and income totals of `period`, from the ledger files of the years it
+/// touches and the files they include.
+pub async fn period_report(
+    store: &dyn Store,
+    settings: &Settings,
+    period: &Period,
+    group_by: GroupBy,
+) -> Result<Report> {
+    let ledger = period_ledger(store, settings, period).await?;
+    Ok(report(&ledger, period, group_by))
+}
+
+/// The transactions of `period` as CSV, see [`export_csv`].
+pub async fn period_export(
+    store: &dyn Store,
+    settings: &Settings,
+    period: &Period,
+    query: &Query,
+) -> Result<String> {
+    let ledger = period_ledger(store, settings, period).await?;
+    export_csv(&ledger, period, query)
+}
+
+/// The transactions matching `query`, from the ledger files of the years its
+/// dates allow, or of every year up to today, see [`history_ledger`].
+pub async fn search(store: &dyn Store, settings: &Settings, query: &Query) -> Result<Vec<Entry>> {
+    let ledger = match query.bounds() {
+        (Some(from), to) => {
+            let to = to.unwrap_or_else(|| settings.today()).max(from);
+            period_ledger(store, settings, &Period { from, to }).await?
+        }
+        (None, to) => {
+            history_ledger(store, settings, to.unwrap_or_else(|| settings.today())).await?
+        }
+    };
+    Ok(query.filter(&ledger).cloned().collect())
+}
+
+/// `2024-01-05 KFC lunch 12.50 AUD`
+pub(crate) fn search_line(entry: &Entry) -> String {
+    let moved = entry.directive.moved().unwrap_or_default();
+    let (payee, narration) = match &entry.directive {
+        Directive::Transaction {
+            payee, narration, ..
+        } => (payee.as_deref().unwrap_or(""), narration.as_str()),
+        _ => ("", ""),
+    };
+    let text = [payee, narration]
+        .iter()
+        .filter(|v| !v.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "{} {} {:.2} {}",
+        entry.date, text, moved.number, moved.currency
+    )
+}
+
+/// The ledger files of the years `period` touches and the files they include.
+pub(crate) async fn period_ledger(
+    store: &dyn Store,
+    settings: &Settings,
+    period: &Period,
+) -> Result<Ledger> {
+    let files: Vec<String> = period
+        .years()
+        .iter()
+        .flat_map(|year| settings.year_paths(year))
+        .collect();
+    Ok(load_ledger(store, &files).await?)
+}
+
+/// Month-to-date status of every `[budgets]` prefix on `date`, from the ledger
+/// file of its year and the files it includes.
+pub async fn budgets_on(
+    store: &dyn Store,
+    settings: &Settings,
+    date: NaiveDate,
+) -> Result<Vec<BudgetStatus>> {
+    if settings.budgets.is_empty() {
+        return Ok(Vec::new());
+    }
+    let files = settings.year_paths(&date.year().to_string());
+    let ledger = load_ledger(store, &files).await?;
+    Ok(budget_status(&ledger, settings, date))
+}
+
+/// Transactions of `year` with the same payee and amount at most `days` apart,
+/// from its ledger file and the files it includes.
+pub async fn year_duplicates(
+    store: &dyn Store,
+    settings: &Settings,
+    year: i32,
+    days: i64,
+) -> Result<Vec<DuplicateGroup>> {
+    let ledger = load_ledger(store, &settings.year_paths(&year.to_string())).await?;
+    Ok(find_duplicates(&ledger, days))
+}
+
+/// Assets and liabilities on `date` in the settings currency. Every posting
+/// counts, so the whole history is read, see [`history_ledger`].
+pub async fn net_worth_on(
+    store: &dyn Store,
+    settings: &Settings,
+    date: NaiveDate,
+) -> Result<NetWorth> {
+    let ledger = history_ledger(store, settings, date).await?;
+    Ok(net_worth(&ledger, &settings.currency, date))
+}
+
+/// What `account` and the accounts under it hold on `date`, per currency.
+pub async fn account_balance(
+    store: &dyn Store,
+    settings: &Settings,
+    account: &str,
+    date: NaiveDate,
+) -> Result<BTreeMap<String, f64>> {
+    let ledger = history_ledger(store, settings, date).await?;
+    let children = format!("{}:", account);
+    let mut balances = BTreeMap::new();
+    for ((posted_to, currency), amount) in ledger.totals(None, Some(date)) {
+        if posted_to == account || posted_to.starts_with(&children) {
+            *balances.entry(currency).or_insert(0.0) += amount;
+        }
+    }
+    balances.retain(|_, amount: &mut f64| amount.abs() >= 0.005);
+    Ok(balances)
+}
+
+pub(crate) fn balance_to_text(
+    account: &str,
+    date: NaiveDate,
+    balances: &BTreeMap<String, f64>,
+) -> String {
+    let mut text = format!("Balance of {} on {}\n", account, date);
+    if balances.is_empty() {
+        text.push_str(&format!("{:>10.2}\n", 0.0));
+    }
+    for (currency, amount) in balances {
+        text.push_str(&format!("{:>10.2} {}\n", amount, currency));
+    }
+    text
+}
+
+/// Every entry up to `date`: the `discover_accounts` files when set, otherwise
+/// the ledger files of each year back from `date` until a year has none.
+pub(crate) async fn history_ledger(
+    store: &dyn Store,
+    settings: &Settings,
+    date: NaiveDate,
+) -> Result<Ledger> {
+    let mut read = HashMap::new();
+    let files = if settings.discover_accounts.is_empty() {
+        let mut files = Vec::new();
+        for year in (1..=date.year()).rev() {
+            let mut found = false;
+            for path in settings.year_paths(&year.to_string()) {
+                let content = store.read(&path).await?;
+                if content.is_some() {
+                    found = true;
+                    files.push(path.clone());
+                }
+                read.insert(path, content);
+            }
+            if !found {
+                break;
+            }
+        }
+        files
+    } else {
+        settings.discover_accounts.clone()
+    };
+    Ok(Ledger::from_files(
+        &read_files_from(store, &files, read).await?,
+    ))
+}
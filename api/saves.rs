@@ -0,0 +1,600 @@
+use crate::admin::record_error;
+use crate::reports::budgets_on;
+use crate::stores::{create_store, store_for, tenant_store};
+use crate::telegram::{bot_token, call_telegram};
+use crate::updates::reply_response;
+use crate::webhook::{dead_letter_text, Handled, TenantState, HANDLED_UPDATES, TENANT_STATE};
+use anyhow::{anyhow, Result};
+use beancount_core::budget::crossed_budgets;
+use beancount_core::reply::{format_reply, month_to_date, Reply};
+use beancount_core::{
+    parser::{ParseError, Transaction},
+    settings::{PriceSettings, Settings},
+    tenants::{RecentUpdates, Tenant},
+};
+use bot_message::telegram::{InlineKeyboardButton, InlineKeyboardMarkup, ResponseBody};
+use chrono::NaiveDate;
+use log::{error, info, warn};
+use metrics::{counter, histogram};
+use repository::dead_letter;
+use repository::duplicates::{self, pending_id};
+use repository::error::StoreError;
+use repository::prices::record_price;
+use repository::rates::{self, Rate, RateCache};
+use repository::recent_updates;
+use repository::Store;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+static RATES: RateCache = RateCache::new();
+
+/// Charges the paying account in its own currency when the message was in
+/// another one, at the `[rates]` provider's rate of the transaction date, and
+/// records the rate in the transaction metadata. A price given in the message
+/// is kept. Returns the rate when `record_prices` wants it in the prices file.
+pub(crate) async fn convert_currency(
+    settings: &Settings,
+    transaction: &mut Transaction,
+) -> Result<Option<Rate>> {
+    let rates = match &settings.rates {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if transaction.total_price().is_some() {
+        return Ok(None);
+    }
+    let account_currency = settings.currency_of(transaction.from_account());
+    if transaction.currency() == account_currency {
+        return Ok(None);
+    }
+
+    let date = NaiveDate::parse_from_str(transaction.date(), "%Y-%m-%d")?.min(settings.today());
+    let source = rates::from_settings(rates)?;
+    let rate = RATES
+        .get(
+            source.as_ref(),
+            transaction.currency(),
+            account_currency,
+            date,
+        )
+        .await?;
+    let pair = format!(
+        "{} {}/{}",
+        rate.rate,
+        account_currency,
+        transaction.currency()
+    );
+    transaction.convert(rate.rate, account_currency);
+    transaction.add_metadata("fx_rate", &pair);
+    transaction.add_metadata("fx_source", &format!("{} {}", rate.source, rate.date));
+    Ok(rates.record_prices.then_some(rate))
+}
+
+/// A parsed transaction still to be saved, see
+/// [`handle_update_deferred`](crate::handle_update_deferred).
+pub struct PendingSave {
+    pub(crate) update_id: u64,
+    pub(crate) tenant: Option<Tenant>,
+    pub(crate) settings: Settings,
+    pub(crate) transaction: Transaction,
+    /// Lines at the end of the file to look for a copy of the transaction in,
+    /// see [`Store::save_checked`].
+    pub(crate) duplicate_lines: usize,
+    /// The rate the transaction was converted at, to record as a price.
+    pub(crate) rate: Option<Rate>,
+    pub(crate) chat_id: u64,
+    pub(crate) message_id: u64,
+    /// The bot's "saving" message, edited into the reply once saved.
+    pub(crate) ack_message_id: Option<u64>,
+    pub(crate) body: String,
+}
+
+impl PendingSave {
+    pub(crate) async fn save(&self) -> Result<Reply> {
+        let (settings, transaction) = (&self.settings, &self.transaction);
+        let store = store_for(self.tenant.as_ref(), Some(settings)).map_err(|e| {
+            counter!("beancount_save_failures_total", "cause" => "store").increment(1);
+            anyhow!("Failed to create store: {}", e)
+        })?;
+
+        let started = Instant::now();
+        let result = store
+            .save_checked(transaction.clone(), self.duplicate_lines)
+            .await;
+        histogram!("beancount_save_duration_seconds").record(started.elapsed().as_secs_f64());
+        match result {
+            Ok(text) => {
+                info!("Successfully saved transaction!");
+                counter!("beancount_saves_total").increment(1);
+                if let Some(rate) = &self.rate {
+                    self.record_rate(store.as_ref(), rate).await;
+                }
+                let total = if settings.reply.month_to_date {
+                    monthly_total(store.as_ref(), settings, transaction).await
+                } else {
+                    None
+                };
+                let alerts = budget_alerts(store.as_ref(), settings, transaction).await;
+                Ok(format_reply(settings, transaction, &text, total).with_lines(&alerts))
+            }
+            Err(e @ StoreError::LikelyDuplicate { .. }) => Err(e.into()),
+            Err(e) => {
+                error!("Failed to save transaction: {}", e);
+                counter!("beancount_save_failures_total", "cause" => e.kind()).increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Holds the transaction back in the state store when saving found it
+    /// looks like a copy of `line` of `path`, and asks with Save anyway and
+    /// Skip buttons whether to save it, see `answer_pending_prompt`.
+    async fn confirm_duplicate(&self, path: &str, line: usize) -> Result<ResponseBody> {
+        let pending = duplicates::Pending {
+            id: pending_id(self.chat_id, self.message_id),
+            chat_id: self.chat_id,
+            transaction: self.transaction.clone(),
+        };
+        duplicates::save_pending(store_for(self.tenant.as_ref(), None)?.as_ref(), &pending).await?;
+        counter!("beancount_likely_duplicates_total").increment(1);
+        let button = |text: &str, action: &str| InlineKeyboardButton {
+            text: text.into(),
+            callback_data: format!("duplicate:{}:{}", action, pending.id),
+        };
+        Ok(ResponseBody {
+            method: "sendMessage".into(),
+            chat_id: self.chat_id,
+            text: format!(
+                "Looks like a duplicate of {}:{}, save anyway?\n{}",
+                path,
+                line,
+                String::from(pending.transaction.clone())
+            ),
+            reply_to_message_id: self.message_id,
+            parse_mode: None,
+            reply_markup: Some(InlineKeyboardMarkup {
+                inline_keyboard: vec![vec![button("Save anyway", "save"), button("Skip", "skip")]],
+            }),
+        })
+    }
+
+    /// Appends the conversion rate to the prices file; the transaction is
+    /// saved already, so a failure is only logged.
+    async fn record_rate(&self, store: &dyn Store, rate: &Rate) {
+        let file = self
+            .settings
+            .prices
+            .as_ref()
+            .map_or(PriceSettings::DEFAULT_FILE, |prices| prices.file.as_str());
+        let currency = self.settings.currency_of(self.transaction.from_account());
+        match record_price(store, file, self.transaction.currency(), currency, rate).await {
+            Ok(Some(directive)) => info!("recorded {}", directive),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to record the price of {}: {}",
+                self.transaction.currency(),
+                e
+            ),
+        }
+    }
+
+    pub(crate) async fn save_and_reply(self) -> Result<String> {
+        match self.save().await {
+            Ok(reply) => reply_response(self.chat_id, self.message_id, reply),
+            Err(e) => match likely_duplicate(&e) {
+                Some((path, line)) => Ok(serde_json::to_string(
+                    &self.confirm_duplicate(&path, line).await?,
+                )?),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Replies "saving" through the Bot API, whose answer carries the id of
+    /// the message to edit later. If that fails the transaction is saved
+    /// before answering after all.
+    pub(crate) async fn acknowledge(self) -> Result<Handled> {
+        let sent = match bot_token(self.tenant.as_ref()) {
+            Ok(token) => {
+                let body = serde_json::json!({
+                    "chat_id": self.chat_id,
+                    "text": ACK_TEXT,
+                    "reply_to_message_id": self.message_id,
+                });
+                call_telegram(&token, "sendMessage", &body).await
+            }
+            Err(e) => Err(e),
+        };
+        match sent.map(|sent| sent["message_id"].as_u64()) {
+            Ok(Some(ack_message_id)) => {
+                IN_FLIGHT.lock().unwrap().insert(
+                    self.update_id,
+                    InFlight {
+                        tenant: self.tenant.clone(),
+                        chat_id: self.chat_id,
+                        ack_message_id,
+                        body: self.body.clone(),
+                    },
+                );
+                Ok(Handled {
+                    response: "saving in the background".into(),
+                    pending: Some(PendingSave {
+                        ack_message_id: Some(ack_message_id),
+                        ..self
+                    }),
+                })
+            }
+            Ok(None) => {
+                warn!("sendMessage answered without a message_id, saving before answering");
+                self.save_and_reply().await.map(Handled::from)
+            }
+            Err(e) => {
+                warn!("Failed to acknowledge, saving before answering: {}", e);
+                self.save_and_reply().await.map(Handled::from)
+            }
+        }
+    }
+
+    /// Saves the transaction and edits the acknowledgement into the usual
+    /// reply. A failure can't be retried by Telegram any more since the
+    /// webhook was answered, so the update is dead-lettered for `/replay`.
+    pub async fn run(self) {
+        let saved = self.save().await;
+        let in_flight = IN_FLIGHT.lock().unwrap().remove(&self.update_id);
+        if in_flight.is_none() {
+            // `flush_state` gave up on this save and dead-lettered it meanwhile.
+            if saved.is_ok() {
+                let removed = match store_for(self.tenant.as_ref(), None) {
+                    Ok(store) => dead_letter::remove(store.as_ref(), self.update_id).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = removed {
+                    warn!("Failed to remove dead letter {}: {}", self.update_id, e);
+                }
+            }
+            return;
+        }
+        let saved = match saved {
+            Ok(reply) => Ok((reply, None)),
+            Err(e) => match likely_duplicate(&e) {
+                Some((path, line)) => self
+                    .confirm_duplicate(&path, line)
+                    .await
+                    .map(|question| (Reply::plain(question.text), question.reply_markup)),
+                None => Err(e),
+            },
+        };
+        let (reply, buttons) = match saved {
+            Ok(answer) => answer,
+            Err(e) => {
+                let text = match dead_letter_text(self.tenant.as_ref(), &self.body, e).await {
+                    Ok(text) => text,
+                    Err(e) => {
+                        record_error(&e);
+                        format!(
+                            "⚠️\n==============================\n{}\nSend the message again to retry.",
+                            failure_text(&e)
+                        )
+                    }
+                };
+                (Reply::plain(text), None)
+            }
+        };
+        let edited = match bot_token(self.tenant.as_ref()) {
+            Ok(token) => {
+                let mut body = serde_json::json!({
+                    "chat_id": self.chat_id,
+                    "message_id": self.ack_message_id,
+                    "text": reply.text,
+                    "parse_mode": reply.parse_mode,
+                });
+                if let Some(buttons) = buttons {
+                    body["reply_markup"] = serde_json::json!(buttons);
+                }
+                call_telegram(&token, "editMessageText", &body).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = edited {
+            error!(
+                "Failed to edit the reply to message {}: {}",
+                self.message_id, e
+            );
+        }
+    }
+}
+
+/// Where the entry is that saving found the transaction looks like a copy of.
+fn likely_duplicate(e: &anyhow::Error) -> Option<(String, usize)> {
+    match e.downcast_ref::<StoreError>() {
+        Some(StoreError::LikelyDuplicate { path, line }) => Some((path.clone(), *line)),
+        _ => None,
+    }
+}
+
+const ACK_TEXT: &str = "Parsed ✓, saving…";
+
+/// What's needed to dead-letter a background save that didn't finish.
+struct InFlight {
+    tenant: Option<Tenant>,
+    chat_id: u64,
+    ack_message_id: u64,
+    body: String,
+}
+
+/// Background saves acknowledged and not finished yet, by update id.
+static IN_FLIGHT: Mutex<BTreeMap<u64, InFlight>> = Mutex::new(BTreeMap::new());
+
+/// How many acknowledged transactions are still being saved.
+pub fn saves_in_flight() -> usize {
+    IN_FLIGHT.lock().unwrap().len()
+}
+
+/// Called once before the process exits, after in-flight saves had their
+/// chance to finish: the ones still running are dead-lettered so `/replay`
+/// can save them later, and the recent update ids of the default ledger and of
+/// each tenant are written to its repository so redeliveries after the restart
+/// aren't saved twice.
+pub async fn flush_state() -> Result<()> {
+    let mut failure = None;
+    let in_flight = std::mem::take(&mut *IN_FLIGHT.lock().unwrap());
+    for (update_id, pending) in in_flight {
+        let error = anyhow!("the bot shut down before the transaction was saved");
+        match dead_letter_text(pending.tenant.as_ref(), &pending.body, error).await {
+            Ok(text) => {
+                let edited = match bot_token(pending.tenant.as_ref()) {
+                    Ok(token) => {
+                        let body = serde_json::json!({
+                            "chat_id": pending.chat_id,
+                            "message_id": pending.ack_message_id,
+                            "text": text,
+                        });
+                        call_telegram(&token, "editMessageText", &body).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = edited {
+                    warn!(
+                        "Failed to tell chat {} about update {}: {}",
+                        pending.chat_id, update_id, e
+                    );
+                }
+            }
+            Err(e) => {
+                error!("Lost update {} at shutdown: {}", update_id, e);
+                failure.get_or_insert(e);
+            }
+        }
+    }
+
+    let handled_updates = HANDLED_UPDATES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(RecentUpdates::ids);
+    if let Some(update_ids) = handled_updates {
+        let saved = match create_store(None) {
+            Ok(store) => recent_updates::save(store.as_ref(), &update_ids).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            error!("Failed to keep recent updates of the default ledger: {}", e);
+            failure.get_or_insert(e);
+        }
+    }
+
+    let states: Vec<Arc<TenantState>> = TENANT_STATE.lock().unwrap().values().cloned().collect();
+    for state in states {
+        let update_ids = state.recent_updates.lock().unwrap().ids();
+        let saved = match tenant_store(&state.tenant, None) {
+            Ok(store) => recent_updates::save(store.as_ref(), &update_ids).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            error!(
+                "Failed to keep recent updates of tenant {}: {}",
+                state.tenant.name, e
+            );
+            failure.get_or_insert(e);
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn failure_text(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<StoreError>() {
+        Some(e) => save_failure_text(e),
+        None => format!("Failed to handle message: {}", e),
+    }
+}
+
+/// What to tell the user when a save fails for good, by the kind of failure.
+fn save_failure_text(e: &StoreError) -> String {
+    match e {
+        StoreError::Auth { .. } => {
+            "Failed to save transaction: the ledger repository rejected the bot's token, check GITHUB_TOKEN.".into()
+        }
+        StoreError::NotFound(path) => {
+            format!("Failed to save transaction: {} doesn't exist in the ledger repository.", path)
+        }
+        StoreError::Conflict(_) => {
+            "Failed to save transaction: the ledger file was changed at the same time, send the message again.".into()
+        }
+        _ => format!("Failed to save transaction: {}", e),
+    }
+}
+
+/// What to tell the user when a message isn't a transaction, by the kind of
+/// mistake.
+pub(crate) fn parse_failure_text(e: &ParseError, settings: &Settings) -> String {
+    match e {
+        ParseError::UnknownAccount { suggestions, .. } if suggestions.is_empty() => {
+            let mut aliases: Vec<&str> = settings.accounts.keys().map(String::as_str).collect();
+            aliases.sort_unstable();
+            format!(
+                "Failed to parse input: {}, the accounts are {}.",
+                e,
+                aliases.join(", ")
+            )
+        }
+        ParseError::NoFromAccount => {
+            "Failed to parse input: say which account paid before `>`, e.g. `@KFC 12.40 cba > food`, or set default_from_account.".into()
+        }
+        ParseError::Syntax(_) | ParseError::Empty => format!(
+            "Failed to parse input: {}\nWrite transactions like `@KFC hamburger 12.40 cba > food`.",
+            e
+        ),
+        _ => format!("Failed to parse input: {}", e),
+    }
+}
+
+/// Spending on the transaction's account this month, read back from its ledger
+/// file; the reply goes out without it if the file can't be read.
+async fn monthly_total(
+    store: &dyn Store,
+    settings: &Settings,
+    transaction: &Transaction,
+) -> Option<f64> {
+    match store
+        .read(&transaction.ledger_path(settings.ledger_path.as_deref()))
+        .await
+    {
+        Ok(content) => content.map(|content| {
+            month_to_date(
+                &content,
+                transaction.to_account(),
+                transaction.currency(),
+                transaction.date(),
+            )
+        }),
+        Err(e) => {
+            warn!("Failed to read month to date total: {}", e);
+            None
+        }
+    }
+}
+
+/// Warnings for the budgets the transaction took to 80% of their limit or
+/// over it; the reply goes out without them if the ledger can't be read.
+async fn budget_alerts(
+    store: &dyn Store,
+    settings: &Settings,
+    transaction: &Transaction,
+) -> Vec<String> {
+    if settings.budget_for(transaction.to_account()).is_none() {
+        return Vec::new();
+    }
+    let date = match NaiveDate::parse_from_str(transaction.date(), "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => return Vec::new(),
+    };
+    match budgets_on(store, settings, date).await {
+        Ok(statuses) => crossed_budgets(
+            &statuses,
+            transaction.to_account(),
+            f64::from(transaction.amount()),
+            transaction.currency(),
+        ),
+        Err(e) => {
+            warn!("Failed to read budgets: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::BudgetSettings;
+    use repository::memory_store::MemoryStore;
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn saves_warn_when_they_cross_a_budget_threshold() {
+        let settings = Settings::builder("AUD")
+            .account("cash", "Assets:Cash")
+            .account("food", "Expenses:Food")
+            .budget(
+                "food",
+                BudgetSettings {
+                    limit: 50.0,
+                    currency: None,
+                },
+            )
+            .build()
+            .unwrap();
+        let store = MemoryStore::new();
+        let parser = BeancountParser::new(settings.clone());
+        let mut alerts = Vec::new();
+        for text in [
+            "2021-09-08 @KFC 30 cash > food",
+            "2021-09-09 @KFC 12 cash > food",
+            "2021-09-10 @KFC 2 cash > food",
+            "2021-09-11 @Coles 10 cash > food",
+        ] {
+            let transaction = parser.parse(text).unwrap();
+            store.save(transaction.clone()).await.unwrap();
+            alerts.push(budget_alerts(&store, &settings, &transaction).await);
+        }
+        assert_eq!(
+            alerts,
+            vec![
+                vec![],
+                vec!["⚠️ 84% of the Expenses:Food budget spent: 42.00 / 50.00 AUD".to_string()],
+                vec![],
+                vec!["❗ Over the Expenses:Food budget: 54.00 / 50.00 AUD".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn save_failure_wording_follows_error_kind() {
+        let error = StoreError::NotFound("2021.bean".into());
+        assert_eq!(
+            save_failure_text(&error),
+            "Failed to save transaction: 2021.bean doesn't exist in the ledger repository."
+        );
+        let error = StoreError::Conflict("Failed to push file 2021.bean".into());
+        assert!(error.is_retryable());
+        assert_eq!(
+            save_failure_text(&error),
+            "Failed to save transaction: the ledger file was changed at the same time, send the message again."
+        );
+        let error = StoreError::RateLimited {
+            message: "Failed to push file 2021.bean".into(),
+            retry_after: None,
+        };
+        assert_eq!(
+            save_failure_text(&error),
+            "Failed to save transaction: Failed to push file 2021.bean: rate limited"
+        );
+    }
+
+    #[test]
+    fn parse_failure_wording_follows_error_kind() {
+        let parser = BeancountParser::new(settings());
+        let text = |input: &str| parse_failure_text(&parser.parse(input).unwrap_err(), &settings());
+        assert_eq!(
+            text("@KFC 12.40 bank > food"),
+            "Failed to parse input: account bank doesn't exist in current setting, the accounts are cash, food."
+        );
+        assert_eq!(
+            text("@KFC 12.40 cash > fxxd"),
+            "Failed to parse input: account fxxd doesn't exist in current setting, did you mean `food`?"
+        );
+        assert!(text("KFC 12.40 cash > food")
+            .ends_with("\nWrite transactions like `@KFC hamburger 12.40 cba > food`."));
+    }
+}
@@ -0,0 +1,37 @@
+use beancount::{
+    block_on, slack_request, SLACK_RETRY_HEADER, SLACK_SIGNATURE_HEADER, SLACK_TIMESTAMP_HEADER,
+};
+use http::StatusCode;
+use log::warn;
+use vercel_lambda::{error::VercelError, lambda, IntoResponse, Request, Response};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    lambda!(handler);
+    Ok(())
+}
+
+/// `POST /api/slack`, the request URL of the Slack app's events and slash
+/// commands.
+fn handler(request: Request) -> Result<impl IntoResponse, VercelError> {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let retry = header(SLACK_RETRY_HEADER).is_some();
+    match block_on(slack_request(
+        header(SLACK_SIGNATURE_HEADER),
+        header(SLACK_TIMESTAMP_HEADER),
+        retry,
+        request.body(),
+    )) {
+        Ok(response) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(response)?),
+        Err(e) => {
+            warn!("Slack request failed: {}", e);
+            Ok(Response::builder()
+                .status(e.status())
+                .header("Content-Type", "text/plain")
+                .body(e.to_string())?)
+        }
+    }
+}
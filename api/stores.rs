@@ -0,0 +1,52 @@
+#[cfg(not(feature = "github"))]
+use anyhow::anyhow;
+use anyhow::Result;
+use beancount_core::{settings::Settings, tenants::Tenant};
+use repository::{Store, StoreFactory};
+
+/// The store for the ledger `settings` write to, or for the default ledger
+/// repository with no settings applied when `None`.
+pub fn create_store(settings: Option<&Settings>) -> Result<Box<dyn Store>> {
+    StoreFactory::new()
+        .with_file_header(settings.and_then(|s| s.file_header.clone()))
+        .with_ledger_path(settings.and_then(|s| s.ledger_path.clone()))
+        .with_commit_message(settings.and_then(|s| s.commit_message.clone()))
+        .with_repository(settings.and_then(|s| s.ledger_repo()).map(String::from))
+        .build()
+}
+
+/// The store of a tenant's ledger, or of the deployment's own one without a
+/// tenant, see [`create_store`].
+pub(crate) fn store_for(
+    tenant: Option<&Tenant>,
+    settings: Option<&Settings>,
+) -> Result<Box<dyn Store>> {
+    match tenant {
+        Some(tenant) => tenant_store(tenant, settings),
+        None => create_store(settings),
+    }
+}
+
+/// Tenants keep their ledger in GitHub, reached with their own token.
+#[cfg(feature = "github")]
+pub(crate) fn tenant_store(tenant: &Tenant, settings: Option<&Settings>) -> Result<Box<dyn Store>> {
+    StoreFactory::new()
+        .with_github_repo(
+            &tenant.github_owner,
+            &tenant.github_repo,
+            &tenant.github_token,
+        )
+        .with_file_header(settings.and_then(|s| s.file_header.clone()))
+        .with_ledger_path(settings.and_then(|s| s.ledger_path.clone()))
+        .with_commit_message(settings.and_then(|s| s.commit_message.clone()))
+        .with_repository(settings.and_then(|s| s.ledger_repo()).map(String::from))
+        .build()
+}
+
+#[cfg(not(feature = "github"))]
+pub(crate) fn tenant_store(
+    tenant: &Tenant,
+    _settings: Option<&Settings>,
+) -> Result<Box<dyn Store>> {
+    Err(anyhow!("tenant {} needs the github feature", tenant.name))
+}
@@ -0,0 +1,112 @@
+use crate::config::load_settings;
+use crate::stores::create_store;
+use anyhow::{anyhow, Result};
+use beancount_core::reply::Reply;
+use beancount_core::secret::{redact, Secret};
+use beancount_core::tenants::Tenant;
+use log::error;
+use std::env;
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+
+fn telegram_api_url() -> String {
+    env::var("TELEGRAM_API_URL").unwrap_or_else(|_| TELEGRAM_API_URL.into())
+}
+
+fn telegram_url(token: &Secret<String>, method: &str) -> String {
+    format!("{}/bot{}/{}", telegram_api_url(), token.expose(), method)
+}
+
+/// Downloads a file sent to the bot, looking up its path with `getFile`.
+pub(crate) async fn download_file(token: &Secret<String>, file_id: &str) -> Result<Vec<u8>> {
+    let file = call_telegram(token, "getFile", &serde_json::json!({ "file_id": file_id })).await?;
+    let path = file["file_path"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Telegram has no file_path for {}", file_id))?;
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/file/bot{}/{}",
+            telegram_api_url(),
+            token.expose(),
+            path
+        ))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        error!("Response status was {}", response.status());
+        return Err(anyhow!("Failed to download {}", path));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// The token of the bot a tenant's chats talk to, `TELEGRAM_BOT_TOKEN` unless
+/// the tenant has its own bot.
+pub(crate) fn bot_token(tenant: Option<&Tenant>) -> Result<Secret<String>> {
+    match tenant.and_then(|tenant| tenant.bot_token.clone()) {
+        Some(token) => Ok(token),
+        None => Secret::from_env("TELEGRAM_BOT_TOKEN"),
+    }
+}
+
+/// Calls a Bot API method without parameters and returns its JSON response.
+pub(crate) async fn telegram_get(method: &str) -> Result<String> {
+    let token = Secret::from_env("TELEGRAM_BOT_TOKEN")?;
+    let response = reqwest::Client::new()
+        .get(telegram_url(&token, method))
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        error!("Response status was {}", status);
+        error!("Response body was {}", redact(&body));
+        return Err(anyhow!("Failed to call {}: {}", method, status));
+    }
+    Ok(body)
+}
+
+/// Sends a message on the bot's own initiative rather than as a webhook reply.
+pub(crate) async fn send_message(chat_id: u64, reply: Reply) -> Result<()> {
+    let token = Secret::from_env("TELEGRAM_BOT_TOKEN")?;
+    let body = serde_json::json!({
+        "chat_id": chat_id,
+        "text": reply.text,
+        "parse_mode": reply.parse_mode,
+    });
+    call_telegram(&token, "sendMessage", &body).await?;
+    Ok(())
+}
+
+/// Calls a Bot API method and returns the `result` of its response.
+pub(crate) async fn call_telegram(
+    token: &Secret<String>,
+    method: &str,
+    body: &serde_json::Value,
+) -> Result<serde_json::Value> {
+    let response = reqwest::Client::new()
+        .post(telegram_url(token, method))
+        .json(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        error!("Response status was {}", response.status());
+        error!(
+            "Response body was {}",
+            redact(&response.text().await.unwrap_or_default())
+        );
+        return Err(anyhow!("Failed to call {}", method));
+    }
+    let mut response: serde_json::Value = response.json().await?;
+    Ok(response["result"].take())
+}
+
+/// Whether settings are valid and the ledger store answers, for readiness
+/// probes.
+pub async fn check_ready() -> Result<()> {
+    let settings = load_settings().await?;
+    let store = create_store(Some(&settings))?;
+    store
+        .read(&settings.ledger_path(&settings.today().format("%Y").to_string()))
+        .await?;
+    Ok(())
+}
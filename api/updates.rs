@@ -0,0 +1,366 @@
+use crate::commands::{handle_command, CommandContext};
+use crate::config::{load_settings, load_tenant_settings, with_active_profile};
+use crate::imports::{import_document, read_receipt};
+use crate::saves::{convert_currency, parse_failure_text, PendingSave};
+use crate::stores::store_for;
+use crate::telegram::{bot_token, call_telegram};
+use anyhow::{anyhow, Result};
+use beancount_core::reply::Reply;
+use beancount_core::secret::redact;
+use beancount_core::{
+    parser::{BeancountParser, Transaction},
+    tenants::Tenant,
+};
+use bot_message::telegram::{CallbackQuery, ResponseBody, Update};
+use log::{error, info, warn};
+use metrics::counter;
+#[cfg(feature = "bank-feed")]
+use repository::bank_feed;
+use repository::duplicates;
+use repository::ocr;
+use repository::Store;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{info_span, Instrument, Span};
+
+/// A `sendMessage` webhook response replying to a message.
+pub(crate) fn reply_response(chat_id: u64, message_id: u64, reply: Reply) -> Result<String> {
+    let response_body = ResponseBody {
+        method: "sendMessage".into(),
+        chat_id,
+        text: reply.text,
+        reply_to_message_id: message_id,
+        parse_mode: reply.parse_mode,
+        reply_markup: None,
+    };
+    Ok(serde_json::to_string(&response_body)?)
+}
+
+/// What handling an update comes to before anything is written: a response
+/// right away, or a transaction to save.
+pub(crate) enum Prepared {
+    Reply(String),
+    Save(Box<PendingSave>),
+}
+
+/// Handles an update start to end, for `/replay`.
+async fn process_update(tenant: Option<&Tenant>, body: &str) -> Result<String> {
+    match prepare_update(tenant, body).await? {
+        Prepared::Reply(response) => Ok(response),
+        Prepared::Save(save) => (*save).save_and_reply().await,
+    }
+}
+
+/// [`process_update`] behind a box, since `/replay` runs it from within the
+/// update it handles.
+pub(crate) fn process_update_boxed<'a>(
+    tenant: Option<&'a Tenant>,
+    body: &'a str,
+) -> Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>> {
+    Box::pin(process_update(tenant, body))
+}
+
+pub(crate) async fn prepare_update(tenant: Option<&Tenant>, body: &str) -> Result<Prepared> {
+    info!("request body is {}", redact(body));
+
+    let update: Update = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => {
+            warn!("Failed to deserialize request body: {}", redact(body));
+            return Ok(Prepared::Reply("Failed to deserialize request body".into()));
+        }
+    };
+
+    let span = Span::current();
+    span.record("update_id", update.update_id);
+    if let Some(callback) = &update.callback_query {
+        return answer_button(tenant, update.update_id, callback)
+            .await
+            .map(Prepared::Reply);
+    }
+    let message = match update.message {
+        Some(v) => v,
+        None => match update.edited_message {
+            Some(v) => v,
+            None => {
+                warn!("Could not get message or edited_message from request");
+                return Ok(Prepared::Reply(
+                    "Could not get message or edited_message from request".into(),
+                ));
+            }
+        },
+    };
+
+    span.record("chat_id", message.chat.id);
+    counter!("beancount_messages_received_total").increment(1);
+
+    let settings = async {
+        match tenant {
+            Some(tenant) => load_tenant_settings(tenant, false).await,
+            None => load_settings().await,
+        }
+    }
+    .instrument(info_span!("settings.load"))
+    .await
+    .map_err(|e| {
+        error!("Failed to load settings: {}", e);
+        counter!("beancount_save_failures_total", "cause" => "settings").increment(1);
+        e
+    })?
+    .for_chat(message.chat.id)
+    .for_user(message.from.id);
+    let settings = with_active_profile(tenant, settings, message.chat.id)
+        .await
+        .map_err(|e| {
+            error!("Failed to load ledger profile: {}", e);
+            counter!("beancount_save_failures_total", "cause" => "settings").increment(1);
+            e
+        })?;
+    let parser = BeancountParser::new(settings.clone());
+
+    let ok_response = |text| {
+        reply_response(message.chat.id, message.message_id, Reply::plain(text)).map(Prepared::Reply)
+    };
+
+    if let Some(document) = &message.document {
+        let store = store_for(tenant, Some(&settings))
+            .map_err(|e| anyhow!("Failed to create store: {}", e))?;
+        let caption = message.caption.as_deref();
+        return match import_document(tenant, store.as_ref(), &settings, document, caption).await {
+            Ok(text) => ok_response(text),
+            Err(e) => {
+                error!("Failed to import statement: {}", e);
+                ok_response(format!(
+                    "⚠️\n==============================\nFailed to import statement: {}",
+                    e
+                ))
+            }
+        };
+    }
+
+    if !message.photo.is_empty() {
+        return match read_receipt(tenant, &settings, &message).await {
+            Ok(response) => Ok(Prepared::Reply(response)),
+            Err(e) => {
+                error!("Failed to read receipt: {}", e);
+                ok_response(format!(
+                    "⚠️\n==============================\nFailed to read receipt: {}",
+                    e
+                ))
+            }
+        };
+    }
+
+    if message.text.starts_with('/') {
+        let store = store_for(tenant, Some(&settings))
+            .map_err(|e| anyhow!("Failed to create store: {}", e))?;
+        let state_store =
+            store_for(tenant, None).map_err(|e| anyhow!("Failed to create store: {}", e))?;
+        let context = CommandContext {
+            store: store.as_ref(),
+            state_store: state_store.as_ref(),
+            tenant,
+            settings: &settings,
+            user_id: message.from.id,
+            chat_id: message.chat.id,
+        };
+        return match handle_command(&context, &message.text).await {
+            Ok(text) => ok_response(text),
+            Err(e) => {
+                error!("Failed to run command: {}", e);
+                ok_response(format!(
+                    "⚠️\n==============================\nFailed to run command: {}",
+                    e
+                ))
+            }
+        };
+    }
+
+    let mut transaction = match parser.parse(&message.text) {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            error!("Failed to parse input: {}", e);
+            counter!("beancount_parse_failures_total").increment(1);
+            return ok_response(format!(
+                "⚠️\n==============================\n{}",
+                parse_failure_text(&e, &settings)
+            ));
+        }
+    };
+
+    let rate = match convert_currency(&settings, &mut transaction).await {
+        Ok(rate) => rate,
+        Err(e) => {
+            error!("Failed to convert currency: {}", e);
+            return ok_response(format!(
+                "⚠️\n==============================\nFailed to convert {}: {}",
+                transaction.currency(),
+                e
+            ));
+        }
+    };
+
+    transaction.set_message(message.chat.id, message.message_id);
+    info!("parsed transaction is {:?}", transaction);
+
+    Ok(Prepared::Save(Box::new(PendingSave {
+        update_id: update.update_id,
+        tenant: tenant.cloned(),
+        duplicate_lines: settings.duplicate_lines,
+        settings,
+        transaction,
+        rate,
+        chat_id: message.chat.id,
+        message_id: message.message_id,
+        ack_message_id: None,
+        body: body.into(),
+    })))
+}
+
+/// Answers a tap on a button under one of the bot's prompts, by the prefix of
+/// its data.
+async fn answer_button(
+    tenant: Option<&Tenant>,
+    update_id: u64,
+    callback: &CallbackQuery,
+) -> Result<String> {
+    let (prefix, load, remove): (&str, LoadPending, RemovePending) = match callback.data.as_deref()
+    {
+        Some(data) if data.starts_with("receipt:") => (
+            "receipt",
+            |store, id| {
+                Box::pin(async move {
+                    Ok(ocr::load_pending(store, id)
+                        .await?
+                        .map(|pending| (pending.chat_id, pending.transaction)))
+                })
+            },
+            |store, id| Box::pin(ocr::remove_pending(store, id)),
+        ),
+        Some(data) if data.starts_with("duplicate:") => (
+            "duplicate",
+            |store, id| {
+                Box::pin(async move {
+                    Ok(duplicates::load_pending(store, id)
+                        .await?
+                        .map(|pending| (pending.chat_id, pending.transaction)))
+                })
+            },
+            |store, id| Box::pin(duplicates::remove_pending(store, id)),
+        ),
+        #[cfg(feature = "bank-feed")]
+        Some(data) if data.starts_with("bank:") => (
+            "bank",
+            |store, id| {
+                Box::pin(async move {
+                    Ok(bank_feed::load_pending(store, id)
+                        .await?
+                        .map(|pending| (pending.chat_id, pending.transaction)))
+                })
+            },
+            |store, id| Box::pin(bank_feed::remove_pending(store, id)),
+        ),
+        _ => return answer_callback(callback, "Unknown button"),
+    };
+    answer_pending_prompt(tenant, update_id, callback, prefix, load, remove).await
+}
+
+/// Shows `text` to whoever tapped the button.
+fn answer_callback(callback: &CallbackQuery, text: &str) -> Result<String> {
+    Ok(serde_json::json!({
+        "method": "answerCallbackQuery",
+        "callback_query_id": callback.id,
+        "text": text,
+    })
+    .to_string())
+}
+
+/// Reads a transaction held back in the state store by its id, as the chat
+/// it was asked about in and the transaction.
+type LoadPending = for<'a> fn(
+    &'a dyn Store,
+    &'a str,
+) -> Pin<
+    Box<dyn Future<Output = Result<Option<(u64, Transaction)>>> + Send + 'a>,
+>;
+
+/// Drops a transaction held back in the state store once answered.
+type RemovePending =
+    for<'a> fn(&'a dyn Store, &'a str) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Saves or skips the transaction held back under the id of a Save or Skip
+/// button tapped under a prompt, the receipt photos of [`read_receipt`], the
+/// likely duplicates of [`PendingSave::confirm_duplicate`] or the bank
+/// transactions of `bank_webhook`, then answers the tap. Saving goes through
+/// [`PendingSave::save`] like any message, without looking for a copy again.
+async fn answer_pending_prompt(
+    tenant: Option<&Tenant>,
+    update_id: u64,
+    callback: &CallbackQuery,
+    prefix: &str,
+    load: LoadPending,
+    remove: RemovePending,
+) -> Result<String> {
+    let answer = |text: &str| answer_callback(callback, text);
+    let (action, id) = match callback
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(prefix))
+        .and_then(|data| data.strip_prefix(':'))
+        .and_then(|data| data.split_once(':'))
+    {
+        Some(button) => button,
+        None => return answer("Unknown button"),
+    };
+    let state_store = store_for(tenant, None)?;
+    let (chat_id, transaction) = match load(state_store.as_ref(), id).await? {
+        Some(pending) => pending,
+        None => return answer("Already answered"),
+    };
+    let message = match &callback.message {
+        Some(message) if message.chat.id == chat_id => message,
+        _ => return answer("Unknown button"),
+    };
+    let reply = match action {
+        "save" => {
+            let settings = match tenant {
+                Some(tenant) => load_tenant_settings(tenant, false).await?,
+                None => load_settings().await?,
+            }
+            .for_chat(message.chat.id)
+            .for_user(callback.from.id);
+            let settings = with_active_profile(tenant, settings, message.chat.id).await?;
+            let reply = PendingSave {
+                update_id,
+                tenant: tenant.cloned(),
+                settings,
+                transaction,
+                duplicate_lines: 0,
+                rate: None,
+                chat_id,
+                message_id: message.message_id,
+                ack_message_id: None,
+                body: String::new(),
+            }
+            .save()
+            .await?;
+            counter!("beancount_transactions_saved_total").increment(1);
+            reply
+        }
+        "skip" => Reply::plain(format!("Skipped\n{}", String::from(transaction))),
+        _ => return answer("Unknown button"),
+    };
+    remove(state_store.as_ref(), id).await?;
+    call_telegram(
+        &bot_token(tenant)?,
+        "editMessageText",
+        &serde_json::json!({
+            "chat_id": message.chat.id,
+            "message_id": message.message_id,
+            "text": reply.text,
+            "parse_mode": reply.parse_mode,
+        }),
+    )
+    .await?;
+    answer(if action == "save" { "Saved" } else { "Skipped" })
+}
@@ -0,0 +1,608 @@
+use crate::admin::record_error;
+use crate::auth::is_webhook_authorized;
+use crate::saves::{failure_text, PendingSave};
+use crate::stores::{create_store, store_for, tenant_store};
+use crate::updates::{prepare_update, reply_response, Prepared};
+use anyhow::{anyhow, Result};
+use beancount_core::reply::Reply;
+use beancount_core::tenants::{RateLimiter, RecentUpdates, Tenant, TenantRegistry};
+use bot_message::telegram::{Message, Update};
+use chrono::Utc;
+use log::{error, info, warn};
+use metrics::{counter, histogram};
+use repository::account_discovery::AccountDiscovery;
+use repository::aliases::AliasCache;
+use repository::dead_letter::{self, DeadLetter};
+use repository::error::StoreError;
+use repository::recent_updates;
+use repository::settings_cache::SettingsCache;
+use repository::Store;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{instrument, Span};
+
+/// Telegram updates are a few kilobytes; anything much bigger isn't one.
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// The header Telegram sends the `secret_token` the webhook was set with in.
+pub const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// Why a webhook request was refused before its body was deserialized.
+#[derive(Debug, Error, PartialEq)]
+pub enum Rejection {
+    #[error("secret token doesn't match")]
+    Forbidden,
+    #[error("request body is larger than {MAX_BODY_BYTES} bytes")]
+    TooLarge,
+    #[error("request body must be application/json")]
+    UnsupportedMediaType,
+    #[error("request body isn't valid UTF-8")]
+    InvalidUtf8,
+}
+
+impl Rejection {
+    /// The HTTP status code to answer with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Rejection::Forbidden => 403,
+            Rejection::TooLarge => 413,
+            Rejection::UnsupportedMediaType => 415,
+            Rejection::InvalidUtf8 => 400,
+        }
+    }
+}
+
+/// Checks the secret token, size, content type and encoding of a webhook
+/// request and returns its body as text, for every platform to run before
+/// [`handle_update`]. With `TELEGRAM_WEBHOOK_SECRET` set, requests must carry it
+/// in [`SECRET_TOKEN_HEADER`], as Telegram does for a webhook set with that
+/// `secret_token`.
+pub fn validate_request<'a>(
+    content_type: Option<&str>,
+    secret_token: Option<&str>,
+    body: &'a [u8],
+) -> Result<&'a str, Rejection> {
+    if !is_webhook_authorized(secret_token) {
+        return Err(Rejection::Forbidden);
+    }
+    if body.len() > MAX_BODY_BYTES {
+        return Err(Rejection::TooLarge);
+    }
+    let is_json = content_type
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"));
+    if !is_json {
+        return Err(Rejection::UnsupportedMediaType);
+    }
+    std::str::from_utf8(body).map_err(|_| Rejection::InvalidUtf8)
+}
+
+/// Updates still failing this long after they were sent are dead-lettered
+/// instead of being left to Telegram's retries.
+const RETRY_WINDOW_SECONDS: i64 = 600;
+
+/// Handles a Telegram webhook request body and returns the JSON to answer with,
+/// a `sendMessage` reply in the same chat, for the standalone server; an error
+/// means the update should be retried.
+///
+/// With a tenant registry configured the update goes to the tenant its chat
+/// belongs to, and updates from other chats are ignored.
+#[instrument(name = "handle_update", skip_all, fields(update_id, chat_id, tenant))]
+pub async fn handle_update(body: &str) -> Result<String> {
+    route_update(None, body, SaveMode::Inline, UpdateLog::Memory)
+        .await
+        .map(|handled| handled.response)
+}
+
+/// Handles an update sent to the webhook of a tenant's own bot, see
+/// [`Tenant::bot_id`].
+#[instrument(name = "handle_update", skip_all, fields(update_id, chat_id, tenant))]
+pub async fn handle_bot_update(bot_id: &str, body: &str) -> Result<String> {
+    route_update(Some(bot_id), body, SaveMode::Inline, UpdateLog::Memory)
+        .await
+        .map(|handled| handled.response)
+}
+
+/// Like [`handle_update`] and [`handle_bot_update`], for deployments such as
+/// Vercel and Lambda that may stop right after answering and never run
+/// [`flush_state`](crate::flush_state): each handled update leaves a marker in
+/// the state store, so a redelivery reaching another instance still gets the
+/// original response.
+#[instrument(name = "handle_update", skip_all, fields(update_id, chat_id, tenant))]
+pub async fn handle_serverless_update(bot_id: Option<&str>, body: &str) -> Result<String> {
+    route_update(bot_id, body, SaveMode::Inline, UpdateLog::Store)
+        .await
+        .map(|handled| handled.response)
+}
+
+/// Like [`handle_update`] and [`handle_bot_update`], but a parsed transaction is
+/// acknowledged in the chat right away and saved by [`PendingSave::run`] after
+/// the webhook was answered, so a slow store never hits Telegram's timeout.
+/// Only for deployments that keep running after answering.
+#[instrument(name = "handle_update", skip_all, fields(update_id, chat_id, tenant))]
+pub async fn handle_update_deferred(bot_id: Option<&str>, body: &str) -> Result<Handled> {
+    route_update(bot_id, body, SaveMode::Deferred, UpdateLog::Memory).await
+}
+
+/// Whether a parsed transaction is saved before the webhook is answered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SaveMode {
+    Inline,
+    Deferred,
+}
+
+/// Where handled updates are remembered besides memory: nowhere until
+/// [`flush_state`](crate::flush_state), or a marker per update in the state
+/// store.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UpdateLog {
+    Memory,
+    Store,
+}
+
+/// The webhook response for an update, and the save still to run once it was
+/// sent when the transaction was only acknowledged.
+pub struct Handled {
+    pub response: String,
+    pub pending: Option<PendingSave>,
+}
+
+impl From<String> for Handled {
+    fn from(response: String) -> Self {
+        Handled {
+            response,
+            pending: None,
+        }
+    }
+}
+
+async fn route_update(
+    bot_id: Option<&str>,
+    body: &str,
+    mode: SaveMode,
+    log: UpdateLog,
+) -> Result<Handled> {
+    let started = Instant::now();
+    let response = match (TenantRegistry::from_env()?, bot_id) {
+        (Some(registry), bot_id) => match tenant_for(&registry, bot_id, body) {
+            Ok((tenant, update_id, message)) => {
+                Span::current().record("tenant", tenant.name.as_str());
+                handle_tenant_update(tenant, update_id, &message, body, mode, log).await
+            }
+            Err(reason) => {
+                warn!("Ignored update: {}", reason);
+                Ok(reason.into())
+            }
+        },
+        (None, Some(bot_id)) => {
+            warn!(
+                "Ignored update for bot {}, no tenants are configured",
+                bot_id
+            );
+            Ok(Handled::from("no tenants are configured".to_string()))
+        }
+        (None, None) => handle_default_update(body, mode, log).await,
+    };
+    if let Err(e) = &response {
+        record_error(e);
+    }
+    histogram!("beancount_update_duration_seconds").record(started.elapsed().as_secs_f64());
+    response
+}
+
+/// Dead-letters updates that failed for good, see [`should_retry`].
+async fn handle_with_dead_letters(
+    tenant: Option<&Tenant>,
+    body: &str,
+    mode: SaveMode,
+) -> Result<Handled> {
+    let handled = match prepare_update(tenant, body).await {
+        Ok(Prepared::Reply(response)) => Ok(response.into()),
+        Ok(Prepared::Save(save)) if mode == SaveMode::Deferred => (*save).acknowledge().await,
+        Ok(Prepared::Save(save)) => (*save).save_and_reply().await.map(Handled::from),
+        Err(e) => Err(e),
+    };
+    match handled {
+        Err(e) if !should_retry(&e, body, Utc::now().timestamp()) => {
+            dead_letter(tenant, body, e).await.map(Handled::from)
+        }
+        handled => handled,
+    }
+}
+
+/// The tenant an update belongs to, or why it's ignored.
+fn tenant_for<'a>(
+    registry: &'a TenantRegistry,
+    bot_id: Option<&str>,
+    body: &str,
+) -> Result<(&'a Tenant, u64, Message), String> {
+    let update: Update =
+        serde_json::from_str(body).map_err(|_| "Failed to deserialize request body".to_string())?;
+    // A tapped button belongs to the chat of the message it's under.
+    let message = match update.callback_query {
+        Some(callback) => callback
+            .message
+            .ok_or_else(|| "Could not get the message of callback_query".to_string())?,
+        None => update
+            .message
+            .or(update.edited_message)
+            .ok_or_else(|| "Could not get message or edited_message from request".to_string())?,
+    };
+    let tenant = match bot_id {
+        Some(bot_id) => registry
+            .for_bot(bot_id)
+            .filter(|tenant| tenant.allows_chat(message.chat.id)),
+        None => registry.for_chat(message.chat.id),
+    };
+    match tenant {
+        Some(tenant) => Ok((tenant, update.update_id, message)),
+        None => Err(format!(
+            "chat {} isn't registered to a tenant",
+            message.chat.id
+        )),
+    }
+}
+
+/// What a deployment keeps in memory per tenant, so tenants share neither
+/// caches nor limits.
+pub(crate) struct TenantState {
+    pub(crate) tenant: Tenant,
+    pub(crate) settings: SettingsCache,
+    pub(crate) accounts: AccountDiscovery,
+    pub(crate) aliases: AliasCache,
+    pub(crate) recent_updates: Mutex<RecentUpdates>,
+    pub(crate) rate_limiter: Mutex<RateLimiter>,
+}
+
+/// Update ids remembered per tenant to drop redeliveries of handled updates.
+const RECENT_UPDATES: usize = 100;
+
+pub(crate) static TENANT_STATE: Mutex<BTreeMap<String, Arc<TenantState>>> =
+    Mutex::new(BTreeMap::new());
+
+/// The tenant's state, created with the update ids
+/// [`flush_state`](crate::flush_state) kept before the last restart.
+pub(crate) async fn tenant_state(tenant: &Tenant) -> Arc<TenantState> {
+    let existing = TENANT_STATE.lock().unwrap().get(&tenant.name).cloned();
+    if let Some(state) = existing {
+        return state;
+    }
+    let recent_updates = load_recent_updates(
+        tenant_store(tenant, None),
+        &format!("tenant {}", tenant.name),
+    )
+    .await;
+    let state = Arc::new(TenantState {
+        tenant: tenant.clone(),
+        settings: SettingsCache::isolated(),
+        accounts: AccountDiscovery::new(),
+        aliases: AliasCache::new(),
+        recent_updates: Mutex::new(recent_updates),
+        rate_limiter: Mutex::new(RateLimiter::default()),
+    });
+    // Another update of the tenant may have created it while this one loaded.
+    TENANT_STATE
+        .lock()
+        .unwrap()
+        .entry(tenant.name.clone())
+        .or_insert(state)
+        .clone()
+}
+
+/// The update ids [`flush_state`](crate::flush_state) kept in `store` before the
+/// last restart.
+async fn load_recent_updates(store: Result<Box<dyn Store>>, owner: &str) -> RecentUpdates {
+    let mut recent_updates = RecentUpdates::new(RECENT_UPDATES);
+    let loaded = match store {
+        Ok(store) => recent_updates::load(store.as_ref()).await,
+        Err(e) => Err(e),
+    };
+    match loaded {
+        Ok(update_ids) => update_ids
+            .into_iter()
+            .for_each(|update_id| recent_updates.insert(update_id)),
+        Err(e) => warn!("Failed to load recent updates of {}: {}", owner, e),
+    }
+    recent_updates
+}
+
+/// The response for an update that was already handled: the one it got the
+/// first time, or a note when that was before a restart.
+fn redelivered(recent_updates: &RecentUpdates, update_id: u64) -> Option<Handled> {
+    if !recent_updates.contains(update_id) {
+        return None;
+    }
+    counter!("beancount_duplicate_updates_total").increment(1);
+    Some(
+        recent_updates
+            .response(update_id)
+            .map(String::from)
+            .unwrap_or_else(|| format!("update {} was already handled", update_id))
+            .into(),
+    )
+}
+
+/// Marks `update_id` as being handled, unless it's a redelivery: that gets the
+/// response of [`redelivered`], or fails while the update is still being handled
+/// so Telegram retries once there is a response to send.
+fn start_update(recent_updates: &mut RecentUpdates, update_id: u64) -> Result<Option<Handled>> {
+    if recent_updates.is_handling(update_id) {
+        counter!("beancount_duplicate_updates_total").increment(1);
+        return Err(anyhow!("update {} is still being handled", update_id));
+    }
+    if let Some(handled) = redelivered(recent_updates, update_id) {
+        return Ok(Some(handled));
+    }
+    recent_updates.start(update_id);
+    Ok(None)
+}
+
+/// Records the response of an update [`start_update`] marked, or forgets it
+/// failed so Telegram's retry is handled again.
+fn finish_update(recent_updates: &mut RecentUpdates, update_id: u64, handled: &Result<Handled>) {
+    match handled {
+        Ok(handled) => recent_updates.record(update_id, &handled.response),
+        Err(_) => recent_updates.abandon(update_id),
+    }
+}
+
+/// Update ids the deployment's own bot handled, loaded on its first update.
+pub(crate) static HANDLED_UPDATES: Mutex<Option<RecentUpdates>> = Mutex::new(None);
+
+/// Answers updates Telegram redelivers, e.g. after the webhook timed out, with
+/// the response they got the first time instead of saving them again.
+async fn handle_default_update(body: &str, mode: SaveMode, log: UpdateLog) -> Result<Handled> {
+    let update_id = match serde_json::from_str::<Update>(body) {
+        Ok(update) => update.update_id,
+        Err(_) => return handle_with_dead_letters(None, body, mode).await,
+    };
+    if HANDLED_UPDATES.lock().unwrap().is_none() {
+        let loaded = load_recent_updates(create_store(None), "the default ledger").await;
+        HANDLED_UPDATES.lock().unwrap().get_or_insert(loaded);
+    }
+    let duplicate = match HANDLED_UPDATES.lock().unwrap().as_mut() {
+        Some(recent_updates) => start_update(recent_updates, update_id)?,
+        None => None,
+    };
+    if let Some(handled) = duplicate {
+        info!("update {} was already handled", update_id);
+        return Ok(handled);
+    }
+    let handled = handle_logged(None, update_id, body, mode, log).await;
+    if let Some(recent_updates) = HANDLED_UPDATES.lock().unwrap().as_mut() {
+        finish_update(recent_updates, update_id, &handled);
+    }
+    handled
+}
+
+/// [`handle_with_dead_letters`], answering from and leaving a marker of the
+/// update in the state store with [`UpdateLog::Store`].
+async fn handle_logged(
+    tenant: Option<&Tenant>,
+    update_id: u64,
+    body: &str,
+    mode: SaveMode,
+    log: UpdateLog,
+) -> Result<Handled> {
+    if log == UpdateLog::Memory {
+        return handle_with_dead_letters(tenant, body, mode).await;
+    }
+    let store = store_for(tenant, None)?;
+    match recent_updates::marked(store.as_ref(), update_id).await {
+        Ok(Some(response)) => {
+            info!(
+                "update {} was already handled by another instance",
+                update_id
+            );
+            counter!("beancount_duplicate_updates_total").increment(1);
+            return Ok(response.into());
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to look for a marker of update {}: {}", update_id, e),
+    }
+    let handled = handle_with_dead_letters(tenant, body, mode).await?;
+    if let Err(e) = recent_updates::mark(store.as_ref(), update_id, &handled.response).await {
+        warn!("Failed to mark update {} as handled: {}", update_id, e);
+    }
+    Ok(handled)
+}
+
+/// Answers updates the tenant already handled with their original response
+/// and messages over its rate limit without handling them.
+async fn handle_tenant_update(
+    tenant: &Tenant,
+    update_id: u64,
+    message: &Message,
+    body: &str,
+    mode: SaveMode,
+    log: UpdateLog,
+) -> Result<Handled> {
+    let state = tenant_state(tenant).await;
+    let duplicate = start_update(&mut state.recent_updates.lock().unwrap(), update_id)?;
+    if let Some(handled) = duplicate {
+        info!(
+            "update {} was already handled for tenant {}",
+            update_id, tenant.name
+        );
+        return Ok(handled);
+    }
+    if !state
+        .rate_limiter
+        .lock()
+        .unwrap()
+        .allow(tenant.rate_limit, Instant::now())
+    {
+        warn!("tenant {} is over its rate limit", tenant.name);
+        state.recent_updates.lock().unwrap().abandon(update_id);
+        counter!("beancount_rate_limited_total", "tenant" => tenant.name.clone()).increment(1);
+        let text = format!(
+            "⚠️\n==============================\nToo many messages, at most {} a minute are handled. Send it again later.",
+            tenant.rate_limit
+        );
+        return reply_response(message.chat.id, message.message_id, Reply::plain(text))
+            .map(Handled::from);
+    }
+    let handled = handle_logged(Some(tenant), update_id, body, mode, log).await;
+    finish_update(
+        &mut state.recent_updates.lock().unwrap(),
+        update_id,
+        &handled,
+    );
+    handled
+}
+
+/// Answering with an error status makes Telegram redeliver the update, which is
+/// only worth it for transient store errors while the message is recent.
+fn should_retry(e: &anyhow::Error, body: &str, now: i64) -> bool {
+    let retryable = e.chain().any(|cause| {
+        cause
+            .downcast_ref::<StoreError>()
+            .is_some_and(StoreError::is_retryable)
+    });
+    let sent_at = serde_json::from_str::<Update>(body)
+        .ok()
+        .and_then(|update| update.message.or(update.edited_message))
+        .map(|message| message.date as i64);
+    retryable && sent_at.is_some_and(|sent_at| now - sent_at < RETRY_WINDOW_SECONDS)
+}
+
+/// Keeps an update that failed for good so it can be replayed with `/replay`,
+/// and acknowledges it so Telegram stops redelivering. If it can't be kept the
+/// error is returned and Telegram keeps trying.
+pub(crate) async fn dead_letter(
+    tenant: Option<&Tenant>,
+    body: &str,
+    error: anyhow::Error,
+) -> Result<String> {
+    let message = match serde_json::from_str::<Update>(body)
+        .ok()
+        .and_then(|update| update.message.or(update.edited_message))
+    {
+        Some(message) => message,
+        None => return Err(error),
+    };
+    let text = dead_letter_text(tenant, body, error).await?;
+    reply_response(message.chat.id, message.message_id, Reply::plain(text))
+}
+
+/// Keeps the update and returns what to tell the user, or the error back if it
+/// can't be kept.
+pub(crate) async fn dead_letter_text(
+    tenant: Option<&Tenant>,
+    body: &str,
+    error: anyhow::Error,
+) -> Result<String> {
+    let (update_id, message) = match serde_json::from_str::<Update>(body) {
+        Ok(update) => match update.message.or(update.edited_message) {
+            Some(message) => (update.update_id, message),
+            None => return Err(error),
+        },
+        Err(_) => return Err(error),
+    };
+    let letter = DeadLetter {
+        update_id,
+        chat_id: message.chat.id,
+        error: error.to_string(),
+        failed_at: Utc::now().to_rfc3339(),
+        body: body.into(),
+    };
+    let saved = match store_for(tenant, None) {
+        Ok(store) => dead_letter::save(store.as_ref(), &letter).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = saved {
+        error!("Failed to dead-letter update {}: {}", update_id, e);
+        return Err(error);
+    }
+    warn!("dead-lettered update {}: {}", update_id, error);
+    record_error(&error);
+    counter!("beancount_dead_letters_total").increment(1);
+    Ok(format!(
+        "⚠️\n==============================\n{}\nThe message was kept, send /replay {} to try again.",
+        failure_text(&error),
+        update_id
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::settings::ConfigFormat;
+    use std::env;
+
+    #[test]
+    fn requests_are_validated_before_deserializing() {
+        let json = Some("application/json; charset=utf-8");
+        assert_eq!(validate_request(json, None, b"{}"), Ok("{}"));
+        assert_eq!(
+            validate_request(Some("text/plain"), None, b"{}"),
+            Err(Rejection::UnsupportedMediaType)
+        );
+        assert_eq!(
+            validate_request(None, None, b"{}"),
+            Err(Rejection::UnsupportedMediaType)
+        );
+        assert_eq!(
+            validate_request(json, None, &[b'{', 0xff, b'}']),
+            Err(Rejection::InvalidUtf8)
+        );
+        let body = vec![b' '; MAX_BODY_BYTES + 1];
+        assert_eq!(
+            validate_request(json, None, &body),
+            Err(Rejection::TooLarge)
+        );
+        assert_eq!(Rejection::TooLarge.status(), 413);
+
+        env::set_var("TELEGRAM_WEBHOOK_SECRET", "s3cr3t");
+        let forged = validate_request(json, Some("guess"), b"{}");
+        let missing = validate_request(json, None, b"{}");
+        let signed = validate_request(json, Some("s3cr3t"), b"{}");
+        env::remove_var("TELEGRAM_WEBHOOK_SECRET");
+        assert_eq!(forged, Err(Rejection::Forbidden));
+        assert_eq!(missing, Err(Rejection::Forbidden));
+        assert_eq!(signed, Ok("{}"));
+        assert_eq!(Rejection::Forbidden.status(), 403);
+    }
+
+    #[test]
+    fn only_recent_updates_failing_transiently_are_retried() {
+        let body = "{\"update_id\":459592837, \"message\":{\"message_id\":7,\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1631506802,\"text\":\"@KFC chicken 12.9 AUD CBA > food\"}}";
+        let conflict = anyhow::Error::from(StoreError::Conflict("Failed to save".into()));
+        assert!(should_retry(&conflict, body, 1631506802 + 60));
+        assert!(!should_retry(&conflict, body, 1631506802 + 3600));
+
+        let not_found = anyhow::Error::from(StoreError::NotFound("2021.bean".into()));
+        assert!(!should_retry(&not_found, body, 1631506802 + 60));
+        assert!(!should_retry(
+            &anyhow!("CONFIG env not set!"),
+            body,
+            1631506802
+        ));
+    }
+
+    #[test]
+    fn updates_go_to_the_tenant_of_their_chat() {
+        let registry = TenantRegistry::parse(
+            "[tenants.alice]\nchat_ids = [247673932]\ngithub_owner = \"alice\"\ngithub_repo = \"beancount\"\ngithub_token = \"a\"\n[tenants.bob]\nbot_token = \"123456:bob\"\ngithub_owner = \"bob\"\ngithub_repo = \"beancount\"\ngithub_token = \"b\"\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        let body = |chat_id: u64| {
+            format!("{{\"update_id\":459592837, \"message\":{{\"message_id\":7,\"from\":{{\"id\":{0},\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"}},\"chat\":{{\"id\":{0},\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"}},\"date\":1631506802,\"text\":\"/ledger\"}}}}", chat_id)
+        };
+
+        let (tenant, update_id, _) = tenant_for(&registry, None, &body(247673932)).unwrap();
+        assert_eq!((tenant.name.as_str(), update_id), ("alice", 459592837));
+        let (tenant, _, _) = tenant_for(&registry, Some("123456"), &body(1)).unwrap();
+        assert_eq!(tenant.name, "bob");
+
+        assert_eq!(
+            tenant_for(&registry, None, &body(1)).unwrap_err(),
+            "chat 1 isn't registered to a tenant"
+        );
+        assert!(tenant_for(&registry, Some("123456"), "{}").is_err());
+        assert!(tenant_for(&registry, Some("654321"), &body(247673932)).is_err());
+    }
+}
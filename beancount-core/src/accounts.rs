@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::settings::AccountSettings;
+
+lazy_static! {
+    static ref OPEN_CLOSE_RE: Regex = Regex::new(
+        r"^\d{4}-\d{2}-\d{2}\s+(open|close)\s+([A-Z][A-Za-z0-9-]*(?::[A-Z0-9][A-Za-z0-9-]*)+)(?:\s+([A-Z][A-Z0-9'._-]*(?:\s*,\s*[A-Z][A-Z0-9'._-]*)*))?"
+    )
+    .unwrap();
+    static ref ALIAS_RE: Regex = Regex::new(r#"^\s+alias:\s*"([^"]+)""#).unwrap();
+}
+
+/// An account opened in the ledger and not closed since, with the currencies its
+/// `open` directive restricts it to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenAccount {
+    pub account: String,
+    pub currencies: Vec<String>,
+    /// The alias its `open` directive names in an `alias` metadata line.
+    pub alias: Option<String>,
+}
+
+/// Applies the `open` and `close` directives of `contents`, in order, and returns
+/// the accounts still open sorted by name.
+pub fn open_accounts<'a>(contents: impl IntoIterator<Item = &'a str>) -> Vec<OpenAccount> {
+    let mut accounts: BTreeMap<String, OpenAccount> = BTreeMap::new();
+    let mut opened: Option<String> = None;
+    for line in contents.into_iter().flat_map(str::lines) {
+        if let Some(captures) = OPEN_CLOSE_RE.captures(line) {
+            let account = captures[2].to_string();
+            opened = None;
+            if &captures[1] == "open" {
+                let currencies = captures
+                    .get(3)
+                    .map(|c| c.as_str().split(',').map(|c| c.trim().into()).collect())
+                    .unwrap_or_default();
+                accounts.insert(
+                    account.clone(),
+                    OpenAccount {
+                        account: account.clone(),
+                        currencies,
+                        alias: None,
+                    },
+                );
+                opened = Some(account);
+            } else {
+                accounts.remove(&account);
+            }
+        } else if let Some(captures) = ALIAS_RE.captures(line) {
+            if let Some(open) = opened.as_ref().and_then(|name| accounts.get_mut(name)) {
+                open.alias = Some(captures[1].to_string());
+            }
+        } else if !line.starts_with(char::is_whitespace) {
+            opened = None;
+        }
+    }
+    accounts.into_values().collect()
+}
+
+/// An `open` directive for `account` on `date`, with the alias discovery should
+/// give it, see [`open_accounts`].
+pub fn open_directive(date: NaiveDate, account: &str, alias: Option<&str>) -> String {
+    match alias {
+        Some(alias) => format!("{} open {}\n  alias: \"{}\"", date, account, alias),
+        None => format!("{} open {}", date, account),
+    }
+}
+
+/// Suggests an alias for every account from its last segment in lowercase, e.g.
+/// `food` for `Expenses:Food`. Accounts whose last segment is shared fall back to
+/// the full name in lowercase, e.g. `assets:cash` and `expenses:cash`.
+pub fn suggest_aliases(accounts: &[OpenAccount]) -> HashMap<String, AccountSettings> {
+    let mut by_segment: HashMap<String, Vec<&OpenAccount>> = HashMap::new();
+    for open in accounts {
+        let segment = open.account.rsplit(':').next().unwrap_or_default();
+        by_segment
+            .entry(segment.to_lowercase())
+            .or_default()
+            .push(open);
+    }
+
+    let mut aliases = HashMap::new();
+    for (segment, opens) in by_segment {
+        let opens: Vec<&OpenAccount> = opens
+            .into_iter()
+            .filter(|open| open.alias.is_none())
+            .collect();
+        let unique = opens.len() == 1;
+        for open in opens {
+            let alias = if unique {
+                segment.clone()
+            } else {
+                open.account.to_lowercase()
+            };
+            aliases.insert(alias, account_settings(open));
+        }
+    }
+    // Named aliases win over suggested ones.
+    for open in accounts {
+        if let Some(alias) = &open.alias {
+            aliases.insert(alias.clone(), account_settings(open));
+        }
+    }
+    aliases
+}
+
+fn account_settings(open: &OpenAccount) -> AccountSettings {
+    let root = open.account.split(':').next().unwrap_or_default();
+    let account_type = match root {
+        "Assets" => "asset",
+        "Liabilities" => "liability",
+        "Equity" => "equity",
+        "Income" => "income",
+        _ => "expense",
+    };
+    AccountSettings {
+        account: open.account.clone(),
+        currency: match open.currencies.as_slice() {
+            [currency] => Some(currency.clone()),
+            _ => None,
+        },
+        account_type: Some(account_type.into()),
+        emoji: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tracks_open_and_close_directives() {
+        let accounts = open_accounts(vec![
+            "option \"title\" \"Ledger\"\n2020-01-01 open Assets:Bank:ING AUD\n2020-01-01 open Expenses:Food\n2020-01-01 open Assets:Cash AUD, USD ; wallet\n",
+            "2021-06-30 close Assets:Cash\n",
+        ]);
+        assert_eq!(
+            accounts,
+            vec![
+                OpenAccount {
+                    account: "Assets:Bank:ING".into(),
+                    currencies: vec!["AUD".into()],
+                    alias: None,
+                },
+                OpenAccount {
+                    account: "Expenses:Food".into(),
+                    currencies: vec![],
+                    alias: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_suggests_aliases_from_last_segment() {
+        let accounts = open_accounts(vec![
+            "2020-01-01 open Assets:Bank:ING AUD\n2020-01-01 open Assets:Cash\n2020-01-01 open Expenses:Cash\n",
+        ]);
+        let aliases = suggest_aliases(&accounts);
+        let ing = &aliases["ing"];
+        assert_eq!(ing.account, "Assets:Bank:ING");
+        assert_eq!(ing.currency.as_deref(), Some("AUD"));
+        assert_eq!(ing.account_type.as_deref(), Some("asset"));
+        assert_eq!(aliases["assets:cash"].account, "Assets:Cash");
+        assert_eq!(aliases["expenses:cash"].account, "Expenses:Cash");
+        assert!(!aliases.contains_key("cash"));
+    }
+
+    #[test]
+    fn it_prefers_aliases_named_in_open_directives() {
+        let date = NaiveDate::from_ymd_opt(2021, 9, 8).unwrap();
+        let ledger = format!(
+            "2020-01-01 open Assets:Bank:ING AUD\n{}\n{}\n",
+            open_directive(date, "Assets:Bank:NewCard", Some("new")),
+            open_directive(date, "Assets:Cash", None)
+        );
+        assert_eq!(
+            ledger,
+            "2020-01-01 open Assets:Bank:ING AUD\n2021-09-08 open Assets:Bank:NewCard\n  alias: \"new\"\n2021-09-08 open Assets:Cash\n"
+        );
+        let accounts = open_accounts(vec![ledger.as_str()]);
+        assert_eq!(accounts[1].alias.as_deref(), Some("new"));
+        let aliases = suggest_aliases(&accounts);
+        assert_eq!(aliases["new"].account, "Assets:Bank:NewCard");
+        assert!(!aliases.contains_key("newcard"));
+        assert_eq!(aliases["cash"].account, "Assets:Cash");
+    }
+}
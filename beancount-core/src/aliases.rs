@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use config::{Config, File, FileFormat};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref BARE_KEY: Regex = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+}
+
+/// Reads a file of aliases added from chat, one `alias = "Account"` per line,
+/// as [`render_aliases`] writes it.
+pub fn parse_aliases(content: &str) -> Result<BTreeMap<String, String>> {
+    let mut document = Config::default();
+    document.merge(File::from_str(content, FileFormat::Toml))?;
+    Ok(document.try_into()?)
+}
+
+/// `aliases` as TOML sorted by alias, keys quoted when they need to be.
+pub fn render_aliases(aliases: &BTreeMap<String, String>) -> String {
+    aliases
+        .iter()
+        .map(|(alias, account)| {
+            let key = if BARE_KEY.is_match(alias) {
+                alias.clone()
+            } else {
+                format!("{:?}", alias)
+            };
+            format!("{} = {:?}\n", key, account)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_back_what_it_writes() {
+        let aliases: BTreeMap<String, String> = [
+            ("groceries", "Expenses:Food:Groceries"),
+            ("cba", "Assets:CBA"),
+            ("uber eats", "Expenses:Food:Takeaway"),
+        ]
+        .iter()
+        .map(|(alias, account)| (alias.to_string(), account.to_string()))
+        .collect();
+        let content = render_aliases(&aliases);
+        assert_eq!(
+            content,
+            "cba = \"Assets:CBA\"\ngroceries = \"Expenses:Food:Groceries\"\n\"uber eats\" = \"Expenses:Food:Takeaway\"\n"
+        );
+        assert_eq!(parse_aliases(&content).unwrap(), aliases);
+        assert!(parse_aliases("").unwrap().is_empty());
+        assert!(parse_aliases("cba = ").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref DATED_RE: Regex = Regex::new(r"^(\d{4}-\d{2}-\d{2})\s").unwrap();
+    static ref POSTING_RE: Regex = Regex::new(
+        r"^\s+([!*]\s+)?([A-Z][A-Za-z0-9-]*(?::[A-Z0-9][A-Za-z0-9-]*)+)(?:\s+(-?[0-9.]+)\s+([A-Z][A-Z0-9'._-]*))?(.*)$"
+    )
+    .unwrap();
+}
+
+/// A dated block of ledger text: the directive line plus its indented lines and
+/// any comments directly above it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub date: String,
+    pub text: String,
+}
+
+/// Splits ledger content into undated header lines (options, plugins, includes)
+/// and dated entries.
+pub fn split_entries(content: &str) -> (Vec<String>, Vec<Entry>) {
+    let mut header = Vec::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut pending: Vec<&str> = Vec::new();
+
+    for line in content.lines() {
+        if let Some(captures) = DATED_RE.captures(line) {
+            pending.push(line);
+            entries.push(Entry {
+                date: captures[1].to_string(),
+                text: pending.join("\n"),
+            });
+            pending.clear();
+        } else if line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            match entries.last_mut() {
+                Some(entry) if pending.is_empty() => {
+                    entry.text.push('\n');
+                    entry.text.push_str(line);
+                }
+                _ => pending.push(line),
+            }
+        } else if line.starts_with(';') {
+            pending.push(line);
+        } else if !line.trim().is_empty() {
+            header.extend(pending.drain(..).map(String::from));
+            header.push(line.to_string());
+        }
+    }
+    header.extend(pending.into_iter().map(String::from));
+
+    (header, entries)
+}
+
+/// Re-indents postings so account names and amounts line up in columns.
+pub fn format_entries(entries: &[Entry]) -> Vec<Entry> {
+    let postings = entries
+        .iter()
+        .flat_map(|e| e.text.lines())
+        .filter_map(|line| POSTING_RE.captures(line));
+    let (mut account_width, mut amount_width) = (0, 0);
+    for captures in postings {
+        account_width = account_width.max(captures[2].len());
+        if let Some(amount) = captures.get(3) {
+            amount_width = amount_width.max(amount.as_str().len());
+        }
+    }
+
+    entries
+        .iter()
+        .map(|entry| Entry {
+            date: entry.date.clone(),
+            text: entry
+                .text
+                .lines()
+                .map(|line| match POSTING_RE.captures(line) {
+                    Some(captures) => format_posting(&captures, account_width, amount_width),
+                    None => line.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        })
+        .collect()
+}
+
+fn format_posting(captures: &regex::Captures, account_width: usize, amount_width: usize) -> String {
+    let flag = captures.get(1).map_or("", |m| m.as_str().trim());
+    let flag = if flag.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", flag)
+    };
+    let account = &captures[2];
+    let rest = captures.get(5).map_or("", |m| m.as_str().trim_end());
+    match (captures.get(3), captures.get(4)) {
+        (Some(amount), Some(currency)) => format!(
+            "  {}{:<aw$}  {:>nw$} {}{}",
+            flag,
+            account,
+            amount.as_str(),
+            currency.as_str(),
+            rest,
+            aw = account_width,
+            nw = amount_width
+        ),
+        _ => format!("  {}{}{}", flag, account, rest),
+    }
+}
+
+/// Sums postings per (account, currency). A single posting without an amount is
+/// assigned the residual of the others, as beancount does.
+pub fn balances<'a>(
+    contents: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<(String, String), f64> {
+    let mut totals = BTreeMap::new();
+    for content in contents {
+        let (_, entries) = split_entries(content);
+        for entry in entries {
+            let mut residual: BTreeMap<String, f64> = BTreeMap::new();
+            let mut elided = None;
+            for captures in entry.text.lines().filter_map(|l| POSTING_RE.captures(l)) {
+                let account = captures[2].to_string();
+                match (captures.get(3), captures.get(4)) {
+                    (Some(amount), Some(currency)) => {
+                        let amount = amount.as_str().parse::<f64>().unwrap_or(0.0);
+                        *residual.entry(currency.as_str().to_string()).or_default() += amount;
+                        *totals
+                            .entry((account, currency.as_str().to_string()))
+                            .or_default() += amount;
+                    }
+                    _ => elided = Some(account),
+                }
+            }
+            if let Some(account) = elided {
+                for (currency, amount) in residual {
+                    *totals.entry((account.clone(), currency)).or_default() -= amount;
+                }
+            }
+        }
+    }
+    totals
+}
+
+/// Sorts and formats a finished year's ledger and appends `balance` assertions for
+/// every asset and liability account as of January 1st of the following year.
+///
+/// `history` holds the content of earlier ledger files so closing balances are
+/// cumulative rather than just this year's movements.
+pub fn close_year(content: &str, history: &[String], year: i32) -> String {
+    let closing_date = format!("{}-01-01", year + 1);
+    let totals = balances(history.iter().map(String::as_str).chain(Some(content)));
+
+    let mut output = sort_year(content);
+    let closing: Vec<String> = totals
+        .iter()
+        .filter(|((account, _), _)| {
+            account.starts_with("Assets:") || account.starts_with("Liabilities:")
+        })
+        .map(|((account, currency), amount)| {
+            format!(
+                "{} balance {} {:.2} {}",
+                closing_date, account, amount, currency
+            )
+        })
+        .collect();
+    if !closing.is_empty() {
+        output.push_str(&format!("\n; closing balances for {}\n", year));
+        output.push_str(&closing.join("\n"));
+        output.push('\n');
+    }
+    output
+}
+
+/// Sorts and formats a finished year's ledger without closing balances, for
+/// the files of a year split by month or account but the last.
+pub fn sort_year(content: &str) -> String {
+    let (header, mut entries) = split_entries(content);
+    entries.sort_by(|a, b| a.date.cmp(&b.date));
+    let entries = format_entries(&entries);
+
+    let mut output = String::new();
+    for line in header.iter() {
+        output.push_str(line);
+        output.push('\n');
+    }
+    for entry in entries.iter() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&entry.text);
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDGER: &str = "option \"title\" \"Home\"\n\n2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:MasterCard:CBA        -12.40 AUD\n  Expense:Food        12.40 AUD\n\n; weekly shop\n2021-03-01 * \"Coles\" \"\"\n  Assets:MasterCard:CBA        -100.00 AUD\n  Expense:Food\n";
+
+    #[test]
+    fn it_splits_header_and_entries_with_leading_comments() {
+        let (header, entries) = split_entries(LEDGER);
+        assert_eq!(header, vec!["option \"title\" \"Home\""]);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].date, "2021-03-01");
+        assert!(entries[1].text.starts_with("; weekly shop\n2021-03-01"));
+    }
+
+    #[test]
+    fn it_computes_balances_with_elided_amount() {
+        let totals = balances(vec![LEDGER]);
+        let cba = ("Assets:MasterCard:CBA".to_string(), "AUD".to_string());
+        let food = ("Expense:Food".to_string(), "AUD".to_string());
+        assert!((totals[&cba] + 112.40).abs() < 1e-9);
+        assert!((totals[&food] - 112.40).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_closes_year_sorted_aligned_and_with_balances() {
+        let history = vec!["2020-12-01 * \"Salary\" \"\"\n  Assets:MasterCard:CBA  1000 AUD\n  Income:Salary  -1000 AUD\n".to_string()];
+        let closed = close_year(LEDGER, &history, 2021);
+        assert_eq!(
+            closed,
+            "option \"title\" \"Home\"\n\n; weekly shop\n2021-03-01 * \"Coles\" \"\"\n  Assets:MasterCard:CBA  -100.00 AUD\n  Expense:Food\n\n2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:MasterCard:CBA   -12.40 AUD\n  Expense:Food             12.40 AUD\n\n; closing balances for 2021\n2022-01-01 balance Assets:MasterCard:CBA 887.60 AUD\n"
+        );
+    }
+}
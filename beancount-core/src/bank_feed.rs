@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::merchant::{MerchantMatch, MerchantRules};
+use crate::parser::{BeancountParser, Transaction};
+use crate::settings::{BankFeedSettings, Settings};
+
+/// Metadata key holding the bank's id of a booked transaction, so a webhook
+/// delivered twice isn't saved twice.
+pub const BANK_ID_KEY: &str = "bank_id";
+
+/// A transaction as a bank pushes it, whatever its API looks like.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BankTransaction {
+    pub id: String,
+    pub account_id: String,
+    /// `YYYY-MM-DD`.
+    pub date: String,
+    /// The bank's cleaned up merchant name, e.g. `Woolworths`.
+    pub description: String,
+    /// What the merchant sent, e.g. `WOOLWORTHS 1234 SYDNEY`, matched against
+    /// merchant rules before the description.
+    #[serde(default)]
+    pub raw_text: Option<String>,
+    /// Money out is negative.
+    pub amount: f64,
+    pub currency: String,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl BankTransaction {
+    /// The marker [`book`] adds to the entry, for finding it in a ledger file.
+    pub fn marker(&self) -> String {
+        format!("{}: \"{}\"", BANK_ID_KEY, self.id)
+    }
+}
+
+/// Books `bank` from the account mapped to its account id to the one of the
+/// first merchant rule matching its raw text or description, then of its
+/// category, then `default_account`; money in goes the other way round.
+pub fn book(
+    settings: &Settings,
+    feed: &BankFeedSettings,
+    bank: &BankTransaction,
+) -> Result<Transaction> {
+    let alias = feed.accounts.get(&bank.account_id).ok_or_else(|| {
+        anyhow!(
+            "bank account {} isn't in [bank_feed.accounts]",
+            bank.account_id
+        )
+    })?;
+    let rules = MerchantRules::new(settings)?;
+    let matched = bank
+        .raw_text
+        .as_deref()
+        .and_then(|raw| rules.apply(raw))
+        .or_else(|| rules.apply(&bank.description));
+    let category = || {
+        bank.category
+            .as_ref()
+            .and_then(|category| feed.categories.get(category))
+            .or(feed.default_account.as_ref())
+            .cloned()
+    };
+    let (payee, narration, other) = match matched {
+        Some(MerchantMatch {
+            payee,
+            account,
+            narration,
+        }) => (payee, narration, account.or_else(category)),
+        None => (bank.description.clone(), None, category()),
+    };
+    let other = other.ok_or_else(|| {
+        anyhow!(
+            "no merchant rule, category or default_account books {}",
+            bank.description
+        )
+    })?;
+    let (from, to) = if bank.amount < 0.0 {
+        (alias.as_str(), other.as_str())
+    } else {
+        (other.as_str(), alias.as_str())
+    };
+    let mut transaction = BeancountParser::new(settings.clone()).from_fields(
+        &bank.date,
+        &payee,
+        narration.as_deref().unwrap_or_default(),
+        bank.amount.abs() as f32,
+        Some(from),
+        to,
+    )?;
+    transaction.set_currency(&bank.currency);
+    transaction.add_metadata(BANK_ID_KEY, &bank.id);
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MerchantRule;
+    use std::collections::HashMap;
+
+    #[test]
+    fn it_books_with_merchant_rules_then_categories() {
+        let settings = Settings::builder("AUD")
+            .account("up", "Assets:Up")
+            .account("food", "Expenses:Food")
+            .account("transport", "Expenses:Transport")
+            .account("salary", "Income:Salary")
+            .merchant_rule(MerchantRule {
+                pattern: "^WOOLWORTHS".into(),
+                payee: "Woolworths".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+            .build()
+            .unwrap();
+        let feed = BankFeedSettings {
+            accounts: HashMap::from([("acc-1".to_string(), "up".to_string())]),
+            categories: HashMap::from([("public-transport".to_string(), "transport".to_string())]),
+            default_account: Some("salary".into()),
+            ..BankFeedSettings::default()
+        };
+        let bank = BankTransaction {
+            id: "tx-1".into(),
+            account_id: "acc-1".into(),
+            date: "2021-09-08".into(),
+            description: "Woolies".into(),
+            raw_text: Some("WOOLWORTHS 1234 SYDNEY".into()),
+            amount: -12.4,
+            currency: "AUD".into(),
+            category: Some("groceries".into()),
+        };
+        assert_eq!(
+            String::from(book(&settings, &feed, &bank).unwrap()),
+            "2021-09-08 * \"Woolworths\" \"groceries\"\n  bank_id: \"tx-1\"\n  Assets:Up        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+        );
+        assert_eq!(bank.marker(), "bank_id: \"tx-1\"");
+
+        let train = BankTransaction {
+            description: "Opal".into(),
+            raw_text: None,
+            category: Some("public-transport".into()),
+            ..bank.clone()
+        };
+        let transaction = book(&settings, &feed, &train).unwrap();
+        assert_eq!(transaction.to_account(), "Expenses:Transport");
+
+        let pay = BankTransaction {
+            description: "ACME".into(),
+            raw_text: None,
+            amount: 3000.0,
+            category: None,
+            ..bank.clone()
+        };
+        let transaction = book(&settings, &feed, &pay).unwrap();
+        assert_eq!(
+            (transaction.from_account(), transaction.to_account()),
+            ("Income:Salary", "Assets:Up")
+        );
+
+        let elsewhere = BankTransaction {
+            account_id: "acc-2".into(),
+            ..bank
+        };
+        assert!(book(&settings, &feed, &elsewhere).is_err());
+    }
+}
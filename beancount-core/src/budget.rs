@@ -0,0 +1,290 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::ledger::{Directive, Ledger};
+use crate::report::Period;
+use crate::settings::{covers, Settings};
+
+/// Share of a limit from which a budget counts as nearly spent.
+pub const NEAR_LIMIT: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetState {
+    Under,
+    /// [`NEAR_LIMIT`] of the limit or more is spent.
+    Near,
+    Over,
+}
+
+/// Month-to-date spend under one `[budgets]` prefix.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BudgetStatus {
+    pub prefix: String,
+    pub currency: String,
+    pub limit: f64,
+    pub spent: f64,
+    /// Negative once over the limit.
+    pub remaining: f64,
+    pub state: BudgetState,
+}
+
+impl BudgetStatus {
+    /// Spent share of the limit, `1.0` when all of it is.
+    pub fn used(&self) -> f64 {
+        self.spent / self.limit
+    }
+}
+
+/// The status of every budget from the start of the month `date` is in up to
+/// `date`, sorted by prefix.
+///
+/// A budget adds up the postings to accounts under its prefix, those of nested
+/// budgets included, in its currency; postings in other currencies are left out.
+pub fn budget_status(ledger: &Ledger, settings: &Settings, date: NaiveDate) -> Vec<BudgetStatus> {
+    let month = Period::month_of(date);
+    let mut statuses: Vec<BudgetStatus> = settings
+        .budgets
+        .iter()
+        .map(|(key, budget)| BudgetStatus {
+            prefix: settings.budget_prefix(key).to_string(),
+            currency: budget
+                .currency
+                .clone()
+                .unwrap_or_else(|| settings.currency.clone()),
+            limit: budget.limit,
+            spent: 0.0,
+            remaining: budget.limit,
+            state: BudgetState::Under,
+        })
+        .collect();
+    statuses.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+
+    let entries = ledger
+        .transactions()
+        .filter(|entry| month.from <= entry.date && entry.date <= date);
+    for entry in entries {
+        let postings = match &entry.directive {
+            Directive::Transaction { postings, .. } => postings,
+            _ => continue,
+        };
+        for posting in postings {
+            let amount = match &posting.amount {
+                Some(v) => v,
+                None => continue,
+            };
+            for status in statuses.iter_mut().filter(|status| {
+                status.currency == amount.currency && covers(&status.prefix, &posting.account)
+            }) {
+                status.spent += amount.number;
+            }
+        }
+    }
+
+    for status in statuses.iter_mut() {
+        status.remaining = status.limit - status.spent;
+        status.state = state_of(status.spent, status.limit);
+    }
+    statuses
+}
+
+fn state_of(spent: f64, limit: f64) -> BudgetState {
+    if spent > limit {
+        BudgetState::Over
+    } else if spent / limit >= NEAR_LIMIT {
+        BudgetState::Near
+    } else {
+        BudgetState::Under
+    }
+}
+
+/// Warnings for the budgets covering `account` that a posting of `amount` in
+/// `currency` took to [`NEAR_LIMIT`] or over their limit, from `statuses`
+/// that count it already. Budgets that were there before it stay quiet.
+pub fn crossed_budgets(
+    statuses: &[BudgetStatus],
+    account: &str,
+    amount: f64,
+    currency: &str,
+) -> Vec<String> {
+    statuses
+        .iter()
+        .filter(|status| status.currency == currency && covers(&status.prefix, account))
+        .filter(|status| state_of(status.spent - amount, status.limit) != status.state)
+        .filter_map(|status| match status.state {
+            BudgetState::Under => None,
+            BudgetState::Near => Some(format!(
+                "⚠️ {:.0}% of the {} budget spent: {:.2} / {:.2} {}",
+                status.used() * 100.0,
+                status.prefix,
+                status.spent,
+                status.limit,
+                status.currency
+            )),
+            BudgetState::Over => Some(format!(
+                "❗ Over the {} budget: {:.2} / {:.2} {}",
+                status.prefix, status.spent, status.limit, status.currency
+            )),
+        })
+        .collect()
+}
+
+/// One line per budget: spent, limit and what's left.
+pub fn budgets_to_text(statuses: &[BudgetStatus]) -> String {
+    if statuses.is_empty() {
+        return "No budgets configured\n".into();
+    }
+    let lines: Vec<String> = statuses
+        .iter()
+        .map(|status| {
+            let mark = match status.state {
+                BudgetState::Under => "",
+                BudgetState::Near => " ⚠️",
+                BudgetState::Over => " ❗",
+            };
+            format!(
+                "{:<30} {:>10.2} / {:.2} {} ({:.0}%){}",
+                status.prefix,
+                status.spent,
+                status.limit,
+                status.currency,
+                status.used() * 100.0,
+                mark
+            )
+        })
+        .collect();
+    format!("{}\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::BudgetSettings;
+
+    const LEDGER: &str = r#"
+2021-08-31 * "Coles" "groceries"
+  Assets:CBA                -40.00 AUD
+  Expenses:Food:Groceries
+
+2021-09-01 * "Coles" "groceries"
+  Assets:CBA               -350.00 AUD
+  Expenses:Food:Groceries
+
+2021-09-03 * "Cafe" "coffee"
+  Assets:Wise:USD            -5.00 USD
+  Expenses:Food:Cafe          5.00 USD
+
+2021-09-05 * "Starbucks" "coffee"
+  Assets:CBA                -60.00 AUD
+  Expenses:Food:Cafe
+
+2021-09-20 * "Shell" "fuel"
+  Assets:CBA                -80.00 AUD
+  Expenses:Car
+"#;
+
+    #[test]
+    fn it_warns_when_a_posting_crosses_a_threshold() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .account("cafe", "Expenses:Food:Cafe")
+            .budget(
+                "food",
+                BudgetSettings {
+                    limit: 500.0,
+                    currency: None,
+                },
+            )
+            .budget(
+                "cafe",
+                BudgetSettings {
+                    limit: 50.0,
+                    currency: None,
+                },
+            )
+            .build()
+            .unwrap();
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let statuses = budget_status(
+            &ledger,
+            &settings,
+            NaiveDate::from_ymd_opt(2021, 9, 10).unwrap(),
+        );
+        assert_eq!(statuses[0].prefix, "Expenses:Food");
+
+        // The Starbucks coffee took food to 82% and cafe over its limit.
+        assert_eq!(
+            crossed_budgets(&statuses, "Expenses:Food:Cafe", 60.0, "AUD"),
+            vec![
+                "⚠️ 82% of the Expenses:Food budget spent: 410.00 / 500.00 AUD",
+                "❗ Over the Expenses:Food:Cafe budget: 60.00 / 50.00 AUD",
+            ]
+        );
+        // Both were there before a 5.00 coffee.
+        assert_eq!(
+            crossed_budgets(&statuses, "Expenses:Food:Cafe", 5.0, "AUD"),
+            Vec::<String>::new()
+        );
+        assert!(crossed_budgets(&statuses, "Expenses:Food", 60.0, "USD").is_empty());
+    }
+
+    #[test]
+    fn it_adds_up_month_to_date_spend_per_budget() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .account("cafe", "Expenses:Food:Cafe")
+            .account("car", "Expenses:Car")
+            .budget(
+                "Expenses:Food",
+                BudgetSettings {
+                    limit: 500.0,
+                    currency: None,
+                },
+            )
+            .budget(
+                "Expenses:Food:Cafe",
+                BudgetSettings {
+                    limit: 50.0,
+                    currency: None,
+                },
+            )
+            .budget(
+                "Expenses:Car",
+                BudgetSettings {
+                    limit: 200.0,
+                    currency: None,
+                },
+            )
+            .build()
+            .unwrap();
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let statuses = budget_status(
+            &ledger,
+            &settings,
+            NaiveDate::from_ymd_opt(2021, 9, 10).unwrap(),
+        );
+
+        let summary: Vec<(&str, f64, BudgetState)> = statuses
+            .iter()
+            .map(|s| (s.prefix.as_str(), s.spent, s.state))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Expenses:Car", 0.0, BudgetState::Under),
+                ("Expenses:Food", 410.0, BudgetState::Near),
+                ("Expenses:Food:Cafe", 60.0, BudgetState::Over),
+            ]
+        );
+        assert_eq!(statuses[2].remaining, -10.0);
+        assert_eq!(
+            budgets_to_text(&statuses).lines().nth(2).unwrap(),
+            format!(
+                "{:<30} {:>10.2} / 50.00 AUD (120%) ❗",
+                "Expenses:Food:Cafe", 60.0
+            )
+        );
+    }
+}
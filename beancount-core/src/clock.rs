@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+
+/// Where the current time comes from. `Instant::now` and `SystemTime::now`
+/// panic on wasm32-unknown-unknown, so code shared with Workers or the browser
+/// asks a clock the host chooses instead.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system time. On wasm it needs chrono's `wasmbind` feature, which reads
+/// the JavaScript `Date`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same time, for tests and hosts that pass the time in.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
@@ -0,0 +1,274 @@
+use chrono::NaiveDate;
+
+use crate::ledger::{Directive, Entry, Ledger};
+use crate::parser::MESSAGE_KEY;
+
+/// Days apart two transactions can be and still count as the same one.
+pub const DEFAULT_WINDOW_DAYS: i64 = 3;
+
+/// Transactions with the same payee and amount dated close together, likely
+/// one purchase saved more than once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub payee: String,
+    pub amount: f64,
+    pub currency: String,
+    /// In date order, the first one is probably the original.
+    pub entries: Vec<Entry>,
+}
+
+impl DuplicateGroup {
+    pub fn first_date(&self) -> NaiveDate {
+        self.entries[0].date
+    }
+}
+
+/// The payee, or the narration without one.
+fn payee_of(entry: &Entry) -> Option<&str> {
+    match &entry.directive {
+        Directive::Transaction {
+            payee, narration, ..
+        } => Some(
+            payee
+                .as_deref()
+                .filter(|payee| !payee.is_empty())
+                .unwrap_or(narration),
+        ),
+        _ => None,
+    }
+}
+
+/// Groups the transactions of `ledger` that have the same payee and amount and
+/// are at most `window_days` after the first of their group.
+pub fn find_duplicates(ledger: &Ledger, window_days: i64) -> Vec<DuplicateGroup> {
+    let transactions: Vec<(&Entry, String, f64, String)> = ledger
+        .transactions()
+        .filter_map(|entry| {
+            let payee = payee_of(entry)?.to_lowercase();
+            let moved = entry.directive.moved()?;
+            Some((entry, payee, moved.number, moved.currency))
+        })
+        .filter(|(_, payee, amount, _)| !payee.is_empty() && *amount != 0.0)
+        .collect();
+
+    let mut grouped = vec![false; transactions.len()];
+    let mut groups = Vec::new();
+    for (i, (first, payee, amount, currency)) in transactions.iter().enumerate() {
+        if grouped[i] {
+            continue;
+        }
+        let mut entries = vec![(*first).clone()];
+        for (j, (other, other_payee, other_amount, other_currency)) in
+            transactions.iter().enumerate().skip(i + 1)
+        {
+            if (other.date - first.date).num_days() > window_days {
+                break;
+            }
+            if !grouped[j]
+                && other_payee == payee
+                && other_currency == currency
+                && (other_amount - amount).abs() < 0.005
+            {
+                grouped[j] = true;
+                entries.push((*other).clone());
+            }
+        }
+        if entries.len() > 1 {
+            groups.push(DuplicateGroup {
+                payee: payee_of(first).unwrap_or_default().to_string(),
+                amount: *amount,
+                currency: currency.clone(),
+                entries,
+            });
+        }
+    }
+    groups
+}
+
+/// What makes two transactions the same: date, payee and the amount of each
+/// account, in cents.
+type Fingerprint = (NaiveDate, String, Vec<(String, i64, String)>);
+
+fn fingerprint(entry: &Entry) -> Option<Fingerprint> {
+    let postings = match &entry.directive {
+        Directive::Transaction { postings, .. } => postings,
+        _ => return None,
+    };
+    let mut amounts = postings
+        .iter()
+        .map(|posting| {
+            let amount = posting.amount.as_ref()?;
+            Some((
+                posting.account.clone(),
+                (amount.number * 100.0).round() as i64,
+                amount.currency.clone(),
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    amounts.sort();
+    Some((entry.date, payee_of(entry)?.to_lowercase(), amounts))
+}
+
+fn message_of(entry: &Entry) -> Option<&str> {
+    match &entry.directive {
+        Directive::Transaction { metadata, .. } => metadata
+            .iter()
+            .find(|(key, _)| key == MESSAGE_KEY)
+            .map(|(_, value)| value.as_str()),
+        _ => None,
+    }
+}
+
+/// The entry in the last `lines` lines of `content`, the ledger file at
+/// `path`, with the same date, payee, amounts and accounts as the transaction
+/// `entry`, the latest one when there are several. The entry of the message
+/// `entry` was edited from doesn't count, saving replaces it.
+pub fn find_copy(path: &str, content: &str, entry: &str, lines: usize) -> Option<Entry> {
+    if lines == 0 {
+        return None;
+    }
+    let new = Ledger::parse(path, entry).entries.pop()?;
+    let wanted = fingerprint(&new)?;
+    let skipped = content.lines().count().saturating_sub(lines);
+    let tail: Vec<&str> = content.lines().skip(skipped).collect();
+    Ledger::parse(path, &tail.join("\n"))
+        .entries
+        .into_iter()
+        .filter(|entry| message_of(&new).is_none() || message_of(entry) != message_of(&new))
+        .filter(|entry| fingerprint(entry).as_ref() == Some(&wanted))
+        .max_by_key(|entry| entry.line)
+        .map(|entry| Entry {
+            line: entry.line + skipped,
+            ..entry
+        })
+}
+
+/// One line per group with where its entries are, e.g.
+/// `2021-09-08 KFC 12.50 AUD: 2021.bean:12, 2021.bean:20 (2021-09-09)`.
+pub fn duplicates_to_text(groups: &[DuplicateGroup]) -> String {
+    if groups.is_empty() {
+        return "No likely duplicates\n".into();
+    }
+    let lines: Vec<String> = groups
+        .iter()
+        .map(|group| {
+            let places: Vec<String> = group
+                .entries
+                .iter()
+                .map(|entry| {
+                    if entry.date == group.first_date() {
+                        format!("{}:{}", entry.file, entry.line)
+                    } else {
+                        format!("{}:{} ({})", entry.file, entry.line, entry.date)
+                    }
+                })
+                .collect();
+            format!(
+                "{} {} {:.2} {}: {}",
+                group.first_date(),
+                group.payee,
+                group.amount,
+                group.currency,
+                places.join(", ")
+            )
+        })
+        .collect();
+    format!("{}\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BeancountParser;
+    use crate::settings::Settings;
+
+    #[test]
+    fn it_finds_a_copy_near_the_end_of_the_file() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let parser = BeancountParser::new(settings);
+        let content = r#"2021-09-08 * "KFC" "lunch"
+  telegram_message: "247673932/7"
+  Assets:CBA    -12.40 AUD
+  Expenses:Food
+
+2021-09-08 * "Coles" "groceries"
+  Assets:CBA    -12.40 AUD
+  Expenses:Food  12.40 AUD
+"#;
+        let transaction = parser
+            .parse("2021-09-08 @kfc hamburger 12.40 AUD cba > food")
+            .unwrap();
+        let mut resent = transaction.clone();
+        resent.set_message(247673932, 8);
+        let resent = String::from(resent);
+        let copy = find_copy("2021.bean", content, &resent, 200).unwrap();
+        assert_eq!((copy.file.as_str(), copy.line), ("2021.bean", 1));
+        assert!(find_copy("2021.bean", content, &resent, 4).is_none());
+
+        // Editing the message replaces its own entry.
+        let mut edited = transaction;
+        edited.set_message(247673932, 7);
+        let edited = String::from(edited);
+        assert!(find_copy("2021.bean", content, &edited, 200).is_none());
+
+        let coles = String::from(
+            parser
+                .parse("2021-09-08 @Coles 12.40 AUD cba > food")
+                .unwrap(),
+        );
+        assert_eq!(find_copy("2021.bean", content, &coles, 3).unwrap().line, 6);
+        for other in [
+            "2021-09-09 @Coles 12.40 AUD cba > food",
+            "2021-09-08 @Coles 12.41 AUD cba > food",
+            "2021-09-08 @Aldi 12.40 AUD cba > food",
+        ] {
+            let other = String::from(parser.parse(other).unwrap());
+            assert!(find_copy("2021.bean", content, &other, 200).is_none());
+        }
+    }
+
+    #[test]
+    fn it_groups_same_payee_and_amount_within_the_window() {
+        let ledger = Ledger::parse(
+            "2021.bean",
+            r#"2021-09-08 * "KFC" "lunch"
+  Assets:CBA    -12.50 AUD
+  Expenses:Food
+
+2021-09-08 * "Coles" "groceries"
+  Assets:CBA    -12.50 AUD
+  Expenses:Food
+
+2021-09-09 * "kfc" "lunch again"
+  Assets:CBA    -12.50 AUD
+  Expenses:Food
+
+2021-09-10 * "KFC" "lunch"
+  Assets:CBA    -12.50 AUD
+  Expenses:Food
+
+2021-09-20 * "KFC" "lunch"
+  Assets:CBA    -12.50 AUD
+  Expenses:Food
+
+2021-09-21 * "KFC" "dinner"
+  Assets:CBA    -22.50 AUD
+  Expenses:Food
+"#,
+        );
+        let groups = find_duplicates(&ledger, DEFAULT_WINDOW_DAYS);
+        assert_eq!(groups.len(), 1);
+        let lines: Vec<usize> = groups[0].entries.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 9, 13]);
+        assert_eq!(
+            duplicates_to_text(&groups),
+            "2021-09-08 KFC 12.50 AUD: 2021.bean:1, 2021.bean:9 (2021-09-09), 2021.bean:13 (2021-09-10)\n"
+        );
+        assert_eq!(find_duplicates(&ledger, 0).len(), 0);
+        assert_eq!(find_duplicates(&ledger, 12)[0].entries.len(), 4);
+    }
+}
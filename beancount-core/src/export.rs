@@ -0,0 +1,125 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::ledger::{Directive, Ledger};
+use crate::query::Query;
+use crate::report::Period;
+
+/// A transaction as a spreadsheet row.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportRow {
+    pub date: String,
+    pub payee: String,
+    pub narration: String,
+    /// What moved, see [`Directive::moved`].
+    pub amount: String,
+    pub currency: String,
+    /// Where the money came from and went to, e.g. `Assets:CBA > Expenses:Food`.
+    pub accounts: String,
+    /// Space separated, without `#`.
+    pub tags: String,
+}
+
+/// The transactions dated in `period` that match `query`.
+pub fn export_rows(ledger: &Ledger, period: &Period, query: &Query) -> Vec<ExportRow> {
+    query
+        .filter(ledger)
+        .filter(|entry| period.from <= entry.date && entry.date <= period.to)
+        .filter_map(|entry| match &entry.directive {
+            Directive::Transaction {
+                payee,
+                narration,
+                tags,
+                postings,
+                ..
+            } => {
+                let moved = entry.directive.moved().unwrap_or_default();
+                let accounts = |outgoing: bool| {
+                    postings
+                        .iter()
+                        .filter(|posting| {
+                            posting
+                                .amount
+                                .as_ref()
+                                .is_some_and(|amount| (amount.number < 0.0) == outgoing)
+                        })
+                        .map(|posting| posting.account.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                Some(ExportRow {
+                    date: entry.date.to_string(),
+                    payee: payee.clone().unwrap_or_default(),
+                    narration: narration.clone(),
+                    amount: format!("{:.2}", moved.number),
+                    currency: moved.currency,
+                    accounts: format!("{} > {}", accounts(true), accounts(false)),
+                    tags: tags.join(" "),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The transactions dated in `period` that match `query` as CSV with a header
+/// row.
+pub fn export_csv(ledger: &Ledger, period: &Period, query: &Query) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let rows = export_rows(ledger, period, query);
+    if rows.is_empty() {
+        writer.write_record([
+            "date",
+            "payee",
+            "narration",
+            "amount",
+            "currency",
+            "accounts",
+            "tags",
+        ])?;
+    }
+    for row in rows.iter() {
+        writer.serialize(row)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_exports_transactions_of_a_period() {
+        let ledger = Ledger::parse(
+            "2021.bean",
+            r#"
+2021-08-31 * "Coles" "groceries"
+  Assets:CBA                -40.00 AUD
+  Expenses:Food
+
+2021-09-01 * "KFC" "lunch, with \"Sam\"" #trip #food
+  Assets:CBA                -12.50 AUD
+  Expenses:Food
+
+2021-09-08 * "Steam" ""
+  Liabilities:AMEX          -27.16 AUD
+  Expenses:Games             20.00 USD @@ 27.16 AUD
+"#,
+        );
+        let csv = export_csv(&ledger, &"2021-09".parse().unwrap(), &Query::default()).unwrap();
+        assert_eq!(
+            csv,
+            "date,payee,narration,amount,currency,accounts,tags\n\
+             2021-09-01,KFC,\"lunch, with \"\"Sam\"\"\",12.50,AUD,Assets:CBA > Expenses:Food,trip food\n\
+             2021-09-08,Steam,,20.00,USD,Liabilities:AMEX > Expenses:Games,\n"
+        );
+        let query = "account:Expenses:Games".parse().unwrap();
+        assert!(export_csv(&ledger, &"2021".parse().unwrap(), &query)
+            .unwrap()
+            .ends_with("\n2021-09-08,Steam,,20.00,USD,Liabilities:AMEX > Expenses:Games,\n"));
+        assert_eq!(
+            export_csv(&ledger, &"2021-10".parse().unwrap(), &Query::default()).unwrap(),
+            "date,payee,narration,amount,currency,accounts,tags\n"
+        );
+    }
+}
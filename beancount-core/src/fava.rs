@@ -0,0 +1,99 @@
+use chrono::NaiveDate;
+
+use crate::report::Period;
+
+/// Links into a [fava](https://beancount.github.io/fava/) instance serving the
+/// ledger, from the `fava_url` setting, e.g. `https://fava.example.com/ledger`.
+#[derive(Debug, Clone, Copy)]
+pub struct Fava<'a> {
+    base: &'a str,
+}
+
+impl<'a> Fava<'a> {
+    pub fn new(base: &'a str) -> Self {
+        Fava {
+            base: base.trim_end_matches('/'),
+        }
+    }
+
+    /// The journal of `date` filtered to `payee`, where a saved transaction is.
+    pub fn transaction(&self, date: &str, payee: &str) -> String {
+        let mut url = format!("{}/journal/?time={}", self.base, encode(date));
+        if !payee.is_empty() {
+            url.push_str(&format!("&filter={}", encode(&format!("\"{}\"", payee))));
+        }
+        url
+    }
+
+    /// The journal and balances of `account` over `period`.
+    pub fn account(&self, account: &str, period: &Period) -> String {
+        format!(
+            "{}/account/{}/?time={}",
+            self.base,
+            encode(account),
+            encode(&time(period))
+        )
+    }
+
+    /// The income statement of `period`.
+    pub fn income_statement(&self, period: &Period) -> String {
+        format!(
+            "{}/income_statement/?time={}",
+            self.base,
+            encode(&time(period))
+        )
+    }
+}
+
+/// Fava's time filter: `2021-09`, `2021`, or `2021-01-01 - 2021-06-30`.
+fn time(period: &Period) -> String {
+    let text = period.to_string();
+    match text.split_once("..") {
+        Some((from, to)) => format!("{} - {}", from, to),
+        None => text,
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and `:`, which fava's
+/// account URLs keep as is.
+fn encode(component: &str) -> String {
+    component
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The month of a `YYYY-MM-DD` date, for linking an account around a saved
+/// transaction.
+pub fn month_of(date: &str) -> Option<Period> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .map(Period::month_of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_links_journal_accounts_and_reports() {
+        let fava = Fava::new("https://fava.example.com/ledger/");
+        assert_eq!(
+            fava.transaction("2021-09-08", "Café & Co"),
+            "https://fava.example.com/ledger/journal/?time=2021-09-08&filter=%22Caf%C3%A9%20%26%20Co%22"
+        );
+        assert_eq!(
+            fava.account("Expenses:Food", &month_of("2021-09-08").unwrap()),
+            "https://fava.example.com/ledger/account/Expenses:Food/?time=2021-09"
+        );
+        assert_eq!(
+            fava.income_statement(&"2021-01..2021-06".parse().unwrap()),
+            "https://fava.example.com/ledger/income_statement/?time=2021-01-01%20-%202021-06-30"
+        );
+    }
+}
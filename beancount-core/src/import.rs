@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+use csv::StringRecord;
+
+use crate::ledger::{Directive, Ledger};
+use crate::merchant::{MerchantMatch, MerchantRules};
+use crate::parser::{BeancountParser, Transaction};
+use crate::settings::{ImportProfile, Settings, SignConvention};
+
+mod ofx;
+mod qif;
+
+/// A statement row turned into a transaction.
+#[derive(Debug, Clone)]
+pub struct ImportedRow {
+    /// Line of the row in the CSV, for pointing at it in messages.
+    pub line: usize,
+    pub transaction: Transaction,
+}
+
+/// The rows of a statement that can be booked, and why the others can't.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// The account the statement is of, e.g. `Assets:CBA`.
+    pub account: String,
+    pub rows: Vec<ImportedRow>,
+    pub skipped: Vec<String>,
+}
+
+impl Statement {
+    /// Years of the rows, for finding the ledger files they go to.
+    pub fn years(&self) -> Vec<String> {
+        let mut years: Vec<String> = self.rows.iter().map(|row| row.transaction.year()).collect();
+        years.sort();
+        years.dedup();
+        years
+    }
+
+    /// Moves rows already in `ledger` to the skipped ones. A row is already
+    /// there when a transaction of the same day posts the same amount to the
+    /// statement's account; each one accounts for a single row, so two equal
+    /// purchases on a day with one of them saved still import the other.
+    pub fn drop_known(&mut self, ledger: &Ledger) {
+        let mut known: HashMap<(NaiveDate, i64), usize> = HashMap::new();
+        for entry in ledger.transactions() {
+            let postings = match &entry.directive {
+                Directive::Transaction { postings, .. } => postings,
+                _ => continue,
+            };
+            for posting in postings.iter().filter(|p| p.account == self.account) {
+                if let Some(amount) = &posting.amount {
+                    *known.entry((entry.date, cents(amount.number))).or_default() += 1;
+                }
+            }
+        }
+
+        let account = self.account.clone();
+        let mut rows = Vec::new();
+        for row in self.rows.drain(..) {
+            let transaction = &row.transaction;
+            let amount = transaction.amount() as f64;
+            let posted = if transaction.from_account() == account {
+                -amount
+            } else {
+                amount
+            };
+            let date = NaiveDate::parse_from_str(transaction.date(), "%Y-%m-%d").ok();
+            match date.and_then(|date| known.get_mut(&(date, cents(posted)))) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    self.skipped.push(format!(
+                        "line {}: {} is already in the ledger",
+                        row.line,
+                        transaction.payee()
+                    ));
+                }
+                _ => rows.push(row),
+            }
+        }
+        self.rows = rows;
+    }
+}
+
+fn cents(number: f64) -> i64 {
+    (number * 100.0).round() as i64
+}
+
+/// File formats statements are read from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatementFormat {
+    Csv,
+    Ofx,
+    Qif,
+}
+
+impl StatementFormat {
+    /// The format of a file by its extension, `.qfx` being Quicken's OFX.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension.to_lowercase().as_str() {
+            "csv" => Some(StatementFormat::Csv),
+            "ofx" | "qfx" => Some(StatementFormat::Ofx),
+            "qif" => Some(StatementFormat::Qif),
+            _ => None,
+        }
+    }
+}
+
+/// A statement row before it's booked, money out being negative.
+#[derive(Debug, Clone, PartialEq)]
+struct RawRow {
+    line: usize,
+    /// `YYYY-MM-DD`.
+    date: String,
+    description: String,
+    amount: f32,
+}
+
+/// The rows of a statement in order, or why one can't be read.
+type RawRows = Vec<Result<RawRow, String>>;
+
+/// Reads a statement with `profile`, booking each row with the merchant rules:
+/// money out goes from the statement's account to the account of the matching
+/// rule, money in the other way round. Rows without an account to book to, a
+/// date or an amount are skipped with the reason.
+///
+/// Only CSV statements use the profile's columns, sign and delimiter; OFX and
+/// QIF ones say which amounts are money out themselves.
+pub fn read_statement(
+    settings: &Settings,
+    profile: &ImportProfile,
+    format: StatementFormat,
+    input: impl Read,
+) -> Result<Statement> {
+    let alias = profile
+        .account
+        .as_deref()
+        .or(settings.default_from_account.as_deref())
+        .ok_or_else(|| {
+            anyhow!("the import profile names no account and no default_from_account is configured")
+        })?;
+    let account = settings
+        .accounts
+        .get(alias)
+        .ok_or_else(|| anyhow!("account {} doesn't exist in current setting", alias))?
+        .account
+        .clone();
+    let parser = BeancountParser::new(settings.clone());
+    let rules = MerchantRules::for_profile(settings, profile)?;
+
+    let rows = match format {
+        StatementFormat::Csv => read_csv(profile, input)?,
+        StatementFormat::Ofx => ofx::read(&read_text(input)?),
+        StatementFormat::Qif => qif::read(profile, &read_text(input)?),
+    };
+
+    let mut statement = Statement {
+        account,
+        rows: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for row in rows {
+        let RawRow {
+            line,
+            date,
+            description,
+            amount,
+        } = match row {
+            Ok(v) => v,
+            Err(reason) => {
+                statement.skipped.push(reason);
+                continue;
+            }
+        };
+        let (payee, narration, other) = match rules.apply(&description) {
+            Some(MerchantMatch {
+                payee,
+                account,
+                narration,
+            }) => match account.or_else(|| profile.default_account.clone()) {
+                Some(account) => (payee, narration, account),
+                None => {
+                    statement.skipped.push(format!(
+                        "line {}: the rule for {} has no account",
+                        line, description
+                    ));
+                    continue;
+                }
+            },
+            None => match &profile.default_account {
+                Some(account) => (description.clone(), None, account.clone()),
+                None => {
+                    statement.skipped.push(format!(
+                        "line {}: no merchant rule matches {}",
+                        line, description
+                    ));
+                    continue;
+                }
+            },
+        };
+        let (from, to) = if amount < 0.0 {
+            (alias, other.as_str())
+        } else {
+            (other.as_str(), alias)
+        };
+        match parser.from_fields(
+            &date,
+            &payee,
+            narration.as_deref().unwrap_or_default(),
+            amount.abs(),
+            Some(from),
+            to,
+        ) {
+            Ok(transaction) => statement.rows.push(ImportedRow { line, transaction }),
+            Err(e) => statement.skipped.push(format!("line {}: {}", line, e)),
+        }
+    }
+    Ok(statement)
+}
+
+/// OFX and QIF files predate UTF-8 and are often Latin-1, whose accented
+/// characters are replaced rather than failing the whole statement.
+fn read_text(mut input: impl Read) -> Result<String> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_csv(profile: &ImportProfile, input: impl Read) -> Result<RawRows> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(profile.has_headers)
+        .delimiter(profile.delimiter as u8)
+        .flexible(true)
+        .from_reader(input);
+    let headers = match profile.has_headers {
+        true => Some(reader.headers()?.clone()),
+        false => None,
+    };
+    let column = |name: &str| column(headers.as_ref(), name);
+    let columns = &profile.columns;
+    let date_column = column(&columns.date)?;
+    let description_column = column(&columns.description)?;
+    let amount_columns = match columns.amount() {
+        Some(amount) => AmountColumns::Signed(column(amount)?),
+        None => AmountColumns::Split(
+            columns.debit.as_deref().map(column).transpose()?,
+            columns.credit.as_deref().map(column).transpose()?,
+        ),
+    };
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record
+            .position()
+            .map_or(0, |position| position.line() as usize);
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |index: usize| record.get(index).unwrap_or_default().trim();
+        let description = field(description_column);
+        let row = parse_date(field(date_column), profile.date_format.as_deref())
+            .and_then(|date| {
+                let amount = amount_columns
+                    .read(&field, profile.sign)?
+                    .ok_or_else(|| anyhow!("{} has no amount", description))?;
+                Ok(RawRow {
+                    line,
+                    date,
+                    description: description.to_string(),
+                    amount,
+                })
+            })
+            .map_err(|e| format!("line {}: {}", line, e));
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+enum AmountColumns {
+    Signed(usize),
+    Split(Option<usize>, Option<usize>),
+}
+
+impl AmountColumns {
+    /// The amount of a row, negative for money out, `None` when it has none.
+    fn read<'a>(
+        &self,
+        field: &impl Fn(usize) -> &'a str,
+        sign: SignConvention,
+    ) -> Result<Option<f32>> {
+        let amount = |index: Option<usize>| -> Result<Option<f32>> {
+            match index.map(field).filter(|text| !text.is_empty()) {
+                Some(text) => parse_amount(text).map(Some),
+                None => Ok(None),
+            }
+        };
+        let amount = match self {
+            AmountColumns::Signed(index) => match (amount(Some(*index))?, sign) {
+                (Some(v), SignConvention::DebitsNegative) => v,
+                (Some(v), SignConvention::DebitsPositive) => -v,
+                (None, _) => return Ok(None),
+            },
+            AmountColumns::Split(debit, credit) => match (amount(*debit)?, amount(*credit)?) {
+                (Some(v), _) if v != 0.0 => -v.abs(),
+                (_, Some(v)) => v.abs(),
+                _ => return Ok(None),
+            },
+        };
+        Ok(Some(amount).filter(|amount| *amount != 0.0))
+    }
+}
+
+/// The index of a column, by header name or, without headers, 1-based number.
+fn column(headers: Option<&StringRecord>, name: &str) -> Result<usize> {
+    match headers {
+        Some(headers) => headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| anyhow!("the statement has no {} column", name)),
+        None => name
+            .parse::<usize>()
+            .ok()
+            .filter(|number| *number > 0)
+            .map(|number| number - 1)
+            .ok_or_else(|| anyhow!("{} isn't a column number", name)),
+    }
+}
+
+/// A `YYYY-MM-DD` date from `format`, or ISO and Australian `DD/MM/YYYY` dates.
+fn parse_date(date: &str, format: Option<&str>) -> Result<String> {
+    let parsed = match format {
+        Some(format) => NaiveDate::parse_from_str(date, format),
+        None => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(date, "%d/%m/%Y")),
+    };
+    // %Y takes any number of digits, so a two digit year would be the first century
+    parsed
+        .ok()
+        .filter(|date| date.year() >= 1000)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .ok_or(())
+        .map_err(|_| match format {
+            Some(format) => anyhow!("{} isn't a {} date", date, format),
+            None => anyhow!("{} isn't a YYYY-MM-DD or DD/MM/YYYY date", date),
+        })
+}
+
+/// Amounts like `-1,234.50`, `$12.40` or `(12.40)`, the last two as banks write
+/// them.
+fn parse_amount(text: &str) -> Result<f32> {
+    let (text, negative) = match text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        Some(inner) => (inner, true),
+        None => (text, false),
+    };
+    let cleaned: String = text
+        .chars()
+        .filter(|c| !matches!(c, ',' | '$' | ' '))
+        .collect();
+    let amount: f32 = cleaned
+        .parse()
+        .map_err(|_| anyhow!("{} isn't an amount", text))?;
+    Ok(if negative { -amount } else { amount })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{ImportColumns, MerchantRule};
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("amex", "Liabilities:AMEX")
+            .account("food", "Expenses:Food")
+            .account("salary", "Income:Salary")
+            .account("other", "Expenses:Uncategorized")
+            .default_from_account("cba")
+            .merchant_rule(MerchantRule {
+                pattern: "^WOOLWORTHS".into(),
+                payee: "Woolworths".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn entries(statement: &Statement) -> Vec<String> {
+        statement
+            .rows
+            .iter()
+            .map(|row| String::from(row.transaction.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn it_books_debits_and_credits_with_merchant_rules() {
+        let profile = ImportProfile {
+            default_account: Some("other".into()),
+            merchant_rules: vec![MerchantRule {
+                pattern: "SALARY".into(),
+                payee: "ACME".into(),
+                account: Some("salary".into()),
+                narration: None,
+            }],
+            ..ImportProfile::default()
+        };
+        let csv = "Date,Description,Amount\n\
+                   08/09/2021,WOOLWORTHS 1234 SYDNEY,\"-1,023.50\"\n\
+                   2021-09-09,SALARY,3000\n\
+                   2021-09-10,COLES 0421,-4.00\n\
+                   soon,KFC,-12.00\n\
+                   2021-09-11,KFC,\n";
+        let statement =
+            read_statement(&settings(), &profile, StatementFormat::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(
+            entries(&statement),
+            vec![
+                "2021-09-08 * \"Woolworths\" \"groceries\"\n  Assets:CBA        -1023.50 AUD\n  Expenses:Food        1023.50 AUD\n",
+                "2021-09-09 * \"ACME\" \"\"\n  Income:Salary        -3000.00 AUD\n  Assets:CBA        3000.00 AUD\n",
+                "2021-09-10 * \"COLES 0421\" \"\"\n  Assets:CBA        -4.00 AUD\n  Expenses:Uncategorized        4.00 AUD\n",
+            ]
+        );
+        assert_eq!(
+            statement.skipped,
+            vec![
+                "line 5: soon isn't a YYYY-MM-DD or DD/MM/YYYY date",
+                "line 6: KFC has no amount"
+            ]
+        );
+        assert_eq!(statement.years(), vec!["2021"]);
+    }
+
+    #[test]
+    fn it_reads_numbered_debit_and_credit_columns() {
+        let profile = ImportProfile {
+            account: Some("amex".into()),
+            has_headers: false,
+            delimiter: ';',
+            date_format: Some("%d.%m.%Y".into()),
+            columns: ImportColumns {
+                date: "1".into(),
+                description: "3".into(),
+                amount: None,
+                debit: Some("4".into()),
+                credit: Some("5".into()),
+            },
+            ..ImportProfile::default()
+        };
+        let csv = "08.09.2021;x;WOOLWORTHS METRO;$12.40;\n09.09.2021;x;WOOLWORTHS REFUND;;(2.00)\n10.09.2021;x;COLES;3.00;\n";
+        let statement =
+            read_statement(&settings(), &profile, StatementFormat::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(
+            entries(&statement),
+            vec![
+                "2021-09-08 * \"Woolworths\" \"groceries\"\n  Liabilities:AMEX        -12.40 AUD\n  Expenses:Food        12.40 AUD\n",
+                "2021-09-09 * \"Woolworths\" \"groceries\"\n  Expenses:Food        -2.00 AUD\n  Liabilities:AMEX        2.00 AUD\n",
+            ]
+        );
+        assert_eq!(
+            statement.skipped,
+            vec!["line 3: no merchant rule matches COLES"]
+        );
+
+        let positive = ImportProfile {
+            sign: SignConvention::DebitsPositive,
+            ..ImportProfile::default()
+        };
+        let csv = "date,description,amount\n2021-09-08,WOOLWORTHS,12.40\n";
+        let statement =
+            read_statement(&settings(), &positive, StatementFormat::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(statement.rows[0].transaction.from_account(), "Assets:CBA");
+        assert!(read_statement(
+            &settings(),
+            &positive,
+            StatementFormat::Csv,
+            "when,what\n".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_books_ofx_and_qif_statements_like_csv_ones() {
+        let ofx =
+            "<OFX><STMTTRN><DTPOSTED>20210908<TRNAMT>-12.40<NAME>WOOLWORTHS 1234</STMTTRN></OFX>";
+        let qif = "!Type:Bank\nD2021-09-08\nT-12.40\nPWOOLWORTHS 1234\n^\n";
+        for (format, input) in [(StatementFormat::Ofx, ofx), (StatementFormat::Qif, qif)] {
+            let statement = read_statement(
+                &settings(),
+                &ImportProfile::default(),
+                format,
+                input.as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                entries(&statement),
+                vec!["2021-09-08 * \"Woolworths\" \"groceries\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"]
+            );
+        }
+        assert_eq!(
+            StatementFormat::from_file_name("Statement.QFX"),
+            Some(StatementFormat::Ofx)
+        );
+        assert_eq!(StatementFormat::from_file_name("statement.pdf"), None);
+    }
+
+    #[test]
+    fn it_drops_rows_already_in_the_ledger_once_each() {
+        let csv = "date,description,amount\n\
+                   2021-09-08,WOOLWORTHS 1,-12.40\n\
+                   2021-09-08,WOOLWORTHS 2,-12.40\n\
+                   2021-09-09,WOOLWORTHS 3,-12.40\n";
+        let mut statement = read_statement(
+            &settings(),
+            &ImportProfile::default(),
+            StatementFormat::Csv,
+            csv.as_bytes(),
+        )
+        .unwrap();
+        let ledger = Ledger::parse(
+            "2021.bean",
+            "2021-09-08 * \"Woolworths\" \"\"\n  Assets:CBA  -12.40 AUD\n  Expenses:Food\n",
+        );
+        statement.drop_known(&ledger);
+        let lines: Vec<usize> = statement.rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![3, 4]);
+        assert_eq!(
+            statement.skipped,
+            vec!["line 2: Woolworths is already in the ledger"]
+        );
+    }
+}
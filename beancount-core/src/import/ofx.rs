@@ -0,0 +1,98 @@
+use chrono::NaiveDate;
+
+use super::{parse_amount, RawRow, RawRows};
+
+/// The `<STMTTRN>` transactions of an OFX statement, SGML (OFX 1) or XML
+/// (OFX 2), described by their `NAME`, or `MEMO` without one.
+pub(super) fn read(text: &str) -> RawRows {
+    // Tags are case-insensitive in practice; ASCII upper-casing keeps offsets.
+    let upper = text.to_ascii_uppercase();
+    let starts: Vec<usize> = upper.match_indices("<STMTTRN>").map(|(i, _)| i).collect();
+    let mut rows = Vec::new();
+    for (index, start) in starts.iter().enumerate() {
+        let next = starts.get(index + 1).copied().unwrap_or(text.len());
+        let end = upper[*start..next]
+            .find("</STMTTRN>")
+            .map_or(next, |end| start + end);
+        let (block, upper_block) = (&text[*start..end], &upper[*start..end]);
+        let line = text[..*start].matches('\n').count() + 1;
+        let field = |tag: &str| {
+            let open = format!("<{}>", tag);
+            let value = &block[upper_block.find(&open)? + open.len()..];
+            let value = value[..value.find(['<', '\r', '\n']).unwrap_or(value.len())].trim();
+            Some(decode(value)).filter(|value| !value.is_empty())
+        };
+
+        let description = field("NAME").or_else(|| field("MEMO")).unwrap_or_default();
+        let date = field("DTPOSTED").unwrap_or_default();
+        let row = NaiveDate::parse_from_str(date.get(..8).unwrap_or_default(), "%Y%m%d")
+            .map_err(|_| format!("line {}: {} isn't an OFX date", line, date))
+            .and_then(|date| {
+                let amount = match field("TRNAMT") {
+                    Some(amount) => {
+                        parse_amount(&amount).map_err(|e| format!("line {}: {}", line, e))?
+                    }
+                    None => 0.0,
+                };
+                if amount == 0.0 {
+                    return Err(format!("line {}: {} has no amount", line, description));
+                }
+                Ok(RawRow {
+                    line,
+                    date: date.format("%Y-%m-%d").to_string(),
+                    description: description.clone(),
+                    amount,
+                })
+            });
+        rows.push(row);
+    }
+    rows
+}
+
+fn decode(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_sgml_and_xml_transactions() {
+        let sgml = "OFXHEADER:100\nDATA:OFXSGML\n\n<OFX>\n<BANKMSGSRSV1><STMTTRNRS><STMTRS>\n<BANKTRANLIST>\n<STMTTRN>\n<TRNTYPE>DEBIT\n<DTPOSTED>20210908120000[+10:AEST]\n<TRNAMT>-12.40\n<FITID>1\n<NAME>WOOLWORTHS &amp; CO\n</STMTTRN>\n<STMTTRN>\n<TRNTYPE>CREDIT\n<DTPOSTED>20210909\n<TRNAMT>3000.00\n<FITID>2\n<MEMO>SALARY\n</STMTTRN>\n<STMTTRN>\n<DTPOSTED>soon\n<TRNAMT>1\n</STMTTRN>\n</BANKTRANLIST>\n</STMTRS></STMTTRNRS></BANKMSGSRSV1>\n</OFX>\n";
+        assert_eq!(
+            read(sgml),
+            vec![
+                Ok(RawRow {
+                    line: 7,
+                    date: "2021-09-08".into(),
+                    description: "WOOLWORTHS & CO".into(),
+                    amount: -12.4,
+                }),
+                Ok(RawRow {
+                    line: 14,
+                    date: "2021-09-09".into(),
+                    description: "SALARY".into(),
+                    amount: 3000.0,
+                }),
+                Err("line 21: soon isn't an OFX date".into()),
+            ]
+        );
+
+        let xml = "<?xml version=\"1.0\"?>\n<OFX><BANKTRANLIST><stmttrn><DTPOSTED>20210910</DTPOSTED><TRNAMT>-4.00</TRNAMT><NAME>COLES</NAME></stmttrn></BANKTRANLIST></OFX>";
+        assert_eq!(
+            read(xml),
+            vec![Ok(RawRow {
+                line: 2,
+                date: "2021-09-10".into(),
+                description: "COLES".into(),
+                amount: -4.0,
+            })]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::settings::ImportProfile;
+
+use super::{parse_amount, parse_date, RawRow, RawRows};
+
+/// The transactions of a QIF file: `D` date, `T` amount, `P` payee and `M` memo
+/// lines ended by `^`, described by their payee, or memo without one. Dates are
+/// read with the profile's `date_format`, Quicken's `'` before a short year
+/// standing for `/`.
+pub(super) fn read(profile: &ImportProfile, text: &str) -> RawRows {
+    let mut rows = Vec::new();
+    let mut record = Record::default();
+    // `!Account` starts a record describing the account, not a transaction.
+    let mut in_account = false;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('!') {
+            in_account = line.eq_ignore_ascii_case("!Account");
+            continue;
+        }
+        if line == "^" {
+            if !in_account {
+                rows.extend(record.finish(profile));
+            }
+            record = Record::default();
+            in_account = false;
+            continue;
+        }
+        if line.is_empty() || in_account {
+            continue;
+        }
+        record.line.get_or_insert(index + 1);
+        let mut chars = line.chars();
+        let code = chars.next();
+        let value = chars.as_str().trim().to_string();
+        match code {
+            Some('D') => record.date = Some(value),
+            Some('T') | Some('U') => record.amount = Some(value),
+            Some('P') => record.payee = Some(value),
+            Some('M') => record.memo = Some(value),
+            _ => (),
+        }
+    }
+    rows.extend(record.finish(profile));
+    rows
+}
+
+#[derive(Default)]
+struct Record {
+    line: Option<usize>,
+    date: Option<String>,
+    amount: Option<String>,
+    payee: Option<String>,
+    memo: Option<String>,
+}
+
+impl Record {
+    fn finish(self, profile: &ImportProfile) -> Option<Result<RawRow, String>> {
+        let Record {
+            line,
+            date,
+            amount,
+            payee,
+            memo,
+        } = self;
+        let line = line?;
+        let description = payee
+            .filter(|payee| !payee.is_empty())
+            .or(memo)
+            .unwrap_or_default();
+        let date = date.unwrap_or_default().replace('\'', "/").replace(' ', "");
+        let row = parse_date(&date, profile.date_format.as_deref()).and_then(|date| {
+            let amount = match amount {
+                Some(amount) => parse_amount(&amount)?,
+                None => 0.0,
+            };
+            if amount == 0.0 {
+                return Err(anyhow::anyhow!("{} has no amount", description));
+            }
+            Ok(RawRow {
+                line,
+                date,
+                description,
+                amount,
+            })
+        });
+        Some(row.map_err(|e| format!("line {}: {}", line, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_transactions_and_skips_the_account_record() {
+        let qif = "!Account\nNCBA\nTBank\n^\n!Type:Bank\nD08/09/2021\nT-1,012.40\nPWOOLWORTHS\nMgroceries\n^\nD09/09'21\nT3000.00\nMSALARY\n^\nD10/09/2021\nPCOLES\n^\n";
+        let profile = ImportProfile {
+            date_format: Some("%d/%m/%Y".into()),
+            ..ImportProfile::default()
+        };
+        let rows = read(&profile, qif);
+        assert_eq!(
+            rows[0],
+            Ok(RawRow {
+                line: 6,
+                date: "2021-09-08".into(),
+                description: "WOOLWORTHS".into(),
+                amount: -1012.4,
+            })
+        );
+        assert_eq!(
+            rows[1],
+            Err("line 11: 09/09/21 isn't a %d/%m/%Y date".into())
+        );
+        assert_eq!(rows[2], Err("line 15: COLES has no amount".into()));
+
+        let short = ImportProfile {
+            date_format: Some("%d/%m/%y".into()),
+            ..ImportProfile::default()
+        };
+        assert_eq!(read(&short, qif)[1].as_ref().unwrap().date, "2021-09-09");
+    }
+}
@@ -0,0 +1,651 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+use serde::Serialize;
+
+const ACCOUNT: &str = r"[A-Z][A-Za-z0-9-]*(?::[A-Z0-9][A-Za-z0-9-]*)+";
+const CURRENCY: &str = r"[A-Z][A-Z0-9'._-]*";
+
+lazy_static! {
+    static ref DIRECTIVE_RE: Regex = Regex::new(r"^(\d{4}-\d{2}-\d{2})\s+(\S+)\s*(.*)$").unwrap();
+    static ref STRING_RE: Regex = Regex::new(r#""((?:[^"\\]|\\.)*)""#).unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"(?:^|\s)([#^])([A-Za-z0-9_/.-]+)").unwrap();
+    static ref METADATA_RE: Regex = Regex::new(r"^([a-z][A-Za-z0-9_-]*):\s*(.*)$").unwrap();
+    static ref POSTING_RE: Regex = Regex::new(&format!(
+        r"^(?:([!*])\s+)?({})(?:\s+(-?[0-9][0-9,]*(?:\.[0-9]*)?)\s+({}))?",
+        ACCOUNT, CURRENCY
+    ))
+    .unwrap();
+    static ref ACCOUNT_AMOUNT_RE: Regex = Regex::new(&format!(
+        r"^({})\s+(-?[0-9][0-9,]*(?:\.[0-9]*)?)\s+({})",
+        ACCOUNT, CURRENCY
+    ))
+    .unwrap();
+    static ref OPEN_RE: Regex =
+        Regex::new(&format!(r"^({})((?:\s*,?\s*{})*)", ACCOUNT, CURRENCY)).unwrap();
+    static ref CLOSE_RE: Regex = Regex::new(&format!(r"^({})", ACCOUNT)).unwrap();
+    static ref PRICE_RE: Regex = Regex::new(&format!(
+        r"^({})\s+(-?[0-9][0-9,]*(?:\.[0-9]*)?)\s+({})",
+        CURRENCY, CURRENCY
+    ))
+    .unwrap();
+    static ref INCLUDE_RE: Regex = Regex::new(r#"^include\s+"([^"]+)""#).unwrap();
+    static ref PUSHTAG_RE: Regex = Regex::new(r"^(pushtag|poptag)\s+#(\S+)").unwrap();
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Amount {
+    pub number: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Posting {
+    pub flag: Option<char>,
+    pub account: String,
+    /// `None` when the amount was elided and couldn't be inferred, e.g. in a
+    /// transaction with postings in several currencies.
+    pub amount: Option<Amount>,
+}
+
+/// The directives the bot reads. Others, such as `pad`, `note` or `commodity`,
+/// are skipped.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Directive {
+    Transaction {
+        flag: char,
+        payee: Option<String>,
+        narration: String,
+        tags: Vec<String>,
+        links: Vec<String>,
+        metadata: Vec<(String, String)>,
+        postings: Vec<Posting>,
+    },
+    Balance {
+        account: String,
+        amount: Amount,
+    },
+    Open {
+        account: String,
+        currencies: Vec<String>,
+    },
+    Close {
+        account: String,
+    },
+    Price {
+        commodity: String,
+        amount: Amount,
+    },
+}
+
+impl Directive {
+    /// What a transaction moved: the sum of its positive postings in the
+    /// currency of the first one. `None` for other directives.
+    pub fn moved(&self) -> Option<Amount> {
+        let postings = match self {
+            Directive::Transaction { postings, .. } => postings,
+            _ => return None,
+        };
+        let amounts = || {
+            postings
+                .iter()
+                .filter_map(|posting| posting.amount.as_ref())
+        };
+        let currency = amounts()
+            .find(|amount| amount.number > 0.0)
+            .map(|amount| amount.currency.clone())
+            .unwrap_or_default();
+        let number = amounts()
+            .filter(|amount| amount.number > 0.0 && amount.currency == currency)
+            .map(|amount| amount.number)
+            .sum();
+        Some(Amount { number, currency })
+    }
+}
+
+/// A dated directive and where it was written.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub date: NaiveDate,
+    pub file: String,
+    /// 1-based line of the directive in `file`.
+    pub line: usize,
+    #[serde(flatten)]
+    pub directive: Directive,
+}
+
+/// The entries of one or more ledger files, sorted by date. Entries of the same
+/// date keep the order they were written in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ledger {
+    pub entries: Vec<Entry>,
+}
+
+impl Ledger {
+    /// Parses the content of the file at `path`, without following includes.
+    /// Lines that can't be read are logged and skipped.
+    pub fn parse(path: &str, content: &str) -> Self {
+        let mut entries = parse_entries(path, content);
+        entries.sort_by_key(|entry| entry.date);
+        Ledger { entries }
+    }
+
+    /// Reads `files` and the files they include with `read`, which returns `None`
+    /// for a missing file. Missing files are skipped, as are includes with globs.
+    pub fn load(
+        files: &[String],
+        read: impl FnMut(&str) -> Result<Option<String>>,
+    ) -> Result<Self> {
+        Ok(Ledger::from_files(&read_files(files, read)?))
+    }
+
+    /// Parses files already read as `(path, content)`, e.g. by [`read_files`].
+    pub fn from_files(files: &[(String, String)]) -> Self {
+        let mut entries = Vec::new();
+        for (path, content) in files {
+            entries.extend(parse_entries(path, content));
+        }
+        entries.sort_by_key(|entry| entry.date);
+        Ledger { entries }
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.directive, Directive::Transaction { .. }))
+    }
+
+    /// Sums postings per (account, currency) over transactions dated from
+    /// `from` to `to`, both inclusive.
+    pub fn totals(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> BTreeMap<(String, String), f64> {
+        let mut totals = BTreeMap::new();
+        let in_range = |date: NaiveDate| {
+            from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to)
+        };
+        for entry in self.transactions().filter(|entry| in_range(entry.date)) {
+            if let Directive::Transaction { postings, .. } = &entry.directive {
+                for posting in postings {
+                    if let Some(amount) = &posting.amount {
+                        *totals
+                            .entry((posting.account.clone(), amount.currency.clone()))
+                            .or_insert(0.0) += amount.number;
+                    }
+                }
+            }
+        }
+        totals
+    }
+}
+
+/// Reads `files` and, depth first, the files they include, returning each file
+/// once as `(path, content)` in the order it was reached.
+pub fn read_files(
+    files: &[String],
+    mut read: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<Vec<(String, String)>> {
+    let mut pending: Vec<String> = files.iter().rev().cloned().collect();
+    let mut seen = HashSet::new();
+    let mut contents = Vec::new();
+    while let Some(path) = pending.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        let content = match read(&path)? {
+            Some(v) => v,
+            None => {
+                warn!("ledger file {} doesn't exist, skipping", path);
+                continue;
+            }
+        };
+        for include in includes(&content).into_iter().rev() {
+            if include.contains('*') {
+                warn!("skipping glob include {} in {}", include, path);
+                continue;
+            }
+            pending.push(resolve_include(&path, &include));
+        }
+        contents.push((path, content));
+    }
+    Ok(contents)
+}
+
+/// Paths named by `include` directives, as written in the file.
+pub fn includes(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| INCLUDE_RE.captures(line.trim()))
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Resolves an include path relative to the directory of the including file.
+pub fn resolve_include(from: &str, include: &str) -> String {
+    match from.rfind('/') {
+        Some(index) if !include.starts_with('/') => format!("{}/{}", &from[..index], include),
+        _ => include.trim_start_matches('/').into(),
+    }
+}
+
+fn parse_entries(path: &str, content: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut pushed_tags: Vec<String> = Vec::new();
+    // Indented lines belong to the last directive, if it was a transaction.
+    let mut in_transaction = false;
+
+    for (index, line) in content.lines().enumerate() {
+        let text = strip_comment(line);
+        if text.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some(entry) = entries.last_mut().filter(|_| in_transaction) {
+                add_transaction_line(entry, text.trim(), path, index);
+            }
+            continue;
+        }
+
+        in_transaction = false;
+        if let Some(captures) = PUSHTAG_RE.captures(text) {
+            let tag = captures[2].to_string();
+            if &captures[1] == "pushtag" {
+                pushed_tags.push(tag);
+            } else if let Some(position) = pushed_tags.iter().rposition(|t| *t == tag) {
+                pushed_tags.remove(position);
+            }
+            continue;
+        }
+        let captures = match DIRECTIVE_RE.captures(text) {
+            Some(v) => v,
+            None => continue,
+        };
+        let date = match NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d") {
+            Ok(v) => v,
+            Err(_) => {
+                warn!("{}:{}: invalid date {}", path, index + 1, &captures[1]);
+                continue;
+            }
+        };
+        let rest = captures[3].trim();
+        let directive = match &captures[2] {
+            "*" | "!" | "txn" => {
+                in_transaction = true;
+                let flag = match &captures[2] {
+                    "!" => '!',
+                    _ => '*',
+                };
+                Some(transaction(flag, rest, &pushed_tags))
+            }
+            "balance" => ACCOUNT_AMOUNT_RE.captures(rest).and_then(|c| {
+                Some(Directive::Balance {
+                    account: c[1].to_string(),
+                    amount: amount(&c[2], &c[3])?,
+                })
+            }),
+            "open" => OPEN_RE.captures(rest).map(|c| Directive::Open {
+                account: c[1].to_string(),
+                currencies: c[2]
+                    .split(|ch: char| ch == ',' || ch.is_whitespace())
+                    .filter(|currency| !currency.is_empty())
+                    .map(String::from)
+                    .collect(),
+            }),
+            "close" => CLOSE_RE.captures(rest).map(|c| Directive::Close {
+                account: c[1].to_string(),
+            }),
+            "price" => PRICE_RE.captures(rest).and_then(|c| {
+                Some(Directive::Price {
+                    commodity: c[1].to_string(),
+                    amount: amount(&c[2], &c[3])?,
+                })
+            }),
+            _ => continue,
+        };
+        match directive {
+            Some(directive) => entries.push(Entry {
+                date,
+                file: path.into(),
+                line: index + 1,
+                directive,
+            }),
+            None => warn!("{}:{}: can't read {}", path, index + 1, text.trim()),
+        }
+    }
+
+    for entry in entries.iter_mut() {
+        if let Directive::Transaction { postings, .. } = &mut entry.directive {
+            infer_elided_amount(postings);
+        }
+    }
+    entries
+}
+
+fn transaction(flag: char, header: &str, pushed_tags: &[String]) -> Directive {
+    let strings: Vec<String> = STRING_RE
+        .captures_iter(header)
+        .map(|c| unescape(&c[1]))
+        .collect();
+    let (payee, narration) = match strings.as_slice() {
+        [] => (None, String::new()),
+        [narration] => (None, narration.clone()),
+        [payee, narration, ..] => (Some(payee.clone()), narration.clone()),
+    };
+    let unquoted = STRING_RE.replace_all(header, "");
+    let mut tags: Vec<String> = pushed_tags.to_vec();
+    let mut links = Vec::new();
+    for captures in TAG_RE.captures_iter(&unquoted) {
+        match &captures[1] {
+            "#" => tags.push(captures[2].to_string()),
+            _ => links.push(captures[2].to_string()),
+        }
+    }
+    Directive::Transaction {
+        flag,
+        payee,
+        narration,
+        tags,
+        links,
+        metadata: Vec::new(),
+        postings: Vec::new(),
+    }
+}
+
+fn add_transaction_line(entry: &mut Entry, text: &str, path: &str, index: usize) {
+    let (metadata, postings) = match &mut entry.directive {
+        Directive::Transaction {
+            metadata, postings, ..
+        } => (metadata, postings),
+        _ => return,
+    };
+    if let Some(captures) = POSTING_RE.captures(text) {
+        postings.push(Posting {
+            flag: captures.get(1).and_then(|f| f.as_str().chars().next()),
+            account: captures[2].to_string(),
+            amount: match (captures.get(3), captures.get(4)) {
+                (Some(number), Some(currency)) => amount(number.as_str(), currency.as_str()),
+                _ => None,
+            },
+        });
+    } else if let Some(captures) = METADATA_RE.captures(text) {
+        // Metadata below a posting belongs to the posting, which isn't kept.
+        if postings.is_empty() {
+            metadata.push((captures[1].to_string(), unquote(&captures[2])));
+        }
+    } else {
+        warn!("{}:{}: can't read {}", path, index + 1, text);
+    }
+}
+
+/// Fills in the one posting without an amount so the transaction balances, when
+/// every other posting is in the same currency.
+fn infer_elided_amount(postings: &mut [Posting]) {
+    let elided: Vec<usize> = postings
+        .iter()
+        .enumerate()
+        .filter(|(_, posting)| posting.amount.is_none())
+        .map(|(index, _)| index)
+        .collect();
+    if elided.len() != 1 {
+        return;
+    }
+    let currencies: HashSet<&str> = postings
+        .iter()
+        .filter_map(|posting| posting.amount.as_ref())
+        .map(|amount| amount.currency.as_str())
+        .collect();
+    if currencies.len() != 1 {
+        return;
+    }
+    let currency = currencies.into_iter().next().unwrap().to_string();
+    let sum: f64 = postings
+        .iter()
+        .filter_map(|posting| posting.amount.as_ref())
+        .map(|amount| amount.number)
+        .sum();
+    postings[elided[0]].amount = Some(Amount {
+        number: round(-sum),
+        currency,
+    });
+}
+
+fn amount(number: &str, currency: &str) -> Option<Amount> {
+    Some(Amount {
+        number: number.replace(',', "").parse().ok()?,
+        currency: currency.into(),
+    })
+}
+
+/// The inside of a string with its `\"` and `\\` escapes undone.
+pub(crate) fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Drops a `;` comment, unless the `;` is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    match STRING_RE.captures(value) {
+        Some(captures) if captures[0].len() == value.len() => unescape(&captures[1]),
+        _ => value.to_string(),
+    }
+}
+
+/// Rounds away the float noise of summing decimal amounts.
+fn round(number: f64) -> f64 {
+    (number * 1e8).round() / 1e8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    const LEDGER: &str = r#"option "title" "2021"
+pushtag #y2021
+include "prices.bean"
+
+2021-01-01 open Assets:Bank:ING AUD
+2021-01-01 open Assets:Cash AUD, USD ; wallet
+2021-01-01 open Expenses:Food
+
+2021-09-06 * "KFC" "lunch; with \"friends\"" #work ^receipt-1
+  document: "documents/2021/kfc.jpg"
+  Assets:Bank:ING        -12.50 AUD
+  Expenses:Food
+    note: "posting metadata"
+
+2021-09-05 ! "top-up"
+  Assets:Cash             1,000.00 AUD
+  Assets:Bank:ING
+
+2021-09-30 balance Assets:Bank:ING   -1012.50 AUD
+2021-12-31 close Assets:Cash
+2021-10-01 pad Assets:Cash Equity:Opening
+poptag #y2021
+2021-10-02 * "untagged"
+  Assets:Cash   -1 USD
+  Expenses:Food  1.40 AUD
+  Assets:Cash
+"#;
+
+    #[test]
+    fn it_reads_directives_in_date_order() {
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let dates: Vec<String> = ledger
+            .entries
+            .iter()
+            .map(|entry| format!("{} {}", entry.date, entry.line))
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                "2021-01-01 5",
+                "2021-01-01 6",
+                "2021-01-01 7",
+                "2021-09-05 15",
+                "2021-09-06 9",
+                "2021-09-30 19",
+                "2021-10-02 23",
+                "2021-12-31 20",
+            ]
+        );
+        assert_eq!(
+            ledger.entries[1].directive,
+            Directive::Open {
+                account: "Assets:Cash".into(),
+                currencies: vec!["AUD".into(), "USD".into()],
+            }
+        );
+        assert_eq!(
+            ledger.entries[5].directive,
+            Directive::Balance {
+                account: "Assets:Bank:ING".into(),
+                amount: Amount {
+                    number: -1012.5,
+                    currency: "AUD".into()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn it_reads_transactions() {
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let kfc = &ledger.entries[4].directive;
+        assert_eq!(
+            *kfc,
+            Directive::Transaction {
+                flag: '*',
+                payee: Some("KFC".into()),
+                narration: "lunch; with \"friends\"".into(),
+                tags: vec!["y2021".into(), "work".into()],
+                links: vec!["receipt-1".into()],
+                metadata: vec![("document".into(), "documents/2021/kfc.jpg".into())],
+                postings: vec![
+                    Posting {
+                        flag: None,
+                        account: "Assets:Bank:ING".into(),
+                        amount: Some(Amount {
+                            number: -12.5,
+                            currency: "AUD".into()
+                        }),
+                    },
+                    Posting {
+                        flag: None,
+                        account: "Expenses:Food".into(),
+                        amount: Some(Amount {
+                            number: 12.5,
+                            currency: "AUD".into()
+                        }),
+                    },
+                ],
+            }
+        );
+
+        match &ledger.entries[3].directive {
+            Directive::Transaction {
+                flag,
+                payee,
+                postings,
+                ..
+            } => {
+                assert_eq!(*flag, '!');
+                assert_eq!(*payee, None);
+                assert_eq!(postings[1].amount.as_ref().unwrap().number, -1000.0);
+            }
+            other => panic!("expected a transaction, got {:?}", other),
+        }
+
+        match &ledger.entries[6].directive {
+            Directive::Transaction { tags, postings, .. } => {
+                assert!(tags.is_empty());
+                assert_eq!(postings[2].amount, None);
+            }
+            other => panic!("expected a transaction, got {:?}", other),
+        }
+
+        let totals = ledger.totals(None, NaiveDate::from_ymd_opt(2021, 9, 30));
+        assert_eq!(totals[&("Assets:Bank:ING".into(), "AUD".into())], -1012.5);
+        assert_eq!(totals[&("Expenses:Food".into(), "AUD".into())], 12.5);
+    }
+
+    #[test]
+    fn it_follows_includes() {
+        let files = [
+            (
+                "main.bean",
+                "include \"ledger/2021.bean\"\ninclude \"main.bean\"\n",
+            ),
+            (
+                "ledger/2021.bean",
+                "include \"prices.bean\"\n2021-01-01 open Assets:Cash\n",
+            ),
+            ("ledger/prices.bean", "2021-01-02 price USD 1.40 AUD\n"),
+        ];
+        let read = |path: &str| -> Result<Option<String>> {
+            if path == "broken.bean" {
+                return Err(anyhow!("boom"));
+            }
+            Ok(files
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, content)| content.to_string()))
+        };
+
+        let ledger = Ledger::load(&["main.bean".into(), "missing.bean".into()], read).unwrap();
+        assert_eq!(ledger.entries.len(), 2);
+        assert_eq!(ledger.entries[1].file, "ledger/prices.bean");
+        assert_eq!(
+            ledger.entries[1].directive,
+            Directive::Price {
+                commodity: "USD".into(),
+                amount: Amount {
+                    number: 1.4,
+                    currency: "AUD".into()
+                },
+            }
+        );
+        assert!(Ledger::load(&["broken.bean".into()], read).is_err());
+    }
+
+    #[test]
+    fn it_finds_include_paths() {
+        assert_eq!(
+            includes("include \"accounts.bean\"\n  include \"2021.bean\" ; this year\n"),
+            vec!["accounts.bean", "2021.bean"]
+        );
+    }
+}
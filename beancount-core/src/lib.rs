@@ -2,5 +2,28 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod accounts;
+pub mod aliases;
+pub mod archive;
+pub mod bank_feed;
+pub mod budget;
+pub mod clock;
+pub mod duplicates;
+pub mod export;
+pub mod fava;
+pub mod import;
+pub mod ledger;
+pub mod merchant;
+pub mod migration;
+pub mod networth;
+pub mod ocr;
 pub mod parser;
+pub mod query;
+pub mod reply;
+pub mod report;
+pub mod schedule;
+pub mod secret;
 pub mod settings;
+pub mod splitwise;
+pub mod tenants;
+pub mod validation;
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+use crate::settings::{ImportProfile, MerchantRule, Settings};
+
+/// What a merchant rule says about a raw merchant string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerchantMatch {
+    pub payee: String,
+    /// Alias of the account to book to, if the rule names one.
+    pub account: Option<String>,
+    pub narration: Option<String>,
+}
+
+/// The `[[merchant_rules]]` of the settings, compiled once so importers can run
+/// them over every line of a statement.
+pub struct MerchantRules {
+    rules: Vec<(Regex, MerchantRule)>,
+}
+
+pub(crate) fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+impl MerchantRules {
+    pub fn new(settings: &Settings) -> Result<Self> {
+        Self::compile_all(settings.merchant_rules.iter())
+    }
+
+    /// The rules of an import `profile` followed by the top-level ones.
+    pub fn for_profile(settings: &Settings, profile: &ImportProfile) -> Result<Self> {
+        Self::compile_all(
+            profile
+                .merchant_rules
+                .iter()
+                .chain(settings.merchant_rules.iter()),
+        )
+    }
+
+    fn compile_all<'a>(rules: impl Iterator<Item = &'a MerchantRule>) -> Result<Self> {
+        let rules = rules
+            .map(|rule| Ok((compile(&rule.pattern)?, rule.clone())))
+            .collect::<Result<_>>()?;
+        Ok(MerchantRules { rules })
+    }
+
+    /// The first rule matching `merchant`, e.g. `WOOLWORTHS 1234 SYDNEY`.
+    pub fn apply(&self, merchant: &str) -> Option<MerchantMatch> {
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(merchant.trim()))
+            .map(|(_, rule)| MerchantMatch {
+                payee: rule.payee.clone(),
+                account: rule.account.clone(),
+                narration: rule.narration.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_applies_first_matching_rule() {
+        let settings = Settings::builder("AUD")
+            .account("food", "Expenses:Food")
+            .merchant_rule(MerchantRule {
+                pattern: r"^woolworths \d+".into(),
+                payee: "Woolworths".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+            .merchant_rule(MerchantRule {
+                pattern: "WOOL".into(),
+                payee: "Wool shop".into(),
+                account: None,
+                narration: None,
+            })
+            .build()
+            .unwrap();
+        let rules = MerchantRules::new(&settings).unwrap();
+        assert_eq!(
+            rules.apply(" WOOLWORTHS 1234 SYDNEY"),
+            Some(MerchantMatch {
+                payee: "Woolworths".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+        );
+        assert_eq!(rules.apply("WOOLWORTHS METRO").unwrap().payee, "Wool shop");
+        assert!(rules.apply("COLES 0421").is_none());
+    }
+}
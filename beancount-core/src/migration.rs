@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use config::{Config, ConfigError, Value};
+use log::warn;
+
+/// Layout version written by this release. Documents without `version` are
+/// version 1.
+pub const CONFIG_VERSION: i64 = 2;
+
+/// Upgrades a config document to [`CONFIG_VERSION`] in place, one version at a
+/// time, logging a deprecation notice for every step applied.
+///
+/// - 1 → 2: accounts are tables (`food = { account = "Expenses:Food" }`) rather
+///   than a flat map of alias to account name, in `[accounts]` and every
+///   `[users.<id>.accounts]`.
+pub fn migrate(document: &mut Config) -> Result<()> {
+    let mut version = match document.get_int("version") {
+        Ok(v) => v,
+        Err(ConfigError::NotFound(_)) => 1,
+        Err(e) => return Err(e.into()),
+    };
+    if version > CONFIG_VERSION {
+        return Err(anyhow!(
+            "config version {} is newer than the supported version {}",
+            version,
+            CONFIG_VERSION
+        ));
+    }
+
+    if version == 1 {
+        if upgrade_flat_accounts(document)? {
+            warn!(
+                "config version 1 is deprecated: set `version = 2` and write accounts as tables, e.g. `food = {{ account = \"Expenses:Food\" }}`"
+            );
+        }
+        version = 2;
+    }
+
+    document.set("version", version)?;
+    Ok(())
+}
+
+/// Returns whether any flat account entry was found.
+fn upgrade_flat_accounts(document: &mut Config) -> Result<bool> {
+    let mut upgraded = false;
+    if let Ok(accounts) = document.get_table("accounts") {
+        let (accounts, changed) = account_tables(accounts)?;
+        document.set("accounts", accounts)?;
+        upgraded |= changed;
+    }
+    if let Ok(users) = document.get_table("users") {
+        let mut migrated = HashMap::new();
+        for (user_id, user) in users {
+            let mut user = user.into_table()?;
+            if let Some(accounts) = user.remove("accounts") {
+                let (accounts, changed) = account_tables(accounts.into_table()?)?;
+                user.insert("accounts".into(), Value::from(accounts));
+                upgraded |= changed;
+            }
+            migrated.insert(user_id, Value::from(user));
+        }
+        document.set("users", migrated)?;
+    }
+    Ok(upgraded)
+}
+
+fn account_tables(accounts: HashMap<String, Value>) -> Result<(HashMap<String, Value>, bool)> {
+    let mut changed = false;
+    let mut tables = HashMap::new();
+    for (alias, entry) in accounts {
+        let entry = match entry.clone().into_table() {
+            Ok(_) => entry,
+            Err(_) => {
+                changed = true;
+                let mut table = HashMap::new();
+                table.insert("account".to_string(), Value::from(entry.into_str()?));
+                Value::from(table)
+            }
+        };
+        tables.insert(alias, entry);
+    }
+    Ok((tables, changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{File, FileFormat};
+
+    fn document(toml: &str) -> Config {
+        let mut document = Config::default();
+        document
+            .merge(File::from_str(toml, FileFormat::Toml))
+            .unwrap();
+        document
+    }
+
+    #[test]
+    fn it_upgrades_flat_accounts_to_tables() {
+        let mut document = document(
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\ning = { account = \"Assets:ING\", currency = \"USD\" }\n[users.42.accounts]\nvisa = \"Liabilities:Visa\"\n",
+        );
+        migrate(&mut document).unwrap();
+        assert_eq!(document.get_int("version").unwrap(), 2);
+        assert_eq!(
+            document.get_str("accounts.cba.account").unwrap(),
+            "Assets:CBA"
+        );
+        assert_eq!(document.get_str("accounts.ing.currency").unwrap(), "USD");
+        assert_eq!(
+            document.get_str("users.42.accounts.visa.account").unwrap(),
+            "Liabilities:Visa"
+        );
+    }
+
+    #[test]
+    fn it_rejects_newer_versions() {
+        let mut document = document("version = 3\ncurrency = \"AUD\"\n");
+        assert_eq!(
+            migrate(&mut document).unwrap_err().to_string(),
+            "config version 3 is newer than the supported version 2"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::ledger::{Directive, Ledger};
+
+/// Balances smaller than this are left out, they are rounding leftovers.
+const EPSILON: f64 = 0.005;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountBalance {
+    pub account: String,
+    pub currency: String,
+    pub amount: f64,
+    /// `amount` in the net worth currency, `None` without a price for
+    /// `currency`.
+    pub value: Option<f64>,
+}
+
+/// Assets and liabilities on a date, converted to one currency.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetWorth {
+    pub date: NaiveDate,
+    pub currency: String,
+    pub assets: f64,
+    pub liabilities: f64,
+    pub total: f64,
+    pub accounts: Vec<AccountBalance>,
+    /// Currencies left out of the totals for lack of a price.
+    pub unpriced: Vec<String>,
+}
+
+/// The latest price of each currency pair on a date.
+struct Prices(HashMap<(String, String), f64>);
+
+impl Prices {
+    fn on(ledger: &Ledger, date: NaiveDate) -> Self {
+        let mut prices = HashMap::new();
+        for entry in ledger.entries.iter().filter(|entry| entry.date <= date) {
+            if let Directive::Price { commodity, amount } = &entry.directive {
+                prices.insert((commodity.clone(), amount.currency.clone()), amount.number);
+            }
+        }
+        Prices(prices)
+    }
+
+    /// Converts with the direct price, or the inverse of the opposite one.
+    fn convert(&self, amount: f64, from: &str, to: &str) -> Option<f64> {
+        if from == to {
+            return Some(amount);
+        }
+        if let Some(price) = self.0.get(&(from.to_string(), to.to_string())) {
+            return Some(amount * price);
+        }
+        self.0
+            .get(&(to.to_string(), from.to_string()))
+            .filter(|price| **price != 0.0)
+            .map(|price| amount / price)
+    }
+}
+
+/// Adds up the postings to `Assets` and `Liabilities` accounts up to `date`,
+/// converting them to `currency` with the latest `price` directives.
+pub fn net_worth(ledger: &Ledger, currency: &str, date: NaiveDate) -> NetWorth {
+    let mut balances: BTreeMap<(String, String), f64> = BTreeMap::new();
+    for entry in ledger.transactions().filter(|entry| entry.date <= date) {
+        if let Directive::Transaction { postings, .. } = &entry.directive {
+            for posting in postings {
+                let is_balance_sheet = posting.account.starts_with("Assets:")
+                    || posting.account.starts_with("Liabilities:");
+                if let Some(amount) = posting.amount.as_ref().filter(|_| is_balance_sheet) {
+                    *balances
+                        .entry((posting.account.clone(), amount.currency.clone()))
+                        .or_insert(0.0) += amount.number;
+                }
+            }
+        }
+    }
+
+    let prices = Prices::on(ledger, date);
+    let mut unpriced = BTreeSet::new();
+    let (mut assets, mut liabilities) = (0.0, 0.0);
+    let accounts: Vec<AccountBalance> = balances
+        .into_iter()
+        .filter(|(_, amount)| amount.abs() >= EPSILON)
+        .map(|((account, from), amount)| {
+            let value = prices.convert(amount, &from, currency);
+            match value {
+                Some(value) if account.starts_with("Assets:") => assets += value,
+                Some(value) => liabilities += value,
+                None => {
+                    unpriced.insert(from.clone());
+                }
+            }
+            AccountBalance {
+                account,
+                currency: from,
+                amount,
+                value,
+            }
+        })
+        .collect();
+
+    NetWorth {
+        date,
+        currency: currency.into(),
+        assets,
+        liabilities,
+        total: assets + liabilities,
+        accounts,
+        unpriced: unpriced.into_iter().collect(),
+    }
+}
+
+impl NetWorth {
+    /// One line per account, followed by the totals.
+    pub fn to_text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .accounts
+            .iter()
+            .map(|balance| match balance.value {
+                Some(value) if balance.currency != self.currency => format!(
+                    "{:<40} {:>10.2} {} = {:.2} {}",
+                    balance.account, balance.amount, balance.currency, value, self.currency
+                ),
+                _ => format!(
+                    "{:<40} {:>10.2} {}",
+                    balance.account, balance.amount, balance.currency
+                ),
+            })
+            .collect();
+        lines.push(String::new());
+        for (label, amount) in [
+            ("Assets", self.assets),
+            ("Liabilities", self.liabilities),
+            ("Net worth", self.total),
+        ] {
+            lines.push(format!("{:<40} {:>10.2} {}", label, amount, self.currency));
+        }
+        if !self.unpriced.is_empty() {
+            lines.push(format!(
+                "Left out, no price in {}: {}",
+                self.currency,
+                self.unpriced.join(", ")
+            ));
+        }
+        format!("{}\n", lines.join("\n"))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDGER: &str = r#"
+2021-01-01 price USD 1.30 AUD
+2021-06-01 price USD 1.40 AUD
+2021-06-01 price AUD 75.00 JPY
+
+2021-01-02 * "opening"
+  Assets:CBA              1000.00 AUD
+  Assets:Wise:USD          100.00 USD
+  Assets:Wallet:JPY       7500.00 JPY
+  Assets:Wallet:BTC          0.01 BTC
+  Equity:Opening
+
+2021-05-01 * "KFC" "dinner"
+  Liabilities:AMEX         -50.00 AUD
+  Expenses:Food
+
+2021-09-01 * "Payday"
+  Income:Salary          -2000.00 AUD
+  Assets:CBA
+"#;
+
+    #[test]
+    fn it_converts_balances_with_the_latest_prices() {
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let worth = net_worth(
+            &ledger,
+            "AUD",
+            NaiveDate::from_ymd_opt(2021, 6, 30).unwrap(),
+        );
+        assert_eq!(worth.assets, 1000.0 + 140.0 + 100.0);
+        assert_eq!(worth.liabilities, -50.0);
+        assert_eq!(worth.total, 1190.0);
+        assert_eq!(worth.unpriced, vec!["BTC"]);
+        assert_eq!(
+            worth.accounts[3],
+            AccountBalance {
+                account: "Assets:Wise:USD".into(),
+                currency: "USD".into(),
+                amount: 100.0,
+                value: Some(140.0),
+            }
+        );
+        assert!(worth.to_text().ends_with(&format!(
+            "{:<40} {:>10.2} AUD\nLeft out, no price in AUD: BTC\n",
+            "Net worth", 1190.0
+        )));
+
+        let earlier = net_worth(&ledger, "AUD", NaiveDate::from_ymd_opt(2021, 3, 1).unwrap());
+        assert_eq!(earlier.liabilities, 0.0);
+        assert_eq!(earlier.accounts[3].value, Some(130.0));
+        assert_eq!(earlier.unpriced, vec!["BTC", "JPY"]);
+    }
+}
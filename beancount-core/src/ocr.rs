@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::merchant::{MerchantMatch, MerchantRules};
+use crate::parser::{BeancountParser, Transaction};
+use crate::settings::{OcrSettings, Settings};
+
+lazy_static! {
+    static ref AMOUNT: Regex = Regex::new(r"(\d{1,3}(?:,\d{3})+|\d+)\.(\d{2})\b").unwrap();
+    static ref ISO_DATE: Regex = Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})\b").unwrap();
+    static ref DAY_FIRST_DATE: Regex =
+        Regex::new(r"\b(\d{1,2})[/.-](\d{1,2})[/.-](\d{4}|\d{2})\b").unwrap();
+    static ref TOTAL: Regex =
+        Regex::new(r"(?i)\b(total|amount\s+due|balance\s+due|to\s+pay)\b").unwrap();
+    /// Lines that mention the total without being it.
+    static ref NOT_TOTAL: Regex =
+        Regex::new(r"(?i)sub\s*-?\s*total|\b(gst|tax|vat|saving|savings|discount|items?)\b")
+            .unwrap();
+    /// Lines above the merchant's name or about it rather than naming it.
+    static ref NOT_MERCHANT: Regex = Regex::new(
+        r"(?i)^(tax\s+invoice|invoice|receipt|welcome|thank|abn\b|acn\b|ph\b|phone|tel\b|www\.)"
+    )
+    .unwrap();
+}
+
+/// What the text of a receipt photo says, as far as it could be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt {
+    /// The first line naming a business, e.g. `COLES SUPERMARKETS`.
+    pub merchant: Option<String>,
+    pub total: Option<f64>,
+    pub date: Option<NaiveDate>,
+}
+
+impl Receipt {
+    /// Picks the merchant, total and date out of OCR text: the merchant from
+    /// the first line with words, the total from the first line saying
+    /// `TOTAL` or `AMOUNT DUE`, or the largest amount without one, and the
+    /// first `YYYY-MM-DD` or `DD/MM/YYYY` date.
+    pub fn from_text(text: &str) -> Self {
+        let lines: Vec<String> = text
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect();
+        let date = lines.iter().find_map(|line| read_date(line));
+        let merchant = lines
+            .iter()
+            .find(|line| {
+                line.chars().filter(|c| c.is_alphabetic()).count() >= 3
+                    && !NOT_MERCHANT.is_match(line)
+                    && read_date(line).is_none()
+                    && last_amount(line).is_none()
+            })
+            .cloned();
+        let total = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| TOTAL.is_match(line) && !NOT_TOTAL.is_match(line))
+            // OCR often reads the amount column as the line after the label.
+            .find_map(|(i, line)| {
+                last_amount(line).or_else(|| lines.get(i + 1).and_then(|next| last_amount(next)))
+            })
+            .or_else(|| {
+                lines
+                    .iter()
+                    .filter_map(|line| last_amount(line))
+                    .fold(None, |max: Option<f64>, amount| {
+                        Some(max.map_or(amount, |max| max.max(amount)))
+                    })
+            });
+        Receipt {
+            merchant,
+            total,
+            date,
+        }
+    }
+}
+
+fn read_date(line: &str) -> Option<NaiveDate> {
+    let number = |captures: &regex::Captures, i: usize| captures[i].parse::<u32>().ok();
+    if let Some(date) = ISO_DATE.captures_iter(line).find_map(|captures| {
+        NaiveDate::from_ymd_opt(
+            captures[1].parse().ok()?,
+            number(&captures, 2)?,
+            number(&captures, 3)?,
+        )
+    }) {
+        return Some(date);
+    }
+    DAY_FIRST_DATE.captures_iter(line).find_map(|captures| {
+        let year: i32 = captures[3].parse().ok()?;
+        let year = if captures[3].len() == 2 {
+            2000 + year
+        } else {
+            year
+        };
+        NaiveDate::from_ymd_opt(year, number(&captures, 2)?, number(&captures, 1)?)
+    })
+}
+
+/// The rightmost amount of a line, where receipts put prices, leaving out the
+/// digits of dates such as `08.09.2021`.
+fn last_amount(line: &str) -> Option<f64> {
+    let line = DAY_FIRST_DATE.replace_all(line, " ");
+    let line = ISO_DATE.replace_all(&line, " ");
+    AMOUNT.captures_iter(&line).last().and_then(|captures| {
+        format!("{}.{}", captures[1].replace(',', ""), &captures[2])
+            .parse()
+            .ok()
+    })
+}
+
+/// Books `receipt` from `from_alias`, or `default_from_account`, to the
+/// account of the first merchant rule matching its merchant, or
+/// `default_account`. Receipts whose date couldn't be read are dated today.
+pub fn book(
+    settings: &Settings,
+    ocr: &OcrSettings,
+    receipt: &Receipt,
+    from_alias: Option<&str>,
+) -> Result<Transaction> {
+    let merchant = receipt
+        .merchant
+        .as_deref()
+        .ok_or_else(|| anyhow!("couldn't read the merchant of the receipt"))?;
+    let total = receipt
+        .total
+        .ok_or_else(|| anyhow!("couldn't read the total of the receipt"))?;
+    let rules = MerchantRules::new(settings)?;
+    let (payee, narration, account) = match rules.apply(merchant) {
+        Some(MerchantMatch {
+            payee,
+            account,
+            narration,
+        }) => (
+            payee,
+            narration,
+            account.or_else(|| ocr.default_account.clone()),
+        ),
+        None => (merchant.to_string(), None, ocr.default_account.clone()),
+    };
+    let account = account.ok_or_else(|| {
+        anyhow!(
+            "no merchant rule or [ocr] default_account books {}",
+            merchant
+        )
+    })?;
+    let date = receipt.date.unwrap_or_else(|| settings.today());
+    Ok(BeancountParser::new(settings.clone()).from_fields(
+        &date.format("%Y-%m-%d").to_string(),
+        &payee,
+        narration.as_deref().unwrap_or_default(),
+        total as f32,
+        from_alias,
+        &account,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MerchantRule;
+
+    const COLES: &str = "TAX INVOICE\nCOLES SUPERMARKETS\nABN 45 004 189 708\nCarlton  Ph: 03 9347 0000\n\nBANANAS 1KG        3.90\nMILK 2L            3.10\nSUBTOTAL          7.00\nTOTAL\n$7.00\nGST INCLUDED IN TOTAL  0.00\nEFTPOS            50.00\n08/09/2021 12:40\n";
+
+    #[test]
+    fn it_reads_merchant_total_and_date() {
+        assert_eq!(
+            Receipt::from_text(COLES),
+            Receipt {
+                merchant: Some("COLES SUPERMARKETS".into()),
+                total: Some(7.0),
+                date: NaiveDate::from_ymd_opt(2021, 9, 8),
+            }
+        );
+
+        let receipt =
+            Receipt::from_text("Bunnings Warehouse\n2021-10-02\nHose 1,249.00\nPaid 1,249.00");
+        assert_eq!(receipt.merchant.as_deref(), Some("Bunnings Warehouse"));
+        assert_eq!(receipt.total, Some(1249.0));
+        assert_eq!(receipt.date, NaiveDate::from_ymd_opt(2021, 10, 2));
+
+        let receipt = Receipt::from_text("12.40\n31.13.21");
+        assert_eq!(receipt.merchant, None);
+        assert_eq!(receipt.total, Some(12.4));
+        assert_eq!(receipt.date, None);
+    }
+
+    #[test]
+    fn it_books_with_merchant_rules_then_default_account() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("amex", "Liabilities:Amex")
+            .account("food", "Expenses:Food")
+            .account("uncategorized", "Expenses:Uncategorized")
+            .default_from_account("cba")
+            .merchant_rule(MerchantRule {
+                pattern: "^COLES".into(),
+                payee: "Coles".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+            .build()
+            .unwrap();
+        let ocr = OcrSettings {
+            url: "http://localhost/ocr".into(),
+            api_key: None,
+            default_account: Some("uncategorized".into()),
+        };
+        let transaction = book(&settings, &ocr, &Receipt::from_text(COLES), None).unwrap();
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Coles\" \"groceries\"\n  Assets:CBA        -7.00 AUD\n  Expenses:Food        7.00 AUD\n"
+        );
+
+        let receipt = Receipt {
+            merchant: Some("Bunnings Warehouse".into()),
+            total: Some(30.0),
+            date: NaiveDate::from_ymd_opt(2021, 10, 2),
+        };
+        let transaction = book(&settings, &ocr, &receipt, Some("amex")).unwrap();
+        assert_eq!(
+            String::from(transaction),
+            "2021-10-02 * \"Bunnings Warehouse\" \"\"\n  Liabilities:Amex        -30.00 AUD\n  Expenses:Uncategorized        30.00 AUD\n"
+        );
+
+        let unreadable = Receipt {
+            total: None,
+            ..receipt
+        };
+        assert_eq!(
+            book(&settings, &ocr, &unreadable, None)
+                .unwrap_err()
+                .to_string(),
+            "couldn't read the total of the receipt"
+        );
+    }
+}
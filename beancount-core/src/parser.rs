@@ -1,14 +1,95 @@
-use anyhow::{anyhow, Result};
 use chrono::prelude::Local;
+use thiserror::Error;
 
-use crate::settings::Settings;
+use crate::clock::{Clock, SystemClock};
+use crate::ledger::unescape;
+use crate::settings::{render_entry_path, AccountSettings, Settings, DEFAULT_COMMIT_MESSAGE};
 use pest::Parser;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[grammar = "transaction.pest"]
 pub struct TransactionParser;
 
-#[derive(Debug)]
+/// Why a message couldn't be turned into a transaction. All of these are the
+/// sender's to fix, so they are answered rather than retried.
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("{0}")]
+    Syntax(Box<pest::error::Error<Rule>>),
+    #[error("Invalid input")]
+    Empty,
+    #[error("invalid amount {0}")]
+    InvalidAmount(String),
+    #[error("{0} isn't in currency_symbols")]
+    UnknownCurrencySymbol(String),
+    /// `suggestions` are the aliases close to `alias`, if any.
+    #[error(
+        "account {alias} doesn't exist in current setting{}",
+        did_you_mean(.suggestions)
+    )]
+    UnknownAccount {
+        alias: String,
+        suggestions: Vec<String>,
+    },
+    #[error("no account to pay from was given and no default_from_account is configured")]
+    NoFromAccount,
+    #[error("{field} is longer than {max} characters")]
+    TooLong { field: &'static str, max: usize },
+    #[error("the split amounts add up to {split:.2}, not {total:.2}")]
+    UnbalancedSplit { total: f32, split: f32 },
+    #[error("{0} isn't in split_accounts")]
+    UnknownPartner(String),
+}
+
+/// Metadata key holding the Telegram message a transaction was sent in, as
+/// `<chat_id>/<message_id>`, so an edit of the message replaces the entry.
+pub const MESSAGE_KEY: &str = "telegram_message";
+
+/// Payee of a transfer written without one, e.g. `500 cba > savings`. Its
+/// narration comes from `[narrations]` like any payee's.
+pub const TRANSFER_PAYEE: &str = "Transfer";
+
+/// Longest payee accepted, in characters.
+pub const MAX_PAYEE_CHARS: usize = 64;
+/// Longest narration accepted, in characters.
+pub const MAX_NARRATION_CHARS: usize = 256;
+/// Longest comment accepted, in characters.
+pub const MAX_COMMENT_CHARS: usize = 256;
+
+fn did_you_mean(aliases: &[String]) -> String {
+    if aliases.is_empty() {
+        return String::new();
+    }
+    let quoted: Vec<String> = aliases.iter().map(|alias| format!("`{}`", alias)).collect();
+    format!(", did you mean {}?", quoted.join(" or "))
+}
+
+/// How many single character insertions, deletions and substitutions turn `a`
+/// into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn check_length(field: &'static str, value: &str, max: usize) -> Result<(), ParseError> {
+    if value.chars().count() > max {
+        return Err(ParseError::TooLong { field, max });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     date: String,
     payee: String,
@@ -17,87 +98,597 @@ pub struct Transaction {
     currency: String,
     from_account: String,
     to_account: String,
+    #[serde(default)]
+    metadata: Vec<(String, String)>,
+    /// What the paying account was charged for `amount`, in its own currency.
+    #[serde(default)]
+    total_price: Option<(f32, String)>,
+    /// Accounts and amounts `amount` is split across, e.g. `food 50, household
+    /// 30`. Empty when it all goes to `to_account`, which is otherwise the
+    /// first of them.
+    #[serde(default)]
+    splits: Vec<(String, f32)>,
+    /// Tags without their `#`.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Links without their `^`.
+    #[serde(default)]
+    links: Vec<String>,
+    /// Flagged `!` for review rather than `*`.
+    #[serde(default)]
+    pending: bool,
+    /// Memos written as `; comment` lines below the metadata, from `// memo`
+    /// or `# memo` at the end of a message.
+    #[serde(default)]
+    comments: Vec<String>,
 }
 
 impl Default for Transaction {
     fn default() -> Self {
+        Transaction::dated(Local::now().format("%Y-%m-%d").to_string())
+    }
+}
+
+impl Transaction {
+    /// An empty transaction on `date`, without reading the system clock.
+    fn dated(date: String) -> Self {
         Transaction {
-            date: Local::now().format("%Y-%m-%d").to_string(),
+            date,
             payee: String::default(),
             narration: String::default(),
             amount: 0.0,
             currency: "AUD".to_string(),
             from_account: String::default(),
             to_account: String::default(),
+            metadata: Vec::new(),
+            total_price: None,
+            splits: Vec::new(),
+            tags: Vec::new(),
+            links: Vec::new(),
+            pending: false,
+            comments: Vec::new(),
         }
     }
-}
 
-impl Transaction {
+    pub fn date(&self) -> &str {
+        &self.date
+    }
+
     pub fn year(&self) -> String {
         self.date.split('-').next().unwrap().into()
     }
+
+    /// The file this transaction goes to under the ledger path `template`, see
+    /// [`render_entry_path`].
+    pub fn ledger_path(&self, template: Option<&str>) -> String {
+        let month = self.date.split('-').nth(1).unwrap_or_default();
+        render_entry_path(template, &self.year(), month, &self.from_account)
+    }
+
+    /// The message of the commit saving this transaction, see
+    /// [`Settings::commit_message`].
+    pub fn commit_message(&self, template: Option<&str>) -> String {
+        // The last segment names the account the way a discovered alias does.
+        let short = |account: &str| {
+            account
+                .rsplit(':')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase()
+        };
+        template
+            .unwrap_or(DEFAULT_COMMIT_MESSAGE)
+            .replace("{date}", &self.date)
+            .replace("{payee}", &self.payee)
+            .replace("{narration}", &self.narration)
+            .replace("{amount}", &format!("{:.2}", self.amount))
+            .replace("{currency}", &self.currency)
+            .replace("{from_account}", &self.from_account)
+            .replace("{to_account}", &self.to_account)
+            .replace("{from}", &short(&self.from_account))
+            .replace("{to}", &short(&self.to_account))
+    }
+
+    pub fn payee(&self) -> &str {
+        &self.payee
+    }
+
+    pub fn narration(&self) -> &str {
+        &self.narration
+    }
+
+    pub fn amount(&self) -> f32 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn from_account(&self) -> &str {
+        &self.from_account
+    }
+
+    pub fn to_account(&self) -> &str {
+        &self.to_account
+    }
+
+    /// The accounts the amount is split across with their share, empty
+    /// unless it was split.
+    pub fn splits(&self) -> &[(String, f32)] {
+        &self.splits
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn links(&self) -> &[String] {
+        &self.links
+    }
+
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// The total the paying account was charged, when it differs in currency.
+    pub fn total_price(&self) -> Option<(f32, &str)> {
+        self.total_price
+            .as_ref()
+            .map(|(amount, currency)| (*amount, currency.as_str()))
+    }
+
+    /// Charges the paying account `rate` `currency` per unit of the amount. The
+    /// other leg keeps the original amount, priced with `@@`.
+    pub fn convert(&mut self, rate: f64, currency: &str) {
+        let total = (self.amount as f64 * rate * 100.0).round() / 100.0;
+        self.total_price = Some((total as f32, currency.into()));
+    }
+
+    /// Adds a `key: "value"` metadata line, rendered below the transaction header.
+    pub fn add_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.push((key.into(), value.into()));
+    }
+
+    /// Records the Telegram message the transaction was sent in, see
+    /// [`MESSAGE_KEY`].
+    pub fn set_message(&mut self, chat_id: u64, message_id: u64) {
+        self.add_metadata(MESSAGE_KEY, &format!("{}/{}", chat_id, message_id));
+    }
+
+    /// The metadata line of the message the transaction was sent in, which
+    /// marks its entry in a ledger file.
+    pub fn message_marker(&self) -> Option<String> {
+        self.metadata
+            .iter()
+            .find(|(key, _)| key == MESSAGE_KEY)
+            .map(|(key, value)| format!("  {}: \"{}\"", key, value))
+    }
+
+    /// Books the amount in `currency` rather than the paying account's.
+    pub fn set_currency(&mut self, currency: &str) {
+        self.currency = currency.into();
+    }
+
+    /// Tags the transaction, `tag` being given without its `#`.
+    pub fn add_tag(&mut self, tag: &str) {
+        self.tags.push(tag.into());
+    }
+
+    /// Links the transaction, `link` being given without its `^`.
+    pub fn add_link(&mut self, link: &str) {
+        self.links.push(link.into());
+    }
+
+    /// References a stored receipt or statement, e.g. `documents/2021/kfc.jpg`.
+    pub fn attach_document(&mut self, path: &str) {
+        self.add_metadata("document", path);
+    }
 }
 
 impl From<Transaction> for String {
     fn from(transaction: Transaction) -> Self {
+        let metadata: String = transaction
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("  {}: \"{}\"\n", key, escape(value)))
+            .chain(
+                transaction
+                    .comments
+                    .iter()
+                    .map(|comment| format!("  ; {}\n", comment)),
+            )
+            .collect();
+        let labels: String = transaction
+            .tags
+            .iter()
+            .map(|tag| format!(" #{}", tag))
+            .chain(transaction.links.iter().map(|link| format!(" ^{}", link)))
+            .collect();
+        // A refund's negative amount credits the paying account, and total
+        // prices are unsigned.
+        let (paid, price) = match &transaction.total_price {
+            Some((total, currency)) => (
+                format!("{:.2} {}", -total, currency),
+                format!(" @@ {:.2} {}", total.abs(), currency),
+            ),
+            None => (
+                format!("{:.2} {}", -transaction.amount, transaction.currency),
+                String::new(),
+            ),
+        };
+        let postings = if transaction.splits.is_empty() {
+            format!(
+                "  {}        {:.2} {}{}\n",
+                transaction.to_account, transaction.amount, transaction.currency, price
+            )
+        } else {
+            split_postings(&transaction)
+        };
         format!(
-            "{} * \"{}\" \"{}\"\n  {}        -{:.2} {}\n  {}        {:.2} {}\n",
+            "{} {} \"{}\" \"{}\"{}\n{}  {}        {}\n{}",
             transaction.date,
-            transaction.payee,
-            transaction.narration,
+            if transaction.pending { '!' } else { '*' },
+            escape(&transaction.payee),
+            escape(&transaction.narration),
+            labels,
+            metadata,
             transaction.from_account,
-            transaction.amount,
-            transaction.currency,
-            transaction.to_account,
-            transaction.amount,
-            transaction.currency
+            paid,
+            postings
         )
     }
 }
 
+/// `value` as the inside of a beancount string, its `"` and `\` escaped.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The text of a payee written as `"Urbn Surf"`, or `value` as it is.
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(quoted) => unescape(quoted),
+        None => value.to_string(),
+    }
+}
+
+/// One posting per split. A converted total is shared out in proportion to
+/// the splits, the last one taking what rounding leaves, so the entry balances.
+fn split_postings(transaction: &Transaction) -> String {
+    let mut priced = 0.0;
+    let last = transaction.splits.len() - 1;
+    transaction
+        .splits
+        .iter()
+        .enumerate()
+        .map(|(i, (account, amount))| {
+            let price = match &transaction.total_price {
+                Some((total, currency)) => {
+                    let share = if i == last {
+                        total - priced
+                    } else {
+                        (total * amount / transaction.amount * 100.0).round() / 100.0
+                    };
+                    priced += share;
+                    format!(" @@ {:.2} {}", share.abs(), currency)
+                }
+                None => String::new(),
+            };
+            format!(
+                "  {}        {:.2} {}{}\n",
+                account, amount, transaction.currency, price
+            )
+        })
+        .collect()
+}
+
 pub struct BeancountParser {
     settings: Settings,
+    clock: Arc<dyn Clock>,
 }
 
 impl BeancountParser {
     pub fn new(settings: Settings) -> Self {
-        Self { settings }
+        Self {
+            settings,
+            clock: Arc::new(SystemClock),
+        }
     }
 
-    pub fn parse(&self, input: &str) -> Result<Transaction> {
-        if let Some(pairs) = TransactionParser::parse(Rule::transaction, input)?.next() {
-            let mut transaction = Transaction::default();
+    /// Resolves `today` and `yesterday` with `clock` instead of the system time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    #[tracing::instrument(name = "parser.parse", skip_all)]
+    pub fn parse(&self, input: &str) -> Result<Transaction, ParseError> {
+        let mut pairs = TransactionParser::parse(Rule::transaction, input)
+            .map_err(|e| ParseError::Syntax(Box::new(e)))?;
+        if let Some(pairs) = pairs.next() {
+            let today = self.settings.today_by(self.clock.as_ref());
+            let mut transaction = Transaction::dated(today.format("%Y-%m-%d").to_string());
+            let mut currency = None;
+            let mut from_alias = None;
+            let mut partner = None;
+            let mut price = None;
             for pair in pairs.into_inner() {
                 match pair.as_rule() {
-                    Rule::date => transaction.date = pair.as_str().into(),
-                    Rule::payee => transaction.payee = pair.as_str().trim_matches('@').into(),
+                    Rule::pending => transaction.pending = true,
+                    Rule::date => {
+                        transaction.date = match pair.as_str().to_lowercase().as_str() {
+                            "today" => today.format("%Y-%m-%d").to_string(),
+                            "yesterday" => today.pred().format("%Y-%m-%d").to_string(),
+                            date => date.into(),
+                        }
+                    }
+                    Rule::payee => transaction.payee = unquote(&pair.as_str()[1..]),
                     Rule::narration => transaction.narration = pair.as_str().into(),
-                    Rule::amount => transaction.amount = pair.as_str().parse::<f32>()?,
-                    Rule::currency => transaction.currency = pair.as_str().into(),
-                    Rule::from_account => {
-                        transaction.from_account = self.parse_account(pair.as_str())?
+                    Rule::amount => {
+                        let (amount, symbol_currency) = self.parse_amount(pair.as_str())?;
+                        transaction.amount = amount;
+                        currency = symbol_currency;
                     }
+                    Rule::currency => currency = Some(pair.as_str()),
+                    Rule::price => price = Some(self.parse_price(pair)?),
+                    Rule::from_account => from_alias = Some(pair.as_str()),
                     Rule::to_account => {
                         transaction.to_account = self.parse_account(pair.as_str())?
                     }
+                    Rule::splits => transaction.splits = self.parse_splits(pair)?,
+                    Rule::shared => partner = pair.into_inner().next().map(|p| p.as_str()),
+                    Rule::tag => transaction.add_tag(pair.as_str().trim_start_matches('#')),
+                    Rule::link => transaction.add_link(pair.as_str().trim_start_matches('^')),
+                    Rule::meta => {
+                        for meta_pair in pair.into_inner() {
+                            let mut inner = meta_pair.into_inner();
+                            if let (Some(key), Some(value)) = (inner.next(), inner.next()) {
+                                transaction
+                                    .add_metadata(key.as_str(), value.as_str().trim_matches('"'));
+                            }
+                        }
+                    }
+                    Rule::comment => {
+                        if let Some(text) = pair.into_inner().next() {
+                            let comment = text.as_str().trim();
+                            check_length("comment", comment, MAX_COMMENT_CHARS)?;
+                            transaction.comments.push(comment.into());
+                        }
+                    }
                     Rule::EOI => break,
                     _ => unreachable!("Unexpected rule {:?}", pair.as_rule()),
                 }
             }
+            // The grammar reads an uppercase alias such as `CBA` right before
+            // `>` as a currency, which it is not when an alias matches it.
+            if let (None, Some(code)) = (from_alias, currency) {
+                if self
+                    .settings
+                    .accounts
+                    .keys()
+                    .any(|alias| alias.eq_ignore_ascii_case(code))
+                {
+                    from_alias = Some(code);
+                    currency = None;
+                }
+            }
+            let from_alias = from_alias.or(self.settings.default_from_account.as_deref());
+            if transaction.payee.is_empty() {
+                transaction.payee = TRANSFER_PAYEE.into();
+            }
+            if let Some((account, _)) = transaction.splits.first() {
+                transaction.to_account = account.clone();
+                let split: f32 = transaction.splits.iter().map(|(_, amount)| amount).sum();
+                if (split * 100.0).round() != (transaction.amount * 100.0).round() {
+                    return Err(ParseError::UnbalancedSplit {
+                        total: transaction.amount,
+                        split,
+                    });
+                }
+            }
+            if let Some(partner) = partner {
+                self.share(&mut transaction, partner)?;
+            }
+            let mut transaction = self.complete(transaction, currency, from_alias)?;
+            if let Some((total, price_currency)) = price {
+                let price_currency = price_currency
+                    .unwrap_or_else(|| self.settings.currency_of(&transaction.from_account));
+                transaction.total_price = Some((
+                    total.abs().copysign(transaction.amount),
+                    price_currency.into(),
+                ));
+            }
             return Ok(transaction);
         }
 
-        Err(anyhow!("Invalid input"))
+        Err(ParseError::Empty)
+    }
+
+    /// Builds a transaction from already separated fields, e.g. a row of a bank
+    /// statement, resolving aliases and defaults the same way as [`parse`].
+    ///
+    /// [`parse`]: BeancountParser::parse
+    pub fn from_fields(
+        &self,
+        date: &str,
+        payee: &str,
+        narration: &str,
+        amount: f32,
+        from_alias: Option<&str>,
+        to_alias: &str,
+    ) -> Result<Transaction, ParseError> {
+        let transaction = Transaction {
+            payee: payee.into(),
+            narration: narration.into(),
+            amount,
+            to_account: self.parse_account(to_alias)?,
+            ..Transaction::dated(date.into())
+        };
+        self.complete(
+            transaction,
+            None,
+            from_alias.or(self.settings.default_from_account.as_deref()),
+        )
+    }
+
+    fn complete(
+        &self,
+        mut transaction: Transaction,
+        currency: Option<&str>,
+        from_alias: Option<&str>,
+    ) -> Result<Transaction, ParseError> {
+        check_length("payee", &transaction.payee, MAX_PAYEE_CHARS)?;
+        check_length("narration", &transaction.narration, MAX_NARRATION_CHARS)?;
+        let from_alias = match from_alias {
+            Some(v) => v,
+            None => return Err(ParseError::NoFromAccount),
+        };
+        let from = self.resolve_account(from_alias)?;
+        transaction.from_account = from.account.clone();
+        if transaction.narration.is_empty() {
+            if let Some(narration) = self.settings.default_narration(&transaction.payee) {
+                transaction.narration = narration.into();
+            }
+        }
+        // explicit currency > currency of the paying account > settings currency
+        transaction.currency = currency
+            .map(String::from)
+            .or_else(|| from.currency.clone())
+            .unwrap_or_else(|| self.settings.currency.clone());
+        Ok(transaction)
+    }
+
+    fn parse_splits(
+        &self,
+        splits: pest::iterators::Pair<Rule>,
+    ) -> Result<Vec<(String, f32)>, ParseError> {
+        splits
+            .into_inner()
+            .map(|split| {
+                let mut inner = split.into_inner();
+                let (alias, amount) = match (inner.next(), inner.next()) {
+                    (Some(alias), Some(amount)) => (alias.as_str(), amount.as_str()),
+                    _ => unreachable!("split without account or amount"),
+                };
+                let (amount, _) = self.parse_amount(amount)?;
+                Ok((self.parse_account(alias)?, amount))
+            })
+            .collect()
+    }
+
+    /// The total of an `@ 30.5 AUD` price and its currency, the paying account's
+    /// when left out.
+    fn parse_price<'i>(
+        &'i self,
+        price: pest::iterators::Pair<'i, Rule>,
+    ) -> Result<(f32, Option<&'i str>), ParseError> {
+        let mut inner = price.into_inner();
+        let (total, symbol_currency) = match inner.next() {
+            Some(amount) => self.parse_amount(amount.as_str())?,
+            None => unreachable!("price without amount"),
+        };
+        Ok((
+            total,
+            inner
+                .next()
+                .map(|currency| currency.as_str())
+                .or(symbol_currency),
+        ))
+    }
+
+    /// An amount as pasted from a banking app, e.g. `-$1,234.56`, and the
+    /// currency its symbol stands for, see `currency_symbols`.
+    fn parse_amount<'a>(&'a self, text: &str) -> Result<(f32, Option<&'a str>), ParseError> {
+        let (sign, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", text),
+        };
+        let (currency, digits) = match unsigned.chars().next() {
+            Some(symbol) if !symbol.is_ascii_digit() => {
+                let currency = self
+                    .settings
+                    .currency_symbols
+                    .get(&symbol.to_string())
+                    .ok_or_else(|| ParseError::UnknownCurrencySymbol(symbol.into()))?;
+                (Some(currency.as_str()), &unsigned[symbol.len_utf8()..])
+            }
+            _ => (None, unsigned),
+        };
+        let amount = format!("{}{}", sign, digits.replace(',', ""))
+            .parse::<f32>()
+            .map_err(|_| ParseError::InvalidAmount(text.into()))?;
+        Ok((amount, currency))
+    }
+
+    /// Halves what `to_account`, or each split, gets; the other halves go to the
+    /// account `partner` owes through, see `split_accounts`. An odd cent stays
+    /// with the expense.
+    fn share(&self, transaction: &mut Transaction, partner: &str) -> Result<(), ParseError> {
+        let receivable = match self.settings.split_accounts.get(partner) {
+            Some(alias) => self.parse_account(alias)?,
+            None => return Err(ParseError::UnknownPartner(partner.into())),
+        };
+        let mut splits = if transaction.splits.is_empty() {
+            vec![(transaction.to_account.clone(), transaction.amount)]
+        } else {
+            std::mem::take(&mut transaction.splits)
+        };
+        for (_, amount) in splits.iter_mut() {
+            *amount = (*amount * 50.0).round() / 100.0;
+        }
+        let yours: f32 = splits.iter().map(|(_, amount)| amount).sum();
+        splits.push((receivable, transaction.amount - yours));
+        transaction.splits = splits;
+        Ok(())
+    }
+
+    fn parse_account(&self, matched: &str) -> Result<String, ParseError> {
+        Ok(self.resolve_account(matched)?.account.clone())
     }
 
-    fn parse_account(&self, matched: &str) -> Result<String> {
-        match self.settings.accounts.get(matched) {
-            Some(account) => Ok(account.to_string()),
-            None => Err(anyhow!(format!(
-                "account {} doesn't exist in current setting",
-                matched
-            ))),
+    /// Resolves an alias, or the only one it's a typo of: one edit away, a
+    /// different case or the start of it. Aliases two edits away are suggested.
+    fn resolve_account(&self, matched: &str) -> Result<&AccountSettings, ParseError> {
+        if let Some(entry) = self.settings.accounts.get(matched) {
+            return Ok(entry);
+        }
+        let typed = matched.to_lowercase();
+        let mut near: Vec<(usize, &String)> = self
+            .settings
+            .accounts
+            .keys()
+            .filter_map(|alias| {
+                let alias_lower = alias.to_lowercase();
+                let distance = if typed.len() >= 2 && alias_lower.starts_with(&typed) {
+                    1
+                } else {
+                    edit_distance(&typed, &alias_lower)
+                };
+                (distance <= 2).then_some((distance, alias))
+            })
+            .collect();
+        near.sort();
+        match near.as_slice() {
+            [(distance, alias)] | [(distance, alias), (2, _), ..] if *distance <= 1 => self
+                .settings
+                .accounts
+                .get(*alias)
+                .ok_or_else(|| ParseError::UnknownAccount {
+                    alias: matched.into(),
+                    suggestions: Vec::new(),
+                }),
+            _ => Err(ParseError::UnknownAccount {
+                alias: matched.into(),
+                suggestions: near.iter().map(|(_, alias)| alias.to_string()).collect(),
+            }),
         }
     }
 }
@@ -137,6 +728,37 @@ mod tests {
         assert_eq!("2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:MasterCard:CBA        -12.40 AUD\n  Expense:Food        12.40 AUD\n", actual_text);
     }
 
+    #[test]
+    fn transaction_renders_its_ledger_path() {
+        let transaction = create_parser()
+            .parse("2021-09-08 @KFC 12.40 cba > food")
+            .unwrap();
+        assert_eq!(transaction.ledger_path(None), "2021.bean");
+        assert_eq!(
+            transaction.ledger_path(Some("ledger/{year}/{month}.bean")),
+            "ledger/2021/09.bean"
+        );
+        assert_eq!(
+            transaction.ledger_path(Some("{account}/{year}.bean")),
+            "Assets/MasterCard/CBA/2021.bean"
+        );
+    }
+
+    #[test]
+    fn transaction_renders_its_commit_message() {
+        let transaction = create_parser()
+            .parse("2021-09-08 @KFC hamburger 12.4 cba > food")
+            .unwrap();
+        assert_eq!(
+            transaction.commit_message(None),
+            "2021-09-08 KFC 12.40 AUD (cba→food)"
+        );
+        assert_eq!(
+            transaction.commit_message(Some("{payee}: {narration} to {to_account}")),
+            "KFC: hamburger to Expense:Food"
+        );
+    }
+
     #[test]
     fn parser_can_parse_standard_input_with_multi_space_in_between() {
         let parser = create_parser();
@@ -260,6 +882,192 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn transaction_renders_attached_document_as_metadata() {
+        let parser = create_parser();
+        let mut transaction = parser
+            .parse("2021-09-08 @KFC hamburger 12.40 AUD cba > food")
+            .unwrap();
+        transaction.attach_document("documents/2021/kfc.jpg");
+        let actual_text: String = transaction.into();
+        assert_eq!("2021-09-08 * \"KFC\" \"hamburger\"\n  document: \"documents/2021/kfc.jpg\"\n  Assets:MasterCard:CBA        -12.40 AUD\n  Expense:Food        12.40 AUD\n", actual_text);
+    }
+
+    #[test]
+    fn parser_uses_default_from_account_and_currency_from_settings() {
+        let mut settings = create_parser().settings;
+        settings.currency = "USD".into();
+        settings.default_from_account = Some("amex".into());
+        let parser = BeancountParser::new(settings);
+
+        for input in ["@KFC hamburger 12.40 food", "@KFC hamburger 12.40 > food"] {
+            let transaction = parser.parse(input).unwrap();
+            assert_eq!(transaction.currency, "USD");
+            assert_eq!(
+                transaction.from_account,
+                "Liabilities:CreditCard:AMEX:Liang"
+            );
+            assert_eq!(transaction.to_account, "Expense:Food");
+        }
+
+        let transaction = parser.parse("@KFC 12.40 cba > food").unwrap();
+        assert_eq!(transaction.from_account, "Assets:MasterCard:CBA");
+    }
+
+    #[test]
+    fn transaction_is_marked_with_its_message() {
+        let parser = create_parser();
+        let mut transaction = parser.parse("2021-09-08 @KFC 12.40 cba > food").unwrap();
+        assert_eq!(transaction.message_marker(), None);
+        transaction.set_message(247673932, 276);
+        assert_eq!(
+            transaction.message_marker().unwrap(),
+            "  telegram_message: \"247673932/276\""
+        );
+        assert!(String::from(transaction).contains("\n  telegram_message: \"247673932/276\"\n"));
+    }
+
+    #[test]
+    fn parser_reads_thousands_separators_and_currency_symbols() {
+        let mut settings = create_parser().settings;
+        settings.currency_symbols = [("$".into(), "AUD".into()), ("¥".into(), "CNY".into())]
+            .iter()
+            .cloned()
+            .collect();
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser.parse("@Apple 1,234.56 cba > food").unwrap();
+        assert_eq!(transaction.amount, 1234.56);
+        assert_eq!(transaction.currency, "AUD");
+
+        let transaction = parser.parse("@麦当劳 ¥45 cba > food").unwrap();
+        assert_eq!(transaction.amount, 45.0);
+        assert_eq!(transaction.currency, "CNY");
+
+        let transaction = parser.parse("@Amazon refund -$35 USD cba > food").unwrap();
+        assert_eq!(transaction.amount, -35.0);
+        assert_eq!(transaction.currency, "USD");
+
+        assert!(matches!(
+            parser.parse("@Tesco £12 cba > food"),
+            Err(ParseError::UnknownCurrencySymbol(symbol)) if symbol == "£"
+        ));
+        assert!(parser.parse("@Apple 1,23 cba > food").is_err());
+    }
+
+    #[test]
+    fn parser_reads_total_price() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse("2021-09-08 @Steam 20 USD @ 30.5 AUD cba > food")
+            .unwrap();
+        assert_eq!(transaction.total_price(), Some((30.5, "AUD")));
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Steam\" \"\"\n  Assets:MasterCard:CBA        -30.50 AUD\n  Expense:Food        20.00 USD @@ 30.50 AUD\n"
+        );
+
+        let transaction = parser
+            .parse("@Steam refund -20 USD @ 30.5 cba > food")
+            .unwrap();
+        assert_eq!(transaction.total_price(), Some((-30.5, "AUD")));
+    }
+
+    #[test]
+    fn converted_transactions_charge_the_account_currency() {
+        let parser = create_parser();
+        let mut transaction = parser.parse("2021-09-08 @Steam 20 USD cba > food").unwrap();
+        assert_eq!(
+            parser.settings.currency_of(transaction.from_account()),
+            "AUD"
+        );
+        transaction.convert(1.3579, "AUD");
+        transaction.add_metadata("fx_rate", "1.3579 AUD/USD");
+        assert_eq!(transaction.total_price(), Some((27.16, "AUD")));
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Steam\" \"\"\n  fx_rate: \"1.3579 AUD/USD\"\n  Assets:MasterCard:CBA        -27.16 AUD\n  Expense:Food        20.00 USD @@ 27.16 AUD\n"
+        );
+    }
+
+    #[test]
+    fn parser_uses_currency_of_paying_account() {
+        let mut settings = create_parser().settings;
+        settings.accounts.insert(
+            "wise".into(),
+            AccountSettings {
+                account: "Assets:Wise:USD".into(),
+                currency: Some("USD".into()),
+                account_type: Some("asset".into()),
+                emoji: None,
+            },
+        );
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser.parse("@Steam 20 wise > food").unwrap();
+        assert_eq!(transaction.currency, "USD");
+        let transaction = parser.parse("@Steam 20 EUR wise > food").unwrap();
+        assert_eq!(transaction.currency, "EUR");
+        let transaction = parser.parse("@Steam 20 cba > food").unwrap();
+        assert_eq!(transaction.currency, "AUD");
+    }
+
+    #[test]
+    fn parser_uses_default_narration_of_payee() {
+        let mut settings = create_parser().settings;
+        settings
+            .narrations
+            .insert("ptv".into(), "Myki top-up".into());
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser.parse("@PTV 20 cba > food").unwrap();
+        assert_eq!(transaction.narration, "Myki top-up");
+        let transaction = parser.parse("@PTV weekly pass 45 cba > food").unwrap();
+        assert_eq!(transaction.narration, "weekly pass");
+    }
+
+    #[test]
+    fn parser_resolves_relative_dates_in_configured_timezone() {
+        let mut settings = create_parser().settings;
+        settings.timezone = Some("Australia/Melbourne".into());
+        let today = settings.today();
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser.parse("@KFC 12 cba > food").unwrap();
+        assert_eq!(transaction.date, today.format("%Y-%m-%d").to_string());
+        let transaction = parser.parse("Yesterday @KFC 12 cba > food").unwrap();
+        assert_eq!(
+            transaction.date,
+            today.pred().format("%Y-%m-%d").to_string()
+        );
+    }
+
+    #[test]
+    fn parser_reads_today_from_its_clock() {
+        use crate::clock::FixedClock;
+        use chrono::{TimeZone, Utc};
+
+        let mut settings = create_parser().settings;
+        settings.timezone = Some("Australia/Melbourne".into());
+        let clock = FixedClock(Utc.ymd(2021, 12, 31).and_hms(14, 0, 0));
+        let parser = BeancountParser::new(settings).with_clock(Arc::new(clock));
+
+        assert_eq!(
+            parser.parse("@KFC 12 cba > food").unwrap().date,
+            "2022-01-01"
+        );
+        assert_eq!(
+            parser.parse("yesterday @KFC 12 cba > food").unwrap().date,
+            "2021-12-31"
+        );
+    }
+
+    #[test]
+    fn parser_return_error_without_from_account_or_default() {
+        let parser = create_parser();
+        assert!(parser.parse("@KFC hamburger 12.40 food").is_err());
+    }
+
     #[test]
     fn parser_can_parse_multi_words_narration() {
         let parser = create_parser();
@@ -274,4 +1082,380 @@ mod tests {
         assert_eq!(transaction.from_account, "Assets:MasterCard:CBA");
         assert_eq!(transaction.to_account, "Expense:Food");
     }
+
+    #[test]
+    fn parser_reads_transfers_without_payee() {
+        let mut settings = create_parser().settings;
+        settings
+            .narrations
+            .insert("transfer".into(), "between accounts".into());
+        let parser = BeancountParser::new(settings);
+        let transaction = parser.parse("2021-09-08 500 cba > amex #card").unwrap();
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Transfer\" \"between accounts\" #card\n  Assets:MasterCard:CBA        -500.00 AUD\n  Liabilities:CreditCard:AMEX:Liang        500.00 AUD\n"
+        );
+
+        let transaction = create_parser().parse("500 USD cba > amex").unwrap();
+        assert_eq!(transaction.payee, "Transfer");
+        assert_eq!(transaction.narration, "");
+        assert_eq!(transaction.currency, "USD");
+        assert!(create_parser().parse("lunch 12 cba > food").is_err());
+        assert!(create_parser().parse("500 cba amex").is_err());
+    }
+
+    #[test]
+    fn parser_can_parse_quoted_and_unicode_payees() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse("2021-09-08 @\"Urbn Surf\" lesson 80 cba > food")
+            .unwrap();
+        assert_eq!(transaction.payee, "Urbn Surf");
+        assert_eq!(transaction.narration, "lesson");
+
+        let transaction = parser.parse("2021-09-08 @麦当劳 12 cba > food").unwrap();
+        assert_eq!(transaction.payee, "麦当劳");
+
+        let transaction = parser
+            .parse(r#"2021-09-08 @"Joe's \"Diner\"" 12 cba > food"#)
+            .unwrap();
+        assert_eq!(transaction.payee, "Joe's \"Diner\"");
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Joe's \\\"Diner\\\"\" \"\"\n  Assets:MasterCard:CBA        -12.00 AUD\n  Expense:Food        12.00 AUD\n"
+        );
+        assert!(parser.parse("@\"\" 12 cba > food").is_err());
+    }
+
+    #[test]
+    fn parser_builds_transaction_from_fields() {
+        let parser = create_parser();
+        let transaction = parser
+            .from_fields(
+                "2021-09-08",
+                "Woolworths Metro",
+                "",
+                23.5,
+                Some("amex"),
+                "food",
+            )
+            .unwrap();
+        assert_eq!(transaction.payee, "Woolworths Metro");
+        assert_eq!(
+            transaction.from_account,
+            "Liabilities:CreditCard:AMEX:Liang"
+        );
+        assert_eq!(transaction.to_account, "Expense:Food");
+        assert_eq!(transaction.currency, "AUD");
+        assert!(parser
+            .from_fields("2021-09-08", "Coles", "", 4.0, None, "food")
+            .is_err());
+    }
+
+    #[test]
+    fn parser_splits_amount_across_accounts() {
+        let mut settings = create_parser().settings;
+        settings.accounts.insert(
+            "household".into(),
+            AccountSettings {
+                account: "Expense:Household".into(),
+                currency: None,
+                account_type: None,
+                emoji: None,
+            },
+        );
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser
+            .parse("2021-09-08 @Costco 80 cba > food 50, household 30")
+            .unwrap();
+        assert_eq!(transaction.to_account(), "Expense:Food");
+        assert_eq!(
+            transaction.splits(),
+            &[
+                ("Expense:Food".to_string(), 50.0),
+                ("Expense:Household".to_string(), 30.0)
+            ]
+        );
+        assert_eq!(
+            String::from(transaction.clone()),
+            "2021-09-08 * \"Costco\" \"\"\n  Assets:MasterCard:CBA        -80.00 AUD\n  Expense:Food        50.00 AUD\n  Expense:Household        30.00 AUD\n"
+        );
+
+        let mut converted = transaction;
+        converted.convert(1.5, "USD");
+        assert!(String::from(converted).ends_with(
+            "  Expense:Food        50.00 AUD @@ 75.00 USD\n  Expense:Household        30.00 AUD @@ 45.00 USD\n"
+        ));
+
+        assert!(matches!(
+            parser.parse("@Costco 80 cba > food 50, household 20"),
+            Err(ParseError::UnbalancedSplit { .. })
+        ));
+        assert!(matches!(
+            parser.parse("@Costco 80 cba > food 50, garden 30"),
+            Err(ParseError::UnknownAccount { alias, .. }) if alias == "garden"
+        ));
+    }
+
+    #[test]
+    fn parser_shares_amount_with_a_partner() {
+        let mut settings = create_parser().settings;
+        for (alias, account) in [
+            ("household", "Expense:Household"),
+            ("liang", "Assets:Receivable:Liang"),
+        ] {
+            settings
+                .accounts
+                .insert(alias.into(), account.to_string().into());
+        }
+        settings
+            .split_accounts
+            .insert("liang".into(), "liang".into());
+        let parser = BeancountParser::new(settings);
+
+        let transaction = parser
+            .parse("2021-09-08 @Woolies 60 cba > food split liang #groceries")
+            .unwrap();
+        assert_eq!(transaction.to_account(), "Expense:Food");
+        assert_eq!(transaction.tags(), &["groceries".to_string()]);
+        assert_eq!(
+            String::from(transaction),
+            "2021-09-08 * \"Woolies\" \"\" #groceries\n  Assets:MasterCard:CBA        -60.00 AUD\n  Expense:Food        30.00 AUD\n  Assets:Receivable:Liang        30.00 AUD\n"
+        );
+
+        let transaction = parser
+            .parse("@Costco 80 cba > food 50, household 30 split liang")
+            .unwrap();
+        assert_eq!(
+            transaction.splits(),
+            &[
+                ("Expense:Food".to_string(), 25.0),
+                ("Expense:Household".to_string(), 15.0),
+                ("Assets:Receivable:Liang".to_string(), 40.0)
+            ]
+        );
+        let transaction = parser
+            .parse("2021-09-08 @Bakery 5.01 cba > food split liang")
+            .unwrap();
+        assert!(String::from(transaction).ends_with(
+            "  Expense:Food        2.51 AUD\n  Assets:Receivable:Liang        2.50 AUD\n"
+        ));
+
+        assert!(matches!(
+            parser.parse("@Woolies 60 cba > food split sam"),
+            Err(ParseError::UnknownPartner(name)) if name == "sam"
+        ));
+    }
+
+    #[test]
+    fn parser_flags_pending_transactions() {
+        let parser = create_parser();
+        let transaction = parser.parse("! 2021-09-08 @Ikea 230 cba > food").unwrap();
+        assert!(transaction.pending());
+        assert!(String::from(transaction).starts_with("2021-09-08 ! \"Ikea\" \"\"\n"));
+        let transaction = parser.parse("!@Ikea 230 cba > food").unwrap();
+        assert!(transaction.pending());
+        assert!(!parser.parse("@Ikea 230 cba > food").unwrap().pending());
+    }
+
+    #[test]
+    fn parser_reads_metadata() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse("2024-03-02 @Chemist 24 cba > food #health meta: receipt=IMG_2024.jpg, note=\"repeat script\"")
+            .unwrap();
+        assert_eq!(
+            String::from(transaction),
+            "2024-03-02 * \"Chemist\" \"\" #health\n  receipt: \"IMG_2024.jpg\"\n  note: \"repeat script\"\n  Assets:MasterCard:CBA        -24.00 AUD\n  Expense:Food        24.00 AUD\n"
+        );
+        assert!(parser
+            .parse("@Chemist 24 cba > food meta: Receipt=x")
+            .is_err());
+        assert!(parser.parse("@Chemist 24 cba > food meta:").is_err());
+    }
+
+    #[test]
+    fn parser_reads_tags_and_links() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse("2024-03-02 @KFC lunch 12.4 cba > food #travel ^trip-2024")
+            .unwrap();
+        assert_eq!(transaction.tags(), &["travel".to_string()]);
+        assert_eq!(transaction.links(), &["trip-2024".to_string()]);
+        assert_eq!(
+            String::from(transaction),
+            "2024-03-02 * \"KFC\" \"lunch\" #travel ^trip-2024\n  Assets:MasterCard:CBA        -12.40 AUD\n  Expense:Food        12.40 AUD\n"
+        );
+
+        let transaction = parser
+            .parse("@KFC 12.4 cba > food ^trip-2024 #travel #work")
+            .unwrap();
+        assert_eq!(transaction.tags(), &["travel", "work"]);
+        assert!(parser.parse("@KFC 12.4 cba > food #").is_err());
+    }
+
+    #[test]
+    fn parser_reads_comments_at_the_end() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse(
+                "2024-03-02 @KFC lunch 48 cba > food #work // paid for team lunch, reimburse later",
+            )
+            .unwrap();
+        assert_eq!(transaction.tags(), &["work"]);
+        assert_eq!(
+            transaction.comments(),
+            &["paid for team lunch, reimburse later"]
+        );
+        assert_eq!(
+            String::from(transaction),
+            "2024-03-02 * \"KFC\" \"lunch\" #work\n  ; paid for team lunch, reimburse later\n  Assets:MasterCard:CBA        -48.00 AUD\n  Expense:Food        48.00 AUD\n"
+        );
+
+        let mut transaction = parser
+            .parse("@KFC 48 cba > food meta: note=x # first\n// second")
+            .unwrap();
+        transaction.set_message(247673932, 7);
+        assert_eq!(
+            String::from(transaction)
+                .lines()
+                .skip(1)
+                .take(4)
+                .collect::<Vec<_>>(),
+            vec![
+                "  note: \"x\"",
+                "  telegram_message: \"247673932/7\"",
+                "  ; first",
+                "  ; second"
+            ]
+        );
+        assert!(parser.parse("@KFC 48 cba > food //").is_err());
+        assert!(parser
+            .parse(&format!(
+                "@KFC 48 cba > food // {}",
+                "a".repeat(MAX_COMMENT_CHARS + 1)
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn parser_resolves_or_suggests_misspelt_aliases() {
+        let parser = create_parser();
+        for input in [
+            "@KFC 12.40 cbaa > food",
+            "@KFC 12.40 CBA > food",
+            "@KFC 12.40 cba > fo",
+        ] {
+            let transaction = parser.parse(input).unwrap();
+            assert_eq!(transaction.from_account, "Assets:MasterCard:CBA");
+            assert_eq!(transaction.to_account, "Expense:Food");
+        }
+
+        let mut settings = create_parser().settings;
+        settings.default_from_account = Some("cba".into());
+        // A currency no alias matches stays one.
+        let transaction = BeancountParser::new(settings.clone())
+            .parse("@KFC 12.40 USD > food")
+            .unwrap();
+        assert_eq!(transaction.from_account, "Assets:MasterCard:CBA");
+        assert_eq!(transaction.currency, "USD");
+
+        settings.accounts.insert(
+            "ing".into(),
+            AccountSettings {
+                account: "Assets:ING".into(),
+                currency: Some("USD".into()),
+                account_type: None,
+                emoji: None,
+            },
+        );
+        // The currency comes from the alias resolved, not the one typed.
+        let transaction = BeancountParser::new(settings.clone())
+            .parse("@KFC 12.40 ingg > food")
+            .unwrap();
+        assert_eq!(transaction.from_account, "Assets:ING");
+        assert_eq!(transaction.currency, "USD");
+
+        settings.accounts.insert(
+            "cbb".into(),
+            AccountSettings {
+                account: "Assets:CBB".into(),
+                currency: None,
+                account_type: None,
+                emoji: None,
+            },
+        );
+        let parser = BeancountParser::new(settings);
+        assert_eq!(
+            parser
+                .parse("@KFC 12.40 cbx > food")
+                .unwrap_err()
+                .to_string(),
+            "account cbx doesn't exist in current setting, did you mean `cba` or `cbb`?"
+        );
+        assert!(matches!(
+            parser.parse("@KFC 12.40 cash > food"),
+            Err(ParseError::UnknownAccount { alias, suggestions })
+                if alias == "cash" && suggestions.is_empty()
+        ));
+    }
+
+    #[test]
+    fn parser_books_refunds_with_negative_amounts() {
+        let parser = create_parser();
+        let transaction = parser
+            .parse("2021-09-08 @Amazon refund -35 cba > food")
+            .unwrap();
+        assert_eq!(transaction.amount(), -35.0);
+        assert_eq!(
+            String::from(transaction.clone()),
+            "2021-09-08 * \"Amazon\" \"refund\"\n  Assets:MasterCard:CBA        35.00 AUD\n  Expense:Food        -35.00 AUD\n"
+        );
+
+        let mut converted = transaction;
+        converted.convert(1.5, "USD");
+        assert!(String::from(converted).ends_with(
+            "  Assets:MasterCard:CBA        52.50 USD\n  Expense:Food        -35.00 AUD @@ 52.50 USD\n"
+        ));
+        assert!(parser.parse("@Amazon refund - 35 cba > food").is_err());
+    }
+
+    #[test]
+    fn parser_reports_error_kind() {
+        let parser = create_parser();
+        assert!(matches!(
+            parser.parse("@KFC hamburger 12.40 cba > drinks"),
+            Err(ParseError::UnknownAccount { alias, .. }) if alias == "drinks"
+        ));
+        assert!(matches!(
+            parser.parse("KFC hamburger 12.40 cba > food"),
+            Err(ParseError::Syntax(_))
+        ));
+        assert!(matches!(
+            parser.parse("@KFC hamburger 12.40 food"),
+            Err(ParseError::NoFromAccount)
+        ));
+    }
+
+    #[test]
+    fn parser_caps_payee_and_narration_length() {
+        let parser = create_parser();
+        let input = format!(
+            "@{} hamburger 12.40 cba > food",
+            "K".repeat(MAX_PAYEE_CHARS + 1)
+        );
+        assert_eq!(
+            parser.parse(&input).unwrap_err().to_string(),
+            "payee is longer than 64 characters"
+        );
+        let narration = "a".repeat(MAX_NARRATION_CHARS + 1);
+        assert!(matches!(
+            parser.from_fields("2021-09-08", "KFC", &narration, 12.4, Some("cba"), "food"),
+            Err(ParseError::TooLong {
+                field: "narration",
+                ..
+            })
+        ));
+    }
 }
@@ -0,0 +1,322 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+
+use crate::ledger::{Directive, Entry, Ledger};
+use crate::report::Period;
+use crate::settings::covers;
+
+/// How a field compares with the value of a filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Comparison::Less => ordering == Less,
+            Comparison::LessOrEqual => ordering != Greater,
+            Comparison::Equal => ordering == Equal,
+            Comparison::GreaterOrEqual => ordering != Less,
+            Comparison::Greater => ordering == Greater,
+        }
+    }
+}
+
+/// One term of a [`Query`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// A posting to the account or one under it, `account:Expenses:Food`.
+    Account(String),
+    /// Part of the payee, `payee:kfc`.
+    Payee(String),
+    /// Part of the narration, `narration:lunch`.
+    Narration(String),
+    /// Part of the payee or the narration, a bare word.
+    Text(String),
+    /// `tag:trip` or `#trip`.
+    Tag(String),
+    /// `link:invoice-42` or `^invoice-42`.
+    Link(String),
+    /// A posting in the currency, `currency:USD`.
+    Currency(String),
+    /// The transaction date against a period, `date>=2024-01` or
+    /// `date=2024-01-01..2024-03-31`. `>=` compares with its first day, `<=` its
+    /// last, `=` is anywhere in it.
+    Date(Comparison, Period),
+    /// What the transaction moved, see [`Directive::moved`], `amount>100`.
+    Amount(Comparison, f64),
+    /// Any term prefixed with `-`, e.g. `-tag:reimbursed`.
+    Not(Box<Filter>),
+}
+
+/// A bean-query-like filter over transactions: terms separated by spaces, all of
+/// which must match, e.g. `account:Expenses:Food date>=2024-01 amount>100`. Text
+/// is compared case-insensitively; an empty query matches every transaction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub filters: Vec<Filter>,
+    text: String,
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Whether `entry` is a transaction matching every filter.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        matches!(entry.directive, Directive::Transaction { .. })
+            && self.filters.iter().all(|filter| filter.matches(entry))
+    }
+
+    /// The matching transactions of `ledger`, in date order.
+    pub fn filter<'a>(&'a self, ledger: &'a Ledger) -> impl Iterator<Item = &'a Entry> + 'a {
+        ledger
+            .entries
+            .iter()
+            .filter(move |entry| self.matches(entry))
+    }
+
+    /// The dates the query is limited to, for loading only the ledger files
+    /// that can match: the first and last day allowed by its `date` filters.
+    pub fn bounds(&self) -> (Option<NaiveDate>, Option<NaiveDate>) {
+        let (mut from, mut to): (Option<NaiveDate>, Option<NaiveDate>) = (None, None);
+        for filter in self.filters.iter() {
+            let (lower, upper) = match filter {
+                Filter::Date(Comparison::Less, period) => (None, period.from.pred_opt()),
+                Filter::Date(Comparison::LessOrEqual, period) => (None, Some(period.to)),
+                Filter::Date(Comparison::Equal, period) => (Some(period.from), Some(period.to)),
+                Filter::Date(Comparison::GreaterOrEqual, period) => (Some(period.from), None),
+                Filter::Date(Comparison::Greater, period) => (period.to.succ_opt(), None),
+                _ => (None, None),
+            };
+            if let Some(lower) = lower {
+                from = Some(from.map_or(lower, |from| from.max(lower)));
+            }
+            if let Some(upper) = upper {
+                to = Some(to.map_or(upper, |to| to.min(upper)));
+            }
+        }
+        (from, to)
+    }
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        let (payee, narration, tags, links, postings) = match &entry.directive {
+            Directive::Transaction {
+                payee,
+                narration,
+                tags,
+                links,
+                postings,
+                ..
+            } => (
+                payee.as_deref().unwrap_or(""),
+                narration,
+                tags,
+                links,
+                postings,
+            ),
+            _ => return false,
+        };
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(part);
+        match self {
+            Filter::Account(account) => postings
+                .iter()
+                .any(|posting| covers(&account.to_lowercase(), &posting.account.to_lowercase())),
+            Filter::Payee(part) => contains(payee, part),
+            Filter::Narration(part) => contains(narration, part),
+            Filter::Text(part) => contains(payee, part) || contains(narration, part),
+            Filter::Tag(tag) => tags.iter().any(|t| t.to_lowercase() == *tag),
+            Filter::Link(link) => links.iter().any(|l| l.to_lowercase() == *link),
+            Filter::Currency(currency) => postings.iter().any(|posting| {
+                posting
+                    .amount
+                    .as_ref()
+                    .is_some_and(|amount| amount.currency.eq_ignore_ascii_case(currency))
+            }),
+            Filter::Date(Comparison::Equal, period) => {
+                period.from <= entry.date && entry.date <= period.to
+            }
+            Filter::Date(comparison, period) => {
+                let bound = match comparison {
+                    Comparison::LessOrEqual | Comparison::Greater => period.to,
+                    _ => period.from,
+                };
+                comparison.holds(entry.date.cmp(&bound))
+            }
+            Filter::Amount(comparison, value) => entry.directive.moved().is_some_and(|moved| {
+                moved
+                    .number
+                    .partial_cmp(value)
+                    .is_some_and(|ordering| comparison.holds(ordering))
+            }),
+            Filter::Not(filter) => !filter.matches(entry),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(term: &str) -> Result<Self> {
+        if let Some(term) = term.strip_prefix('-').filter(|t| !t.is_empty()) {
+            return Ok(Filter::Not(Box::new(term.parse()?)));
+        }
+        if let Some(tag) = term.strip_prefix('#') {
+            return Ok(Filter::Tag(tag.to_lowercase()));
+        }
+        if let Some(link) = term.strip_prefix('^') {
+            return Ok(Filter::Link(link.to_lowercase()));
+        }
+        for (operator, comparison) in [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+            ("=", Comparison::Equal),
+        ] {
+            if let Some((field, value)) = term.split_once(operator) {
+                return match field {
+                    "date" => Ok(Filter::Date(comparison, value.parse()?)),
+                    "amount" => Ok(Filter::Amount(
+                        comparison,
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("{} isn't an amount", value))?,
+                    )),
+                    _ => Err(anyhow!(
+                        "only date and amount can be compared, not {}",
+                        field
+                    )),
+                };
+            }
+        }
+        let (field, value) = match term.split_once(':') {
+            Some(v) => v,
+            None => return Ok(Filter::Text(term.to_lowercase())),
+        };
+        let value = value.to_string();
+        match field {
+            "account" => Ok(Filter::Account(value)),
+            "payee" => Ok(Filter::Payee(value.to_lowercase())),
+            "narration" => Ok(Filter::Narration(value.to_lowercase())),
+            "tag" => Ok(Filter::Tag(value.to_lowercase())),
+            "link" => Ok(Filter::Link(value.to_lowercase())),
+            "currency" => Ok(Filter::Currency(value)),
+            "date" => Ok(Filter::Date(Comparison::Equal, value.parse()?)),
+            _ => Err(anyhow!(
+                "unknown field {}, use account, payee, narration, tag, link, currency, date or amount",
+                field
+            )),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Query {
+            filters: s
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_>>()?,
+            text: s.split_whitespace().collect::<Vec<_>>().join(" "),
+        })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDGER: &str = r#"
+2023-12-30 * "Coles" "groceries"
+  Assets:CBA                -140.00 AUD
+  Expenses:Food:Groceries
+
+2024-01-05 * "KFC" "lunch with Sam" #work
+  Assets:CBA                 -12.50 AUD
+  Expenses:Food:Takeaway
+
+2024-01-20 * "Harvey Norman" "tv" ^invoice-42
+  Liabilities:AMEX          -999.00 AUD
+  Expenses:Home
+
+2024-02-02 * "Woolworths" "groceries"
+  Assets:CBA                -150.00 AUD
+  Expenses:Food:Groceries
+
+2024-02-10 * "Steam" "game" #reimbursed
+  Assets:Wise               -20.00 USD
+  Expenses:Food:Fun
+"#;
+
+    fn payees(query: &str) -> Vec<String> {
+        let ledger = Ledger::parse("2024.bean", LEDGER);
+        let query: Query = query.parse().unwrap();
+        query
+            .filter(&ledger)
+            .map(|entry| match &entry.directive {
+                Directive::Transaction { payee, .. } => payee.clone().unwrap_or_default(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_filters_transactions() {
+        assert_eq!(
+            payees("account:Expenses:Food date>=2024-01 amount>100"),
+            vec!["Woolworths"]
+        );
+        assert_eq!(payees("account:expenses:food date=2024-01"), vec!["KFC"]);
+        assert_eq!(payees("date<2024-01"), vec!["Coles"]);
+        assert_eq!(payees("date>2024-01 -#reimbursed"), vec!["Woolworths"]);
+        assert_eq!(payees("groceries amount<=140"), vec!["Coles"]);
+        assert_eq!(payees("#WORK"), vec!["KFC"]);
+        assert_eq!(payees("^invoice-42 payee:harvey"), vec!["Harvey Norman"]);
+        assert_eq!(payees("currency:usd"), vec!["Steam"]);
+        assert_eq!(payees("account:Expenses:Fo").len(), 0);
+        assert_eq!(payees("").len(), 5);
+    }
+
+    #[test]
+    fn it_rejects_malformed_terms_and_knows_its_dates() {
+        assert!("amount>lots".parse::<Query>().is_err());
+        assert!("payee>kfc".parse::<Query>().is_err());
+        assert!("memo:kfc".parse::<Query>().is_err());
+        assert!("date>=soon".parse::<Query>().is_err());
+
+        let query: Query = "date>=2023-11 date<2024-03 date<=2024-06".parse().unwrap();
+        assert_eq!(
+            query.bounds(),
+            (
+                NaiveDate::from_ymd_opt(2023, 11, 1),
+                NaiveDate::from_ymd_opt(2024, 2, 29)
+            )
+        );
+        assert_eq!(
+            query.to_string(),
+            "date>=2023-11 date<2024-03 date<=2024-06"
+        );
+        assert_eq!(Query::default().bounds(), (None, None));
+    }
+}
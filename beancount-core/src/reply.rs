@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use crate::archive::{balances, split_entries};
+use crate::fava::{self, Fava};
+use crate::parser::Transaction;
+use crate::settings::{Settings, Verbosity};
+
+/// Text of a confirmation message and the Telegram `parse_mode` it needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub parse_mode: Option<String>,
+}
+
+impl Reply {
+    pub fn plain(text: String) -> Self {
+        Reply {
+            text,
+            parse_mode: None,
+        }
+    }
+
+    /// Monospaced, so columns line up.
+    pub fn code_block(text: &str) -> Self {
+        Reply {
+            text: format!(
+                "```\n{}\n```",
+                text.replace('\\', "\\\\").replace('`', "\\`")
+            ),
+            parse_mode: Some("MarkdownV2".into()),
+        }
+    }
+
+    /// Appends `lines`, such as links, on lines of their own, below a code
+    /// block if any.
+    pub fn with_lines(mut self, lines: &[String]) -> Self {
+        for line in lines {
+            self.text.push('\n');
+            if self.parse_mode.as_deref() == Some("MarkdownV2") {
+                self.text.push_str(&escape_markdown(line));
+            } else {
+                self.text.push_str(line);
+            }
+        }
+        self
+    }
+}
+
+/// Escapes the characters MarkdownV2 reserves outside of code.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Builds the confirmation for a saved transaction according to `settings.reply`.
+/// `entry` is the text written to the ledger and `month_to_date` the total spent
+/// on the account paid to, when it was looked up.
+pub fn format_reply(
+    settings: &Settings,
+    transaction: &Transaction,
+    entry: &str,
+    month_to_date: Option<f64>,
+) -> Reply {
+    let preferences = &settings.reply;
+    let mut text = match preferences.verbosity {
+        Verbosity::Full => entry.trim_end().to_string(),
+        Verbosity::Summary => {
+            let mut summary = format!(
+                "{:.2} {} {}",
+                transaction.amount(),
+                transaction.currency(),
+                transaction.payee()
+            );
+            if !transaction.narration().is_empty() {
+                summary.push_str(&format!(" · {}", transaction.narration()));
+            }
+            summary.push_str(&format!(" → {}", transaction.to_account()));
+            if preferences.emoji {
+                let emoji = settings
+                    .account_emoji(transaction.to_account())
+                    .unwrap_or("✅");
+                summary = format!("{} {}", emoji, summary);
+            }
+            summary
+        }
+    };
+    if let Some(total) = month_to_date {
+        text.push_str(&format!(
+            "\nMonth to date: {:.2} {} on {}",
+            total,
+            transaction.currency(),
+            transaction.to_account()
+        ));
+    }
+
+    let reply = if preferences.code_block {
+        Reply::code_block(&text)
+    } else {
+        Reply::plain(text)
+    };
+    match &settings.fava_url {
+        Some(url) => {
+            let fava = Fava::new(url);
+            let mut links = vec![fava.transaction(transaction.date(), transaction.payee())];
+            if let Some(month) = fava::month_of(transaction.date()) {
+                links.push(fava.account(transaction.to_account(), &month));
+            }
+            reply.with_lines(&links)
+        }
+        None => reply,
+    }
+}
+
+/// Sum of postings to `account` in `currency` dated in the month of `date`.
+pub fn month_to_date(content: &str, account: &str, currency: &str, date: &str) -> f64 {
+    month_totals(content, &date[..7.min(date.len())])
+        .get(&(account.to_string(), currency.to_string()))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Sums postings per (account, currency) over entries dated in `month`, e.g.
+/// `2021-09`.
+pub fn month_totals(content: &str, month: &str) -> BTreeMap<(String, String), f64> {
+    let (_, entries) = split_entries(content);
+    let texts: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.date.starts_with(month))
+        .map(|entry| entry.text)
+        .collect();
+    balances(texts.iter().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BeancountParser;
+    use crate::settings::{AccountSettings, ReplySettings};
+
+    fn settings(reply: ReplySettings) -> Settings {
+        Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account_settings(
+                "food",
+                AccountSettings {
+                    account: "Expenses:Food".into(),
+                    currency: None,
+                    account_type: None,
+                    emoji: Some("🍔".into()),
+                },
+            )
+            .reply(reply)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn it_formats_summary_with_emoji_and_month_to_date() {
+        let settings = settings(ReplySettings {
+            verbosity: Verbosity::Summary,
+            ..ReplySettings::default()
+        });
+        let transaction = BeancountParser::new(settings.clone())
+            .parse("2021-09-08 @KFC hamburger 12.40 cba > food")
+            .unwrap();
+        let reply = format_reply(&settings, &transaction, "", Some(30.5));
+        assert_eq!(
+            reply,
+            Reply::plain(
+                "🍔 12.40 AUD KFC · hamburger → Expenses:Food\nMonth to date: 30.50 AUD on Expenses:Food"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn it_wraps_full_entry_in_code_block() {
+        let settings = settings(ReplySettings {
+            code_block: true,
+            ..ReplySettings::default()
+        });
+        let transaction = BeancountParser::new(settings.clone())
+            .parse("2021-09-08 @KFC hamburger 12.40 cba > food")
+            .unwrap();
+        let reply = format_reply(
+            &settings,
+            &transaction,
+            "2021-09-08 * \"KFC\" \"`a`\"\n",
+            None,
+        );
+        assert_eq!(reply.text, "```\n2021-09-08 * \"KFC\" \"\\`a\\`\"\n```");
+        assert_eq!(reply.parse_mode.as_deref(), Some("MarkdownV2"));
+    }
+
+    #[test]
+    fn it_links_to_fava_below_the_code_block() {
+        let mut settings = settings(ReplySettings {
+            code_block: true,
+            ..ReplySettings::default()
+        });
+        settings.fava_url = Some("https://fava.example.com/ledger".into());
+        let transaction = BeancountParser::new(settings.clone())
+            .parse("2021-09-08 @KFC hamburger 12.40 cba > food")
+            .unwrap();
+        let reply = format_reply(&settings, &transaction, "2021-09-08 * \"KFC\"\n", None);
+        assert_eq!(
+            reply.text,
+            "```\n2021-09-08 * \"KFC\"\n```\n\
+             https://fava\\.example\\.com/ledger/journal/?time\\=2021\\-09\\-08&filter\\=%22KFC%22\n\
+             https://fava\\.example\\.com/ledger/account/Expenses:Food/?time\\=2021\\-09"
+        );
+    }
+
+    #[test]
+    fn it_sums_postings_of_the_month() {
+        let content = "2021-08-31 * \"KFC\" \"\"\n  Assets:CBA  -5.00 AUD\n  Expenses:Food  5.00 AUD\n2021-09-01 * \"KFC\" \"\"\n  Assets:CBA  -10.00 AUD\n  Expenses:Food  10.00 AUD\n2021-09-08 * \"KFC\" \"\"\n  Assets:CBA  -2.50 AUD\n  Expenses:Food\n";
+        assert_eq!(
+            month_to_date(content, "Expenses:Food", "AUD", "2021-09-08"),
+            12.5
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+
+use crate::ledger::{Directive, Ledger};
+
+/// The dates a report covers, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Period {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Period {
+    pub fn month(year: i32, month: u32) -> Option<Self> {
+        let from = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let next = match month {
+            12 => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+            _ => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+        };
+        Some(Period {
+            from,
+            to: next.pred_opt()?,
+        })
+    }
+
+    pub fn year(year: i32) -> Option<Self> {
+        Some(Period {
+            from: NaiveDate::from_ymd_opt(year, 1, 1)?,
+            to: NaiveDate::from_ymd_opt(year, 12, 31)?,
+        })
+    }
+
+    /// The month `date` is in.
+    pub fn month_of(date: NaiveDate) -> Self {
+        Self::month(date.year(), date.month()).unwrap()
+    }
+
+    /// The month before the one `date` is in.
+    pub fn month_before(date: NaiveDate) -> Self {
+        Self::month_of(Self::month_of(date).from.pred_opt().unwrap())
+    }
+
+    /// Years the period touches, for finding its ledger files.
+    pub fn years(&self) -> Vec<String> {
+        (self.from.year()..=self.to.year())
+            .map(|year| year.to_string())
+            .collect()
+    }
+
+    /// `YYYY-MM` of every month the period touches.
+    pub fn months(&self) -> Vec<String> {
+        let mut months = Vec::new();
+        let mut month = Self::month_of(self.from);
+        while month.from <= self.to {
+            months.push(month.from.format("%Y-%m").to_string());
+            month = Self::month_of(month.to.succ_opt().unwrap());
+        }
+        months
+    }
+
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+}
+
+/// `2021-09`, `2021`, or a range of months or dates such as `2021-01..2021-03`
+/// or `2021-09-01..2021-09-15`.
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "{} isn't a YYYY-MM month, a YYYY year or a FROM..TO range",
+                s
+            )
+        };
+        if let Some((from, to)) = s.split_once("..") {
+            let from: Period = from.parse().map_err(|_| invalid())?;
+            let to: Period = to.parse().map_err(|_| invalid())?;
+            if from.from > to.to {
+                return Err(anyhow!("{} ends before it starts", s));
+            }
+            return Ok(Period {
+                from: from.from,
+                to: to.to,
+            });
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Period {
+                from: date,
+                to: date,
+            });
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d") {
+            return Ok(Self::month_of(date));
+        }
+        match s.len() {
+            4 => s.parse().ok().and_then(Self::year).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if Some(*self) == Self::year(self.from.year()) {
+            write!(f, "{}", self.from.year())
+        } else if *self == Self::month_of(self.from) {
+            write!(f, "{}", self.from.format("%Y-%m"))
+        } else {
+            write!(f, "{}..{}", self.from, self.to)
+        }
+    }
+}
+
+/// How postings are added up into report rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    /// Every account on its own row.
+    Account,
+    /// The first two segments, e.g. `Expenses:Food` for
+    /// `Expenses:Food:Groceries`.
+    Category,
+}
+
+impl GroupBy {
+    fn group(&self, account: &str) -> String {
+        match self {
+            GroupBy::Account => account.into(),
+            GroupBy::Category => account.splitn(3, ':').take(2).collect::<Vec<_>>().join(":"),
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "account" => Ok(GroupBy::Account),
+            "category" => Ok(GroupBy::Category),
+            _ => Err(anyhow!("group by account or category, not {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportRow {
+    pub group: String,
+    pub currency: String,
+    /// Totals by `YYYY-MM`, only for months with postings.
+    pub months: BTreeMap<String, f64>,
+    pub total: f64,
+}
+
+/// Expense and income totals of a period, by account or category and month.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub period: String,
+    pub months: Vec<String>,
+    pub rows: Vec<ReportRow>,
+}
+
+/// Adds up the expense and income postings of `ledger` dated in `period`.
+pub fn report(ledger: &Ledger, period: &Period, group_by: GroupBy) -> Report {
+    let mut rows: BTreeMap<(String, String), ReportRow> = BTreeMap::new();
+    for entry in ledger.transactions().filter(|e| period.contains(e.date)) {
+        let postings = match &entry.directive {
+            Directive::Transaction { postings, .. } => postings,
+            _ => continue,
+        };
+        let month = entry.date.format("%Y-%m").to_string();
+        for posting in postings {
+            let amount = match &posting.amount {
+                Some(v) => v,
+                None => continue,
+            };
+            if !posting.account.starts_with("Expenses:") && !posting.account.starts_with("Income:")
+            {
+                continue;
+            }
+            let group = group_by.group(&posting.account);
+            let row = rows
+                .entry((group.clone(), amount.currency.clone()))
+                .or_insert_with(|| ReportRow {
+                    group,
+                    currency: amount.currency.clone(),
+                    months: BTreeMap::new(),
+                    total: 0.0,
+                });
+            *row.months.entry(month.clone()).or_insert(0.0) += amount.number;
+            row.total += amount.number;
+        }
+    }
+    Report {
+        period: period.to_string(),
+        months: period.months(),
+        rows: rows.into_values().collect(),
+    }
+}
+
+impl Report {
+    /// One row per line, with a column per month when the period spans several.
+    pub fn to_text(&self) -> String {
+        if self.rows.is_empty() {
+            return format!("No expenses or income in {}\n", self.period);
+        }
+        let lines: Vec<String> = match self.months.as_slice() {
+            [_] => self
+                .rows
+                .iter()
+                .map(|row| format!("{:<40} {:>10.2} {}", row.group, row.total, row.currency))
+                .collect(),
+            months => {
+                let header = format!(
+                    "{:<40} {} {:>10}",
+                    "",
+                    months
+                        .iter()
+                        .map(|month| format!("{:>10}", month))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    "Total"
+                );
+                let rows = self.rows.iter().map(|row| {
+                    format!(
+                        "{:<40} {} {:>10.2} {}",
+                        row.group,
+                        months
+                            .iter()
+                            .map(|month| format!(
+                                "{:>10.2}",
+                                row.months.get(month).copied().unwrap_or_default()
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        row.total,
+                        row.currency
+                    )
+                });
+                std::iter::once(header).chain(rows).collect()
+            }
+        };
+        format!("{}\n", lines.join("\n"))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDGER: &str = r#"
+2021-08-31 * "Coles" "groceries"
+  Assets:CBA                -40.00 AUD
+  Expenses:Food:Groceries    40.00 AUD
+
+2021-09-01 * "KFC" "lunch"
+  Assets:CBA                -12.50 AUD
+  Expenses:Food:Takeaway
+
+2021-09-15 * "ACME" "salary"
+  Income:Salary           -1000.00 AUD
+  Assets:CBA
+
+2021-10-02 * "Coles" "groceries"
+  Assets:CBA                -7.50 AUD
+  Expenses:Food:Groceries     7.50 AUD
+"#;
+
+    #[test]
+    fn it_parses_periods() {
+        let month: Period = "2021-09".parse().unwrap();
+        assert_eq!(month, Period::month(2021, 9).unwrap());
+        assert_eq!(month.to, NaiveDate::from_ymd_opt(2021, 9, 30).unwrap());
+        assert_eq!(month.to_string(), "2021-09");
+        assert_eq!("2021".parse::<Period>().unwrap().to_string(), "2021");
+        let range: Period = "2021-11..2022-01".parse().unwrap();
+        assert_eq!(range.months(), vec!["2021-11", "2021-12", "2022-01"]);
+        assert_eq!(range.years(), vec!["2021", "2022"]);
+        assert_eq!(range.to_string(), "2021-11-01..2022-01-31");
+        assert!("2021-10..2021-09".parse::<Period>().is_err());
+        assert!("last month".parse::<Period>().is_err());
+        assert_eq!(
+            Period::month_before(NaiveDate::from_ymd_opt(2022, 1, 15).unwrap()).to_string(),
+            "2021-12"
+        );
+    }
+
+    #[test]
+    fn it_reports_a_month_by_account() {
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let report = report(&ledger, &"2021-09".parse().unwrap(), GroupBy::Account);
+        assert_eq!(
+            report.to_text(),
+            format!(
+                "{:<40} {:>10.2} AUD\n{:<40} {:>10.2} AUD\n",
+                "Expenses:Food:Takeaway", 12.5, "Income:Salary", -1000.0
+            )
+        );
+        assert_eq!(
+            Report {
+                rows: Vec::new(),
+                ..report
+            }
+            .to_text(),
+            "No expenses or income in 2021-09\n"
+        );
+    }
+
+    #[test]
+    fn it_reports_categories_by_month() {
+        let ledger = Ledger::parse("2021.bean", LEDGER);
+        let report = report(
+            &ledger,
+            &"2021-08..2021-10".parse().unwrap(),
+            GroupBy::Category,
+        );
+        assert_eq!(report.rows[0].group, "Expenses:Food");
+        assert_eq!(report.rows[0].total, 60.0);
+        assert_eq!(report.rows[0].months["2021-09"], 12.5);
+        assert_eq!(
+            report.to_text().lines().nth(1).unwrap(),
+            format!(
+                "{:<40} {:>10.2} {:>10.2} {:>10.2} {:>10.2} AUD",
+                "Expenses:Food", 40.0, 12.5, 7.5, 60.0
+            )
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["period"], "2021-08-01..2021-10-31");
+        assert_eq!(json["rows"][1]["group"], "Income:Salary");
+        assert_eq!(json["rows"][1]["months"]["2021-09"], -1000.0);
+    }
+}
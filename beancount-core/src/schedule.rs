@@ -0,0 +1,153 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+
+/// A five-field cron expression, `minute hour day-of-month month day-of-week`,
+/// with `*`, numbers, lists, ranges and `/` steps. Day of week 0 and 7 are
+/// Sunday.
+///
+/// Recurring transactions are posted once on every day the expression matches,
+/// so only the day fields decide anything there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!(
+                "`{}` must have 5 fields: minute hour day-of-month month day-of-week",
+                s
+            ));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays.contains(&7) {
+            weekdays.retain(|d| *d != 7);
+            if !weekdays.contains(&0) {
+                weekdays.insert(0, 0);
+            }
+        }
+        Ok(Schedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+impl Schedule {
+    /// Whether the expression fires at some time on `date`. Like cron, when both
+    /// day of month and day of week are restricted either one matching is enough.
+    pub fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+        let day = self.days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// Whether the expression fires at `hour:minute`.
+    pub fn matches_time(&self, hour: u32, minute: u32) -> bool {
+        self.hours.contains(&hour) && self.minutes.contains(&minute)
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>> {
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse_number(step, 1, max)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => {
+                    (parse_number(start, min, max)?, parse_number(end, min, max)?)
+                }
+                None => {
+                    let start = parse_number(range, min, max)?;
+                    (start, if step > 1 { max } else { start })
+                }
+            },
+        };
+        if start > end {
+            return Err(anyhow!("`{}` is an empty range", part));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+fn parse_number(value: &str, min: u32, max: u32) -> Result<u32> {
+    match value.parse::<u32>() {
+        Ok(v) if (min..=max).contains(&v) => Ok(v),
+        _ => Err(anyhow!(
+            "`{}` must be a number from {} to {}",
+            value,
+            min,
+            max
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn it_matches_day_fields() {
+        let monthly: Schedule = "0 9 1 * *".parse().unwrap();
+        assert!(monthly.matches_date(date("2021-09-01")));
+        assert!(!monthly.matches_date(date("2021-09-02")));
+
+        let fridays_and_sundays: Schedule = "0 9 * * 5,7".parse().unwrap();
+        assert!(fridays_and_sundays.matches_date(date("2021-09-10")));
+        assert!(fridays_and_sundays.matches_date(date("2021-09-12")));
+        assert!(!fridays_and_sundays.matches_date(date("2021-09-11")));
+
+        let quarterly_or_mondays: Schedule = "*/15 8-17/3 15 1-12/3 1".parse().unwrap();
+        assert!(quarterly_or_mondays.matches_date(date("2021-04-15")));
+        assert!(quarterly_or_mondays.matches_date(date("2021-07-12")));
+        assert!(!quarterly_or_mondays.matches_date(date("2021-09-13")));
+        assert!(quarterly_or_mondays.matches_time(14, 45));
+        assert!(!quarterly_or_mondays.matches_time(15, 45));
+    }
+
+    #[test]
+    fn it_rejects_malformed_expressions() {
+        assert!("0 9 1 *".parse::<Schedule>().is_err());
+        assert!("0 9 32 * *".parse::<Schedule>().is_err());
+        assert!("0 9 5-1 * *".parse::<Schedule>().is_err());
+        assert!("0 9 * * mon".parse::<Schedule>().is_err());
+    }
+}
@@ -1,28 +1,1458 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    path::Path,
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Result};
-use config::{Config, File, FileFormat};
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+use chrono_tz::Tz;
+use config::{Config, Environment, File, FileFormat};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+use crate::clock::{Clock, SystemClock};
+use crate::schedule::Schedule;
+use crate::secret::Secret;
+use crate::{migration, validation};
+
+/// Env vars starting with `BEANCOUNT__` override config values, see
+/// [`Settings::parse`].
+const ENV_OVERRIDE_PREFIX: &str = "BEANCOUNT_";
+
+lazy_static! {
+    static ref PLACEHOLDER_RE: Regex =
+        Regex::new(r"\$\$|\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detects the format from a file extension, `None` if it isn't recognised.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(anyhow!("unknown config format {}", s)),
+        }
+    }
+}
+
+impl From<ConfigFormat> for FileFormat {
+    fn from(format: ConfigFormat) -> Self {
+        match format {
+            ConfigFormat::Toml => FileFormat::Toml,
+            ConfigFormat::Yaml => FileFormat::Yaml,
+            ConfigFormat::Json => FileFormat::Json,
+        }
+    }
+}
+
+/// What an account alias resolves to. In the config it's either just the account
+/// name or a table with extra details:
+///
+/// ```toml
+/// [accounts]
+/// cba = "Assets:MasterCard:CBA"
+/// ing = { account = "Assets:Bank:ING", currency = "AUD", type = "asset", emoji = "🏦" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "AccountEntry")]
+pub struct AccountSettings {
+    pub account: String,
+    /// Currency used when a message paid from this account doesn't name one.
+    pub currency: Option<String>,
+    pub account_type: Option<String>,
+    pub emoji: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AccountEntry {
+    Name(String),
+    Table {
+        account: String,
+        currency: Option<String>,
+        #[serde(rename = "type")]
+        account_type: Option<String>,
+        emoji: Option<String>,
+    },
+}
+
+impl From<AccountEntry> for AccountSettings {
+    fn from(entry: AccountEntry) -> Self {
+        match entry {
+            AccountEntry::Name(account) => account.into(),
+            AccountEntry::Table {
+                account,
+                currency,
+                account_type,
+                emoji,
+            } => AccountSettings {
+                account,
+                currency,
+                account_type,
+                emoji,
+            },
+        }
+    }
+}
+
+impl From<String> for AccountSettings {
+    fn from(account: String) -> Self {
+        AccountSettings {
+            account,
+            currency: None,
+            account_type: None,
+            emoji: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
+    /// Layout version of the config document, see [`migration::migrate`].
+    #[serde(default)]
+    pub version: i64,
     pub currency: String,
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountSettings>,
+    /// Written at the top of newly created ledger files, `{year}` is replaced
+    /// with the year of the file.
+    #[serde(default)]
+    pub file_header: Option<String>,
+    /// IANA time zone, e.g. `Australia/Melbourne`, that decides what "today" is.
+    /// The host's local time is used when unset, which is UTC on Vercel.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Alias used when a message doesn't name the account paid from.
+    #[serde(default)]
+    pub default_from_account: Option<String>,
+    /// Narration used for a payee when the message has none, keyed by payee
+    /// (case-insensitive).
+    #[serde(default)]
+    pub narrations: HashMap<String, String>,
+    /// Overrides keyed by Telegram user id, see [`Settings::for_user`].
+    #[serde(default)]
+    pub users: HashMap<String, UserSettings>,
+    /// Overrides keyed by Telegram chat id, see [`Settings::for_chat`].
+    #[serde(default)]
+    pub chats: HashMap<String, UserSettings>,
+    /// Telegram user ids allowed to run admin commands such as `/reload`.
+    #[serde(default)]
+    pub admins: Vec<u64>,
+    /// Monthly limits keyed by account prefix, e.g. `Expenses:Food`, or by
+    /// account alias, e.g. `food`, see [`Settings::budget_for`].
+    #[serde(default)]
+    pub budgets: HashMap<String, BudgetSettings>,
+    /// Path of the ledger file a transaction is appended to, `{year}` is replaced
+    /// with its year, `{month}` and `{account}` as in [`render_entry_path`].
+    /// Defaults to `{year}.bean`, `FILE_PATH_TEMPLATE` overrides it.
+    #[serde(default)]
+    pub ledger_path: Option<String>,
+    /// Message of the commit saving a transaction, with `{date}`, `{payee}`,
+    /// `{narration}`, `{amount}`, `{currency}`, `{from_account}` and
+    /// `{to_account}` replaced, and `{from}`/`{to}` with the last segment of
+    /// the account in lowercase. Defaults to [`DEFAULT_COMMIT_MESSAGE`].
+    #[serde(default)]
+    pub commit_message: Option<String>,
+    /// Lines at the end of the ledger file searched for an entry with the same
+    /// date, payee, amounts and accounts as a new transaction, which is then
+    /// only saved once confirmed. 0 turns the check off.
+    #[serde(default = "Settings::default_duplicate_lines")]
+    pub duplicate_lines: usize,
+    /// Named ledgers keyed by profile name, see [`Settings::for_profile`].
+    #[serde(default)]
+    pub profiles: HashMap<String, LedgerProfile>,
+    /// Profile applied by [`Settings::for_profile`], never read from config.
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    /// Transactions posted by the scheduler, see [`RecurringSettings`].
+    #[serde(default)]
+    pub recurring: Vec<RecurringSettings>,
+    /// Scheduled jobs, see [`JobSettings`].
+    #[serde(default)]
+    pub jobs: Vec<JobSettings>,
+    /// Checked in order, the first matching rule wins.
+    #[serde(default)]
+    pub merchant_rules: Vec<MerchantRule>,
+    /// How confirmations of saved transactions look, see [`ReplySettings`].
+    #[serde(default)]
+    pub reply: ReplySettings,
+    /// Ledger files whose `open` directives, and the files they include, add
+    /// accounts, see [`Settings::with_discovered_accounts`].
+    #[serde(default)]
+    pub discover_accounts: Vec<String>,
+    /// Where exchange rates come from, for messages in another currency than the
+    /// paying account's. Such messages are booked unconverted when unset.
+    #[serde(default)]
+    pub rates: Option<RateSettings>,
+    /// Commodities whose price the `prices` job commits, see [`PriceSettings`].
+    #[serde(default)]
+    pub prices: Option<PriceSettings>,
+    /// Base URL of a fava serving the ledger, e.g.
+    /// `https://fava.example.com/ledger`. Confirmations and reports link to it.
+    #[serde(default)]
+    pub fava_url: Option<String>,
+    /// How to read each bank's CSV statements, keyed by profile name, see
+    /// [`ImportProfile`].
+    #[serde(default)]
+    pub import_profiles: HashMap<String, ImportProfile>,
+    /// Transactions pushed by a bank's webhooks, see [`BankFeedSettings`].
+    #[serde(default)]
+    pub bank_feed: Option<BankFeedSettings>,
+    /// Shares of Splitwise expenses the `splitwise` job books, see
+    /// [`SplitwiseSettings`].
+    #[serde(default)]
+    pub splitwise: Option<SplitwiseSettings>,
+    /// The OCR API receipt photos are read with, see [`OcrSettings`].
+    #[serde(default)]
+    pub ocr: Option<OcrSettings>,
+    /// Aliases of the accounts people owe their half through, keyed by the name
+    /// after `split`, e.g. `liang = "liang"` for `@Woolies 60 cba > food split
+    /// liang`.
+    #[serde(default)]
+    pub split_accounts: HashMap<String, String>,
+    /// Currencies keyed by the symbol an amount may start with, e.g.
+    /// `"$" = "AUD"` for `@KFC $12.40 cba > food`.
+    #[serde(default)]
+    pub currency_symbols: HashMap<String, String>,
+}
+
+/// Books your share of Splitwise expenses, see [`crate::splitwise`].
+///
+/// ```toml
+/// [splitwise]
+/// account = "splitwise"              # e.g. Liabilities:Splitwise
+/// default_account = "uncategorized"
+/// categories = { "Dining out" = "food" }
+/// groups = [2761234]                 # every group by default
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SplitwiseSettings {
+    /// Alias of the account shares are booked from, whose balance follows what
+    /// you owe your friends on Splitwise once your own payments are booked to it.
+    pub account: String,
+    /// Account aliases keyed by Splitwise category name.
+    #[serde(default)]
+    pub categories: HashMap<String, String>,
+    /// Alias booked to when the category has no entry.
+    #[serde(default)]
+    pub default_account: Option<String>,
+    /// Group ids expenses are pulled from, every group when empty.
+    #[serde(default)]
+    pub groups: Vec<u64>,
+    /// How many days back each run looks for expenses.
+    #[serde(default = "SplitwiseSettings::default_days")]
+    pub days: u32,
+}
+
+impl SplitwiseSettings {
+    fn default_days() -> u32 {
+        30
+    }
+}
+
+/// Reads receipt photos sent to the bot, see [`crate::ocr`].
+///
+/// ```toml
+/// [ocr]
+/// url = "https://ocr.example.com/v1/text"
+/// api_key = "${OCR_API_KEY}"
+/// default_account = "uncategorized"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct OcrSettings {
+    /// Where photos are posted; it answers with the text it read as
+    /// `{ "text": "..." }`.
+    pub url: String,
+    /// Sent as a bearer token, when the API needs one.
+    #[serde(default)]
+    pub api_key: Option<Secret<String>>,
+    /// Alias booked to when no merchant rule matches the merchant.
+    #[serde(default)]
+    pub default_account: Option<String>,
+}
+
+/// Books transactions a bank pushes, see [`crate::bank_feed`].
+///
+/// ```toml
+/// [bank_feed]
+/// chat_id = 247673932
+/// default_account = "uncategorized"
+/// accounts = { "a1b2c3d4-0000" = "up" }
+/// categories = { "restaurants-and-cafes" = "food" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct BankFeedSettings {
+    /// Account aliases keyed by the bank's account id.
+    #[serde(default)]
     pub accounts: HashMap<String, String>,
+    /// Account aliases keyed by the bank's category id, for transactions no
+    /// merchant rule books.
+    #[serde(default)]
+    pub categories: HashMap<String, String>,
+    /// Alias booked to when neither a merchant rule nor a category does.
+    #[serde(default)]
+    pub default_account: Option<String>,
+    /// Saves transactions right away instead of asking in `chat_id` first.
+    #[serde(default)]
+    pub auto_commit: bool,
+    /// Chat asked to confirm each transaction, or told it was saved.
+    #[serde(default)]
+    pub chat_id: Option<u64>,
+}
+
+/// ```toml
+/// [rates]
+/// provider = "ecb"   # or "exchangerate_host", with an `access_key`, or "rba"
+/// record_prices = true
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateSettings {
+    pub provider: RateProvider,
+    pub access_key: Option<Secret<String>>,
+    /// Also append the rate a message was converted at to the `[prices]` file,
+    /// `prices.bean` by default, as a `price` directive.
+    #[serde(default)]
+    pub record_prices: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateProvider {
+    /// Euro reference rates of the European Central Bank, any date.
+    Ecb,
+    /// exchangerate.host, any date, needs an `access_key`.
+    ExchangerateHost,
+    /// The Reserve Bank of Australia's latest AUD rates.
+    Rba,
+}
+
+/// ```toml
+/// [prices]
+/// commodities = ["USD", "EUR"]
+/// file = "prices.bean"   # the default
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceSettings {
+    pub commodities: Vec<String>,
+    /// Where `price` directives are appended, include it from the ledger.
+    #[serde(default = "PriceSettings::default_file")]
+    pub file: String,
+    /// Currency the prices are quoted in, the settings currency when unset.
+    pub currency: Option<String>,
+}
+
+impl PriceSettings {
+    /// Where `price` directives go, when no `[prices]` section says.
+    pub const DEFAULT_FILE: &'static str = "prices.bean";
+
+    fn default_file() -> String {
+        Self::DEFAULT_FILE.into()
+    }
+}
+
+/// Monthly spending limit for the accounts under a prefix.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "BudgetEntry")]
+pub struct BudgetSettings {
+    pub limit: f64,
+    /// Currency of the limit, the settings currency when unset.
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BudgetEntry {
+    Limit(f64),
+    Table {
+        limit: f64,
+        currency: Option<String>,
+    },
+}
+
+impl From<BudgetEntry> for BudgetSettings {
+    fn from(entry: BudgetEntry) -> Self {
+        match entry {
+            BudgetEntry::Limit(limit) => BudgetSettings {
+                limit,
+                currency: None,
+            },
+            BudgetEntry::Table { limit, currency } => BudgetSettings { limit, currency },
+        }
+    }
+}
+
+/// A separate set of books a chat can switch to with `/ledger use <name>`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct LedgerProfile {
+    /// GitHub repository as `owner/name`, or `name` under `GITHUB_OWNER`.
+    pub repo: Option<String>,
+    /// Ledger path template, see [`Settings::ledger_path`].
+    pub path: Option<String>,
+    pub currency: Option<String>,
+}
+
+/// A transaction posted on every day its schedule matches, e.g. rent.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RecurringSettings {
+    pub name: String,
+    /// Cron expression, see [`crate::schedule::Schedule`].
+    pub schedule: String,
+    /// A message as it would be sent to the bot, without a date, e.g.
+    /// `@Landlord rent 2000 cba > rent`.
+    pub text: String,
+}
+
+/// Something run on a schedule, either by the built-in scheduler of the server
+/// from `schedule`, or by name from a platform cron (Vercel, EventBridge).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JobSettings {
+    pub name: String,
+    pub kind: JobKind,
+    /// Cron expression in the settings time zone, see
+    /// [`crate::schedule::Schedule`].
+    pub schedule: String,
+    /// Chat reminders and reports are sent to.
+    pub chat_id: Option<u64>,
+    /// Message of a reminder.
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Posts the `[[recurring]]` transactions due today.
+    Recurring,
+    /// Sends `text` to `chat_id`.
+    Reminder,
+    /// Sends last month's expense and income totals to `chat_id`.
+    MonthlyReport,
+    /// Sends the net worth as of today to `chat_id`.
+    NetWorth,
+    /// Sends the likely duplicates of this year's ledger to `chat_id`, if any.
+    Duplicates,
+    /// Commits today's `price` directives of the `[prices]` commodities, from
+    /// the `[rates]` provider.
+    Prices,
+    /// Books your shares of recent `[splitwise]` expenses.
+    Splitwise,
+}
+
+/// Maps raw merchant strings from bank imports and notifications to a payee,
+/// see [`crate::merchant::MerchantRules`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MerchantRule {
+    /// Regex matched case-insensitively against the raw merchant string.
+    pub pattern: String,
+    pub payee: String,
+    /// Alias of the account to book matching transactions to.
+    pub account: Option<String>,
+    pub narration: Option<String>,
+}
+
+/// Reads the CSV statements of one bank, see [`crate::import`].
+///
+/// ```toml
+/// [import_profiles.cba]
+/// account = "cba"
+/// date_format = "%d/%m/%Y"
+/// default_account = "uncategorized"
+/// columns = { date = "Date", description = "Description", amount = "Amount" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImportProfile {
+    /// Alias of the account the statement is of, `default_from_account` when
+    /// unset.
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub columns: ImportColumns,
+    /// chrono format of the date column. `YYYY-MM-DD` and `DD/MM/YYYY` are both
+    /// accepted when unset.
+    #[serde(default)]
+    pub date_format: Option<String>,
+    #[serde(default)]
+    pub sign: SignConvention,
+    /// Whether the first row names the columns. Without one, columns are
+    /// numbered from 1.
+    #[serde(default = "ImportProfile::default_has_headers")]
+    pub has_headers: bool,
+    #[serde(default = "ImportProfile::default_delimiter")]
+    pub delimiter: char,
+    /// Alias booked to when no merchant rule names an account. Such rows are
+    /// skipped when unset.
+    #[serde(default)]
+    pub default_account: Option<String>,
+    /// Checked before the top-level `merchant_rules`.
+    #[serde(default)]
+    pub merchant_rules: Vec<MerchantRule>,
+}
+
+impl Default for ImportProfile {
+    fn default() -> Self {
+        ImportProfile {
+            account: None,
+            columns: ImportColumns::default(),
+            date_format: None,
+            sign: SignConvention::default(),
+            has_headers: Self::default_has_headers(),
+            delimiter: Self::default_delimiter(),
+            default_account: None,
+            merchant_rules: Vec::new(),
+        }
+    }
+}
+
+impl ImportProfile {
+    fn default_has_headers() -> bool {
+        true
+    }
+
+    fn default_delimiter() -> char {
+        ','
+    }
+}
+
+/// Header names, or 1-based numbers without headers, of the statement columns.
+/// Either `amount` or `debit` and `credit` columns are read.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImportColumns {
+    #[serde(default = "ImportColumns::default_date")]
+    pub date: String,
+    #[serde(default = "ImportColumns::default_description")]
+    pub description: String,
+    #[serde(default)]
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub debit: Option<String>,
+    #[serde(default)]
+    pub credit: Option<String>,
+}
+
+impl Default for ImportColumns {
+    fn default() -> Self {
+        ImportColumns {
+            date: Self::default_date(),
+            description: Self::default_description(),
+            amount: None,
+            debit: None,
+            credit: None,
+        }
+    }
+}
+
+impl ImportColumns {
+    fn default_date() -> String {
+        "date".into()
+    }
+
+    fn default_description() -> String {
+        "description".into()
+    }
+
+    /// The `amount` column, `amount` unless split into `debit` and `credit`.
+    pub fn amount(&self) -> Option<&str> {
+        match (&self.amount, &self.debit, &self.credit) {
+            (Some(amount), _, _) => Some(amount),
+            (None, None, None) => Some("amount"),
+            _ => None,
+        }
+    }
+}
+
+/// How a single `amount` column tells money out from money in.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignConvention {
+    /// Money out is negative, like most bank exports.
+    #[default]
+    DebitsNegative,
+    /// Money out is positive, like most credit card exports.
+    DebitsPositive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// The beancount entry as written to the ledger.
+    Full,
+    /// One line with amount, payee, narration and the account paid to.
+    Summary,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplySettings {
+    #[serde(default = "ReplySettings::default_verbosity")]
+    pub verbosity: Verbosity,
+    /// Adds how much was spent this month on the account paid to.
+    #[serde(default)]
+    pub month_to_date: bool,
+    /// Sends the reply as a Telegram code block so columns line up.
+    #[serde(default)]
+    pub code_block: bool,
+    /// Prefixes summaries with the account's emoji, or ✅ when it has none.
+    #[serde(default = "ReplySettings::default_emoji")]
+    pub emoji: bool,
+}
+
+impl ReplySettings {
+    fn default_verbosity() -> Verbosity {
+        Verbosity::Full
+    }
+
+    fn default_emoji() -> bool {
+        true
+    }
+}
+
+impl Default for ReplySettings {
+    fn default() -> Self {
+        ReplySettings {
+            verbosity: Self::default_verbosity(),
+            month_to_date: false,
+            code_block: false,
+            emoji: Self::default_emoji(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserSettings {
+    pub currency: Option<String>,
+    pub default_from_account: Option<String>,
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountSettings>,
 }
 
 impl Settings {
+    fn default_duplicate_lines() -> usize {
+        DEFAULT_DUPLICATE_LINES
+    }
+
+    /// Reads the `CONFIG` env var, in TOML unless `CONFIG_FORMAT` says otherwise.
     pub fn load_from_env() -> Result<Self> {
-        let mut s = Config::default();
+        let (config, format) = Self::env_document()?;
+        Self::parse(&config, format)
+    }
+
+    /// The `CONFIG` env var and its format, unparsed, to layer other documents
+    /// over, see [`Settings::parse_layered`].
+    pub fn env_document() -> Result<(String, ConfigFormat)> {
         let config = match env::var("CONFIG") {
             Ok(v) => v,
             Err(_) => return Err(anyhow!("CONFIG env not set!")),
         };
+        let format = match env::var("CONFIG_FORMAT") {
+            Ok(v) => v.parse()?,
+            Err(_) => ConfigFormat::Toml,
+        };
+        Ok((config, format))
+    }
 
-        s.merge(File::from_str(config.as_str(), FileFormat::Toml))?;
-        s.try_into().map_err(|e| e.into())
+    pub fn from_toml(config: &str) -> Result<Self> {
+        Self::parse(config, ConfigFormat::Toml)
+    }
+
+    /// Deserializes and validates settings, see [`validation::validate`].
+    /// Placeholders are replaced first, see [`interpolate`].
+    ///
+    /// Values are layered, later ones winning: built-in defaults, the config
+    /// document, then `BEANCOUNT__<KEY>` env vars where `__` separates nested
+    /// keys, e.g. `BEANCOUNT__CURRENCY=USD` or `BEANCOUNT__ACCOUNTS__CBA=Assets:CBA`.
+    pub fn parse(config: &str, format: ConfigFormat) -> Result<Self> {
+        Self::parse_layered(&[(config, format)])
+    }
+
+    /// Like [`Settings::parse`] for several documents, each overriding the ones
+    /// before it, e.g. the `CONFIG` env var then the config file of the ledger
+    /// repository. Tables such as `[accounts]` are merged key by key.
+    pub fn parse_layered(documents: &[(&str, ConfigFormat)]) -> Result<Self> {
+        let interpolated = documents
+            .iter()
+            .map(|(config, format)| Ok((interpolate(config)?, *format)))
+            .collect::<Result<Vec<_>>>()?;
+        let documents: Vec<(&str, ConfigFormat)> = interpolated
+            .iter()
+            .map(|(config, format)| (config.as_str(), *format))
+            .collect();
+        Self::parse_layers(&documents, Some(ENV_OVERRIDE_PREFIX))
+    }
+
+    /// Like [`Settings::parse`] but without placeholders or env var overrides,
+    /// for documents that mustn't read the deployment's environment, such as a
+    /// tenant's config file.
+    pub fn parse_isolated(config: &str, format: ConfigFormat) -> Result<Self> {
+        Self::parse_layers(&[(config, format)], None)
+    }
+
+    fn parse_layers(documents: &[(&str, ConfigFormat)], env_prefix: Option<&str>) -> Result<Self> {
+        let mut s = Config::default();
+        // Each document is migrated on its own, so layers can be of different
+        // versions.
+        for (config, format) in documents {
+            let mut document = Config::default();
+            document.merge(File::from_str(config, (*format).into()))?;
+            migration::migrate(&mut document)?;
+            s.merge(document)?;
+        }
+        if let Some(env_prefix) = env_prefix {
+            s.merge(Environment::with_prefix(env_prefix).separator("__"))?;
+            if let Ok(template) = env::var(FILE_PATH_TEMPLATE_ENV) {
+                s.set("ledger_path", template)?;
+            }
+        }
+        let settings: Self = s.try_into()?;
+        validation::validate(&settings)?;
+        Ok(settings)
+    }
+
+    /// Starts settings in code rather than from a config document.
+    pub fn builder(currency: impl Into<String>) -> SettingsBuilder {
+        SettingsBuilder {
+            settings: Self::new(currency.into(), HashMap::new()),
+        }
     }
 
     pub fn new(currency: String, accounts: HashMap<String, String>) -> Self {
-        Self { currency, accounts }
+        Self {
+            version: migration::CONFIG_VERSION,
+            currency,
+            accounts: accounts
+                .into_iter()
+                .map(|(alias, account)| (alias, account.into()))
+                .collect(),
+            file_header: None,
+            timezone: None,
+            default_from_account: None,
+            narrations: HashMap::new(),
+            users: HashMap::new(),
+            chats: HashMap::new(),
+            admins: Vec::new(),
+            budgets: HashMap::new(),
+            ledger_path: None,
+            commit_message: None,
+            duplicate_lines: DEFAULT_DUPLICATE_LINES,
+            profiles: HashMap::new(),
+            active_profile: None,
+            recurring: Vec::new(),
+            jobs: Vec::new(),
+            merchant_rules: Vec::new(),
+            reply: ReplySettings::default(),
+            discover_accounts: Vec::new(),
+            rates: None,
+            prices: None,
+            fava_url: None,
+            import_profiles: HashMap::new(),
+            bank_feed: None,
+            splitwise: None,
+            ocr: None,
+            split_accounts: HashMap::new(),
+            currency_symbols: HashMap::new(),
+        }
+    }
+
+    /// The ledger file for transactions of `year`, when the path only depends on
+    /// the year; [`Settings::year_paths`] lists the files of the year otherwise.
+    pub fn ledger_path(&self, year: &str) -> String {
+        render_ledger_path(self.ledger_path.as_deref(), year)
+    }
+
+    /// Every file transactions of `year` can be in: one per month when the path
+    /// template has `{month}`, times one per configured account when it has
+    /// `{account}`.
+    pub fn year_paths(&self, year: &str) -> Vec<String> {
+        let template = self.ledger_path.as_deref().unwrap_or(DEFAULT_LEDGER_PATH);
+        let months: Vec<String> = if template.contains("{month}") {
+            (1..=12).map(|month| format!("{:02}", month)).collect()
+        } else {
+            vec![String::new()]
+        };
+        let mut accounts: Vec<&str> = if template.contains("{account}") {
+            self.accounts
+                .values()
+                .chain(self.users.values().flat_map(|user| user.accounts.values()))
+                .map(|entry| entry.account.as_str())
+                .collect()
+        } else {
+            vec![""]
+        };
+        accounts.sort_unstable();
+        accounts.dedup();
+        let mut paths = Vec::new();
+        for month in months.iter() {
+            for account in accounts.iter() {
+                paths.push(render_entry_path(Some(template), year, month, account));
+            }
+        }
+        paths
+    }
+
+    /// Switches to the ledger profile `name`: its currency and path replace the
+    /// top-level ones, and [`Settings::ledger_repo`] returns its repository.
+    pub fn for_profile(&self, name: &str) -> Result<Settings> {
+        let profile = match self.profiles.get(name) {
+            Some(v) => v,
+            None => return Err(anyhow!("ledger profile {} doesn't exist", name)),
+        };
+        let mut settings = self.clone();
+        if let Some(currency) = &profile.currency {
+            settings.currency = currency.clone();
+        }
+        if let Some(path) = &profile.path {
+            settings.ledger_path = Some(path.clone());
+        }
+        settings.active_profile = Some(name.into());
+        Ok(settings)
+    }
+
+    /// Repository of the active profile, if it names one.
+    pub fn ledger_repo(&self) -> Option<&str> {
+        self.active_profile
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+            .and_then(|profile| profile.repo.as_deref())
+    }
+
+    /// The budget with the longest prefix covering `account`, with that prefix.
+    pub fn budget_for(&self, account: &str) -> Option<(&str, &BudgetSettings)> {
+        self.budgets
+            .iter()
+            .map(|(key, budget)| (self.budget_prefix(key), budget))
+            .filter(|(prefix, _)| covers(prefix, account))
+            .max_by_key(|(prefix, _)| prefix.len())
+    }
+
+    /// The account prefix a `[budgets]` key stands for: the account of an
+    /// alias, or the key itself.
+    pub fn budget_prefix<'a>(&'a self, key: &'a str) -> &'a str {
+        match self.accounts.get(key) {
+            Some(entry) if !key.contains(':') => &entry.account,
+            _ => key,
+        }
+    }
+
+    /// Emoji configured for the alias of `account`, if any.
+    pub fn account_emoji(&self, account: &str) -> Option<&str> {
+        self.accounts
+            .values()
+            .find(|entry| entry.account == account)
+            .and_then(|entry| entry.emoji.as_deref())
+    }
+
+    pub fn is_admin(&self, user_id: u64) -> bool {
+        self.admins.contains(&user_id)
+    }
+
+    /// The current date in the configured time zone.
+    pub fn today(&self) -> NaiveDate {
+        self.today_by(&SystemClock)
+    }
+
+    /// The current wall-clock time in the configured time zone.
+    pub fn now(&self) -> NaiveDateTime {
+        self.now_by(&SystemClock)
+    }
+
+    /// Like [`Settings::today`], reading the time from `clock`.
+    pub fn today_by(&self, clock: &dyn Clock) -> NaiveDate {
+        self.now_by(clock).date()
+    }
+
+    /// Like [`Settings::now`], reading the time from `clock`.
+    pub fn now_by(&self, clock: &dyn Clock) -> NaiveDateTime {
+        let now = clock.now();
+        match self
+            .timezone
+            .as_deref()
+            .and_then(|tz| tz.parse::<Tz>().ok())
+        {
+            Some(tz) => now.with_timezone(&tz).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    /// Jobs whose schedule fires at `now`, to the minute.
+    pub fn due_jobs(&self, now: NaiveDateTime) -> Vec<&JobSettings> {
+        self.jobs
+            .iter()
+            .filter(|job| {
+                job.schedule.parse::<Schedule>().is_ok_and(|schedule| {
+                    schedule.matches_date(now.date())
+                        && schedule.matches_time(now.hour(), now.minute())
+                })
+            })
+            .collect()
+    }
+
+    pub fn job(&self, name: &str) -> Option<&JobSettings> {
+        self.jobs.iter().find(|job| job.name == name)
+    }
+
+    /// The currency `account` is kept in: the one its alias names, otherwise the
+    /// settings currency.
+    pub fn currency_of(&self, account: &str) -> &str {
+        self.accounts
+            .values()
+            .filter(|entry| entry.account == account)
+            .find_map(|entry| entry.currency.as_deref())
+            .unwrap_or(&self.currency)
+    }
+
+    pub fn default_narration(&self, payee: &str) -> Option<&str> {
+        let payee = payee.to_lowercase();
+        self.narrations
+            .iter()
+            .find(|(key, _)| key.to_lowercase() == payee)
+            .map(|(_, narration)| narration.as_str())
+    }
+
+    /// Adds aliases suggested from the ledger's `open` directives; aliases in the
+    /// config keep their account.
+    pub fn with_discovered_accounts(
+        mut self,
+        discovered: HashMap<String, AccountSettings>,
+    ) -> Self {
+        for (alias, entry) in discovered {
+            self.accounts.entry(alias).or_insert(entry);
+        }
+        self
+    }
+
+    /// Adds the aliases added from chat, see [`crate::aliases`]; aliases in the
+    /// config keep their account.
+    pub fn with_aliases(mut self, aliases: BTreeMap<String, String>) -> Self {
+        for (alias, account) in aliases {
+            self.accounts.entry(alias).or_insert_with(|| account.into());
+        }
+        self
+    }
+
+    /// Applies the `[users.<id>]` overrides of `user_id`; their aliases are added
+    /// to, and take precedence over, the shared ones.
+    pub fn for_user(&self, user_id: u64) -> Settings {
+        self.with_overrides(self.users.get(&user_id.to_string()))
+    }
+
+    /// Applies the `[chats.<id>]` overrides of `chat_id`, e.g. the currency of a
+    /// group chat for a trip. Apply them before [`Settings::for_user`], whose
+    /// overrides win.
+    pub fn for_chat(&self, chat_id: u64) -> Settings {
+        self.with_overrides(self.chats.get(&chat_id.to_string()))
+    }
+
+    fn with_overrides(&self, overrides: Option<&UserSettings>) -> Settings {
+        let mut settings = self.clone();
+        if let Some(overrides) = overrides {
+            if let Some(currency) = &overrides.currency {
+                settings.currency = currency.clone();
+            }
+            if let Some(default_from_account) = &overrides.default_from_account {
+                settings.default_from_account = Some(default_from_account.clone());
+            }
+            settings.accounts.extend(overrides.accounts.clone());
+        }
+        settings
+    }
+}
+
+pub const DEFAULT_LEDGER_PATH: &str = "{year}.bean";
+
+pub const DEFAULT_COMMIT_MESSAGE: &str = "{date} {payee} {amount} {currency} ({from}→{to})";
+
+pub const DEFAULT_DUPLICATE_LINES: usize = 200;
+
+/// Env var replacing the `ledger_path` setting, e.g. `ledger/{year}/{month}.bean`.
+pub const FILE_PATH_TEMPLATE_ENV: &str = "FILE_PATH_TEMPLATE";
+
+/// Renders a ledger path template for `year`, [`DEFAULT_LEDGER_PATH`] when unset.
+pub fn render_ledger_path(template: Option<&str>, year: &str) -> String {
+    template
+        .unwrap_or(DEFAULT_LEDGER_PATH)
+        .replace("{year}", year)
+}
+
+/// Renders a ledger path template for an entry of `year` and `month` (`01` to
+/// `12`) paid from `account`, which becomes a directory per segment, e.g.
+/// `Assets/CBA`.
+pub fn render_entry_path(template: Option<&str>, year: &str, month: &str, account: &str) -> String {
+    render_ledger_path(template, year)
+        .replace("{month}", month)
+        .replace("{account}", &account.replace(':', "/"))
+}
+
+/// Whether `account` is `prefix` or one of its sub-accounts.
+pub fn covers(prefix: &str, account: &str) -> bool {
+    account
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+}
+
+/// Typed setters for [`Settings`], see [`Settings::builder`].
+#[derive(Debug, Clone)]
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+impl SettingsBuilder {
+    pub fn account(self, alias: impl Into<String>, account: impl Into<String>) -> Self {
+        self.account_settings(alias, AccountSettings::from(account.into()))
+    }
+
+    pub fn account_settings(mut self, alias: impl Into<String>, entry: AccountSettings) -> Self {
+        self.settings.accounts.insert(alias.into(), entry);
+        self
+    }
+
+    pub fn file_header(mut self, file_header: impl Into<String>) -> Self {
+        self.settings.file_header = Some(file_header.into());
+        self
+    }
+
+    pub fn timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.settings.timezone = Some(timezone.into());
+        self
+    }
+
+    pub fn default_from_account(mut self, alias: impl Into<String>) -> Self {
+        self.settings.default_from_account = Some(alias.into());
+        self
+    }
+
+    pub fn narration(mut self, payee: impl Into<String>, narration: impl Into<String>) -> Self {
+        self.settings
+            .narrations
+            .insert(payee.into(), narration.into());
+        self
+    }
+
+    pub fn user(mut self, user_id: u64, user: UserSettings) -> Self {
+        self.settings.users.insert(user_id.to_string(), user);
+        self
+    }
+
+    pub fn admin(mut self, user_id: u64) -> Self {
+        self.settings.admins.push(user_id);
+        self
+    }
+
+    pub fn fava_url(mut self, url: impl Into<String>) -> Self {
+        self.settings.fava_url = Some(url.into());
+        self
+    }
+
+    pub fn budget(mut self, prefix: impl Into<String>, budget: BudgetSettings) -> Self {
+        self.settings.budgets.insert(prefix.into(), budget);
+        self
+    }
+
+    pub fn recurring(mut self, recurring: RecurringSettings) -> Self {
+        self.settings.recurring.push(recurring);
+        self
+    }
+
+    pub fn job(mut self, job: JobSettings) -> Self {
+        self.settings.jobs.push(job);
+        self
+    }
+
+    pub fn bank_feed(mut self, bank_feed: BankFeedSettings) -> Self {
+        self.settings.bank_feed = Some(bank_feed);
+        self
+    }
+
+    pub fn splitwise(mut self, splitwise: SplitwiseSettings) -> Self {
+        self.settings.splitwise = Some(splitwise);
+        self
+    }
+
+    pub fn import_profile(mut self, name: impl Into<String>, profile: ImportProfile) -> Self {
+        self.settings.import_profiles.insert(name.into(), profile);
+        self
+    }
+
+    pub fn merchant_rule(mut self, rule: MerchantRule) -> Self {
+        self.settings.merchant_rules.push(rule);
+        self
+    }
+
+    pub fn ledger_path(mut self, template: impl Into<String>) -> Self {
+        self.settings.ledger_path = Some(template.into());
+        self
+    }
+
+    pub fn commit_message(mut self, template: impl Into<String>) -> Self {
+        self.settings.commit_message = Some(template.into());
+        self
+    }
+
+    pub fn duplicate_lines(mut self, lines: usize) -> Self {
+        self.settings.duplicate_lines = lines;
+        self
+    }
+
+    pub fn split_account(mut self, name: impl Into<String>, alias: impl Into<String>) -> Self {
+        self.settings
+            .split_accounts
+            .insert(name.into(), alias.into());
+        self
+    }
+
+    pub fn currency_symbol(
+        mut self,
+        symbol: impl Into<String>,
+        currency: impl Into<String>,
+    ) -> Self {
+        self.settings
+            .currency_symbols
+            .insert(symbol.into(), currency.into());
+        self
+    }
+
+    pub fn profile(mut self, name: impl Into<String>, profile: LedgerProfile) -> Self {
+        self.settings.profiles.insert(name.into(), profile);
+        self
+    }
+
+    pub fn reply(mut self, reply: ReplySettings) -> Self {
+        self.settings.reply = reply;
+        self
+    }
+
+    pub fn discover_accounts(mut self, path: impl Into<String>) -> Self {
+        self.settings.discover_accounts.push(path.into());
+        self
+    }
+
+    /// Validates the settings the same way a config document is.
+    pub fn build(self) -> Result<Settings> {
+        validation::validate(&self.settings)?;
+        Ok(self.settings)
+    }
+}
+
+/// Replaces `${NAME}` with the `NAME` env var, or with `default` for
+/// `${NAME:-default}` when it isn't set. `$$` is a literal `$`.
+pub fn interpolate(config: &str) -> Result<String> {
+    interpolate_with(config, |name| env::var(name).ok())
+}
+
+/// Like [`interpolate`], looking values up with `lookup` instead of the process
+/// environment, e.g. in a Worker whose secrets are bindings.
+pub fn interpolate_with(config: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut missing = Vec::new();
+    let interpolated = PLACEHOLDER_RE.replace_all(config, |captures: &Captures| {
+        let name = match captures.get(1) {
+            Some(v) => v.as_str(),
+            None => return "$".to_string(),
+        };
+        match (lookup(name), captures.get(2)) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.as_str().to_string(),
+            (None, None) => {
+                missing.push(name.to_string());
+                String::new()
+            }
+        }
+    });
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "config references unset env vars: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(interpolated.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn it_interpolates_env_placeholders() {
+        env::set_var("SETTINGS_TEST_CURRENCY", "USD");
+        let toml = "currency = \"${SETTINGS_TEST_CURRENCY}\"\nfile_header = \"$$HOME ${SETTINGS_TEST_UNSET:-none}\"\n[accounts]\ncba = \"Assets:CBA\"\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        assert_eq!(settings.currency, "USD");
+        assert_eq!(settings.file_header.as_deref(), Some("$HOME none"));
+
+        let error = interpolate("a = \"${SETTINGS_TEST_UNSET}\"").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "config references unset env vars: SETTINGS_TEST_UNSET"
+        );
+
+        let lookup = |name: &str| (name == "BINDING").then(|| "from a binding".to_string());
+        assert_eq!(
+            interpolate_with("a = \"${BINDING}\"", lookup).unwrap(),
+            "a = \"from a binding\""
+        );
+    }
+
+    #[test]
+    fn it_layers_env_overrides_over_config() {
+        env::set_var("SETTINGS_LAYER_TEST__CURRENCY", "USD");
+        env::set_var("SETTINGS_LAYER_TEST__ACCOUNTS__CASH", "Assets:Cash");
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n";
+        let settings =
+            Settings::parse_layers(&[(toml, ConfigFormat::Toml)], Some("SETTINGS_LAYER_TEST_"))
+                .unwrap();
+        assert_eq!(settings.currency, "USD");
+        assert_eq!(settings.accounts["cba"].account, "Assets:CBA");
+        assert_eq!(settings.accounts["cash"].account, "Assets:Cash");
+    }
+
+    #[test]
+    fn it_detects_config_format_from_extension() {
+        assert_eq!(
+            ConfigFormat::from_path("bot-config.yml"),
+            Some(ConfigFormat::Yaml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path("config/bot.JSON"),
+            Some(ConfigFormat::Json)
+        );
+        assert_eq!(ConfigFormat::from_path("bot-config"), None);
+        assert!("ini".parse::<ConfigFormat>().is_err());
+    }
+
+    #[test]
+    fn it_parses_yaml_and_json_settings() {
+        let yaml =
+            "currency: AUD\naccounts:\n  cba: Assets:MasterCard:CBA\n  food: Expenses:Food\n";
+        let settings = Settings::parse(yaml, ConfigFormat::Yaml).unwrap();
+        assert_eq!(settings.currency, "AUD");
+        assert_eq!(settings.accounts["food"].account, "Expenses:Food");
+
+        let json = r#"{"currency": "USD", "accounts": {"cba": "Assets:MasterCard:CBA"}}"#;
+        let settings = Settings::parse(json, ConfigFormat::Json).unwrap();
+        assert_eq!(settings.currency, "USD");
+        assert_eq!(settings.accounts["cba"].account, "Assets:MasterCard:CBA");
+    }
+
+    #[test]
+    fn it_builds_validated_settings_in_code() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .default_from_account("cba")
+            .narration("KFC", "hamburger")
+            .timezone("Australia/Melbourne")
+            .admin(42)
+            .build()
+            .unwrap();
+        assert_eq!(settings.accounts["food"].account, "Expenses:Food");
+        assert_eq!(settings.default_narration("kfc"), Some("hamburger"));
+        assert!(settings.is_admin(42));
+
+        let error = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .default_from_account("amex")
+            .build()
+            .unwrap_err();
+        assert!(error
+            .downcast_ref::<validation::ValidationErrors>()
+            .is_some());
+    }
+
+    #[test]
+    fn it_finds_budget_with_longest_prefix() {
+        let toml = "currency = \"AUD\"\n[accounts]\nfood = \"Expenses:Food\"\ncafe = \"Expenses:Food:Cafe\"\nfoodtruck = \"Expenses:FoodTruck\"\n[budgets]\n\"Expenses:Food\" = 500\n\"Expenses:Food:Cafe\" = { limit = 80.5, currency = \"USD\" }\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        let (prefix, budget) = settings.budget_for("Expenses:Food:Cafe").unwrap();
+        assert_eq!(prefix, "Expenses:Food:Cafe");
+        assert_eq!(budget.limit, 80.5);
+        assert_eq!(budget.currency.as_deref(), Some("USD"));
+        assert_eq!(settings.budget_for("Expenses:Food").unwrap().1.limit, 500.0);
+        assert!(settings.budget_for("Expenses:FoodTruck").is_none());
+
+        let toml = "currency = \"AUD\"\n[accounts]\nfood = \"Expenses:Food\"\ncafe = \"Expenses:Food:Cafe\"\n[budgets]\nfood = 600\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        let (prefix, budget) = settings.budget_for("Expenses:Food:Cafe").unwrap();
+        assert_eq!(prefix, "Expenses:Food");
+        assert_eq!(budget.limit, 600.0);
+    }
+
+    #[test]
+    fn it_finds_jobs_due_at_a_minute() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[[jobs]]\nname = \"recurring\"\nkind = \"recurring\"\nschedule = \"0 9 * * *\"\n[[jobs]]\nname = \"report\"\nkind = \"monthly_report\"\nschedule = \"0 9 1 * *\"\nchat_id = 42\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        let due = |s: &str| -> Vec<String> {
+            let now = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+            settings
+                .due_jobs(now)
+                .iter()
+                .map(|job| job.name.clone())
+                .collect()
+        };
+        assert_eq!(due("2021-09-01 09:00"), vec!["recurring", "report"]);
+        assert_eq!(due("2021-09-02 09:00"), vec!["recurring"]);
+        assert!(due("2021-09-02 09:01").is_empty());
+        assert_eq!(settings.job("report").unwrap().kind, JobKind::MonthlyReport);
+    }
+
+    #[test]
+    fn it_switches_ledger_profile() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[profiles.business]\nrepo = \"acme/books\"\npath = \"business/{year}.bean\"\ncurrency = \"USD\"\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        assert_eq!(settings.ledger_path("2021"), "2021.bean");
+        assert_eq!(settings.ledger_repo(), None);
+
+        let business = settings.for_profile("business").unwrap();
+        assert_eq!(business.currency, "USD");
+        assert_eq!(business.ledger_path("2021"), "business/2021.bean");
+        assert_eq!(business.ledger_repo(), Some("acme/books"));
+        assert!(settings.for_profile("personal").is_err());
+    }
+
+    #[test]
+    fn it_lists_the_files_of_a_year() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("amex", "Liabilities:AMEX")
+            .build()
+            .unwrap();
+        assert_eq!(settings.year_paths("2021"), vec!["2021.bean"]);
+
+        let monthly = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .ledger_path("ledger/{year}/{month}.bean")
+            .build()
+            .unwrap();
+        let paths = monthly.year_paths("2021");
+        assert_eq!(paths.len(), 12);
+        assert_eq!(paths[0], "ledger/2021/01.bean");
+        assert_eq!(paths[11], "ledger/2021/12.bean");
+
+        let per_account = Settings {
+            ledger_path: Some("{account}/{year}.bean".into()),
+            ..settings
+        };
+        assert_eq!(
+            per_account.year_paths("2021"),
+            vec!["Assets/CBA/2021.bean", "Liabilities/AMEX/2021.bean"]
+        );
+        assert_eq!(
+            render_entry_path(
+                Some("{year}/{month}-{account}.bean"),
+                "2021",
+                "09",
+                "Assets:CBA"
+            ),
+            "2021/09-Assets/CBA.bean"
+        );
+    }
+
+    #[test]
+    fn it_applies_user_overrides() {
+        let toml = "currency = \"AUD\"\ndefault_from_account = \"cba\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n[users.247673932]\ncurrency = \"USD\"\ndefault_from_account = \"amex\"\n[users.247673932.accounts]\namex = \"Liabilities:AMEX\"\nfood = \"Expenses:Groceries\"\n";
+        let settings = Settings::from_toml(toml).unwrap();
+
+        let user = settings.for_user(247673932);
+        assert_eq!(user.currency, "USD");
+        assert_eq!(user.default_from_account.as_deref(), Some("amex"));
+        assert_eq!(user.accounts["amex"].account, "Liabilities:AMEX");
+        assert_eq!(user.accounts["food"].account, "Expenses:Groceries");
+        assert_eq!(user.accounts["cba"].account, "Assets:CBA");
+
+        let other = settings.for_user(1);
+        assert_eq!(other.currency, "AUD");
+        assert_eq!(other.default_from_account.as_deref(), Some("cba"));
+        assert!(!other.accounts.contains_key("amex"));
+    }
+
+    #[test]
+    fn it_applies_chat_overrides_below_user_ones() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[chats.42]\ncurrency = \"JPY\"\n[users.7]\ncurrency = \"USD\"\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        assert_eq!(settings.for_chat(42).currency, "JPY");
+        assert_eq!(settings.for_chat(42).for_user(7).currency, "USD");
+        assert_eq!(settings.for_chat(1).for_user(1).currency, "AUD");
+    }
+
+    #[test]
+    fn it_layers_documents_later_ones_winning() {
+        let settings = Settings::parse_layered(&[
+            (
+                "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n",
+                ConfigFormat::Toml,
+            ),
+            (
+                "{ \"accounts\": { \"food\": \"Expenses:Groceries\", \"amex\": \"Liabilities:AMEX\" } }",
+                ConfigFormat::Json,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(settings.currency, "AUD");
+        assert_eq!(settings.accounts["cba"].account, "Assets:CBA");
+        assert_eq!(settings.accounts["food"].account, "Expenses:Groceries");
+        assert_eq!(settings.accounts["amex"].account, "Liabilities:AMEX");
+    }
+
+    #[test]
+    fn it_looks_up_default_narration_ignoring_case() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[narrations]\nPTV = \"Myki top-up\"\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        assert_eq!(settings.default_narration("PTV"), Some("Myki top-up"));
+        assert_eq!(settings.default_narration("ptv"), Some("Myki top-up"));
+        assert_eq!(settings.default_narration("KFC"), None);
+    }
+
+    #[test]
+    fn it_resolves_today_in_configured_timezone() {
+        let mut settings = Settings::new("AUD".into(), HashMap::new());
+        settings.timezone = Some("Pacific/Kiritimati".into());
+        let ahead = settings.today();
+        settings.timezone = Some("Pacific/Pago_Pago".into());
+        let behind = settings.today();
+        // UTC+14 and UTC-11 are always a calendar day or more apart
+        assert!(ahead > behind);
+
+        let clock = FixedClock(Utc.ymd(2022, 1, 1).and_hms(12, 0, 0));
+        assert_eq!(settings.today_by(&clock), NaiveDate::from_ymd(2022, 1, 1));
+        settings.timezone = Some("Australia/Melbourne".into());
+        assert_eq!(
+            settings.now_by(&clock),
+            NaiveDate::from_ymd(2022, 1, 1).and_hms(23, 0, 0)
+        );
+    }
+
+    #[test]
+    fn it_parses_plain_and_table_account_entries() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:MasterCard:CBA\"\ning = { account = \"Assets:Bank:ING\", currency = \"USD\", type = \"asset\", emoji = \"🏦\" }\n";
+        let settings = Settings::from_toml(toml).unwrap();
+        assert_eq!(
+            settings.accounts["cba"],
+            AccountSettings::from("Assets:MasterCard:CBA".to_string())
+        );
+        assert_eq!(
+            settings.accounts["ing"],
+            AccountSettings {
+                account: "Assets:Bank:ING".into(),
+                currency: Some("USD".into()),
+                account_type: Some("asset".into()),
+                emoji: Some("🏦".into()),
+            }
+        );
     }
 }
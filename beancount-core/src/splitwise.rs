@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::parser::{BeancountParser, Transaction};
+use crate::settings::{Settings, SplitwiseSettings};
+
+/// Metadata key holding the Splitwise expense id of a booked share, so an
+/// expense pulled again isn't saved twice.
+pub const SPLITWISE_ID_KEY: &str = "splitwise_id";
+
+/// An expense as `GET /get_expenses` of the Splitwise API returns it, with only
+/// the fields booking needs.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SplitwiseExpense {
+    pub id: u64,
+    #[serde(default)]
+    pub group_id: Option<u64>,
+    pub description: String,
+    /// Amounts are strings, e.g. `"30.0"`.
+    pub cost: String,
+    pub currency_code: String,
+    /// `2021-09-08T10:00:00Z`.
+    pub date: String,
+    /// Settle-ups between friends rather than expenses.
+    #[serde(default)]
+    pub payment: bool,
+    #[serde(default)]
+    pub deleted_at: Option<String>,
+    #[serde(default)]
+    pub category: Option<SplitwiseCategory>,
+    #[serde(default)]
+    pub users: Vec<SplitwiseShare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SplitwiseCategory {
+    pub name: String,
+}
+
+/// What one member paid for an expense and owes of it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SplitwiseShare {
+    pub user_id: u64,
+    pub paid_share: String,
+    pub owed_share: String,
+}
+
+impl SplitwiseExpense {
+    /// The marker [`book`] adds to the entry, for finding it in a ledger file.
+    pub fn marker(&self) -> String {
+        format!("{}: \"{}\"", SPLITWISE_ID_KEY, self.id)
+    }
+
+    /// The expense on the Splitwise website.
+    pub fn url(&self) -> String {
+        format!("https://secure.splitwise.com/#/expenses/{}", self.id)
+    }
+}
+
+/// Books the share `user_id` owes of `expense` from the `[splitwise]` account
+/// to the account of its category, or `default_account`. `None` for deleted
+/// expenses, payments, expenses of other groups and those the user owes
+/// nothing of.
+pub fn book(
+    settings: &Settings,
+    splitwise: &SplitwiseSettings,
+    expense: &SplitwiseExpense,
+    user_id: u64,
+) -> Result<Option<Transaction>> {
+    if expense.deleted_at.is_some()
+        || expense.payment
+        || !(splitwise.groups.is_empty()
+            || expense
+                .group_id
+                .is_some_and(|group| splitwise.groups.contains(&group)))
+    {
+        return Ok(None);
+    }
+    let owed = match expense.users.iter().find(|share| share.user_id == user_id) {
+        Some(share) => share
+            .owed_share
+            .parse::<f32>()
+            .map_err(|_| anyhow!("{} isn't an amount", share.owed_share))?,
+        None => return Ok(None),
+    };
+    if owed == 0.0 {
+        return Ok(None);
+    }
+    let to = expense
+        .category
+        .as_ref()
+        .and_then(|category| splitwise.categories.get(&category.name))
+        .or(splitwise.default_account.as_ref())
+        .ok_or_else(|| {
+            anyhow!(
+                "no [splitwise.categories] entry or default_account books {}",
+                expense.description
+            )
+        })?;
+    let mut transaction = BeancountParser::new(settings.clone()).from_fields(
+        expense.date.get(..10).unwrap_or_default(),
+        &expense.description,
+        "",
+        owed,
+        Some(&splitwise.account),
+        to,
+    )?;
+    transaction.set_currency(&expense.currency_code);
+    transaction.add_metadata(SPLITWISE_ID_KEY, &expense.id.to_string());
+    transaction.add_metadata("splitwise_url", &expense.url());
+    Ok(Some(transaction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn expense() -> SplitwiseExpense {
+        serde_json::from_str(
+            r#"{
+                "id": 1587340321,
+                "group_id": 2761234,
+                "description": "Dinner at Chat Thai",
+                "cost": "90.0",
+                "currency_code": "AUD",
+                "date": "2021-09-08T09:30:00Z",
+                "payment": false,
+                "deleted_at": null,
+                "category": { "id": 13, "name": "Dining out" },
+                "users": [
+                    { "user": { "id": 101, "first_name": "Liang" }, "user_id": 101, "paid_share": "90.0", "owed_share": "30.0", "net_balance": "60.0" },
+                    { "user": { "id": 202, "first_name": "Sam" }, "user_id": 202, "paid_share": "0.0", "owed_share": "60.0", "net_balance": "-60.0" }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_books_the_users_share() {
+        let settings = Settings::builder("AUD")
+            .account("splitwise", "Liabilities:Splitwise")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let splitwise = SplitwiseSettings {
+            account: "splitwise".into(),
+            categories: HashMap::from([("Dining out".to_string(), "food".to_string())]),
+            ..SplitwiseSettings::default()
+        };
+        assert_eq!(
+            String::from(book(&settings, &splitwise, &expense(), 101).unwrap().unwrap()),
+            "2021-09-08 * \"Dinner at Chat Thai\" \"\"\n  splitwise_id: \"1587340321\"\n  splitwise_url: \"https://secure.splitwise.com/#/expenses/1587340321\"\n  Liabilities:Splitwise        -30.00 AUD\n  Expenses:Food        30.00 AUD\n"
+        );
+        assert_eq!(expense().marker(), "splitwise_id: \"1587340321\"");
+
+        assert!(book(&settings, &splitwise, &expense(), 303)
+            .unwrap()
+            .is_none());
+        let deleted = SplitwiseExpense {
+            deleted_at: Some("2021-09-09T00:00:00Z".into()),
+            ..expense()
+        };
+        assert!(book(&settings, &splitwise, &deleted, 101)
+            .unwrap()
+            .is_none());
+        let other_group = SplitwiseSettings {
+            groups: vec![1],
+            ..splitwise.clone()
+        };
+        assert!(book(&settings, &other_group, &expense(), 101)
+            .unwrap()
+            .is_none());
+        let uncategorized = SplitwiseExpense {
+            category: None,
+            ..expense()
+        };
+        assert!(book(&settings, &splitwise, &uncategorized, 101).is_err());
+    }
+}
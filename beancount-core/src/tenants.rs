@@ -0,0 +1,386 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use config::{Config, File};
+use serde::Deserialize;
+
+use crate::secret::Secret;
+use crate::settings::{interpolate, ConfigFormat};
+use crate::validation::{ValidationError, ValidationErrors};
+
+/// Messages a tenant may send per minute unless `rate_limit` says otherwise.
+pub const DEFAULT_RATE_LIMIT: u32 = 30;
+
+/// One person's ledger in a multi-tenant deployment: which chats or bot it
+/// answers, the GitHub repository its ledger and settings live in, and the
+/// token to reach it with.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Tenant {
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub chat_ids: Vec<u64>,
+    /// Set for tenants with their own bot, whose webhook is `/webhook/<bot id>`.
+    pub bot_token: Option<Secret<String>>,
+    pub github_owner: String,
+    pub github_repo: String,
+    pub github_token: Secret<String>,
+    /// Inline settings, instead of `config_file` in the tenant's repository.
+    pub config: Option<String>,
+    pub config_file: Option<String>,
+    /// Messages per minute, 0 for no limit.
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: u32,
+}
+
+fn default_rate_limit() -> u32 {
+    DEFAULT_RATE_LIMIT
+}
+
+impl Tenant {
+    /// The numeric id before the `:` of the bot token, which is public and safe
+    /// to put in a webhook URL.
+    pub fn bot_id(&self) -> Option<&str> {
+        self.bot_token
+            .as_ref()
+            .and_then(|token| token.expose().split(':').next())
+    }
+
+    /// Whether the tenant answers `chat_id`. A tenant with its own bot and no
+    /// `chat_ids` answers every chat of that bot.
+    pub fn allows_chat(&self, chat_id: u64) -> bool {
+        self.chat_ids.contains(&chat_id) || (self.chat_ids.is_empty() && self.bot_token.is_some())
+    }
+}
+
+#[derive(Deserialize)]
+struct TenantsDocument {
+    #[serde(default)]
+    tenants: HashMap<String, Tenant>,
+}
+
+/// The tenants one deployment serves, keyed by chat id on the shared bot or by
+/// the id of their own bot.
+#[derive(Debug, Clone)]
+pub struct TenantRegistry {
+    tenants: Vec<Tenant>,
+}
+
+impl TenantRegistry {
+    /// Reads the registry from the `TENANTS` env var or the file at
+    /// `TENANTS_PATH`, `None` when neither is set and the deployment serves a
+    /// single ledger.
+    pub fn from_env() -> Result<Option<Self>> {
+        if let Ok(document) = env::var("TENANTS") {
+            return Self::parse(&document, ConfigFormat::Toml).map(Some);
+        }
+        match env::var("TENANTS_PATH") {
+            Ok(path) => {
+                let document = fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read tenants file {}: {}", path, e))?;
+                let format = ConfigFormat::from_path(&path).unwrap_or(ConfigFormat::Toml);
+                Self::parse(&document, format).map(Some)
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Deserializes and validates a `[tenants.<name>]` document. Placeholders
+    /// are replaced first so tokens can come from env vars, see [`interpolate`].
+    pub fn parse(document: &str, format: ConfigFormat) -> Result<Self> {
+        let mut c = Config::default();
+        c.merge(File::from_str(&interpolate(document)?, format.into()))?;
+        let document: TenantsDocument = c.try_into()?;
+        let mut tenants: Vec<Tenant> = document
+            .tenants
+            .into_iter()
+            .map(|(name, tenant)| Tenant { name, ..tenant })
+            .collect();
+        tenants.sort_by(|a, b| a.name.cmp(&b.name));
+        let registry = TenantRegistry { tenants };
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    /// Every chat and bot belongs to at most one tenant, so an update can never
+    /// reach another tenant's ledger.
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        let mut error =
+            |key: String, message: String| errors.push(ValidationError { key, message });
+        let mut chats = HashMap::new();
+        let mut bots = HashMap::new();
+        for tenant in self.tenants.iter() {
+            let key = format!("tenants.{}", tenant.name);
+            if tenant.chat_ids.is_empty() && tenant.bot_token.is_none() {
+                error(key.clone(), "needs chat_ids or a bot_token".into());
+            }
+            if tenant.config.is_some() && tenant.config_file.is_some() {
+                error(
+                    key.clone(),
+                    "config and config_file can't both be set".into(),
+                );
+            }
+            for chat_id in tenant.chat_ids.iter() {
+                if let Some(other) = chats.insert(*chat_id, &tenant.name) {
+                    error(
+                        format!("{}.chat_ids", key),
+                        format!("chat {} already belongs to tenant {}", chat_id, other),
+                    );
+                }
+            }
+            if let Some(bot_id) = tenant.bot_id() {
+                if let Some(other) = bots.insert(bot_id, &tenant.name) {
+                    error(
+                        format!("{}.bot_token", key),
+                        format!("the bot already belongs to tenant {}", other),
+                    );
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    pub fn tenants(&self) -> &[Tenant] {
+        &self.tenants
+    }
+
+    /// The tenant a chat on the shared bot belongs to.
+    pub fn for_chat(&self, chat_id: u64) -> Option<&Tenant> {
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.bot_token.is_none() && tenant.chat_ids.contains(&chat_id))
+    }
+
+    /// The tenant whose own bot has the id `bot_id`.
+    pub fn for_bot(&self, bot_id: &str) -> Option<&Tenant> {
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.bot_id() == Some(bot_id))
+    }
+}
+
+/// Counts messages in fixed one-minute windows.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    window_started: Option<Instant>,
+    count: u32,
+}
+
+impl RateLimiter {
+    /// Whether one more message fits in `limit` per minute, counting it if so.
+    pub fn allow(&mut self, limit: u32, now: Instant) -> bool {
+        if limit == 0 {
+            return true;
+        }
+        match self.window_started {
+            Some(started) if now.duration_since(started) < Duration::from_secs(60) => {}
+            _ => {
+                self.window_started = Some(now);
+                self.count = 0;
+            }
+        }
+        if self.count >= limit {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+/// The ids of the last updates handled, so an update Telegram redelivers isn't saved
+/// twice, with the responses they were answered with when known, and of the
+/// ones still being handled.
+#[derive(Debug)]
+pub struct RecentUpdates {
+    ids: VecDeque<u64>,
+    responses: HashMap<u64, String>,
+    handling: HashSet<u64>,
+    capacity: usize,
+}
+
+impl RecentUpdates {
+    pub fn new(capacity: usize) -> Self {
+        RecentUpdates {
+            ids: VecDeque::with_capacity(capacity),
+            responses: HashMap::new(),
+            handling: HashSet::new(),
+            capacity,
+        }
+    }
+
+    pub fn contains(&self, update_id: u64) -> bool {
+        self.ids.contains(&update_id)
+    }
+
+    /// The remembered ids, oldest first.
+    pub fn ids(&self) -> Vec<u64> {
+        self.ids.iter().copied().collect()
+    }
+
+    /// Remembers `update_id`, forgetting the oldest one when full.
+    pub fn insert(&mut self, update_id: u64) {
+        if self.contains(update_id) {
+            return;
+        }
+        if self.ids.len() == self.capacity {
+            if let Some(oldest) = self.ids.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+        self.ids.push_back(update_id);
+    }
+
+    /// Remembers `update_id` and the response it was answered with.
+    pub fn record(&mut self, update_id: u64, response: &str) {
+        self.handling.remove(&update_id);
+        self.insert(update_id);
+        self.responses.insert(update_id, response.into());
+    }
+
+    /// Marks `update_id` as being handled until it's recorded or abandoned.
+    pub fn start(&mut self, update_id: u64) {
+        self.handling.insert(update_id);
+    }
+
+    pub fn is_handling(&self, update_id: u64) -> bool {
+        self.handling.contains(&update_id)
+    }
+
+    /// Forgets `update_id` was being handled, e.g. after it failed, so a retry is
+    /// handled again.
+    pub fn abandon(&mut self, update_id: u64) {
+        self.handling.remove(&update_id);
+    }
+
+    /// The response `update_id` was answered with, `None` for ids loaded
+    /// after a restart and ones never recorded.
+    pub fn response(&self, update_id: u64) -> Option<&str> {
+        self.responses.get(&update_id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    const TENANTS: &str = r#"
+[tenants.alice]
+chat_ids = [247673932]
+github_owner = "alice"
+github_repo = "beancount"
+github_token = "${TENANTS_TEST_TOKEN}"
+rate_limit = 2
+
+[tenants.bob]
+bot_token = "123456:bob-bot-token"
+github_owner = "bob"
+github_repo = "ledger"
+github_token = "bob-token"
+config = "currency = \"USD\"\n[accounts]\ncash = \"Assets:Cash\"\n"
+"#;
+
+    #[test]
+    fn it_finds_tenants_by_chat_and_bot() {
+        env::set_var("TENANTS_TEST_TOKEN", "alice-token");
+        let registry = TenantRegistry::parse(TENANTS, ConfigFormat::Toml).unwrap();
+
+        let alice = registry.for_chat(247673932).unwrap();
+        assert_eq!(alice.name, "alice");
+        assert_eq!(alice.github_token.expose(), "alice-token");
+        assert_eq!(alice.rate_limit, 2);
+        assert!(!alice.allows_chat(1));
+
+        let bob = registry.for_bot("123456").unwrap();
+        assert_eq!(bob.name, "bob");
+        assert_eq!(bob.rate_limit, DEFAULT_RATE_LIMIT);
+        assert!(bob.allows_chat(1));
+        assert!(registry.for_chat(1).is_none());
+        assert!(format!("{:?}", bob).contains("[REDACTED]"));
+    }
+
+    #[test]
+    fn it_rejects_chats_shared_by_tenants() {
+        let document = r#"
+[tenants.alice]
+chat_ids = [1]
+github_owner = "alice"
+github_repo = "beancount"
+github_token = "a"
+
+[tenants.bob]
+chat_ids = [1]
+github_owner = "bob"
+github_repo = "beancount"
+github_token = "b"
+
+[tenants.carol]
+github_owner = "carol"
+github_repo = "beancount"
+github_token = "c"
+"#;
+        let error = TenantRegistry::parse(document, ConfigFormat::Toml).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid settings:\n  - tenants.bob.chat_ids: chat 1 already belongs to tenant alice\n  - tenants.carol: needs chat_ids or a bot_token"
+        );
+    }
+
+    #[test]
+    fn isolated_settings_ignore_the_environment() {
+        env::set_var("TENANTS_TEST_SECRET", "deployment-secret");
+        let toml = "currency = \"AUD\"\nfile_header = \"${TENANTS_TEST_SECRET}\"\n[accounts]\ncash = \"Assets:Cash\"\n";
+        let settings = Settings::parse_isolated(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(
+            settings.file_header.as_deref(),
+            Some("${TENANTS_TEST_SECRET}")
+        );
+    }
+
+    #[test]
+    fn it_limits_messages_per_minute() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        assert!(limiter.allow(2, start));
+        assert!(limiter.allow(2, start));
+        assert!(!limiter.allow(2, start + Duration::from_secs(30)));
+        assert!(limiter.allow(2, start + Duration::from_secs(60)));
+        assert!(limiter.allow(0, start));
+    }
+
+    #[test]
+    fn it_forgets_the_oldest_updates() {
+        let mut recent = RecentUpdates::new(2);
+        recent.insert(1);
+        recent.insert(2);
+        recent.insert(2);
+        assert!(recent.contains(1));
+        recent.insert(3);
+        assert!(!recent.contains(1));
+        assert!(recent.contains(2) && recent.contains(3));
+        assert_eq!(recent.ids(), vec![2, 3]);
+
+        recent.record(4, "{\"method\":\"sendMessage\"}");
+        assert_eq!(recent.response(4), Some("{\"method\":\"sendMessage\"}"));
+        assert_eq!(recent.response(3), None);
+        recent.insert(5);
+        recent.insert(6);
+        assert_eq!(recent.response(4), None);
+
+        recent.start(7);
+        assert!(recent.is_handling(7) && !recent.contains(7));
+        recent.record(7, "{}");
+        assert!(!recent.is_handling(7) && recent.contains(7));
+        recent.start(8);
+        recent.abandon(8);
+        assert!(!recent.is_handling(8) && !recent.contains(8));
+    }
+}
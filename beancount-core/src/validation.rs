@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::merchant;
+use crate::parser::BeancountParser;
+use crate::schedule::Schedule;
+use crate::settings::{
+    covers, AccountSettings, JobKind, MerchantRule, RateProvider, Settings, UserSettings,
+};
+
+pub const ROOT_ACCOUNTS: [&str; 5] = ["Assets", "Liabilities", "Equity", "Income", "Expenses"];
+
+pub const ACCOUNT_TYPES: [&str; 5] = ["asset", "liability", "equity", "income", "expense"];
+
+/// Active ISO 4217 codes.
+const CURRENCY_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD",
+    "CDF", "CHF", "CLP", "CNY", "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD",
+    "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ",
+    "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD",
+    "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR",
+    "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR",
+    "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN",
+    "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR",
+    "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB",
+    "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS",
+    "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWL",
+];
+
+lazy_static! {
+    static ref COMPONENT_RE: Regex = Regex::new(r"^[A-Z0-9][A-Za-z0-9-]*$").unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub key: String,
+    pub message: String,
+}
+
+/// Every problem found in a settings document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid settings:")?;
+        for error in self.0.iter() {
+            write!(f, "\n  - {}: {}", error.key, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+pub fn is_known_currency(currency: &str) -> bool {
+    CURRENCY_CODES.contains(&currency)
+}
+
+fn check_merchant_rules(
+    key: &str,
+    rules: &[MerchantRule],
+    settings: &Settings,
+    discovering: bool,
+    errors: &mut Vec<ValidationError>,
+) {
+    for (index, rule) in rules.iter().enumerate() {
+        let key = format!("{}[{}]", key, index);
+        if let Err(e) = merchant::compile(&rule.pattern) {
+            errors.push(ValidationError {
+                key: format!("{}.pattern", key),
+                message: format!("`{}` is not a valid regex: {}", rule.pattern, e),
+            });
+        }
+        if let Some(alias) = &rule.account {
+            if !settings.accounts.contains_key(alias) && !discovering {
+                errors.push(ValidationError {
+                    key: format!("{}.account", key),
+                    message: format!("`{}` is not a configured account alias", alias),
+                });
+            }
+        }
+    }
+}
+
+/// Checks `account` against beancount's naming rules and returns what's wrong
+/// with it.
+pub fn check_account_name(account: &str) -> Option<String> {
+    let mut components = account.split(':');
+    let root = components.next().unwrap_or_default();
+    if !ROOT_ACCOUNTS.contains(&root) {
+        return Some(format!(
+            "`{}` must start with one of {}",
+            account,
+            ROOT_ACCOUNTS.join(", ")
+        ));
+    }
+    let components: Vec<&str> = components.collect();
+    if components.is_empty() {
+        return Some(format!(
+            "`{}` needs at least one component after the root, e.g. `{}:Cash`",
+            account, root
+        ));
+    }
+    components
+        .iter()
+        .find(|c| !COMPONENT_RE.is_match(c))
+        .map(|component| {
+            format!(
+                "`{}` has invalid component `{}`, components start with a capital letter or digit and contain only letters, digits and dashes",
+                account, component
+            )
+        })
+}
+
+fn check_account(key: &str, entry: &AccountSettings, errors: &mut Vec<ValidationError>) {
+    if let Some(message) = check_account_name(&entry.account) {
+        errors.push(ValidationError {
+            key: key.into(),
+            message,
+        });
+    }
+    if let Some(currency) = &entry.currency {
+        if !is_known_currency(currency) {
+            errors.push(ValidationError {
+                key: format!("{}.currency", key),
+                message: format!("`{}` is not a known currency code", currency),
+            });
+        }
+    }
+    if let Some(account_type) = &entry.account_type {
+        if !ACCOUNT_TYPES.contains(&account_type.as_str()) {
+            errors.push(ValidationError {
+                key: format!("{}.type", key),
+                message: format!(
+                    "`{}` must be one of {}",
+                    account_type,
+                    ACCOUNT_TYPES.join(", ")
+                ),
+            });
+        }
+    }
+}
+
+pub fn validate(settings: &Settings) -> Result<(), ValidationErrors> {
+    let mut errors = Vec::new();
+
+    if !is_known_currency(&settings.currency) {
+        errors.push(ValidationError {
+            key: "currency".into(),
+            message: format!("`{}` is not a known currency code", settings.currency),
+        });
+    }
+
+    if let Some(timezone) = &settings.timezone {
+        if timezone.parse::<chrono_tz::Tz>().is_err() {
+            errors.push(ValidationError {
+                key: "timezone".into(),
+                message: format!(
+                    "`{}` is not an IANA time zone name such as `Australia/Melbourne`",
+                    timezone
+                ),
+            });
+        }
+    }
+
+    if let Some(rates) = &settings.rates {
+        if rates.provider == RateProvider::ExchangerateHost && rates.access_key.is_none() {
+            errors.push(ValidationError {
+                key: "rates.access_key".into(),
+                message: "is required by exchangerate_host".into(),
+            });
+        }
+    }
+
+    let discovering = !settings.discover_accounts.is_empty();
+    if settings.accounts.is_empty() && !discovering {
+        errors.push(ValidationError {
+            key: "accounts".into(),
+            message: "at least one account alias is required".into(),
+        });
+    }
+
+    let mut aliases: Vec<&String> = settings.accounts.keys().collect();
+    aliases.sort();
+    let mut normalized: HashMap<String, &str> = HashMap::new();
+    for alias in aliases {
+        let key = format!("accounts.{}", alias);
+        check_account(&key, &settings.accounts[alias], &mut errors);
+        if let Some(other) = normalized.insert(alias.trim().to_lowercase(), alias) {
+            errors.push(ValidationError {
+                key,
+                message: format!("duplicates alias `{}`", other),
+            });
+        }
+    }
+
+    if let Some(alias) = &settings.default_from_account {
+        if !settings.accounts.contains_key(alias) && !discovering {
+            errors.push(ValidationError {
+                key: "default_from_account".into(),
+                message: format!("`{}` is not a configured account alias", alias),
+            });
+        }
+    }
+
+    let mut prefixes: Vec<&String> = settings.budgets.keys().collect();
+    prefixes.sort();
+    for prefix in prefixes {
+        let key = format!("budgets.{}", prefix);
+        let budget = &settings.budgets[prefix];
+        let prefix = settings.budget_prefix(prefix);
+        if !prefix.contains(':') {
+            if !discovering {
+                errors.push(ValidationError {
+                    key: key.clone(),
+                    message: format!("`{}` is not a configured account alias", prefix),
+                });
+            }
+        } else if let Some(message) = check_account_name(prefix) {
+            errors.push(ValidationError {
+                key: key.clone(),
+                message,
+            });
+        } else if !discovering
+            && !settings
+                .accounts
+                .values()
+                .any(|entry| covers(prefix, &entry.account))
+        {
+            errors.push(ValidationError {
+                key: key.clone(),
+                message: format!("no configured account is `{}` or under it", prefix),
+            });
+        }
+        if budget.limit.is_nan() || budget.limit <= 0.0 {
+            errors.push(ValidationError {
+                key: format!("{}.limit", key),
+                message: format!("`{}` must be greater than zero", budget.limit),
+            });
+        }
+        if let Some(currency) = &budget.currency {
+            if !is_known_currency(currency) {
+                errors.push(ValidationError {
+                    key: format!("{}.currency", key),
+                    message: format!("`{}` is not a known currency code", currency),
+                });
+            }
+        }
+    }
+
+    let parser = BeancountParser::new(settings.clone());
+    let mut names = Vec::new();
+    for (index, recurring) in settings.recurring.iter().enumerate() {
+        let key = format!("recurring[{}]", index);
+        if names.contains(&recurring.name.as_str()) {
+            errors.push(ValidationError {
+                key: format!("{}.name", key),
+                message: format!("duplicates recurring transaction `{}`", recurring.name),
+            });
+        }
+        names.push(&recurring.name);
+        if let Err(e) = recurring.schedule.parse::<Schedule>() {
+            errors.push(ValidationError {
+                key: format!("{}.schedule", key),
+                message: e.to_string(),
+            });
+        }
+        if !discovering {
+            if let Err(e) = parser.parse(&recurring.text) {
+                errors.push(ValidationError {
+                    key: format!("{}.text", key),
+                    message: format!("`{}` can't be parsed: {}", recurring.text, e),
+                });
+            }
+        }
+    }
+
+    let mut names = Vec::new();
+    for (index, job) in settings.jobs.iter().enumerate() {
+        let key = format!("jobs[{}]", index);
+        if names.contains(&job.name.as_str()) {
+            errors.push(ValidationError {
+                key: format!("{}.name", key),
+                message: format!("duplicates job `{}`", job.name),
+            });
+        }
+        names.push(&job.name);
+        if let Err(e) = job.schedule.parse::<Schedule>() {
+            errors.push(ValidationError {
+                key: format!("{}.schedule", key),
+                message: e.to_string(),
+            });
+        }
+        let sends_message = matches!(
+            job.kind,
+            JobKind::Reminder | JobKind::MonthlyReport | JobKind::NetWorth | JobKind::Duplicates
+        );
+        if sends_message && job.chat_id.is_none() {
+            errors.push(ValidationError {
+                key: format!("{}.chat_id", key),
+                message: "is required to send the job's message".into(),
+            });
+        }
+        if job.kind == JobKind::Prices && (settings.prices.is_none() || settings.rates.is_none()) {
+            errors.push(ValidationError {
+                key: format!("{}.kind", key),
+                message: "needs the [prices] and [rates] sections".into(),
+            });
+        }
+        if job.kind == JobKind::Splitwise && settings.splitwise.is_none() {
+            errors.push(ValidationError {
+                key: format!("{}.kind", key),
+                message: "needs the [splitwise] section".into(),
+            });
+        }
+        if job.kind == JobKind::Reminder && job.text.as_deref().unwrap_or("").is_empty() {
+            errors.push(ValidationError {
+                key: format!("{}.text", key),
+                message: "is required for a reminder".into(),
+            });
+        }
+    }
+
+    let mut names: Vec<&String> = settings.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let profile = &settings.profiles[name];
+        if let Some(currency) = &profile.currency {
+            if !is_known_currency(currency) {
+                errors.push(ValidationError {
+                    key: format!("profiles.{}.currency", name),
+                    message: format!("`{}` is not a known currency code", currency),
+                });
+            }
+        }
+        if name == "default" {
+            errors.push(ValidationError {
+                key: format!("profiles.{}", name),
+                message: "`default` is reserved for the top-level ledger".into(),
+            });
+        }
+    }
+
+    check_merchant_rules(
+        "merchant_rules",
+        &settings.merchant_rules,
+        settings,
+        discovering,
+        &mut errors,
+    );
+
+    let mut names: Vec<&String> = settings.import_profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let key = format!("import_profiles.{}", name);
+        let profile = &settings.import_profiles[name];
+        let aliases = [
+            ("account", &profile.account),
+            ("default_account", &profile.default_account),
+        ];
+        for (field, alias) in aliases {
+            if let Some(alias) = alias {
+                if !settings.accounts.contains_key(alias) && !discovering {
+                    errors.push(ValidationError {
+                        key: format!("{}.{}", key, field),
+                        message: format!("`{}` is not a configured account alias", alias),
+                    });
+                }
+            }
+        }
+        if profile.account.is_none() && settings.default_from_account.is_none() {
+            errors.push(ValidationError {
+                key: format!("{}.account", key),
+                message: "is required without a default_from_account".into(),
+            });
+        }
+        if !profile.delimiter.is_ascii() {
+            errors.push(ValidationError {
+                key: format!("{}.delimiter", key),
+                message: format!("`{}` is not an ASCII character", profile.delimiter),
+            });
+        }
+        let columns = &profile.columns;
+        if columns.amount.is_some() && (columns.debit.is_some() || columns.credit.is_some()) {
+            errors.push(ValidationError {
+                key: format!("{}.columns", key),
+                message: "takes either an amount column or debit and credit ones".into(),
+            });
+        }
+        check_merchant_rules(
+            &format!("{}.merchant_rules", key),
+            &profile.merchant_rules,
+            settings,
+            discovering,
+            &mut errors,
+        );
+    }
+
+    if let Some(feed) = &settings.bank_feed {
+        let mut aliases: Vec<(String, &String)> = feed
+            .accounts
+            .iter()
+            .map(|(id, alias)| (format!("bank_feed.accounts.{}", id), alias))
+            .chain(
+                feed.categories
+                    .iter()
+                    .map(|(id, alias)| (format!("bank_feed.categories.{}", id), alias)),
+            )
+            .chain(
+                feed.default_account
+                    .iter()
+                    .map(|alias| ("bank_feed.default_account".to_string(), alias)),
+            )
+            .collect();
+        aliases.sort();
+        for (key, alias) in aliases {
+            if !settings.accounts.contains_key(alias) && !discovering {
+                errors.push(ValidationError {
+                    key,
+                    message: format!("`{}` is not a configured account alias", alias),
+                });
+            }
+        }
+        if !feed.auto_commit && feed.chat_id.is_none() {
+            errors.push(ValidationError {
+                key: "bank_feed.chat_id".into(),
+                message: "is required to confirm transactions, unless auto_commit is set".into(),
+            });
+        }
+    }
+
+    if let Some(splitwise) = &settings.splitwise {
+        let mut aliases: Vec<(String, &String)> = splitwise
+            .categories
+            .iter()
+            .map(|(name, alias)| (format!("splitwise.categories.{}", name), alias))
+            .chain(
+                splitwise
+                    .default_account
+                    .iter()
+                    .map(|alias| ("splitwise.default_account".to_string(), alias)),
+            )
+            .collect();
+        aliases.sort();
+        aliases.insert(0, ("splitwise.account".into(), &splitwise.account));
+        for (key, alias) in aliases {
+            if !settings.accounts.contains_key(alias) && !discovering {
+                errors.push(ValidationError {
+                    key,
+                    message: format!("`{}` is not a configured account alias", alias),
+                });
+            }
+        }
+    }
+
+    if let Some(alias) = settings
+        .ocr
+        .as_ref()
+        .and_then(|ocr| ocr.default_account.as_ref())
+    {
+        if !settings.accounts.contains_key(alias) && !discovering {
+            errors.push(ValidationError {
+                key: "ocr.default_account".into(),
+                message: format!("`{}` is not a configured account alias", alias),
+            });
+        }
+    }
+
+    let mut names: Vec<&String> = settings.split_accounts.keys().collect();
+    names.sort();
+    for name in names {
+        let alias = &settings.split_accounts[name];
+        if !settings.accounts.contains_key(alias) && !discovering {
+            errors.push(ValidationError {
+                key: format!("split_accounts.{}", name),
+                message: format!("`{}` is not a configured account alias", alias),
+            });
+        }
+    }
+
+    let mut symbols: Vec<&String> = settings.currency_symbols.keys().collect();
+    symbols.sort();
+    for symbol in symbols {
+        let key = format!("currency_symbols.{}", symbol);
+        if symbol.chars().count() != 1 || symbol.chars().any(|c| c.is_alphanumeric()) {
+            errors.push(ValidationError {
+                key: key.clone(),
+                message: format!("`{}` is not a single currency symbol such as `$`", symbol),
+            });
+        }
+        let currency = &settings.currency_symbols[symbol];
+        if !is_known_currency(currency) {
+            errors.push(ValidationError {
+                key,
+                message: format!("`{}` is not a known currency code", currency),
+            });
+        }
+    }
+
+    for (section, overrides) in [("users", &settings.users), ("chats", &settings.chats)] {
+        let mut ids: Vec<&String> = overrides.keys().collect();
+        ids.sort();
+        for id in ids {
+            check_overrides(
+                section,
+                id,
+                &overrides[id],
+                settings,
+                discovering,
+                &mut errors,
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors(errors))
+    }
+}
+
+/// Checks the `[users.<id>]` or `[chats.<id>]` overrides of `id`.
+fn check_overrides(
+    section: &str,
+    id: &str,
+    overrides: &UserSettings,
+    settings: &Settings,
+    discovering: bool,
+    errors: &mut Vec<ValidationError>,
+) {
+    let prefix = format!("{}.{}", section, id);
+    if id.parse::<u64>().is_err() {
+        let kind = if section == "users" { "user" } else { "chat" };
+        errors.push(ValidationError {
+            key: prefix.clone(),
+            message: format!("`{}` is not a Telegram {} id", id, kind),
+        });
+    }
+    if let Some(currency) = &overrides.currency {
+        if !is_known_currency(currency) {
+            errors.push(ValidationError {
+                key: format!("{}.currency", prefix),
+                message: format!("`{}` is not a known currency code", currency),
+            });
+        }
+    }
+    let mut aliases: Vec<&String> = overrides.accounts.keys().collect();
+    aliases.sort();
+    for alias in aliases {
+        let key = format!("{}.accounts.{}", prefix, alias);
+        check_account(&key, &overrides.accounts[alias], errors);
+    }
+    if let Some(alias) = &overrides.default_from_account {
+        if !overrides.accounts.contains_key(alias)
+            && !settings.accounts.contains_key(alias)
+            && !discovering
+        {
+            errors.push(ValidationError {
+                key: format!("{}.default_from_account", prefix),
+                message: format!("`{}` is not a configured account alias", alias),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_accepts_valid_settings() {
+        let accounts = [("cba".into(), "Assets:MasterCard:CBA".into())]
+            .iter()
+            .cloned()
+            .collect();
+        assert!(validate(&Settings::new("AUD".into(), accounts)).is_ok());
+    }
+
+    #[test]
+    fn it_reports_every_problem_with_its_key() {
+        let accounts = [
+            ("food".into(), "Expense:Food".into()),
+            ("Food".into(), "Expenses:Food".into()),
+            ("cash".into(), "Assets".into()),
+            ("car".into(), "Expenses:car".into()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let errors = validate(&Settings::new("AUDD".into(), accounts)).unwrap_err();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "currency",
+                "accounts.car",
+                "accounts.cash",
+                "accounts.food",
+                "accounts.food"
+            ]
+        );
+        assert_eq!(errors.0[4].message, "duplicates alias `Food`");
+        assert!(errors
+            .to_string()
+            .starts_with("invalid settings:\n  - currency: `AUDD` is not a known currency code\n"));
+    }
+
+    #[test]
+    fn it_validates_user_overrides() {
+        let toml = "currency = \"AUD\"\ndefault_from_account = \"amex\"\n[accounts]\ncba = \"Assets:CBA\"\n[users.42]\ncurrency = \"XYZ\"\ndefault_from_account = \"visa\"\n[users.42.accounts]\nvisa = \"Liabilities:visa\"\n[chats.trip]\ncurrency = \"JPY\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "default_from_account",
+                "users.42.currency",
+                "users.42.accounts.visa",
+                "chats.trip"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_jobs() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[[jobs]]\nname = \"report\"\nkind = \"monthly_report\"\nschedule = \"0 9 1 * *\"\n[[jobs]]\nname = \"report\"\nkind = \"reminder\"\nschedule = \"0 21 * *\"\nchat_id = 42\n[[jobs]]\nname = \"prices\"\nkind = \"prices\"\nschedule = \"0 18 * * 1-5\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "jobs[0].chat_id",
+                "jobs[1].name",
+                "jobs[1].schedule",
+                "jobs[1].text",
+                "jobs[2].kind"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_account_table_details() {
+        let toml = "currency = \"AUD\"\n[accounts]\ning = { account = \"Assets:Bank:ING\", currency = \"AUDD\", type = \"bank\" }\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["accounts.ing.currency", "accounts.ing.type"]);
+    }
+
+    #[test]
+    fn it_validates_budgets() {
+        let toml = "currency = \"AUD\"\n[accounts]\nfood = \"Expenses:Food\"\n[budgets]\n\"Expenses:Food\" = 500\n\"Expenses:Car\" = 100\n\"Expenses:Fo\" = { limit = 0, currency = \"AUDD\" }\nfood = 600\ncar = 100\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "budgets.Expenses:Car",
+                "budgets.Expenses:Fo",
+                "budgets.Expenses:Fo.limit",
+                "budgets.Expenses:Fo.currency",
+                "budgets.car"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_recurring_transactions() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nrent = \"Expenses:Rent\"\n[[recurring]]\nname = \"rent\"\nschedule = \"0 9 1 * *\"\ntext = \"@Landlord rent 2000 cba > rent\"\n[[recurring]]\nname = \"rent\"\nschedule = \"0 9 1 *\"\ntext = \"@Netflix 15 cba > tv\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "recurring[1].name",
+                "recurring[1].schedule",
+                "recurring[1].text"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_merchant_rules() {
+        let toml = "currency = \"AUD\"\n[accounts]\nfood = \"Expenses:Food\"\n[[merchant_rules]]\npattern = \"^WOOLWORTHS\"\npayee = \"Woolworths\"\naccount = \"food\"\n[[merchant_rules]]\npattern = \"UBER(\"\npayee = \"Uber\"\naccount = \"transport\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["merchant_rules[1].pattern", "merchant_rules[1].account"]
+        );
+    }
+
+    #[test]
+    fn it_validates_import_profiles() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[import_profiles.cba]\naccount = \"cba\"\ndefault_account = \"misc\"\ncolumns = { amount = \"Amount\", debit = \"Debit\" }\n[[import_profiles.cba.merchant_rules]]\npattern = \"UBER(\"\npayee = \"Uber\"\n[import_profiles.amex]\ndelimiter = \"é\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "import_profiles.amex.account",
+                "import_profiles.amex.delimiter",
+                "import_profiles.cba.default_account",
+                "import_profiles.cba.columns",
+                "import_profiles.cba.merchant_rules[0].pattern",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_the_bank_feed() {
+        let toml = "currency = \"AUD\"\n[accounts]\nup = \"Assets:Up\"\n[bank_feed]\naccounts = { acc-1 = \"up\" }\ncategories = { groceries = \"food\" }\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["bank_feed.categories.groceries", "bank_feed.chat_id"]
+        );
+    }
+
+    #[test]
+    fn it_validates_splitwise() {
+        let toml = "currency = \"AUD\"\n[accounts]\nsplitwise = \"Liabilities:Splitwise\"\n[splitwise]\naccount = \"sw\"\ncategories = { \"Dining out\" = \"food\" }\n[[jobs]]\nname = \"splitwise\"\nkind = \"splitwise\"\nschedule = \"0 * * * *\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["splitwise.account", "splitwise.categories.Dining out"]
+        );
+        let toml = "currency = \"AUD\"\n[accounts]\nsplitwise = \"Liabilities:Splitwise\"\n[[jobs]]\nname = \"splitwise\"\nkind = \"splitwise\"\nschedule = \"0 * * * *\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        assert_eq!(errors.0[0].key, "jobs[0].kind");
+    }
+
+    #[test]
+    fn it_validates_currency_symbols() {
+        let toml = "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[currency_symbols]\n\"$\" = \"AUD\"\n\"¥\" = \"YEN\"\n\"A$\" = \"AUD\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["currency_symbols.A$", "currency_symbols.¥"]);
+    }
+
+    #[test]
+    fn it_validates_split_accounts() {
+        let toml = "currency = \"AUD\"\n[accounts]\nliang = \"Assets:Receivable:Liang\"\n[split_accounts]\nliang = \"liang\"\nsam = \"sam\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        let keys: Vec<&str> = errors.0.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["split_accounts.sam"]);
+    }
+
+    #[test]
+    fn it_rejects_unknown_timezone() {
+        let toml =
+            "currency = \"AUD\"\ntimezone = \"Melbourne\"\n[accounts]\ncba = \"Assets:CBA\"\n";
+        let error = Settings::from_toml(toml).unwrap_err();
+        let errors = error.downcast_ref::<ValidationErrors>().unwrap();
+        assert_eq!(errors.0[0].key, "timezone");
+        let toml = "currency = \"AUD\"\ntimezone = \"Australia/Melbourne\"\n[accounts]\ncba = \"Assets:CBA\"\n";
+        assert!(Settings::from_toml(toml).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_empty_account_map() {
+        let errors = validate(&Settings::new("AUD".into(), HashMap::new())).unwrap_err();
+        assert_eq!(errors.0[0].key, "accounts");
+        let toml = "currency = \"AUD\"\ndiscover_accounts = [\"main.bean\"]\ndefault_from_account = \"ing\"\n";
+        assert!(Settings::from_toml(toml).is_ok());
+    }
+}
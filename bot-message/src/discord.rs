@@ -0,0 +1,219 @@
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// Discord refuses messages longer than this many characters.
+pub const MAX_CONTENT_CHARS: usize = 2000;
+
+/// What Discord posts to the interactions endpoint: a ping when the endpoint
+/// is set, then the application commands users run.
+#[derive(Deserialize, Debug)]
+pub struct Interaction {
+    #[serde(rename = "type")]
+    pub kind: u8,
+    pub id: String,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    /// Who ran the command in a server.
+    #[serde(default)]
+    pub member: Option<Member>,
+    /// Who ran the command in a direct message.
+    #[serde(default)]
+    pub user: Option<User>,
+    #[serde(default)]
+    pub data: Option<CommandData>,
+}
+
+impl Interaction {
+    pub const PING: u8 = 1;
+    pub const APPLICATION_COMMAND: u8 = 2;
+
+    pub fn user_id(&self) -> Option<&str> {
+        self.member
+            .as_ref()
+            .map(|member| &member.user)
+            .or(self.user.as_ref())
+            .map(|user| user.id.as_str())
+    }
+
+    /// The first string option of the command, e.g. `text` of
+    /// `/beancount text:@KFC 12.4 cba > food`.
+    pub fn text(&self) -> Option<&str> {
+        self.data
+            .as_ref()?
+            .options
+            .iter()
+            .find_map(|option| option.value.as_ref()?.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Member {
+    pub user: User,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct User {
+    pub id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CommandData {
+    pub name: String,
+    #[serde(default)]
+    pub options: Vec<CommandOption>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CommandOption {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: u8,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+/// The answer to an interaction.
+#[derive(Serialize, Debug)]
+pub struct InteractionResponse {
+    #[serde(rename = "type")]
+    pub kind: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<ResponseData>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseData {
+    pub content: String,
+    pub flags: u64,
+}
+
+impl InteractionResponse {
+    const PONG: u8 = 1;
+    const CHANNEL_MESSAGE_WITH_SOURCE: u8 = 4;
+    /// Only the user who ran the command sees the message.
+    const EPHEMERAL: u64 = 1 << 6;
+
+    pub fn pong() -> Self {
+        InteractionResponse {
+            kind: Self::PONG,
+            data: None,
+        }
+    }
+
+    /// A message only the user who ran the command sees, cut to
+    /// [`MAX_CONTENT_CHARS`].
+    pub fn ephemeral(content: String) -> Self {
+        let content = if content.chars().count() > MAX_CONTENT_CHARS {
+            let mut cut: String = content.chars().take(MAX_CONTENT_CHARS - 1).collect();
+            cut.push('…');
+            cut
+        } else {
+            content
+        };
+        InteractionResponse {
+            kind: Self::CHANNEL_MESSAGE_WITH_SOURCE,
+            data: Some(ResponseData {
+                content,
+                flags: Self::EPHEMERAL,
+            }),
+        }
+    }
+}
+
+/// Whether `signature`, in hex, is the Ed25519 signature of `timestamp`
+/// followed by `body` with the application's hex `public_key`.
+pub fn verify_signature(public_key: &str, signature: &str, timestamp: &str, body: &[u8]) -> bool {
+    let key = match hex::decode(public_key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+    {
+        Some(key) => key,
+        None => return false,
+    };
+    let signature = match hex::decode(signature)
+        .ok()
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+    {
+        Some(bytes) => Signature::from_bytes(&bytes),
+        None => return false,
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    key.verify(&message, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn it_deserialize_application_commands() {
+        let json = "{\"type\":1,\"id\":\"786008729715212338\",\"application_id\":\"775799577604751380\",\"token\":\"A_UNIQUE_TOKEN\",\"version\":1}";
+        let ping: Interaction = serde_json::from_str(json).unwrap();
+        assert_eq!(ping.kind, Interaction::PING);
+
+        let json = "{\"type\":2,\"id\":\"786008729715212338\",\"application_id\":\"775799577604751380\",\"channel_id\":\"772908445358620702\",\"token\":\"A_UNIQUE_TOKEN\",\"member\":{\"user\":{\"id\":\"53908232506183680\",\"username\":\"Mason\"},\"roles\":[]},\"data\":{\"id\":\"771825006014889984\",\"name\":\"beancount\",\"type\":1,\"options\":[{\"name\":\"text\",\"type\":3,\"value\":\"@KFC 12.4 cba > food\"}]}}";
+        let command: Interaction = serde_json::from_str(json).unwrap();
+        assert_eq!(command.kind, Interaction::APPLICATION_COMMAND);
+        assert_eq!(command.user_id(), Some("53908232506183680"));
+        assert_eq!(command.text(), Some("@KFC 12.4 cba > food"));
+
+        let json =
+            "{\"type\":2,\"id\":\"1\",\"user\":{\"id\":\"42\"},\"data\":{\"name\":\"beancount\"}}";
+        let command: Interaction = serde_json::from_str(json).unwrap();
+        assert_eq!(command.user_id(), Some("42"));
+        assert_eq!(command.text(), None);
+    }
+
+    #[test]
+    fn it_serializes_responses() {
+        assert_eq!(
+            serde_json::to_string(&InteractionResponse::pong()).unwrap(),
+            "{\"type\":1}"
+        );
+        assert_eq!(
+            serde_json::to_string(&InteractionResponse::ephemeral("Saved".into())).unwrap(),
+            "{\"type\":4,\"data\":{\"content\":\"Saved\",\"flags\":64}}"
+        );
+        let long = InteractionResponse::ephemeral("x".repeat(3000));
+        assert_eq!(
+            long.data.unwrap().content.chars().count(),
+            MAX_CONTENT_CHARS
+        );
+    }
+
+    #[test]
+    fn it_verifies_signatures() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let body = b"{\"type\":1}";
+        let signature = hex::encode(key.sign(b"1700000000{\"type\":1}").to_bytes());
+
+        assert!(verify_signature(
+            &public_key,
+            &signature,
+            "1700000000",
+            body
+        ));
+        assert!(!verify_signature(
+            &public_key,
+            &signature,
+            "1700000001",
+            body
+        ));
+        assert!(!verify_signature(
+            &public_key,
+            &signature,
+            "1700000000",
+            b"{}"
+        ));
+        assert!(!verify_signature(&public_key, "zz", "1700000000", body));
+        assert!(!verify_signature("abcd", &signature, "1700000000", body));
+    }
+}
@@ -1 +1,4 @@
+#[cfg(feature = "discord")]
+pub mod discord;
+pub mod slack;
 pub mod telegram;
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// What the Events API posts: a check of the request URL when it's set, then
+/// the events the app subscribed to.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventPayload {
+    UrlVerification {
+        challenge: String,
+    },
+    EventCallback {
+        event_id: String,
+        event: Event,
+    },
+    /// e.g. `app_rate_limited`.
+    #[serde(other)]
+    Other,
+}
+
+/// A `message` or `app_mention` event.
+#[derive(Deserialize, Debug)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Set on messages posted by bots, the app's own replies included.
+    #[serde(default)]
+    pub bot_id: Option<String>,
+    /// Set on edits, deletions, joins and other messages that aren't plain ones.
+    #[serde(default)]
+    pub subtype: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// `im` for direct messages.
+    #[serde(default)]
+    pub channel_type: Option<String>,
+    /// The message's timestamp, which is also its id to reply in a thread to.
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// A slash command such as `/beancount @KFC 12.4 cba > food`, posted as a form.
+#[derive(Deserialize, Debug)]
+pub struct SlashCommand {
+    pub command: String,
+    /// What was typed after the command.
+    #[serde(default)]
+    pub text: String,
+    pub user_id: String,
+    pub channel_id: String,
+    #[serde(default)]
+    pub response_url: Option<String>,
+}
+
+impl SlashCommand {
+    pub fn from_form(body: &str) -> Result<Self, serde_urlencoded::de::Error> {
+        serde_urlencoded::from_str(body)
+    }
+}
+
+/// A message sent with `chat.postMessage`.
+#[derive(Serialize, Debug)]
+pub struct PostMessage {
+    pub channel: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_ts: Option<String>,
+}
+
+/// The answer to a slash command, which only its sender sees unless
+/// `response_type` is `in_channel`.
+#[derive(Serialize, Debug)]
+pub struct CommandResponse {
+    pub response_type: String,
+    pub text: String,
+}
+
+impl CommandResponse {
+    pub fn ephemeral(text: String) -> Self {
+        CommandResponse {
+            response_type: "ephemeral".into(),
+            text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_deserialize_url_verification() {
+        let json = "{\"token\":\"Jhj5dZrVaK7ZwHHjRyZWjbDl\",\"challenge\":\"3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P\",\"type\":\"url_verification\"}";
+        let payload: EventPayload = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            payload,
+            EventPayload::UrlVerification { challenge } if challenge.starts_with("3eZbrw1a")
+        ));
+    }
+
+    #[test]
+    fn it_deserialize_direct_message_event() {
+        let json = "{\"token\":\"XXYYZZ\",\"team_id\":\"T061EG9R6\",\"api_app_id\":\"A0PNCHHK2\",\"event\":{\"type\":\"message\",\"channel\":\"D024BE91L\",\"user\":\"U2147483697\",\"text\":\"@KFC chicken 12.9 cba > food\",\"ts\":\"1355517523.000005\",\"channel_type\":\"im\"},\"type\":\"event_callback\",\"event_id\":\"Ev0PV52K21\",\"event_time\":1355517523}";
+        let payload: EventPayload = serde_json::from_str(json).unwrap();
+        let event = match payload {
+            EventPayload::EventCallback { event_id, event } => {
+                assert_eq!(event_id, "Ev0PV52K21");
+                event
+            }
+            other => panic!("unexpected payload {:?}", other),
+        };
+        assert_eq!(event.event_type, "message");
+        assert_eq!(event.text, "@KFC chicken 12.9 cba > food");
+        assert_eq!(event.channel_type.as_deref(), Some("im"));
+        assert!(event.bot_id.is_none());
+
+        let json = "{\"type\":\"app_rate_limited\",\"team_id\":\"T123456\",\"minute_rate_limited\":1518467820}";
+        assert!(matches!(
+            serde_json::from_str(json).unwrap(),
+            EventPayload::Other
+        ));
+    }
+
+    #[test]
+    fn it_parses_slash_command_forms() {
+        let body = "token=gIkuvaNzQIHg97ATvDxqgjtO&team_id=T0001&channel_id=C2147483705&user_id=U2147483697&command=%2Fbeancount&text=%40KFC+12.4+cba+%3E+food&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2F1234%2F5678";
+        let command = SlashCommand::from_form(body).unwrap();
+        assert_eq!(command.command, "/beancount");
+        assert_eq!(command.text, "@KFC 12.4 cba > food");
+        assert_eq!(command.user_id, "U2147483697");
+        assert_eq!(
+            command.response_url.as_deref(),
+            Some("https://hooks.slack.com/commands/1234/5678")
+        );
+    }
+}
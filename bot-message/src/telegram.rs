@@ -2,26 +2,70 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Update {
-    update_id: u64,
+    pub update_id: u64,
     pub message: Option<Message>,
     pub edited_message: Option<Message>,
+    pub callback_query: Option<CallbackQuery>,
+}
+
+/// A tap on an inline keyboard button of one of the bot's messages.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    /// The message with the button, missing when it's too old.
+    pub message: Option<Message>,
+    pub data: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     pub message_id: u64,
-    from: User,
+    pub from: User,
     pub chat: Chat,
-    date: u64,
+    pub date: u64,
+    /// Empty for messages without text, e.g. a document.
+    #[serde(default)]
     pub text: String,
+    #[serde(default)]
+    pub document: Option<Document>,
+    /// Sizes of a photo, smallest first; empty for messages without one.
+    #[serde(default)]
+    pub photo: Vec<PhotoSize>,
+    /// Text sent along with a document or photo.
+    #[serde(default)]
+    pub caption: Option<String>,
+}
+
+/// A file sent as a document, downloaded through `getFile`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Document {
+    pub file_id: String,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub file_size: Option<u64>,
+}
+
+/// One size of a photo, downloaded through `getFile` like a document.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PhotoSize {
+    pub file_id: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub file_size: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
-    id: u64,
+    pub id: u64,
     is_bot: bool,
     first_name: String,
     username: String,
+    #[serde(default)]
     language_code: String,
 }
 
@@ -40,6 +84,23 @@ pub struct ResponseBody {
     pub chat_id: u64,
     pub text: String,
     pub reply_to_message_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+/// Buttons under a message, in rows.
+#[derive(Serialize, Debug)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+/// A button sending `callback_data` back in a [`CallbackQuery`] when tapped.
+#[derive(Serialize, Debug)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    pub callback_data: String,
 }
 
 #[cfg(test)]
@@ -48,7 +109,7 @@ mod tests {
     #[test]
     fn it_deserialize_update_with_message() {
         let json = "{\"update_id\":459592837, \"message\":{\"message_id\":7,\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1631506802,\"text\":\"@KFC chicken 12.9 AUD CBA > food\"}}";
-        let update: Update = serde_json::from_str(&json).unwrap();
+        let update: Update = serde_json::from_str(json).unwrap();
         assert_eq!(update.update_id, 459592837);
         assert_eq!(
             update.message.unwrap().text,
@@ -56,10 +117,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_deserialize_update_with_document() {
+        let json = "{\"update_id\":459593100,\"message\":{\"message_id\":280,\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1640933453,\"document\":{\"file_name\":\"statement.csv\",\"mime_type\":\"text/csv\",\"file_id\":\"BQACAgUAAxkBAAIBGGHO\",\"file_unique_id\":\"AgADxQQAAr\",\"file_size\":1024},\"caption\":\"cba\"}}";
+        let update: Update = serde_json::from_str(json).unwrap();
+        let message = update.message.unwrap();
+        assert_eq!(message.text, "");
+        assert_eq!(message.caption.as_deref(), Some("cba"));
+        let document = message.document.unwrap();
+        assert_eq!(document.file_id, "BQACAgUAAxkBAAIBGGHO");
+        assert_eq!(document.file_name.as_deref(), Some("statement.csv"));
+    }
+
+    #[test]
+    fn it_deserialize_update_with_photo() {
+        let json = "{\"update_id\":459593150,\"message\":{\"message_id\":290,\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1640933453,\"photo\":[{\"file_id\":\"AgACAgUAAxkBAAIBIm\",\"file_unique_id\":\"AQADsK0x\",\"file_size\":1402,\"width\":67,\"height\":90},{\"file_id\":\"AgACAgUAAxkBAAIBIn\",\"file_unique_id\":\"AQADsK0y\",\"file_size\":98304,\"width\":960,\"height\":1280}]}}";
+        let update: Update = serde_json::from_str(json).unwrap();
+        let message = update.message.unwrap();
+        assert_eq!(message.text, "");
+        assert_eq!(message.photo.len(), 2);
+        assert_eq!(message.photo[1].file_id, "AgACAgUAAxkBAAIBIn");
+        assert_eq!(message.photo[1].file_size, Some(98304));
+    }
+
+    #[test]
+    fn it_serializes_inline_keyboards() {
+        let body = ResponseBody {
+            method: "sendMessage".into(),
+            chat_id: 1,
+            text: "Save?".into(),
+            reply_to_message_id: 2,
+            parse_mode: None,
+            reply_markup: Some(InlineKeyboardMarkup {
+                inline_keyboard: vec![vec![InlineKeyboardButton {
+                    text: "Save".into(),
+                    callback_data: "receipt:save:1-2".into(),
+                }]],
+            }),
+        };
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            "{\"method\":\"sendMessage\",\"chat_id\":1,\"text\":\"Save?\",\"reply_to_message_id\":2,\"reply_markup\":{\"inline_keyboard\":[[{\"text\":\"Save\",\"callback_data\":\"receipt:save:1-2\"}]]}}"
+        );
+    }
+
     #[test]
     fn it_deserialize_update_with_edited_message() {
         let json = "{\"update_id\":459593047,\"edited_message\":{\"message_id\":276,\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1640933453,\"edit_date\":1640933464,\"text\":\"2021-12-30 @Coles 30 cba > food\",\"entities\":[{\"offset\":11,\"length\":6,\"type\":\"mention\"}]}}";
-        let update: Update = serde_json::from_str(&json).unwrap();
+        let update: Update = serde_json::from_str(json).unwrap();
         assert_eq!(update.update_id, 459593047);
         assert!(update.message.is_none());
         assert_eq!(
@@ -67,4 +172,13 @@ mod tests {
             "2021-12-30 @Coles 30 cba > food"
         );
     }
+
+    #[test]
+    fn it_deserialize_update_with_callback_query() {
+        let json = "{\"update_id\":459593200,\"callback_query\":{\"id\":\"1063732217806040\",\"from\":{\"id\":247673932,\"is_bot\":false,\"first_name\":\"Liang\",\"username\":\"liul85\",\"language_code\":\"en\"},\"message\":{\"message_id\":301,\"from\":{\"id\":123456,\"is_bot\":true,\"first_name\":\"beancount\",\"username\":\"beancount_bot\"},\"chat\":{\"id\":247673932,\"first_name\":\"Liang\",\"username\":\"liul85\",\"type\":\"private\"},\"date\":1631506802,\"text\":\"New bank transaction\"},\"chat_instance\":\"-5093712373\",\"data\":\"bank:save:tx-1\"}}";
+        let update: Update = serde_json::from_str(json).unwrap();
+        let callback = update.callback_query.unwrap();
+        assert_eq!(callback.data.as_deref(), Some("bank:save:tx-1"));
+        assert_eq!(callback.message.unwrap().message_id, 301);
+    }
 }
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Result};
+use beancount_core::import::StatementFormat;
+use beancount_core::parser::BeancountParser;
+use beancount_core::query::Query;
+use beancount_core::report::{GroupBy, Period};
+use clap::{Parser, Subcommand};
+use std::fs::File;
+use std::path::PathBuf;
+
+/// Enters and queries transactions from a terminal, with the same settings and
+/// store env vars as the bot.
+#[derive(Parser)]
+#[command(name = "beancount-bot", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Saves a transaction written like a chat message, e.g.
+    /// `@KFC hamburger 12.40 cba > food`
+    Add { input: String },
+    /// Saves the rows of a CSV, OFX or QIF statement not in the ledger yet,
+    /// read with an import profile and booked with the merchant rules
+    Import {
+        file: PathBuf,
+        /// Name of the `[import_profiles]` entry, needed when there are several
+        #[arg(long)]
+        profile: Option<String>,
+        /// Alias of the account the statement is for, instead of the profile's
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Prints totals per expense and income account
+    Report {
+        /// `YYYY-MM`, `YYYY` or a `FROM..TO` range of months or dates, the
+        /// current month by default
+        period: Option<Period>,
+        /// `account`, or `category` for the first two segments of the account
+        #[arg(long, default_value = "account")]
+        by: GroupBy,
+        /// Prints JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Prints the transactions of a period as CSV, or writes them to a file
+    Export {
+        /// `YYYY-MM`, `YYYY` or a `FROM..TO` range of months or dates, the
+        /// current month by default
+        period: Option<Period>,
+        /// Only the transactions matching a query, e.g.
+        /// `account:Expenses:Food amount>100`
+        #[arg(long, default_value = "")]
+        filter: Query,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Runs a scheduled job by name, or the jobs due this minute, e.g. from a
+    /// Kubernetes CronJob
+    Job { name: Option<String> },
+    /// Loads and validates the settings
+    CheckConfig,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let settings = beancount::load_settings().await?;
+    match cli.command {
+        Command::Add { input } => {
+            let transaction = BeancountParser::new(settings.clone()).parse(&input)?;
+            let store = beancount::create_store(Some(&settings))?;
+            print!("{}", store.save(transaction).await?);
+        }
+        Command::Import {
+            file,
+            profile,
+            account,
+        } => {
+            let (name, mut profile) = beancount::import_profile(&settings, profile.as_deref())?;
+            if account.is_some() {
+                profile.account = account;
+            }
+            let format =
+                StatementFormat::from_file_name(&file.to_string_lossy()).ok_or_else(|| {
+                    anyhow!("{} isn't a .csv, .ofx, .qfx or .qif file", file.display())
+                })?;
+            let store = beancount::create_store(Some(&settings))?;
+            let (saved, skipped) = beancount::import_statement(
+                store.as_ref(),
+                &settings,
+                &name,
+                &profile,
+                format,
+                File::open(file)?,
+            )
+            .await?;
+            for entry in saved.iter() {
+                println!("{}", entry);
+            }
+            for reason in skipped.iter() {
+                eprintln!("skipped {}", reason);
+            }
+            println!(
+                "Imported {} transactions, skipped {}",
+                saved.len(),
+                skipped.len()
+            );
+        }
+        Command::Report { period, by, json } => {
+            let period = period.unwrap_or_else(|| Period::month_of(settings.today()));
+            let store = beancount::create_store(Some(&settings))?;
+            let report = beancount::period_report(store.as_ref(), &settings, &period, by).await?;
+            if json {
+                println!("{}", report.to_json()?);
+            } else {
+                print!("{}", report.to_text());
+            }
+        }
+        Command::Export {
+            period,
+            filter,
+            output,
+        } => {
+            let period = period.unwrap_or_else(|| Period::month_of(settings.today()));
+            let store = beancount::create_store(Some(&settings))?;
+            let csv = beancount::period_export(store.as_ref(), &settings, &period, &filter).await?;
+            match output {
+                Some(path) => std::fs::write(path, csv)?,
+                None => print!("{}", csv),
+            }
+        }
+        Command::Job { name } => {
+            let outcomes = match name {
+                Some(name) => vec![beancount::run_job(&name).await?],
+                None => beancount::run_due_jobs().await?,
+            };
+            for outcome in outcomes.iter() {
+                println!("{}", outcome);
+            }
+        }
+        Command::CheckConfig => println!(
+            "Settings are valid, {} account aliases configured",
+            settings.accounts.len()
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::settings::{MerchantRule, Settings};
+    use repository::memory_store::MemoryStore;
+
+    fn settings() -> Settings {
+        Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .default_from_account("cba")
+            .merchant_rule(MerchantRule {
+                pattern: "^WOOLWORTHS".into(),
+                payee: "Woolworths".into(),
+                account: Some("food".into()),
+                narration: Some("groceries".into()),
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_imports_debits_matching_merchant_rules() {
+        let store = MemoryStore::new();
+        let csv = "date,description,amount\n08/09/2021,WOOLWORTHS 1234 SYDNEY,-23.50\n2021-09-09,SALARY,3000\n2021-09-10,COLES 0421,-4.00\n";
+        let settings = settings();
+        let (name, profile) = beancount::import_profile(&settings, None).unwrap();
+        let import = || {
+            beancount::import_statement(
+                &store,
+                &settings,
+                &name,
+                &profile,
+                StatementFormat::Csv,
+                csv.as_bytes(),
+            )
+        };
+        let (saved, skipped) = import().await.unwrap();
+        assert_eq!(
+            saved,
+            vec!["2021-09-08 * \"Woolworths\" \"groceries\"\n  Assets:CBA        -23.50 AUD\n  Expenses:Food        23.50 AUD\n"]
+        );
+        assert_eq!(
+            skipped,
+            vec![
+                "line 3: no merchant rule matches SALARY",
+                "line 4: no merchant rule matches COLES 0421"
+            ]
+        );
+        let (saved, skipped) = import().await.unwrap();
+        assert!(saved.is_empty());
+        assert_eq!(skipped[2], "line 2: Woolworths is already in the ledger");
+
+        let report = |month: &str| {
+            let period = month.parse().unwrap();
+            let (store, settings) = (&store, &settings);
+            async move {
+                beancount::period_report(store, settings, &period, GroupBy::Account)
+                    .await
+                    .unwrap()
+                    .to_text()
+            }
+        };
+        assert_eq!(
+            report("2021-09").await,
+            format!("{:<40} {:>10.2} AUD\n", "Expenses:Food", 23.5)
+        );
+        assert_eq!(
+            report("2021-10").await,
+            "No expenses or income in 2021-10\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use base64::{decode, encode};
+use beancount_core::parser::BeancountParser;
+use beancount_core::reply::{format_reply, Reply};
+use beancount_core::secret::{redact, Secret};
+use beancount_core::settings::{interpolate_with, ConfigFormat, Settings, FILE_PATH_TEMPLATE_ENV};
+use bot_message::telegram::{Message, ResponseBody, Update};
+use serde::{Deserialize, Serialize};
+use worker::wasm_bindgen::JsValue;
+use worker::{
+    console_error, console_log, event, Context, Env, Error, Fetch, Headers, Method, Request,
+    RequestInit, Response, Result,
+};
+
+/// KV namespace holding handled update ids and the active ledger of each chat.
+const STATE_NAMESPACE: &str = "BOT_STATE";
+/// Same limit as the other deployments, Telegram updates are a few kilobytes.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// Telegram stops retrying a webhook long before this.
+const HANDLED_UPDATE_TTL: u64 = 24 * 60 * 60;
+
+/// Cloudflare Workers entry point. `repository::Store` and its `HttpClient` need
+/// `Send` futures, which the JS-backed `Fetch` doesn't give, and `GithubStore`
+/// waits between retries on tokio's timer, which Workers don't have, so this
+/// saves through the GitHub contents API itself and keeps bot state in KV.
+#[event(fetch)]
+async fn fetch(mut request: Request, env: Env, _ctx: Context) -> Result<Response> {
+    match (request.method(), request.path().as_str()) {
+        (Method::Get, "/healthz") => Response::ok("ok"),
+        (Method::Post, "/") | (Method::Post, "/webhook") => {
+            // Set with the webhook's `secret_token`, Telegram sends it along.
+            if let Ok(secret) = env.secret("TELEGRAM_WEBHOOK_SECRET") {
+                let token = request.headers().get("X-Telegram-Bot-Api-Secret-Token")?;
+                if token.as_deref() != Some(secret.to_string().as_str()) {
+                    return Response::error("secret token doesn't match", 403);
+                }
+            }
+            let is_json = request.headers().get("Content-Type")?.is_some_and(|v| {
+                v.split(';').next().unwrap_or_default().trim() == "application/json"
+            });
+            if !is_json {
+                return Response::error("request body must be application/json", 415);
+            }
+            let body = request.bytes().await?;
+            if body.len() > MAX_BODY_BYTES {
+                return Response::error("request body is too large", 413);
+            }
+            match String::from_utf8(body) {
+                Ok(body) => handle_update(&env, &body).await,
+                Err(_) => Response::error("request body isn't valid UTF-8", 400),
+            }
+        }
+        _ => Response::error("Not Found", 404),
+    }
+}
+
+async fn handle_update(env: &Env, body: &str) -> Result<Response> {
+    console_log!("request body is {}", redact(body));
+    let update: Update = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => return Response::ok("Failed to deserialize request body"),
+    };
+
+    // Telegram retries updates it didn't get a 200 for, which must not be saved
+    // twice but answered as they were the first time.
+    let state = env.kv(STATE_NAMESPACE)?;
+    let handled_key = format!("update:{}", update.update_id);
+    if let Some(response) = state.get(&handled_key).text().await? {
+        console_log!("update {} was already handled", update.update_id);
+        // Updates handled before responses were kept are marked with "1".
+        return match serde_json::from_str::<serde_json::Value>(&response) {
+            Ok(response) if response.is_object() => Response::from_json(&response),
+            _ => Response::ok(""),
+        };
+    }
+
+    let message = match update.message.or(update.edited_message) {
+        Some(v) => v,
+        None => return Response::ok("Could not get message or edited_message from request"),
+    };
+
+    // Placeholders come from the Worker's vars and secrets, there's no process
+    // environment on wasm.
+    let config = interpolate_with(&env.secret("CONFIG")?.to_string(), |name| {
+        env.secret(name)
+            .map(|v| v.to_string())
+            .or_else(|_| env.var(name).map(|v| v.to_string()))
+            .ok()
+    })
+    .map_err(internal)?;
+    let mut settings = Settings::parse_isolated(&config, ConfigFormat::Toml).map_err(internal)?;
+    if let Ok(template) = env.var(FILE_PATH_TEMPLATE_ENV) {
+        settings.ledger_path = Some(template.to_string());
+    }
+    let settings = settings.for_chat(message.chat.id).for_user(message.from.id);
+    let active_key = format!("ledger:{}", message.chat.id);
+    let settings = match state.get(&active_key).text().await? {
+        Some(name) if settings.profiles.contains_key(&name) => {
+            settings.for_profile(&name).map_err(internal)?
+        }
+        _ => settings,
+    };
+
+    let reply = if message.text.starts_with('/') {
+        let text = match message.text.split_whitespace().collect::<Vec<_>>()[..] {
+            ["/ledger", "use", "default"] => {
+                state.delete(&active_key).await?;
+                "Switched to the default ledger".to_string()
+            }
+            ["/ledger", "use", name] => match settings.for_profile(name) {
+                Ok(_) => {
+                    state.put(&active_key, name)?.execute().await?;
+                    format!("Switched to the {} ledger", name)
+                }
+                Err(e) => failure("run command", e),
+            },
+            [command, ..] => failure(
+                "run command",
+                format!("{} isn't available on Cloudflare Workers", command),
+            ),
+            [] => failure("run command", "empty command"),
+        };
+        Reply::plain(text)
+    } else {
+        match BeancountParser::new(settings.clone()).parse(&message.text) {
+            Ok(transaction) => {
+                let saved = transaction.clone();
+                let text = String::from(transaction);
+                let github = GithubContents::from_env(env, settings.ledger_repo())?;
+                github
+                    .append(
+                        &saved.ledger_path(settings.ledger_path.as_deref()),
+                        &text,
+                        settings.file_header.as_deref(),
+                        &saved.year(),
+                        &saved.commit_message(settings.commit_message.as_deref()),
+                    )
+                    .await?;
+                format_reply(&settings, &saved, &text, None)
+            }
+            Err(e) => Reply::plain(failure("parse input", e)),
+        }
+    };
+
+    let response = reply_body(&message, reply);
+    state
+        .put(&handled_key, serde_json::to_string(&response)?)?
+        .expiration_ttl(HANDLED_UPDATE_TTL)
+        .execute()
+        .await?;
+    Response::from_json(&response)
+}
+
+fn reply_body(message: &Message, reply: Reply) -> ResponseBody {
+    ResponseBody {
+        method: "sendMessage".into(),
+        chat_id: message.chat.id,
+        text: reply.text,
+        reply_to_message_id: message.message_id,
+        parse_mode: reply.parse_mode,
+        reply_markup: None,
+    }
+}
+
+fn failure(action: &str, e: impl std::fmt::Display) -> String {
+    console_error!("Failed to {}: {}", action, e);
+    format!(
+        "⚠️\n==============================\nFailed to {}: {}",
+        action, e
+    )
+}
+
+fn internal(e: impl std::fmt::Display) -> Error {
+    Error::RustError(e.to_string())
+}
+
+#[derive(Deserialize)]
+struct FileContent {
+    content: String,
+    sha: String,
+}
+
+#[derive(Serialize)]
+struct UpdateRequest {
+    message: String,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha: Option<String>,
+}
+
+/// The part of the GitHub contents API needed to append a transaction, over
+/// `fetch`.
+struct GithubContents {
+    owner: String,
+    repo: String,
+    token: Secret<String>,
+}
+
+impl GithubContents {
+    /// Reads `GITHUB_OWNER` and `GITHUB_REPO` vars and the `GITHUB_TOKEN` secret;
+    /// `repository` (`owner/name` or `name`) overrides the repo.
+    fn from_env(env: &Env, repository: Option<&str>) -> Result<Self> {
+        let mut owner = env.var("GITHUB_OWNER")?.to_string();
+        let mut repo = env.var("GITHUB_REPO")?.to_string();
+        if let Some(repository) = repository {
+            match repository.split_once('/') {
+                Some((o, r)) => {
+                    owner = o.into();
+                    repo = r.into();
+                }
+                None => repo = repository.into(),
+            }
+        }
+        Ok(GithubContents {
+            owner,
+            repo,
+            token: Secret::new(env.secret("GITHUB_TOKEN")?.to_string()),
+        })
+    }
+
+    async fn append(
+        &self,
+        path: &str,
+        text: &str,
+        header: Option<&str>,
+        year: &str,
+        message: &str,
+    ) -> Result<()> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/contents/{}",
+            self.owner, self.repo, path
+        );
+        let mut response = self.send(Method::Get, &url, None).await?;
+        let (content, sha) = match response.status_code() {
+            200 => {
+                let file: FileContent = response.json().await?;
+                let decoded = decode(file.content.replace('\n', "")).map_err(internal)?;
+                (
+                    String::from_utf8_lossy(&decoded).into_owned(),
+                    Some(file.sha),
+                )
+            }
+            404 => (
+                header
+                    .map(|header| header.replace("{year}", year))
+                    .unwrap_or_default(),
+                None,
+            ),
+            status => {
+                console_error!("Failed to get file {}: {}", path, response.text().await?);
+                return Err(internal(format!("github api returned {}", status)));
+            }
+        };
+
+        let body = serde_json::to_string(&UpdateRequest {
+            message: message.into(),
+            content: encode(format!("{}\n{}", content, text)),
+            sha,
+        })?;
+        let mut response = self.send(Method::Put, &url, Some(body)).await?;
+        match response.status_code() {
+            200 | 201 => Ok(()),
+            status => {
+                console_error!("Failed to save transaction: {}", response.text().await?);
+                Err(internal(format!("github api returned {}", status)))
+            }
+        }
+    }
+
+    async fn send(&self, method: Method, url: &str, body: Option<String>) -> Result<Response> {
+        let mut headers = Headers::new();
+        headers.set("Accept", "application/vnd.github.v3+json")?;
+        headers.set("Authorization", &format!("token {}", self.token.expose()))?;
+        headers.set("User-Agent", "beancount-automation/0.1.0")?;
+        let mut init = RequestInit::new();
+        init.with_method(method)
+            .with_headers(headers)
+            .with_body(body.map(|body| JsValue::from_str(&body)));
+        Fetch::Request(Request::new_with_init(url, &init)?)
+            .send()
+            .await
+    }
+}
@@ -0,0 +1,108 @@
+use crate::settings_cache::is_fresh;
+use crate::Store;
+use anyhow::Result;
+use beancount_core::accounts::{open_accounts, suggest_aliases};
+use beancount_core::clock::{Clock, SystemClock};
+use beancount_core::settings::AccountSettings;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+struct CachedAccounts {
+    files: Vec<String>,
+    loaded_at: DateTime<Utc>,
+    accounts: HashMap<String, AccountSettings>,
+}
+
+/// Accounts discovered from the `open` directives of ledger files, kept for the
+/// given TTL like [`crate::settings_cache::SettingsCache`].
+pub struct AccountDiscovery {
+    cached: Mutex<Option<CachedAccounts>>,
+    clock: &'static dyn Clock,
+}
+
+impl AccountDiscovery {
+    pub const fn new() -> Self {
+        AccountDiscovery {
+            cached: Mutex::new(None),
+            clock: &SystemClock,
+        }
+    }
+
+    /// Ages entries with `clock` instead of the system time.
+    pub const fn with_clock(mut self, clock: &'static dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn get(
+        &self,
+        store: &dyn Store,
+        files: &[String],
+        ttl: Duration,
+    ) -> Result<HashMap<String, AccountSettings>> {
+        if let Some(entry) = self.cached.lock().unwrap().as_ref() {
+            if entry.files == files && is_fresh(entry.loaded_at, self.clock.now(), ttl) {
+                return Ok(entry.accounts.clone());
+            }
+        }
+
+        let accounts = discover(store, files).await?;
+        *self.cached.lock().unwrap() = Some(CachedAccounts {
+            files: files.to_vec(),
+            loaded_at: self.clock.now(),
+            accounts: accounts.clone(),
+        });
+        Ok(accounts)
+    }
+
+    /// Forces the next `get` to scan the ledger again.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+impl Default for AccountDiscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads `files` and the files they include, then suggests aliases for every
+/// account still open. Missing files are skipped, as are includes with globs.
+pub async fn discover(
+    store: &dyn Store,
+    files: &[String],
+) -> Result<HashMap<String, AccountSettings>> {
+    let contents = crate::read_files(store, files).await?;
+    let accounts = open_accounts(contents.iter().map(|(_, content)| content.as_str()));
+    info!("discovered {} open accounts", accounts.len());
+    Ok(suggest_aliases(&accounts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_follows_includes_relative_to_the_including_file() {
+        let store = MemoryStore::new()
+            .with_file(
+                "main.bean",
+                "include \"ledger/accounts.bean\"\ninclude \"main.bean\"\n",
+            )
+            .with_file(
+                "ledger/accounts.bean",
+                "include \"closed.bean\"\n2020-01-01 open Assets:Bank:ING AUD\n2020-01-01 open Expenses:Food\n",
+            )
+            .with_file("ledger/closed.bean", "2021-01-01 close Expenses:Food\n");
+        let accounts = discover(&store, &["main.bean".into(), "missing.bean".into()])
+            .await
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts["ing"].account, "Assets:Bank:ING");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::settings_cache::is_fresh;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::aliases::{parse_aliases, render_aliases};
+use beancount_core::clock::{Clock, SystemClock};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Aliases added with `/alias add`, in the default ledger repository.
+pub const ALIASES_FILE: &str = "aliases.toml";
+
+struct CachedAliases {
+    loaded_at: DateTime<Utc>,
+    aliases: BTreeMap<String, String>,
+}
+
+/// The aliases of [`ALIASES_FILE`], kept for the given TTL like
+/// [`crate::settings_cache::SettingsCache`].
+pub struct AliasCache {
+    cached: Mutex<Option<CachedAliases>>,
+    clock: &'static dyn Clock,
+}
+
+impl AliasCache {
+    pub const fn new() -> Self {
+        AliasCache {
+            cached: Mutex::new(None),
+            clock: &SystemClock,
+        }
+    }
+
+    /// Ages entries with `clock` instead of the system time.
+    pub const fn with_clock(mut self, clock: &'static dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn get(&self, store: &dyn Store, ttl: Duration) -> Result<BTreeMap<String, String>> {
+        if let Some(entry) = self.cached.lock().unwrap().as_ref() {
+            if is_fresh(entry.loaded_at, self.clock.now(), ttl) {
+                return Ok(entry.aliases.clone());
+            }
+        }
+
+        let aliases = load_aliases(store).await?;
+        *self.cached.lock().unwrap() = Some(CachedAliases {
+            loaded_at: self.clock.now(),
+            aliases: aliases.clone(),
+        });
+        Ok(aliases)
+    }
+
+    /// Forces the next `get` to read the file again.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+impl Default for AliasCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The aliases of [`ALIASES_FILE`], none when it doesn't exist yet.
+pub async fn load_aliases(store: &dyn Store) -> Result<BTreeMap<String, String>> {
+    match store.read(ALIASES_FILE).await? {
+        Some(content) => parse_aliases(&content)
+            .map_err(|e| anyhow!("{} isn't a valid aliases file: {}", ALIASES_FILE, e)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Adds `alias` for `account` to [`ALIASES_FILE`], replacing the account it
+/// had there.
+pub async fn add_alias(store: &dyn Store, alias: &str, account: &str) -> Result<()> {
+    let mut aliases = load_aliases(store).await?;
+    aliases.insert(alias.into(), account.into());
+    store
+        .write(
+            ALIASES_FILE,
+            &render_aliases(&aliases),
+            &format!("added alias {} for {}", alias, account),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_adds_aliases_to_the_file() {
+        let store = MemoryStore::new();
+        assert!(load_aliases(&store).await.unwrap().is_empty());
+
+        let cache = AliasCache::new();
+        let ttl = Duration::from_secs(300);
+        add_alias(&store, "groceries", "Expenses:Food:Groceries")
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get(&store, ttl).await.unwrap()["groceries"],
+            "Expenses:Food:Groceries"
+        );
+
+        add_alias(&store, "cba", "Assets:CBA").await.unwrap();
+        assert_eq!(
+            store.file(ALIASES_FILE).unwrap(),
+            "cba = \"Assets:CBA\"\ngroceries = \"Expenses:Food:Groceries\"\n"
+        );
+        assert_eq!(cache.get(&store, ttl).await.unwrap().len(), 1);
+        cache.invalidate();
+        assert_eq!(cache.get(&store, ttl).await.unwrap().len(), 2);
+    }
+}
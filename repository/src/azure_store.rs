@@ -0,0 +1,283 @@
+use crate::error::StoreError;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::encode;
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use log::{error, info, warn};
+use reqwest::{header, Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use tracing::instrument;
+
+const API_VERSION: &str = "7.0";
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Stores the ledger in an Azure DevOps Git repository through the pushes API.
+///
+/// Every push names the branch tip it was based on as `oldObjectId`; when someone
+/// else pushed in between Azure DevOps rejects it with 409 and the push is rebuilt
+/// from the new tip.
+pub struct AzureDevOpsStore {
+    base_url: String,
+    branch: String,
+    client: Client,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    commit_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Refs {
+    value: Vec<GitRef>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GitRef {
+    object_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    content: String,
+}
+
+impl AzureDevOpsStore {
+    pub fn new() -> Result<Self> {
+        let organization = env::var("AZURE_DEVOPS_ORG")?;
+        let project = env::var("AZURE_DEVOPS_PROJECT")?;
+        let repo = env::var("AZURE_DEVOPS_REPO")?;
+        let token = Secret::from_env("AZURE_DEVOPS_TOKEN")?;
+        let branch = env::var("AZURE_DEVOPS_BRANCH").unwrap_or_else(|_| "main".into());
+
+        let mut headers = header::HeaderMap::new();
+        let mut authorization = header::HeaderValue::from_str(&format!(
+            "Basic {}",
+            encode(format!(":{}", token.expose()))
+        ))?;
+        authorization.set_sensitive(true);
+        headers.insert(header::AUTHORIZATION, authorization);
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .user_agent("beancount-automation/0.1.0")
+            .build()?;
+        Ok(AzureDevOpsStore {
+            base_url: format!(
+                "https://dev.azure.com/{}/{}/_apis/git/repositories/{}",
+                organization, project, repo
+            ),
+            branch,
+            client,
+            file_header: None,
+            ledger_path: None,
+            commit_message: None,
+        })
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    async fn branch_tip(&self) -> Result<String, StoreError> {
+        let response = self
+            .client
+            .get(format!("{}/refs", self.base_url))
+            .query(&[
+                ("filter", format!("heads/{}", self.branch)),
+                ("api-version", API_VERSION.into()),
+            ])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let refs: Refs = response.json().await?;
+                refs.value
+                    .into_iter()
+                    .next()
+                    .map(|git_ref| git_ref.object_id)
+                    .ok_or_else(|| {
+                        StoreError::Other(anyhow!("branch {} doesn't exist", self.branch))
+                    })
+            }
+            _ => Err(StoreError::from_response(
+                response,
+                format!("Failed to get branch {}", self.branch),
+            )
+            .await),
+        }
+    }
+
+    async fn get_item(&self, path: &str) -> Result<Option<String>, StoreError> {
+        let response = self
+            .client
+            .get(format!("{}/items", self.base_url))
+            .query(&[
+                ("path", path),
+                ("includeContent", "true"),
+                ("versionDescriptor.version", self.branch.as_str()),
+                ("$format", "json"),
+                ("api-version", API_VERSION),
+            ])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let item: Item = response.json().await?;
+                Ok(Some(item.content))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(StoreError::from_response(response, "Failed to get file content").await),
+        }
+    }
+
+    /// Pushes a single change built from the current file content, retrying when
+    /// the branch moved underneath us.
+    async fn push_with_retry<F>(
+        &self,
+        path: &str,
+        message: &str,
+        change: F,
+    ) -> Result<(), StoreError>
+    where
+        F: Fn(Option<String>) -> Result<Value, StoreError>,
+    {
+        for _ in 0..MAX_ATTEMPTS {
+            let old_object_id = self.branch_tip().await?;
+            let change = change(self.get_item(&item_path(path)).await?)?;
+            let body = json!({
+                "refUpdates": [{
+                    "name": format!("refs/heads/{}", self.branch),
+                    "oldObjectId": old_object_id,
+                }],
+                "commits": [{ "comment": message, "changes": [change] }],
+            });
+            let response = self
+                .client
+                .post(format!("{}/pushes", self.base_url))
+                .query(&[("api-version", API_VERSION)])
+                .json(&body)
+                .send()
+                .await?;
+            match response.status() {
+                StatusCode::OK | StatusCode::CREATED => {
+                    info!("Successfully pushed file {} to {}.", path, self.branch);
+                    return Ok(());
+                }
+                StatusCode::CONFLICT => {
+                    warn!("branch {} moved while pushing, retrying", self.branch);
+                }
+                _ => {
+                    return Err(StoreError::from_response(
+                        response,
+                        format!("Failed to push file {}", path),
+                    )
+                    .await)
+                }
+            }
+        }
+
+        error!("Gave up pushing {} after {} attempts", path, MAX_ATTEMPTS);
+        Err(StoreError::Conflict(format!(
+            "Failed to push file {}",
+            path
+        )))
+    }
+}
+
+fn item_path(path: &str) -> String {
+    format!("/{}", path.trim_start_matches('/'))
+}
+
+fn upsert_change(path: &str, exists: bool, bytes: &[u8]) -> Value {
+    json!({
+        "changeType": if exists { "edit" } else { "add" },
+        "item": { "path": item_path(path) },
+        "newContent": { "content": encode(bytes), "contentType": "base64encoded" },
+    })
+}
+
+#[async_trait]
+impl Store for AzureDevOpsStore {
+    #[instrument(name = "azure.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let message = transaction.commit_message(self.commit_message.as_deref());
+        let transaction_text = String::from(transaction);
+
+        self.push_with_retry(&path, &message, |content| {
+            let exists = content.is_some();
+            let content = content.unwrap_or_else(|| {
+                info!("file {} not found, will create the file", path);
+                crate::render_file_header(self.file_header.as_deref(), &year)
+            });
+            crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+            let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+            Ok(upsert_change(&path, exists, content.as_bytes()))
+        })
+        .await?;
+        Ok(transaction_text)
+    }
+
+    #[instrument(name = "azure.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        self.get_item(&item_path(path)).await
+    }
+
+    #[instrument(name = "azure.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError> {
+        self.push_with_retry(path, message, |content| {
+            Ok(upsert_change(path, content.is_some(), bytes))
+        })
+        .await
+    }
+
+    #[instrument(name = "azure.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError> {
+        self.push_with_retry(path, message, |content| match content {
+            Some(_) => Ok(json!({ "changeType": "delete", "item": { "path": item_path(path) } })),
+            None => Err(StoreError::NotFound(path.into())),
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_add_or_edit_change_for_rooted_path() {
+        let change = upsert_change("2021.bean", false, b"abc");
+        assert_eq!(change["changeType"], "add");
+        assert_eq!(change["item"]["path"], "/2021.bean");
+        assert_eq!(change["newContent"]["content"], "YWJj");
+        assert_eq!(upsert_change("/2021.bean", true, b"")["changeType"], "edit");
+    }
+}
@@ -0,0 +1,263 @@
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::bank_feed::BankTransaction;
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use hmac::{Hmac, Mac};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::env;
+
+pub const UP_API_URL: &str = "https://api.up.com.au/api/v1";
+
+/// Transactions waiting for a chat to confirm them, one file per bank id, in
+/// the default ledger repository.
+pub const PENDING_DIR: &str = ".beancount-bot/bank-feed";
+
+/// Whether `signature`, hex encoded, is the HMAC-SHA256 of `body` with
+/// `secret`, the way Up and most webhook senders sign their requests.
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    if !signature.len().is_multiple_of(2) || !signature.is_ascii() {
+        return false;
+    }
+    let bytes: Option<Vec<u8>> = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).ok())
+        .collect();
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(body);
+    bytes.is_some_and(|bytes| mac.verify_slice(&bytes).is_ok())
+}
+
+/// What an Up webhook event is about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpEvent {
+    /// Sent when the webhook is created or pinged.
+    Ping,
+    /// A new transaction, with its id.
+    TransactionCreated(String),
+    /// Settled and deleted transactions, which were already booked when they
+    /// were created.
+    Other(String),
+}
+
+pub fn up_event(body: &str) -> Result<UpEvent> {
+    let event: Value = serde_json::from_str(body)?;
+    let data = &event["data"];
+    let event_type = data["attributes"]["eventType"]
+        .as_str()
+        .ok_or_else(|| anyhow!("the event has no eventType"))?;
+    match event_type {
+        "PING" => Ok(UpEvent::Ping),
+        "TRANSACTION_CREATED" => data["relationships"]["transaction"]["data"]["id"]
+            .as_str()
+            .map(|id| UpEvent::TransactionCreated(id.into()))
+            .ok_or_else(|| anyhow!("the event has no transaction id")),
+        other => Ok(UpEvent::Other(other.into())),
+    }
+}
+
+/// Reads transactions with an Up personal access token, `UP_API_TOKEN`.
+/// `UP_API_URL` points it at another server, e.g. in tests.
+pub struct UpClient {
+    token: Secret<String>,
+    api_url: String,
+}
+
+impl UpClient {
+    pub fn from_env() -> Result<Self> {
+        Ok(UpClient {
+            token: Secret::from_env("UP_API_TOKEN")?,
+            api_url: env::var("UP_API_URL").unwrap_or_else(|_| UP_API_URL.into()),
+        })
+    }
+
+    pub async fn transaction(&self, id: &str) -> Result<BankTransaction> {
+        let response = reqwest::Client::new()
+            .get(format!("{}/transactions/{}", self.api_url, id))
+            .bearer_auth(self.token.expose())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            error!("Up responded {} for transaction {}", response.status(), id);
+            return Err(anyhow!("Failed to get Up transaction {}", id));
+        }
+        up_transaction(&response.json().await?)
+    }
+}
+
+/// An Up transaction resource, as returned by `GET /transactions/{id}`.
+pub fn up_transaction(resource: &Value) -> Result<BankTransaction> {
+    let data = &resource["data"];
+    let attributes = &data["attributes"];
+    let field = |value: &Value, name: &str| {
+        value
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("the Up transaction has no {}", name))
+    };
+    let amount = field(&attributes["amount"]["value"], "amount")?;
+    let created_at = field(&attributes["createdAt"], "createdAt")?;
+    Ok(BankTransaction {
+        id: field(&data["id"], "id")?,
+        account_id: field(&data["relationships"]["account"]["data"]["id"], "account")?,
+        // Up times carry the account holder's offset, so this is their day.
+        date: created_at.get(..10).unwrap_or_default().to_string(),
+        description: field(&attributes["description"], "description")?,
+        raw_text: attributes["rawText"].as_str().map(String::from),
+        amount: amount
+            .parse()
+            .map_err(|_| anyhow!("{} isn't an amount", amount))?,
+        currency: field(&attributes["amount"]["currencyCode"], "currency")?,
+        category: data["relationships"]["category"]["data"]["id"]
+            .as_str()
+            .map(String::from),
+    })
+}
+
+/// A booked transaction a chat was asked to confirm.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pending {
+    pub bank_id: String,
+    pub chat_id: u64,
+    pub transaction: Transaction,
+}
+
+fn path(bank_id: &str) -> String {
+    let name: String = bank_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}/{}.json", PENDING_DIR, name)
+}
+
+pub async fn save_pending(store: &dyn Store, pending: &Pending) -> Result<()> {
+    store
+        .write(
+            &path(&pending.bank_id),
+            &serde_json::to_string_pretty(pending)?,
+            &format!("bank transaction {} awaits confirmation", pending.bank_id),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn load_pending(store: &dyn Store, bank_id: &str) -> Result<Option<Pending>> {
+    match store.read(&path(bank_id)).await? {
+        Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn remove_pending(store: &dyn Store, bank_id: &str) -> Result<()> {
+    store
+        .delete(
+            &path(bank_id),
+            &format!("bank transaction {} answered", bank_id),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+    use serde_json::json;
+
+    #[test]
+    fn it_verifies_hmac_signatures() {
+        let forged = "4d8b1a6d6d7ad5a1e3d5ef4e0e1b45b7bc1a1b57e0ac2f8f5a8fa3a8b0a7fd3c";
+        assert!(!verify_signature("secret", b"{}", forged));
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"{}");
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert!(verify_signature("secret", b"{}", &signature));
+        assert!(!verify_signature("other", b"{}", &signature));
+        assert!(!verify_signature("secret", b"{}", "zz"));
+    }
+
+    #[test]
+    fn it_reads_up_events_and_transactions() {
+        let event = json!({
+            "data": {
+                "type": "webhook-events",
+                "id": "ev-1",
+                "attributes": { "eventType": "TRANSACTION_CREATED", "createdAt": "2021-09-08T10:00:00+10:00" },
+                "relationships": {
+                    "transaction": { "data": { "type": "transactions", "id": "tx-1" } }
+                }
+            }
+        });
+        assert_eq!(
+            up_event(&event.to_string()).unwrap(),
+            UpEvent::TransactionCreated("tx-1".into())
+        );
+        let ping = json!({ "data": { "attributes": { "eventType": "PING" } } });
+        assert_eq!(up_event(&ping.to_string()).unwrap(), UpEvent::Ping);
+
+        let resource = json!({
+            "data": {
+                "type": "transactions",
+                "id": "tx-1",
+                "attributes": {
+                    "status": "HELD",
+                    "rawText": "WOOLWORTHS 1234 SYDNEY",
+                    "description": "Woolworths",
+                    "amount": { "currencyCode": "AUD", "value": "-12.40", "valueInBaseUnits": -1240 },
+                    "createdAt": "2021-09-08T23:30:00+10:00"
+                },
+                "relationships": {
+                    "account": { "data": { "type": "accounts", "id": "acc-1" } },
+                    "category": { "data": null }
+                }
+            }
+        });
+        assert_eq!(
+            up_transaction(&resource).unwrap(),
+            BankTransaction {
+                id: "tx-1".into(),
+                account_id: "acc-1".into(),
+                date: "2021-09-08".into(),
+                description: "Woolworths".into(),
+                raw_text: Some("WOOLWORTHS 1234 SYDNEY".into()),
+                amount: -12.4,
+                currency: "AUD".into(),
+                category: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_keeps_pending_transactions_until_answered() {
+        let store = MemoryStore::new();
+        let pending = Pending {
+            bank_id: "tx/1".into(),
+            chat_id: 247673932,
+            transaction: Transaction::default(),
+        };
+        save_pending(&store, &pending).await.unwrap();
+        assert_eq!(store.paths(), vec![".beancount-bot/bank-feed/tx_1.json"]);
+        assert_eq!(
+            load_pending(&store, "tx/1").await.unwrap().unwrap().chat_id,
+            247673932
+        );
+        remove_pending(&store, "tx/1").await.unwrap();
+        assert!(load_pending(&store, "tx/1").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::Store;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Which ledger profile each chat writes to, kept in the default ledger
+/// repository so the choice survives cold starts.
+pub const CHAT_PROFILES_FILE: &str = ".beancount-bot/ledgers.json";
+
+async fn read_all(store: &dyn Store) -> Result<BTreeMap<String, String>> {
+    match store.read(CHAT_PROFILES_FILE).await? {
+        Some(content) => Ok(serde_json::from_str(&content)?),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// The profile `chat_id` switched to, `None` for the top-level ledger.
+pub async fn active_profile(store: &dyn Store, chat_id: u64) -> Result<Option<String>> {
+    Ok(read_all(store).await?.remove(&chat_id.to_string()))
+}
+
+/// Switches `chat_id` to `profile`, or back to the top-level ledger for `None`.
+pub async fn set_active_profile(
+    store: &dyn Store,
+    chat_id: u64,
+    profile: Option<&str>,
+) -> Result<()> {
+    let mut profiles = read_all(store).await?;
+    let message = match profile {
+        Some(profile) => {
+            profiles.insert(chat_id.to_string(), profile.into());
+            format!("chat {} uses ledger {}", chat_id, profile)
+        }
+        None => {
+            profiles.remove(&chat_id.to_string());
+            format!("chat {} uses the default ledger", chat_id)
+        }
+    };
+    store
+        .write(
+            CHAT_PROFILES_FILE,
+            &serde_json::to_string_pretty(&profiles)?,
+            &message,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_remembers_profile_per_chat() {
+        let store = MemoryStore::new();
+        assert_eq!(active_profile(&store, 42).await.unwrap(), None);
+        set_active_profile(&store, 42, Some("business"))
+            .await
+            .unwrap();
+        set_active_profile(&store, 7, Some("personal"))
+            .await
+            .unwrap();
+        assert_eq!(
+            active_profile(&store, 42).await.unwrap().as_deref(),
+            Some("business")
+        );
+        set_active_profile(&store, 42, None).await.unwrap();
+        assert_eq!(active_profile(&store, 42).await.unwrap(), None);
+        assert_eq!(
+            active_profile(&store, 7).await.unwrap().as_deref(),
+            Some("personal")
+        );
+    }
+}
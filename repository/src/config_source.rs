@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::info;
+use std::env;
+
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "aws")]
+pub use aws::{SecretsManagerSource, SsmSource};
+
+/// Keys a config source is asked for: the settings document and the tokens the
+/// stores and bot need.
+pub const SECRET_KEYS: [&str; 3] = ["CONFIG", "GITHUB_TOKEN", "TELEGRAM_BOT_TOKEN"];
+
+/// Where the settings document and tokens come from, chosen by `CONFIG_SOURCE`.
+#[async_trait]
+pub trait ConfigSource: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+}
+
+pub struct EnvSource;
+
+#[async_trait]
+impl ConfigSource for EnvSource {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(env::var(key).ok())
+    }
+}
+
+pub async fn from_env() -> Result<Box<dyn ConfigSource>> {
+    match env::var("CONFIG_SOURCE").as_deref() {
+        Ok("env") | Err(_) => Ok(Box::new(EnvSource)),
+        #[cfg(feature = "aws")]
+        Ok("ssm") => Ok(Box::new(SsmSource::new()?)),
+        #[cfg(feature = "aws")]
+        Ok("secretsmanager") => Ok(Box::new(SecretsManagerSource::new().await?)),
+        #[cfg(not(feature = "aws"))]
+        Ok(source @ ("ssm" | "secretsmanager")) => {
+            Err(anyhow!("config source {} needs the aws feature", source))
+        }
+        Ok(source) => Err(anyhow!("unknown config source {}", source)),
+    }
+}
+
+/// Copies [`SECRET_KEYS`] found in `source` into the process environment, where
+/// settings loading and the stores look for them. Keys already set in the
+/// environment win unless `overwrite` is set, which is how a reload picks up
+/// rotated values.
+pub async fn export_to_env(source: &dyn ConfigSource, overwrite: bool) -> Result<()> {
+    for key in SECRET_KEYS.iter() {
+        if !overwrite && env::var(key).is_ok() {
+            continue;
+        }
+        if let Some(value) = source.get(key).await? {
+            info!("loaded {} from config source", key);
+            env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
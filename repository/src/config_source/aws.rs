@@ -0,0 +1,211 @@
+use super::ConfigSource;
+use crate::sigv4::{sha256_hex, sign, Credentials};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::error;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+
+/// Reads `<SSM_PREFIX>/<KEY>` SecureString parameters from SSM Parameter Store.
+pub struct SsmSource {
+    client: AwsClient,
+    prefix: String,
+}
+
+impl SsmSource {
+    pub fn new() -> Result<Self> {
+        let prefix = env::var("SSM_PREFIX").unwrap_or_else(|_| "/beancount-bot".into());
+        Ok(SsmSource {
+            client: AwsClient::from_env()?,
+            prefix: prefix.trim_end_matches('/').into(),
+        })
+    }
+}
+
+#[async_trait]
+impl ConfigSource for SsmSource {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let name = format!("{}/{}", self.prefix, key);
+        let response = self
+            .client
+            .call(
+                "ssm",
+                "AmazonSSM.GetParameter",
+                &json!({ "Name": name, "WithDecryption": true }),
+            )
+            .await?;
+        Ok(response.and_then(|value| {
+            value
+                .pointer("/Parameter/Value")
+                .and_then(Value::as_str)
+                .map(String::from)
+        }))
+    }
+}
+
+/// Reads keys from a single Secrets Manager secret holding a JSON object, e.g.
+/// `{"CONFIG": "...", "GITHUB_TOKEN": "..."}`.
+pub struct SecretsManagerSource {
+    values: HashMap<String, String>,
+}
+
+impl SecretsManagerSource {
+    pub async fn new() -> Result<Self> {
+        let secret_id = env::var("SECRET_ID")?;
+        let response = AwsClient::from_env()?
+            .call(
+                "secretsmanager",
+                "secretsmanager.GetSecretValue",
+                &json!({ "SecretId": secret_id }),
+            )
+            .await?;
+        let secret_string = response
+            .as_ref()
+            .and_then(|value| value["SecretString"].as_str())
+            .ok_or_else(|| anyhow!("secret {} has no SecretString", secret_id))?;
+        Ok(SecretsManagerSource {
+            values: serde_json::from_str(secret_string)?,
+        })
+    }
+}
+
+#[async_trait]
+impl ConfigSource for SecretsManagerSource {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.values.get(key).cloned())
+    }
+}
+
+#[derive(Deserialize)]
+struct AwsError {
+    #[serde(rename = "__type")]
+    error_type: String,
+}
+
+/// Just enough of an AWS JSON 1.1 client for the two read calls above, signing
+/// with the Lambda execution role credentials from the environment.
+struct AwsClient {
+    region: String,
+    credentials: Credentials,
+    client: Client,
+}
+
+impl AwsClient {
+    fn from_env() -> Result<Self> {
+        let region = env::var("AWS_REGION").or_else(|_| env::var("AWS_DEFAULT_REGION"))?;
+        Ok(AwsClient {
+            region,
+            credentials: Credentials::from_env()?,
+            client: Client::builder()
+                .user_agent("beancount-automation/0.1.0")
+                .build()?,
+        })
+    }
+
+    /// The headers to sign, sorted by name as [`sign`] needs them.
+    fn headers(&self, host: &str, target: &str, now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host.to_string()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+            ("x-amz-target", target.to_string()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.expose().clone()));
+        }
+        headers.sort_by_key(|(name, _)| *name);
+        headers
+    }
+
+    /// Returns `None` when the parameter or secret doesn't exist.
+    async fn call(&self, service: &str, target: &str, body: &Value) -> Result<Option<Value>> {
+        let host = format!("{}.{}.amazonaws.com", service, self.region);
+        let body = serde_json::to_string(body)?;
+        let now = Utc::now();
+        let headers = self.headers(&host, target, now);
+        let authorization = sign(
+            &self.credentials.signing_params(&self.region, service, now),
+            "POST",
+            "/",
+            &headers,
+            &sha256_hex(body.as_bytes()),
+        );
+
+        let mut request = self.client.post(format!("https://{}/", host)).body(body);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, value);
+        }
+        let response = request
+            .header("authorization", authorization)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json().await?)),
+            status => {
+                let text = response.text().await?;
+                let not_found = serde_json::from_str::<AwsError>(&text)
+                    .map(|e| e.error_type.ends_with("NotFound"))
+                    .unwrap_or(false);
+                if not_found {
+                    return Ok(None);
+                }
+                error!("{} response status code was [{}]", target, status);
+                error!("{} response body was {}", target, text);
+                Err(anyhow!("Failed to call {}", target))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::secret::Secret;
+    use chrono::TimeZone;
+
+    #[test]
+    fn it_signs_the_session_token_in_header_order() {
+        let client = AwsClient {
+            region: "ap-southeast-2".into(),
+            credentials: Credentials {
+                access_key_id: "ASIAEXAMPLE".into(),
+                secret_access_key: Secret::new("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into()),
+                session_token: Some(Secret::new("session-token".into())),
+            },
+            client: Client::new(),
+        };
+        let now = Utc.ymd(2022, 8, 14).and_hms(10, 0, 0);
+        let headers = client.headers(
+            "ssm.ap-southeast-2.amazonaws.com",
+            "AmazonSSM.GetParameter",
+            now,
+        );
+        let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "content-type",
+                "host",
+                "x-amz-date",
+                "x-amz-security-token",
+                "x-amz-target"
+            ]
+        );
+        let authorization = sign(
+            &client
+                .credentials
+                .signing_params(&client.region, "ssm", now),
+            "POST",
+            "/",
+            &headers,
+            &sha256_hex(b"{}"),
+        );
+        assert!(authorization.contains(
+            "SignedHeaders=content-type;host;x-amz-date;x-amz-security-token;x-amz-target,"
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::error::StoreError;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::{decode, encode};
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use chrono::Utc;
+use log::info;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::env;
+use tracing::instrument;
+
+/// Keeps every transaction as its own CouchDB document instead of appending to a
+/// text file, for deployments without filesystem or git access.
+///
+/// Reading `<year>.bean` exports that year's transaction documents as beancount
+/// text after whatever else was written to the file, such as `balance`
+/// directives, so reporting and maintenance code works unchanged. Other paths
+/// are kept as plain file documents.
+pub struct CouchDbStore {
+    database_url: String,
+    username: String,
+    password: Option<Secret<String>>,
+    client: Client,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TransactionDocument {
+    #[serde(rename = "type")]
+    document_type: String,
+    transaction: Transaction,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FileDocument {
+    #[serde(rename = "_rev", skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    #[serde(rename = "type")]
+    document_type: String,
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AllDocs {
+    rows: Vec<Row>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Row {
+    doc: TransactionDocument,
+}
+
+impl CouchDbStore {
+    pub fn new() -> Result<Self> {
+        let url = env::var("COUCHDB_URL")?;
+        let database = env::var("COUCHDB_DATABASE")?;
+        let username = env::var("COUCHDB_USER")?;
+        let password = Secret::from_env("COUCHDB_PASSWORD").ok();
+        let client = reqwest::Client::builder()
+            .user_agent("beancount-automation/0.1.0")
+            .build()?;
+        Ok(CouchDbStore {
+            database_url: format!("{}/{}", url.trim_end_matches('/'), database),
+            username,
+            password,
+            client,
+        })
+    }
+
+    fn document_url(&self, id: &str) -> String {
+        format!(
+            "{}/{}",
+            self.database_url,
+            id.replace('%', "%25").replace('/', "%2F")
+        )
+    }
+
+    async fn put<T: Serialize + Sync>(&self, id: &str, document: &T) -> Result<(), StoreError> {
+        let response = self
+            .client
+            .put(self.document_url(id))
+            .basic_auth(&self.username, self.password.as_ref().map(Secret::expose))
+            .json(document)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED | StatusCode::ACCEPTED => Ok(()),
+            _ => Err(
+                StoreError::from_response(response, format!("Failed to put document {}", id)).await,
+            ),
+        }
+    }
+
+    async fn get_file(&self, path: &str) -> Result<Option<FileDocument>, StoreError> {
+        let id = file_id(path);
+        let response = self
+            .client
+            .get(self.document_url(&id))
+            .basic_auth(&self.username, self.password.as_ref().map(Secret::expose))
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(
+                StoreError::from_response(response, format!("Failed to get document {}", id)).await,
+            ),
+        }
+    }
+
+    /// Transactions of `year`, ordered by date then insertion time.
+    pub async fn transactions(&self, year: &str) -> Result<Vec<Transaction>, StoreError> {
+        let response = self
+            .client
+            .get(format!("{}/_all_docs", self.database_url))
+            .basic_auth(&self.username, self.password.as_ref().map(Secret::expose))
+            .query(&[
+                ("include_docs", "true".to_string()),
+                ("startkey", format!("\"txn:{}-\"", year)),
+                ("endkey", format!("\"txn:{}-\u{fff0}\"", year)),
+            ])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let all_docs: AllDocs = response.json().await?;
+                Ok(all_docs
+                    .rows
+                    .into_iter()
+                    .map(|row| row.doc.transaction)
+                    .collect())
+            }
+            _ => Err(StoreError::from_response(
+                response,
+                format!("Failed to list transactions of {}", year),
+            )
+            .await),
+        }
+    }
+}
+
+fn file_id(path: &str) -> String {
+    format!("file:{}", path)
+}
+
+fn transaction_id(transaction: &Transaction) -> String {
+    format!(
+        "txn:{}:{}",
+        transaction.date(),
+        Utc::now().timestamp_nanos()
+    )
+}
+
+/// Renders transaction documents the way a text store would have appended them.
+pub fn export(transactions: Vec<Transaction>) -> String {
+    transactions
+        .into_iter()
+        .map(|transaction| format!("\n{}", String::from(transaction)))
+        .collect()
+}
+
+/// What to keep in the file document of a year file written as `text`: the
+/// text without the export of the year's transactions it was read with, or
+/// `None` when it rewrote them.
+fn without_export(text: &str, exported: &str) -> Option<String> {
+    if exported.is_empty() {
+        return Some(text.to_string());
+    }
+    let start = text.find(exported)?;
+    Some(format!(
+        "{}{}",
+        &text[..start],
+        &text[start + exported.len()..]
+    ))
+}
+
+fn year_of(path: &str) -> Option<&str> {
+    let year = path.strip_suffix(".bean")?;
+    if year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) {
+        Some(year)
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl Store for CouchDbStore {
+    #[instrument(name = "couchdb.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        if duplicate_lines > 0 {
+            let content = export(self.transactions(&transaction.year()).await?);
+            let entry = String::from(transaction.clone());
+            let path = transaction.ledger_path(None);
+            crate::refuse_copy(&path, &content, &entry, duplicate_lines)?;
+        }
+        let id = transaction_id(&transaction);
+        let document = TransactionDocument {
+            document_type: "transaction".into(),
+            transaction,
+        };
+        self.put(&id, &document).await?;
+        info!("Successfully saved transaction document {}.", id);
+        Ok(String::from(document.transaction))
+    }
+
+    #[instrument(name = "couchdb.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        let file = match self.get_file(path).await? {
+            Some(file) => Some(String::from_utf8_lossy(&decode(file.content)?).into_owned()),
+            None => None,
+        };
+        let exported = match year_of(path) {
+            Some(year) => export(self.transactions(year).await?),
+            None => String::new(),
+        };
+        match file {
+            Some(content) => Ok(Some(content + &exported)),
+            None if exported.is_empty() => Ok(None),
+            None => Ok(Some(exported)),
+        }
+    }
+
+    #[instrument(name = "couchdb.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        _message: &str,
+    ) -> Result<(), StoreError> {
+        let content = match year_of(path) {
+            Some(year) => {
+                let exported = export(self.transactions(year).await?);
+                let text = String::from_utf8_lossy(bytes);
+                match without_export(&text, &exported) {
+                    Some(rest) => encode(rest),
+                    None => {
+                        return Err(StoreError::Other(anyhow!(
+                            "rewriting the transactions of {} isn't supported by CouchDB stores",
+                            path
+                        )))
+                    }
+                }
+            }
+            None => encode(bytes),
+        };
+        let document = FileDocument {
+            rev: self.get_file(path).await?.and_then(|file| file.rev),
+            document_type: "file".into(),
+            content,
+        };
+        self.put(&file_id(path), &document).await
+    }
+
+    /// Transactions are documents ordered by date rather than lines appended
+    /// to a file, so there is no last one to remove.
+    async fn delete_last(&self, path: &str) -> Result<Option<String>, StoreError> {
+        Err(StoreError::Other(anyhow!(
+            "removing the last transaction of {} isn't supported by CouchDB stores",
+            path
+        )))
+    }
+
+    #[instrument(name = "couchdb.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, _message: &str) -> Result<(), StoreError> {
+        let rev = match self.get_file(path).await?.and_then(|file| file.rev) {
+            Some(v) => v,
+            None => return Err(StoreError::NotFound(path.into())),
+        };
+        let response = self
+            .client
+            .delete(self.document_url(&file_id(path)))
+            .basic_auth(&self.username, self.password.as_ref().map(Secret::expose))
+            .query(&[("rev", rev)])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::ACCEPTED => Ok(()),
+            _ => Err(StoreError::from_response(
+                response,
+                format!("Failed to delete file {}", path),
+            )
+            .await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_exports_transaction_documents_as_beancount_text() {
+        let json = r#"{"rows":[{"doc":{"type":"transaction","transaction":{"date":"2021-09-08","payee":"KFC","narration":"hamburger","amount":12.4,"currency":"AUD","from_account":"Assets:Cash","to_account":"Expenses:Food"}}}]}"#;
+        let all_docs: AllDocs = serde_json::from_str(json).unwrap();
+        let transactions = all_docs.rows.into_iter().map(|row| row.doc.transaction);
+        assert_eq!(
+            export(transactions.collect()),
+            "\n2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:Cash        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_only_what_was_added_to_the_export_of_a_year_file() {
+        let exported = "\n2021-09-08 * \"KFC\" \"\"\n  Assets:Cash        -12.40 AUD\n  Expenses:Food        12.40 AUD\n";
+        let written = format!(
+            "option \"title\" \"Ledger\"\n{}2021-09-09 balance Assets:Cash -12.40 AUD\n",
+            exported
+        );
+        assert_eq!(
+            without_export(&written, exported).unwrap(),
+            "option \"title\" \"Ledger\"\n2021-09-09 balance Assets:Cash -12.40 AUD\n"
+        );
+        assert_eq!(without_export("rewritten\n", "").unwrap(), "rewritten\n");
+        assert!(without_export("2021-09-08 * \"KFC\"\n", exported).is_none());
+    }
+
+    #[test]
+    fn it_only_treats_year_files_as_exports() {
+        assert_eq!(year_of("2021.bean"), Some("2021"));
+        assert_eq!(year_of("archive/2021.bean"), None);
+        assert_eq!(year_of("prices.bean"), None);
+    }
+}
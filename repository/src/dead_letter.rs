@@ -0,0 +1,75 @@
+use crate::Store;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Updates that failed for good are parked here, one file per update id, in the
+/// default ledger repository until they are replayed.
+pub const DEAD_LETTER_DIR: &str = ".beancount-bot/dead-letter";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DeadLetter {
+    pub update_id: u64,
+    pub chat_id: u64,
+    pub error: String,
+    /// RFC 3339 time of the last failure.
+    pub failed_at: String,
+    /// The webhook request body as it was received.
+    pub body: String,
+}
+
+fn path(update_id: u64) -> String {
+    format!("{}/{}.json", DEAD_LETTER_DIR, update_id)
+}
+
+/// Stores `letter`, replacing an earlier one for the same update.
+pub async fn save(store: &dyn Store, letter: &DeadLetter) -> Result<()> {
+    store
+        .write(
+            &path(letter.update_id),
+            &serde_json::to_string_pretty(letter)?,
+            &format!("dead-lettered update {}", letter.update_id),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn load(store: &dyn Store, update_id: u64) -> Result<Option<DeadLetter>> {
+    match store.read(&path(update_id)).await? {
+        Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn remove(store: &dyn Store, update_id: u64) -> Result<()> {
+    store
+        .delete(&path(update_id), &format!("replayed update {}", update_id))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_keeps_dead_letters_until_removed() {
+        let store = MemoryStore::new();
+        let letter = DeadLetter {
+            update_id: 459592837,
+            chat_id: 247673932,
+            error: "Failed to save transaction!".into(),
+            failed_at: "2021-09-08T10:00:00+00:00".into(),
+            body: "{\"update_id\":459592837}".into(),
+        };
+        save(&store, &letter).await.unwrap();
+        assert_eq!(
+            store.paths(),
+            vec![".beancount-bot/dead-letter/459592837.json"]
+        );
+        assert_eq!(load(&store, 459592837).await.unwrap(), Some(letter));
+
+        remove(&store, 459592837).await.unwrap();
+        assert_eq!(load(&store, 459592837).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::parser::Transaction;
+use serde::{Deserialize, Serialize};
+
+/// Transactions that look like a copy of an entry already in the ledger,
+/// waiting for their chat to save them anyway, one file per message, in the
+/// default ledger repository.
+pub const PENDING_DIR: &str = ".beancount-bot/duplicates";
+
+/// A transaction held back as a likely duplicate.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pending {
+    /// `<chat_id>-<message_id>` of the message.
+    pub id: String,
+    pub chat_id: u64,
+    pub transaction: Transaction,
+}
+
+/// The id of the transaction sent as `message_id` in `chat_id`.
+pub fn pending_id(chat_id: u64, message_id: u64) -> String {
+    format!("{}-{}", chat_id, message_id)
+}
+
+fn path(id: &str) -> Result<String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return Err(anyhow!("{} isn't a message id", id));
+    }
+    Ok(format!("{}/{}.json", PENDING_DIR, id))
+}
+
+pub async fn save_pending(store: &dyn Store, pending: &Pending) -> Result<()> {
+    store
+        .write(
+            &path(&pending.id)?,
+            &serde_json::to_string_pretty(pending)?,
+            &format!("likely duplicate {} awaits confirmation", pending.id),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn load_pending(store: &dyn Store, id: &str) -> Result<Option<Pending>> {
+    match store.read(&path(id)?).await? {
+        Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn remove_pending(store: &dyn Store, id: &str) -> Result<()> {
+    store
+        .delete(&path(id)?, &format!("likely duplicate {} answered", id))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::Settings;
+
+    #[tokio::test]
+    async fn it_keeps_likely_duplicates_until_answered() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let transaction = BeancountParser::new(settings)
+            .parse("2021-09-08 @KFC 12.40 cba > food")
+            .unwrap();
+        let store = MemoryStore::new();
+        let pending = Pending {
+            id: pending_id(247673932, 8),
+            chat_id: 247673932,
+            transaction,
+        };
+        save_pending(&store, &pending).await.unwrap();
+        assert!(store
+            .read(".beancount-bot/duplicates/247673932-8.json")
+            .await
+            .unwrap()
+            .is_some());
+        let loaded = load_pending(&store, "247673932-8").await.unwrap().unwrap();
+        assert_eq!(loaded.transaction.payee(), "KFC");
+
+        remove_pending(&store, "247673932-8").await.unwrap();
+        assert!(load_pending(&store, "247673932-8").await.unwrap().is_none());
+        assert!(load_pending(&store, "../secrets").await.is_err());
+    }
+}
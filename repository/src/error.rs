@@ -0,0 +1,179 @@
+use http::StatusCode;
+#[cfg(any(
+    feature = "github-contents",
+    feature = "azure",
+    feature = "couchdb",
+    feature = "gitlab",
+    feature = "s3"
+))]
+use http::{header, HeaderMap};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a store call failed, so callers can decide whether to retry and what to
+/// tell the user without matching on messages.
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("file {0} doesn't exist")]
+    NotFound(String),
+    /// The file changed between reading and writing it.
+    #[error("{0}: the file was changed at the same time")]
+    Conflict(String),
+    /// The transaction looks like a copy of the entry at `line` of `path`, see
+    /// [`crate::Store::save_checked`].
+    #[error("looks like a duplicate of {path}:{line}")]
+    LikelyDuplicate { path: String, line: usize },
+    #[error("{message}: rate limited")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("{message}: credentials were rejected ({status})")]
+    Auth { message: String, status: StatusCode },
+    #[error("{message} ({status})")]
+    Api { message: String, status: StatusCode },
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Decode(#[from] base64::DecodeError),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl StoreError {
+    /// Classifies an unexpected API response, logging its status and body.
+    #[cfg(any(
+        feature = "azure",
+        feature = "couchdb",
+        feature = "gitlab",
+        feature = "s3"
+    ))]
+    pub(crate) async fn from_response(
+        response: reqwest::Response,
+        message: impl Into<String>,
+    ) -> Self {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        Self::classify(status, &headers, &body, message.into())
+    }
+
+    /// Like [`StoreError::from_response`], for a response from an
+    /// [`crate::http_client::HttpClient`].
+    #[cfg(feature = "github-contents")]
+    pub(crate) fn from_http_response(
+        response: &http::Response<Vec<u8>>,
+        message: impl Into<String>,
+    ) -> Self {
+        let body = String::from_utf8_lossy(response.body());
+        Self::classify(response.status(), response.headers(), &body, message.into())
+    }
+
+    #[cfg(any(
+        feature = "github-contents",
+        feature = "azure",
+        feature = "couchdb",
+        feature = "gitlab",
+        feature = "s3"
+    ))]
+    fn classify(status: StatusCode, headers: &HeaderMap, body: &str, message: String) -> Self {
+        use log::error;
+
+        let retry_after = headers
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        let exhausted = headers
+            .get("x-ratelimit-remaining")
+            .is_some_and(|v| v == "0");
+        error!("{}", message);
+        error!("Response status was {}", status);
+        error!("Response body was {}", body);
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => StoreError::RateLimited {
+                message,
+                retry_after,
+            },
+            StatusCode::FORBIDDEN if exhausted || retry_after.is_some() => {
+                StoreError::RateLimited {
+                    message,
+                    retry_after,
+                }
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                StoreError::Auth { message, status }
+            }
+            StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => StoreError::Conflict(message),
+            _ => StoreError::Api { message, status },
+        }
+    }
+
+    /// Whether the same call can succeed later without anything being changed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StoreError::Conflict(_) | StoreError::RateLimited { .. } => true,
+            #[cfg(feature = "reqwest")]
+            StoreError::Http(_) => true,
+            StoreError::Api { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// Short label for metrics and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StoreError::NotFound(_) => "not_found",
+            StoreError::Conflict(_) => "conflict",
+            StoreError::LikelyDuplicate { .. } => "likely_duplicate",
+            StoreError::RateLimited { .. } => "rate_limited",
+            StoreError::Auth { .. } => "auth",
+            StoreError::Api { .. } => "api",
+            #[cfg(feature = "reqwest")]
+            StoreError::Http(_) => "http",
+            StoreError::Json(_) | StoreError::Decode(_) => "malformed_response",
+            StoreError::Other(_) => "other",
+        }
+    }
+}
+
+/// Helpers returning `anyhow` keep a `StoreError` they raised typed when it
+/// crosses back into a `Store` method.
+impl From<anyhow::Error> for StoreError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<StoreError>() {
+            Ok(e) => e,
+            Err(e) => StoreError::Other(e),
+        }
+    }
+}
+
+#[cfg(feature = "git")]
+impl From<git2::Error> for StoreError {
+    fn from(e: git2::Error) -> Self {
+        StoreError::Other(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn it_keeps_store_errors_typed_through_anyhow() {
+        let error: anyhow::Error =
+            StoreError::Conflict("Failed to save transaction!".into()).into();
+        let error = StoreError::from(error);
+        assert!(matches!(error, StoreError::Conflict(_)));
+        assert!(error.is_retryable());
+
+        let error = StoreError::from(anyhow!("GITHUB_TOKEN env not set!"));
+        assert_eq!(error.kind(), "other");
+        assert!(!error.is_retryable());
+        assert_eq!(error.to_string(), "GITHUB_TOKEN env not set!");
+    }
+}
@@ -0,0 +1,192 @@
+use crate::error::StoreError;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use beancount_core::parser::Transaction;
+use log::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use tracing::instrument;
+
+/// Stores the ledger in a directory on disk, e.g. a volume mounted into the
+/// container, for self-hosted deployments without a Git host.
+///
+/// Files are replaced by renaming a written copy over them, so readers never
+/// see half a file, and saves of one process are serialized so two messages
+/// can't append over each other.
+pub struct FsStore {
+    ledger_dir: PathBuf,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    lock: Mutex<()>,
+}
+
+impl FsStore {
+    /// A store in `LEDGER_DIR`.
+    pub fn new() -> Result<Self> {
+        Ok(FsStore::in_dir(env::var("LEDGER_DIR")?))
+    }
+
+    pub fn in_dir(ledger_dir: impl Into<PathBuf>) -> Self {
+        FsStore {
+            ledger_dir: ledger_dir.into(),
+            file_header: None,
+            ledger_path: None,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf, StoreError> {
+        resolve(&self.ledger_dir, path)
+    }
+
+    fn read_file(&self, path: &str) -> Result<Option<String>, StoreError> {
+        match fs::read(self.resolve(path)?) {
+            Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(e, path)),
+        }
+    }
+
+    fn write_file(&self, path: &str, bytes: &[u8]) -> Result<(), StoreError> {
+        let file = self.resolve(path)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).map_err(|e| io_error(e, path))?;
+        }
+        let mut temporary = file.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, bytes).map_err(|e| io_error(e, path))?;
+        fs::rename(&temporary, &file).map_err(|e| io_error(e, path))
+    }
+}
+
+/// `path` under `dir`, refusing paths that would leave it.
+pub(crate) fn resolve(dir: &Path, path: &str) -> Result<PathBuf, StoreError> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(StoreError::Other(anyhow!(
+            "{} is outside of the ledger directory",
+            path
+        )));
+    }
+    Ok(dir.join(relative))
+}
+
+pub(crate) fn io_error(e: io::Error, path: &str) -> StoreError {
+    StoreError::Other(anyhow!("Failed to access file {}: {}", path, e))
+}
+
+#[async_trait]
+impl Store for FsStore {
+    #[instrument(name = "fs.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let _guard = self.lock.lock().unwrap();
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let transaction_text = String::from(transaction);
+
+        let content = self.read_file(&path)?.unwrap_or_else(|| {
+            info!("file {} not found, will create the file", path);
+            crate::render_file_header(self.file_header.as_deref(), &year)
+        });
+        crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+        let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+        self.write_file(&path, content.as_bytes())?;
+        info!("Successfully saved transaction to {}.", path);
+        Ok(transaction_text)
+    }
+
+    #[instrument(name = "fs.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        self.read_file(path)
+    }
+
+    #[instrument(name = "fs.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        _message: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.lock().unwrap();
+        self.write_file(path, bytes)
+    }
+
+    #[instrument(name = "fs.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, _message: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.lock().unwrap();
+        match fs::remove_file(self.resolve(path)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(StoreError::NotFound(path.into())),
+            Err(e) => Err(io_error(e, path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::Settings;
+
+    #[tokio::test]
+    async fn it_appends_to_year_files_on_disk() {
+        let dir = env::temp_dir().join(format!("fs-store-{}", std::process::id()));
+        let store = FsStore::in_dir(&dir)
+            .with_file_header(Some("option \"title\" \"{year}\"\n".into()))
+            .with_ledger_path(Some("ledger/{year}.bean".into()));
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let parser = BeancountParser::new(settings);
+        for text in [
+            "2021-09-08 @KFC 12.40 cba > food",
+            "2021-09-09 @Coles 30 cba > food",
+        ] {
+            store.save(parser.parse(text).unwrap()).await.unwrap();
+        }
+        let resent = parser.parse("2021-09-09 @Coles 30 cba > food").unwrap();
+        assert!(matches!(
+            store.save_checked(resent, 200).await,
+            Err(StoreError::LikelyDuplicate { path, .. }) if path == "ledger/2021.bean"
+        ));
+        let content = fs::read_to_string(dir.join("ledger/2021.bean")).unwrap();
+        assert!(content.starts_with("option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\""));
+        assert!(content.ends_with("Expenses:Food        30.00 AUD\n"));
+        assert_eq!(store.read("ledger/2021.bean").await.unwrap(), Some(content));
+        assert_eq!(store.read("2022.bean").await.unwrap(), None);
+
+        assert!(store.read("../secrets").await.is_err());
+        store.delete("ledger/2021.bean", "").await.unwrap();
+        assert!(matches!(
+            store.delete("ledger/2021.bean", "").await,
+            Err(StoreError::NotFound(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
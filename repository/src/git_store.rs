@@ -0,0 +1,504 @@
+use crate::error::StoreError;
+use crate::fs_store::{io_error, resolve};
+use crate::Store;
+use anyhow::Result;
+use async_trait::async_trait;
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    Cred, ErrorCode, FetchOptions, IndexAddOption, PushOptions, RemoteCallbacks, Repository,
+    ResetType, Signature,
+};
+use log::{error, info, warn};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::instrument;
+
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long reads trust the checkout before fetching the branch again.
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stores the ledger in any Git repository reachable over SSH, e.g. on a
+/// self-hosted Gitea or a bare repository on a server, without a REST API.
+///
+/// The repository is cloned shallow into a local checkout once, then changes
+/// fetch the branch and reset the checkout to it, and reads do when the last
+/// fetch is older than the sync interval. Changes are committed and pushed;
+/// when someone else pushed in between the push is rejected and the change is
+/// made again on the new tip. Calls of one process are serialized and run on
+/// tokio's blocking threads, libgit2 being synchronous.
+#[derive(Clone)]
+pub struct GitStore {
+    url: String,
+    branch: String,
+    checkout_dir: PathBuf,
+    depth: i32,
+    ssh_key: Option<PathBuf>,
+    ssh_passphrase: Option<Secret<String>>,
+    author_name: String,
+    author_email: String,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    commit_message: Option<String>,
+    sync_interval: Duration,
+    lock: Arc<Mutex<()>>,
+    /// When the checkout last caught up with the remote branch.
+    synced: Arc<Mutex<Option<Instant>>>,
+}
+
+impl GitStore {
+    /// A store for the repository at `GIT_URL`, checked out into
+    /// `GIT_CHECKOUT_DIR`.
+    pub fn new() -> Result<Self> {
+        let checkout_dir = env::var("GIT_CHECKOUT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| env::temp_dir().join("beancount-ledger"));
+        let mut store = GitStore::for_remote(env::var("GIT_URL")?, checkout_dir)
+            .with_branch(env::var("GIT_BRANCH").unwrap_or_else(|_| "main".into()));
+        if let Ok(depth) = env::var("GIT_DEPTH") {
+            store = store.with_depth(depth.parse()?);
+        }
+        if let Ok(seconds) = env::var("GIT_SYNC_SECONDS") {
+            store = store.with_sync_interval(Duration::from_secs(seconds.parse()?));
+        }
+        store.ssh_key = env::var("GIT_SSH_KEY").ok().map(PathBuf::from);
+        store.ssh_passphrase = Secret::from_env("GIT_SSH_PASSPHRASE").ok();
+        if let Ok(name) = env::var("GIT_AUTHOR_NAME") {
+            store.author_name = name;
+        }
+        if let Ok(email) = env::var("GIT_AUTHOR_EMAIL") {
+            store.author_email = email;
+        }
+        Ok(store)
+    }
+
+    pub fn for_remote(url: impl Into<String>, checkout_dir: impl Into<PathBuf>) -> Self {
+        GitStore {
+            url: url.into(),
+            branch: "main".into(),
+            checkout_dir: checkout_dir.into(),
+            depth: 1,
+            ssh_key: None,
+            ssh_passphrase: None,
+            author_name: "beancount-bot".into(),
+            author_email: "beancount-bot@localhost".into(),
+            file_header: None,
+            ledger_path: None,
+            commit_message: None,
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+            lock: Arc::new(Mutex::new(())),
+            synced: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = branch.into();
+        self
+    }
+
+    /// How many commits to fetch, 0 for the whole history, which servers
+    /// without shallow fetches need. Defaults to 1.
+    pub fn with_depth(mut self, depth: i32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// How long reads go without fetching the branch, 0 to fetch on every
+    /// read. Defaults to [`DEFAULT_SYNC_INTERVAL`].
+    pub fn with_sync_interval(mut self, sync_interval: Duration) -> Self {
+        self.sync_interval = sync_interval;
+        self
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    /// Authenticates with `GIT_SSH_KEY`, or the SSH agent without one.
+    fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        let mut asked = false;
+        callbacks.credentials(move |_, username, _| {
+            // libgit2 asks again as long as the credentials are rejected.
+            if asked {
+                return Err(git2::Error::from_str("SSH credentials were rejected"));
+            }
+            asked = true;
+            let username = username.unwrap_or("git");
+            match &self.ssh_key {
+                Some(key) => Cred::ssh_key(
+                    username,
+                    None,
+                    key,
+                    self.ssh_passphrase.as_ref().map(|p| p.expose().as_str()),
+                ),
+                None => Cred::ssh_key_from_agent(username),
+            }
+        });
+        callbacks
+    }
+
+    fn fetch_options(&self) -> FetchOptions<'_> {
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(self.callbacks()).depth(self.depth);
+        options
+    }
+
+    /// Runs `call` on a blocking thread, one call of the process at a time.
+    async fn blocking<T, F>(&self, call: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&GitStore) -> Result<T, StoreError> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = store.lock.lock().unwrap();
+            call(&store)
+        })
+        .await
+        .map_err(|e| StoreError::Other(e.into()))?
+    }
+
+    /// The checkout, at the tip of the remote branch.
+    fn sync(&self) -> Result<Repository, StoreError> {
+        let repository = self.fetch()?;
+        *self.synced.lock().unwrap() = Some(Instant::now());
+        Ok(repository)
+    }
+
+    fn fetch(&self) -> Result<Repository, StoreError> {
+        if !self.checkout_dir.join(".git").exists() {
+            info!("cloning {} into {}", self.url, self.checkout_dir.display());
+            return Ok(RepoBuilder::new()
+                .branch(&self.branch)
+                .fetch_options(self.fetch_options())
+                .clone(&self.url, &self.checkout_dir)?);
+        }
+        let repository = Repository::open(&self.checkout_dir)?;
+        repository.find_remote("origin")?.fetch(
+            &[format!(
+                "+refs/heads/{0}:refs/remotes/origin/{0}",
+                self.branch
+            )],
+            Some(&mut self.fetch_options()),
+            None,
+        )?;
+        {
+            let tip = repository
+                .find_reference(&format!("refs/remotes/origin/{}", self.branch))?
+                .peel_to_commit()?;
+            repository.reset(
+                tip.as_object(),
+                ResetType::Hard,
+                Some(CheckoutBuilder::new().remove_untracked(true)),
+            )?;
+        }
+        Ok(repository)
+    }
+
+    /// Pushes the branch, `false` when the remote rejected it because it moved.
+    fn push(&self, repository: &Repository) -> Result<bool, StoreError> {
+        let rejected = RefCell::new(None);
+        let result = {
+            let mut callbacks = self.callbacks();
+            callbacks.push_update_reference(|_, status| {
+                *rejected.borrow_mut() = status.map(String::from);
+                Ok(())
+            });
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+            repository.find_remote("origin")?.push(
+                &[format!("refs/heads/{0}:refs/heads/{0}", self.branch)],
+                Some(&mut options),
+            )
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if e.code() == ErrorCode::NotFastForward => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        match rejected.into_inner() {
+            Some(status) => {
+                warn!("push to {} was rejected: {}", self.branch, status);
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Makes `change` in the checkout, then commits and pushes it, starting
+    /// over from the new tip when the branch moved underneath us. After a
+    /// failure the checkout may hold the change, so the next read syncs.
+    fn commit<F>(&self, message: &str, change: F) -> Result<(), StoreError>
+    where
+        F: Fn(&Path) -> Result<(), StoreError>,
+    {
+        let committed = self.try_commit(message, change);
+        if committed.is_err() {
+            *self.synced.lock().unwrap() = None;
+        }
+        committed
+    }
+
+    fn try_commit<F>(&self, message: &str, change: F) -> Result<(), StoreError>
+    where
+        F: Fn(&Path) -> Result<(), StoreError>,
+    {
+        for _ in 0..MAX_ATTEMPTS {
+            let repository = self.sync()?;
+            change(&self.checkout_dir)?;
+            let mut index = repository.index()?;
+            index.add_all(iter::once("*"), IndexAddOption::DEFAULT, None)?;
+            index.update_all(iter::once("*"), None)?;
+            index.write()?;
+            let tree = repository.find_tree(index.write_tree()?)?;
+            let parent = repository.head()?.peel_to_commit()?;
+            if tree.id() == parent.tree_id() {
+                return Ok(());
+            }
+            let signature = Signature::now(&self.author_name, &self.author_email)?;
+            repository.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &[&parent],
+            )?;
+            if self.push(&repository)? {
+                info!("Successfully pushed \"{}\" to {}.", message, self.branch);
+                return Ok(());
+            }
+            warn!("branch {} moved while pushing, retrying", self.branch);
+        }
+
+        error!(
+            "Gave up pushing to {} after {} attempts",
+            self.branch, MAX_ATTEMPTS
+        );
+        Err(StoreError::Conflict(format!(
+            "Failed to push to {}",
+            self.branch
+        )))
+    }
+}
+
+fn read_file(dir: &Path, path: &str) -> Result<Option<String>, StoreError> {
+    match fs::read(resolve(dir, path)?) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(e, path)),
+    }
+}
+
+fn write_file(dir: &Path, path: &str, bytes: &[u8]) -> Result<(), StoreError> {
+    let file = resolve(dir, path)?;
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).map_err(|e| io_error(e, path))?;
+    }
+    fs::write(file, bytes).map_err(|e| io_error(e, path))
+}
+
+#[async_trait]
+impl Store for GitStore {
+    #[instrument(name = "git.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let message = transaction.commit_message(self.commit_message.as_deref());
+        let transaction_text = String::from(transaction);
+
+        self.blocking(move |store| {
+            store.commit(&message, |dir| {
+                let content = read_file(dir, &path)?.unwrap_or_else(|| {
+                    info!("file {} not found, will create the file", path);
+                    crate::render_file_header(store.file_header.as_deref(), &year)
+                });
+                crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+                let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+                write_file(dir, &path, content.as_bytes())
+            })?;
+            Ok(transaction_text)
+        })
+        .await
+    }
+
+    #[instrument(name = "git.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        let path = path.to_string();
+        self.blocking(move |store| {
+            let synced = *store.synced.lock().unwrap();
+            if synced.is_none_or(|at| at.elapsed() >= store.sync_interval) {
+                store.sync()?;
+            }
+            read_file(&store.checkout_dir, &path)
+        })
+        .await
+    }
+
+    #[instrument(name = "git.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError> {
+        let (path, bytes, message) = (path.to_string(), bytes.to_vec(), message.to_string());
+        self.blocking(move |store| store.commit(&message, |dir| write_file(dir, &path, &bytes)))
+            .await
+    }
+
+    #[instrument(name = "git.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError> {
+        let (path, message) = (path.to_string(), message.to_string());
+        self.blocking(move |store| {
+            store.commit(&message, |dir| {
+                match fs::remove_file(resolve(dir, &path)?) {
+                    Ok(()) => Ok(()),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        Err(StoreError::NotFound(path.clone()))
+                    }
+                    Err(e) => Err(io_error(e, &path)),
+                }
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::Settings;
+
+    /// A bare repository with `2021.bean` on `main`, standing in for a server.
+    fn remote(dir: &Path) -> Repository {
+        let remote = Repository::init_bare(dir).unwrap();
+        {
+            let signature = Signature::now("test", "test@localhost").unwrap();
+            let blob = remote.blob(b"option \"title\" \"2021\"\n").unwrap();
+            let mut builder = remote.treebuilder(None).unwrap();
+            builder.insert("2021.bean", blob, 0o100644).unwrap();
+            let tree = remote.find_tree(builder.write().unwrap()).unwrap();
+            remote
+                .commit(
+                    Some("refs/heads/main"),
+                    &signature,
+                    &signature,
+                    "init",
+                    &tree,
+                    &[],
+                )
+                .unwrap();
+        }
+        remote
+    }
+
+    fn content(repository: &Repository, path: &str) -> String {
+        let tree = repository
+            .find_reference("refs/heads/main")
+            .unwrap()
+            .peel_to_tree()
+            .unwrap();
+        let blob = repository
+            .find_blob(tree.get_path(Path::new(path)).unwrap().id())
+            .unwrap();
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_commits_and_pushes_transactions() {
+        let dir = env::temp_dir().join(format!("git-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let remote = remote(&dir.join("remote.git"));
+        let url = dir.join("remote.git").to_string_lossy().into_owned();
+        // The local transport can't fetch shallow.
+        let store = GitStore::for_remote(url.as_str(), dir.join("checkout")).with_depth(0);
+        let other = GitStore::for_remote(url.as_str(), dir.join("other")).with_depth(0);
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let parser = BeancountParser::new(settings);
+
+        store
+            .save(parser.parse("2021-09-08 @KFC 12.40 cba > food").unwrap())
+            .await
+            .unwrap();
+        // The other checkout is behind now, and catches up before its commit.
+        assert!(other.read("2021.bean").await.unwrap().is_some());
+        other
+            .save(parser.parse("2021-09-09 @Coles 30 cba > food").unwrap())
+            .await
+            .unwrap();
+        store
+            .write(
+                "prices.bean",
+                "2021-09-09 price USD 1.38 AUD\n",
+                "added prices",
+            )
+            .await
+            .unwrap();
+
+        let ledger = content(&remote, "2021.bean");
+        assert!(ledger.starts_with("option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\""));
+        assert!(ledger.contains("\n2021-09-09 * \"Coles\""));
+        let head = remote
+            .find_reference("refs/heads/main")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(head.message(), Some("added prices"));
+        assert_eq!(
+            head.parent(0).unwrap().message(),
+            Some("2021-09-09 Coles 30.00 AUD (cba→food)")
+        );
+        assert_eq!(store.read("2021.bean").await.unwrap(), Some(ledger));
+
+        assert!(store.read("../secrets").await.is_err());
+
+        // Reads trust a checkout synced within the interval.
+        other
+            .write("prices.bean", "", "removed prices")
+            .await
+            .unwrap();
+        assert!(!store.read("prices.bean").await.unwrap().unwrap().is_empty());
+        let fresh = GitStore::for_remote(url.as_str(), dir.join("checkout"))
+            .with_depth(0)
+            .with_sync_interval(Duration::ZERO);
+        assert_eq!(
+            fresh.read("prices.bean").await.unwrap().as_deref(),
+            Some("")
+        );
+        assert!(matches!(
+            store.delete("2022.bean", "removed 2022").await,
+            Err(StoreError::NotFound(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use crate::error::StoreError;
+use crate::github_store::GithubClient;
+use crate::http_client::{json, HttpClient};
+use crate::Store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::encode;
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use http::{Method, StatusCode};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+#[cfg(feature = "native-http")]
+use std::env;
+use std::sync::Arc;
+use tracing::instrument;
+
+const GRAPHQL_URL: &str = "https://api.github.com/graphql";
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Appends transactions with the GraphQL `createCommitOnBranch` mutation.
+///
+/// The head commit the content was read from is sent as `expectedHeadOid`, so a
+/// concurrent push makes the mutation fail instead of overwriting it; the file is
+/// then re-read and the commit retried.
+pub struct GithubGraphqlStore {
+    owner: String,
+    repo: String,
+    client: GithubClient,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    commit_message: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct GraphqlRequest {
+    query: &'static str,
+    variables: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphqlResponse {
+    data: Option<Value>,
+    errors: Option<Vec<GraphqlError>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphqlError {
+    message: String,
+}
+
+struct FileSnapshot {
+    branch: String,
+    head_oid: String,
+    /// The `object` the path resolved to, `None` when there is no such file.
+    blob: Option<Value>,
+}
+
+impl FileSnapshot {
+    /// The content of the file at `path`, `None` when it doesn't exist.
+    fn text(&self, path: &str) -> Result<Option<String>, StoreError> {
+        let blob = match &self.blob {
+            Some(blob) => blob,
+            None => return Ok(None),
+        };
+        match (
+            blob.get("isTruncated").and_then(Value::as_bool),
+            blob.get("text").and_then(Value::as_str),
+        ) {
+            (Some(false), Some(text)) => Ok(Some(text.into())),
+            (Some(true), _) => Err(StoreError::Other(anyhow!(
+                "{} is too big to read through the github graphql api",
+                path
+            ))),
+            _ => Err(StoreError::Other(anyhow!("{} isn't a text file", path))),
+        }
+    }
+}
+
+/// The `data` of `response`, failing when it has `errors`.
+fn data_of(response: GraphqlResponse) -> Result<Value, StoreError> {
+    if let Some(errors) = response.errors.filter(|errors| !errors.is_empty()) {
+        for e in errors.iter() {
+            error!("github graphql api error: {}", e.message);
+        }
+        return Err(StoreError::Other(anyhow!(
+            "github graphql api error: {}",
+            errors[0].message
+        )));
+    }
+    response
+        .data
+        .ok_or_else(|| StoreError::Other(anyhow!("github graphql api answered without data")))
+}
+
+const BRANCH_QUERY: &str = r#"
+query($owner: String!, $repo: String!) {
+  repository(owner: $owner, name: $repo) {
+    defaultBranchRef {
+      name
+      target { oid }
+    }
+  }
+}
+"#;
+
+const BLOB_QUERY: &str = r#"
+query($owner: String!, $repo: String!, $expression: String!) {
+  repository(owner: $owner, name: $repo) {
+    object(expression: $expression) {
+      ... on Blob { text isTruncated }
+    }
+  }
+}
+"#;
+
+const COMMIT_MUTATION: &str = r#"
+mutation($input: CreateCommitOnBranchInput!) {
+  createCommitOnBranch(input: $input) {
+    commit { oid }
+  }
+}
+"#;
+
+impl GithubGraphqlStore {
+    #[cfg(feature = "native-http")]
+    pub fn new() -> Result<Self> {
+        Self::for_repo(
+            &env::var("GITHUB_OWNER")?,
+            &env::var("GITHUB_REPO")?,
+            &Secret::from_env("GITHUB_TOKEN")?,
+        )
+    }
+
+    /// A store for `owner/repo` reached with `token` instead of the `GITHUB_*`
+    /// env vars, e.g. a tenant's ledger.
+    #[cfg(feature = "native-http")]
+    pub fn for_repo(owner: &str, repo: &str, token: &Secret<String>) -> Result<Self> {
+        let http = Arc::new(crate::http_client::ReqwestClient::new()?);
+        Self::with_client(http, owner, repo, token)
+    }
+
+    /// A store sending its requests through `http`, see
+    /// [`crate::github_store::GithubStore::with_client`].
+    pub fn with_client(
+        http: Arc<dyn HttpClient>,
+        owner: &str,
+        repo: &str,
+        token: &Secret<String>,
+    ) -> Result<Self> {
+        Ok(GithubGraphqlStore {
+            owner: owner.into(),
+            repo: repo.into(),
+            client: GithubClient::new(http, token)?,
+            file_header: None,
+            ledger_path: None,
+            commit_message: None,
+        })
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    /// Writes to `repository` (`owner/name`, or `name` under the same owner)
+    /// instead of `GITHUB_REPO`.
+    pub fn with_repository(mut self, repository: Option<&str>) -> Self {
+        if let Some(repository) = repository {
+            let (owner, repo) = crate::github_store::split_repository(&self.owner, repository);
+            self.owner = owner;
+            self.repo = repo;
+        }
+        self
+    }
+
+    async fn execute(
+        &self,
+        query: &'static str,
+        variables: Value,
+    ) -> Result<GraphqlResponse, StoreError> {
+        let request = GraphqlRequest { query, variables };
+        let response = self
+            .client
+            .send_json(Method::POST, GRAPHQL_URL, &request)
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(json(&response)?),
+            _ => Err(StoreError::from_http_response(
+                &response,
+                "Failed to call github graphql api",
+            )),
+        }
+    }
+
+    async fn snapshot(&self, path: &str) -> Result<FileSnapshot, StoreError> {
+        let missing =
+            |field: &str| StoreError::Other(anyhow!("Failed to get {} of {}", field, self.repo));
+        let response = self
+            .execute(
+                BRANCH_QUERY,
+                json!({ "owner": self.owner, "repo": self.repo }),
+            )
+            .await?;
+        let data = data_of(response)?;
+        let branch = data
+            .pointer("/repository/defaultBranchRef/name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| missing("the default branch"))?
+            .to_string();
+        let head_oid = data
+            .pointer("/repository/defaultBranchRef/target/oid")
+            .and_then(Value::as_str)
+            .ok_or_else(|| missing("the head commit"))?
+            .to_string();
+
+        let response = self
+            .execute(
+                BLOB_QUERY,
+                json!({
+                    "owner": self.owner,
+                    "repo": self.repo,
+                    "expression": format!("{}:{}", head_oid, path),
+                }),
+            )
+            .await?;
+        // Only an explicit `null` object means there's no such file.
+        let blob = data_of(response)?
+            .pointer("/repository/object")
+            .cloned()
+            .ok_or_else(|| missing(path))?;
+
+        Ok(FileSnapshot {
+            branch,
+            head_oid,
+            blob: Some(blob).filter(|blob| !blob.is_null()),
+        })
+    }
+
+    async fn commit(
+        &self,
+        snapshot: &FileSnapshot,
+        file_changes: Value,
+        message: &str,
+    ) -> Result<bool, StoreError> {
+        let input = json!({
+            "branch": {
+                "repositoryNameWithOwner": format!("{}/{}", self.owner, self.repo),
+                "branchName": snapshot.branch,
+            },
+            "message": { "headline": message },
+            "fileChanges": file_changes,
+            "expectedHeadOid": snapshot.head_oid,
+        });
+        let response = self
+            .execute(COMMIT_MUTATION, json!({ "input": input }))
+            .await?;
+        match response.errors {
+            None => Ok(true),
+            Some(errors) if errors.iter().any(|e| is_stale_head(&e.message)) => {
+                warn!(
+                    "branch {} moved while committing, retrying",
+                    snapshot.branch
+                );
+                Ok(false)
+            }
+            Some(errors) => {
+                for e in errors.iter() {
+                    error!("github graphql api error: {}", e.message);
+                }
+                Err(StoreError::Other(anyhow!(
+                    "Failed to commit to {}",
+                    self.repo
+                )))
+            }
+        }
+    }
+
+    async fn commit_with_retry<F>(
+        &self,
+        path: &str,
+        message: &str,
+        changes: F,
+    ) -> Result<(), StoreError>
+    where
+        F: Fn(&FileSnapshot) -> Result<Value, StoreError>,
+    {
+        for _ in 0..MAX_ATTEMPTS {
+            let snapshot = self.snapshot(path).await?;
+            let file_changes = changes(&snapshot)?;
+            if self.commit(&snapshot, file_changes, message).await? {
+                info!(
+                    "Successfully committed file {} in repo {}.",
+                    path, self.repo
+                );
+                return Ok(());
+            }
+        }
+
+        error!(
+            "Gave up committing {} after {} attempts",
+            path, MAX_ATTEMPTS
+        );
+        Err(StoreError::Conflict(format!(
+            "Failed to commit to {}",
+            self.repo
+        )))
+    }
+}
+
+fn is_stale_head(message: &str) -> bool {
+    message.contains("Expected branch to point to")
+}
+
+#[async_trait]
+impl Store for GithubGraphqlStore {
+    #[instrument(name = "github_graphql.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let message = transaction.commit_message(self.commit_message.as_deref());
+        let transaction_text = String::from(transaction);
+
+        self.commit_with_retry(&path, &message, |snapshot| {
+            let content = snapshot.text(&path)?.unwrap_or_else(|| {
+                info!("file {} not found, will create the file", path);
+                crate::render_file_header(self.file_header.as_deref(), &year)
+            });
+            crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+            let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+            Ok(json!({ "additions": [{ "path": path, "contents": encode(content) }] }))
+        })
+        .await?;
+        Ok(transaction_text)
+    }
+
+    #[instrument(name = "github_graphql.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        self.snapshot(path).await?.text(path)
+    }
+
+    #[instrument(name = "github_graphql.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError> {
+        self.commit_with_retry(path, message, |_| {
+            Ok(json!({ "additions": [{ "path": path, "contents": encode(bytes) }] }))
+        })
+        .await
+    }
+
+    #[instrument(name = "github_graphql.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError> {
+        self.commit_with_retry(path, message, |snapshot| match snapshot.blob {
+            Some(_) => Ok(json!({ "deletions": [{ "path": path }] })),
+            None => Err(StoreError::NotFound(path.into())),
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_detects_stale_head_error() {
+        assert!(is_stale_head(
+            "Expected branch to point to \"abc\" but it did not. Pull and try again."
+        ));
+        assert!(!is_stale_head("Resource not accessible by integration"));
+    }
+
+    fn snapshot(blob: Value) -> FileSnapshot {
+        FileSnapshot {
+            branch: "main".into(),
+            head_oid: "abc".into(),
+            blob: Some(blob).filter(|blob| !blob.is_null()),
+        }
+    }
+
+    #[test]
+    fn it_reads_only_whole_text_blobs() {
+        let text =
+            snapshot(json!({ "text": "2021-09-08 open Assets:CBA\n", "isTruncated": false }));
+        assert_eq!(
+            text.text("2021.bean").unwrap().as_deref(),
+            Some("2021-09-08 open Assets:CBA\n")
+        );
+        assert_eq!(snapshot(Value::Null).text("2021.bean").unwrap(), None);
+        let truncated = snapshot(json!({ "text": "2021", "isTruncated": true }));
+        assert!(truncated.text("2021.bean").is_err());
+        let binary = snapshot(json!({ "text": null, "isTruncated": false }));
+        assert!(binary.text("receipt.jpg").is_err());
+        assert!(snapshot(json!({})).text("documents").is_err());
+    }
+
+    #[test]
+    fn it_fails_on_graphql_errors() {
+        let response: GraphqlResponse = serde_json::from_value(json!({
+            "data": { "repository": null },
+            "errors": [{ "message": "Could not resolve to a Repository" }],
+        }))
+        .unwrap();
+        assert!(data_of(response).is_err());
+        let response: GraphqlResponse = serde_json::from_value(json!({ "data": null })).unwrap();
+        assert!(data_of(response).is_err());
+        let response: GraphqlResponse =
+            serde_json::from_value(json!({ "data": { "repository": null }, "errors": [] }))
+                .unwrap();
+        assert!(data_of(response).is_ok());
+    }
+}
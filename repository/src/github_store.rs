@@ -1,16 +1,43 @@
+use crate::error::StoreError;
+use crate::http_client::{json, HttpClient};
 use crate::Store;
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use async_trait::async_trait;
 use base64::{decode, encode};
 use beancount_core::parser::Transaction;
-use log::{error, info};
-use reqwest::{blocking::Client, header, StatusCode};
+use beancount_core::secret::Secret;
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env};
+use std::collections::HashMap;
+#[cfg(feature = "native-http")]
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info_span, instrument, Instrument};
+
+/// The API of github.com, `GITHUB_API_URL` points elsewhere for GitHub Enterprise.
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+const USER_AGENT: &str = "beancount-automation/0.1.0";
+/// Saves are tried this many times unless `GITHUB_SAVE_ATTEMPTS` says otherwise.
+const DEFAULT_ATTEMPTS: u32 = 3;
+/// More attempts than this only keep a webhook waiting past Telegram's timeout.
+const MAX_ATTEMPTS: u32 = 10;
+/// The wait before the second attempt, doubled before each one after it.
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+/// The longest wait between two attempts, however many came before.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
 pub struct GithubStore {
+    api_url: String,
     owner: String,
     repo: String,
-    client: Client,
+    client: GithubClient,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    commit_message: Option<String>,
+    attempts: u32,
+    backoff: Duration,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -42,119 +69,559 @@ struct Links {
 struct UpdateRequest {
     message: String,
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeleteRequest {
+    message: String,
     sha: String,
 }
 
 impl GithubStore {
+    #[cfg(feature = "native-http")]
     pub fn new() -> Result<Self> {
-        let owner = env::var("GITHUB_OWNER")?;
-        let repo = env::var("GITHUB_REPO")?;
-        let github_token = env::var("GITHUB_TOKEN")?;
-
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            "Accept",
-            header::HeaderValue::from_static("application/vnd.github.v3+json"),
-        );
+        Self::for_repo(
+            &env::var("GITHUB_OWNER")?,
+            &env::var("GITHUB_REPO")?,
+            &Secret::from_env("GITHUB_TOKEN")?,
+        )
+    }
 
-        let mut token = header::HeaderValue::from_str(&format!("token {}", github_token))?;
-        token.set_sensitive(true);
-        headers.insert(header::AUTHORIZATION, token);
+    /// A store for `owner/repo` reached with `token` instead of the `GITHUB_*`
+    /// env vars, e.g. a tenant's ledger.
+    #[cfg(feature = "native-http")]
+    pub fn for_repo(owner: &str, repo: &str, token: &Secret<String>) -> Result<Self> {
+        let api_url = env::var("GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.into());
+        let http = Arc::new(crate::http_client::ReqwestClient::new()?);
+        let attempts = match env::var("GITHUB_SAVE_ATTEMPTS") {
+            Ok(v) => v.parse()?,
+            Err(_) => DEFAULT_ATTEMPTS,
+        };
+        if !(1..=MAX_ATTEMPTS).contains(&attempts) {
+            return Err(anyhow::anyhow!(
+                "GITHUB_SAVE_ATTEMPTS must be between 1 and {}, not {}",
+                MAX_ATTEMPTS,
+                attempts
+            ));
+        }
+        Ok(Self::with_client(http, &api_url, owner, repo, token)?
+            .with_retries(attempts, DEFAULT_BACKOFF))
+    }
 
-        let client = reqwest::blocking::Client::builder()
-            .default_headers(headers)
-            .user_agent("beancount-automation/0.1.0")
-            .build()?;
+    /// A store sending its requests through `http`, for hosts without reqwest
+    /// such as wasm. Reads no env vars.
+    pub fn with_client(
+        http: Arc<dyn HttpClient>,
+        api_url: &str,
+        owner: &str,
+        repo: &str,
+        token: &Secret<String>,
+    ) -> Result<Self> {
         Ok(GithubStore {
-            owner,
-            repo,
-            client,
+            api_url: api_url.trim_end_matches('/').into(),
+            owner: owner.into(),
+            repo: repo.into(),
+            client: GithubClient::new(http, token)?,
+            file_header: None,
+            ledger_path: None,
+            commit_message: None,
+            attempts: DEFAULT_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
         })
     }
+
+    /// Tries a save up to `attempts` times when the file changed while saving
+    /// (409) or GitHub failed (5xx), waiting `backoff` before the second
+    /// attempt and twice as long before each one after it, up to
+    /// `MAX_BACKOFF`. `attempts` is capped at `MAX_ATTEMPTS`.
+    pub fn with_retries(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts.clamp(1, MAX_ATTEMPTS);
+        self.backoff = backoff;
+        self
+    }
+
+    /// The wait after failed `attempt`, see [`GithubStore::with_retries`].
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt - 1)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    /// Writes to `repository` (`owner/name`, or `name` under the same owner)
+    /// instead of `GITHUB_REPO`.
+    pub fn with_repository(mut self, repository: Option<&str>) -> Self {
+        if let Some(repository) = repository {
+            let (owner, repo) = crate::github_store::split_repository(&self.owner, repository);
+            self.owner = owner;
+            self.repo = repo;
+        }
+        self
+    }
+}
+
+pub(crate) fn split_repository(owner: &str, repository: &str) -> (String, String) {
+    match repository.split_once('/') {
+        Some((owner, repo)) => (owner.into(), repo.into()),
+        None => (owner.into(), repository.into()),
+    }
 }
 
+/// Sends authenticated requests to the GitHub APIs.
+pub(crate) struct GithubClient {
+    http: Arc<dyn HttpClient>,
+    authorization: HeaderValue,
+}
+
+impl GithubClient {
+    pub(crate) fn new(http: Arc<dyn HttpClient>, token: &Secret<String>) -> Result<Self> {
+        let mut authorization = HeaderValue::from_str(&format!("token {}", token.expose()))?;
+        authorization.set_sensitive(true);
+        Ok(GithubClient {
+            http,
+            authorization,
+        })
+    }
+
+    pub(crate) async fn get(&self, url: &str) -> Result<Response<Vec<u8>>, StoreError> {
+        self.send(Method::GET, url, Vec::new()).await
+    }
+
+    pub(crate) async fn send_json(
+        &self,
+        method: Method,
+        url: &str,
+        body: &impl Serialize,
+    ) -> Result<Response<Vec<u8>>, StoreError> {
+        self.send(method, url, serde_json::to_vec(body)?).await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Vec<u8>>, StoreError> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(header::ACCEPT, "application/vnd.github.v3+json")
+            .header(header::AUTHORIZATION, self.authorization.clone())
+            .header(header::USER_AGENT, USER_AGENT);
+        if !body.is_empty() {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+        }
+        let request = request
+            .body(body)
+            .map_err(|e| StoreError::Other(e.into()))?;
+        self.http.send(request).await
+    }
+}
+
+#[async_trait]
 impl Store for GithubStore {
-    fn save(&self, transaction: Transaction) -> Result<String> {
-        let path = format!("{}.bean", transaction.year());
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/contents/{}",
-            self.owner, self.repo, path
+    #[instrument(name = "github.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let message = transaction.commit_message(self.commit_message.as_deref());
+        let transaction_text = String::from(transaction);
+
+        let mut attempt = 1;
+        loop {
+            match self
+                .append(
+                    &path,
+                    &year,
+                    &transaction_text,
+                    marker.as_deref(),
+                    &message,
+                    duplicate_lines,
+                )
+                .await
+            {
+                Ok(()) => break,
+                Err(e) if is_transient(&e) && attempt < self.attempts => {
+                    let delay = self.retry_delay(attempt);
+                    warn!("Saving to {} failed, retrying in {:?}: {}", path, delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        info!(
+            "Successfully created/updated file {} in repo {}.",
+            year, self.repo
         );
+        Ok(transaction_text)
+    }
+
+    #[instrument(name = "github.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        match self.get_file(path).await? {
+            Some(file_content) => {
+                let decoded_value = decode(file_content.content.replace('\n', ""))?;
+                Ok(Some(String::from_utf8_lossy(&decoded_value).into_owned()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "github.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError> {
+        let update_request = UpdateRequest {
+            message: message.to_string(),
+            content: encode(bytes),
+            sha: self
+                .get_file(path)
+                .await?
+                .map(|file_content| file_content.sha),
+        };
+        let response = self
+            .client
+            .send_json(Method::PUT, &self.contents_url(path), &update_request)
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED => Ok(()),
+            _ => Err(StoreError::from_http_response(
+                &response,
+                format!("Failed to write file {}", path),
+            )),
+        }
+    }
+
+    #[instrument(name = "github.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError> {
+        let file_content = match self.get_file(path).await? {
+            Some(v) => v,
+            None => return Err(StoreError::NotFound(path.into())),
+        };
+        let delete_request = DeleteRequest {
+            message: message.to_string(),
+            sha: file_content.sha,
+        };
+        let response = self
+            .client
+            .send_json(Method::DELETE, &self.contents_url(path), &delete_request)
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            _ => Err(StoreError::from_http_response(
+                &response,
+                format!("Failed to delete file {}", path),
+            )),
+        }
+    }
+}
+
+/// Failures a fresh read of the file and another PUT can get past.
+fn is_transient(e: &StoreError) -> bool {
+    match e {
+        StoreError::Conflict(_) => true,
+        StoreError::Api { status, .. } => status.is_server_error(),
+        _ => false,
+    }
+}
 
-        let mut content_response = self.client.get(&url).send()?;
+impl GithubStore {
+    /// Reads the ledger file at `path`, creating it if needed, and puts it
+    /// back with `transaction_text` added, based on the sha that was read.
+    async fn append(
+        &self,
+        path: &str,
+        year: &str,
+        transaction_text: &str,
+        marker: Option<&str>,
+        message: &str,
+        duplicate_lines: usize,
+    ) -> Result<(), StoreError> {
+        let url = self.contents_url(path);
+        let mut content_response = self
+            .client
+            .get(&url)
+            .instrument(info_span!("github.get", path = %path))
+            .await?;
         match content_response.status() {
             StatusCode::OK => (),
             StatusCode::NOT_FOUND => {
                 info!("file {} not found, will create the file", path);
-                self.create_file(path.as_str())?;
+                self.create_file(path, year).await?;
                 info!("new file {} created.", path);
-                content_response = self.client.get(&url).send()?;
+                content_response = self
+                    .client
+                    .get(&url)
+                    .instrument(info_span!("github.get", path = %path))
+                    .await?;
             }
             _ => {
-                error!("Failed to get file!");
-                error!("Response status was {}", content_response.status());
-                error!("Response body was {}", content_response.text()?);
-                return Err(anyhow!("Failed to get file content"));
+                return Err(StoreError::from_http_response(
+                    &content_response,
+                    "Failed to get file content",
+                ))
             }
         };
 
-        let file_content: FileContent = content_response.json()?;
-        let decoded_value = decode(&file_content.content.replace('\n', ""))?;
-        let content = String::from_utf8_lossy(&decoded_value);
-        let transaction_year = transaction.year();
-        let transaction_text = String::from(transaction);
-
+        let file_content: FileContent = json(&content_response)?;
+        let decoded_value = decode(file_content.content.replace('\n', ""))?;
+        let content = String::from_utf8_lossy(&decoded_value).into_owned();
+        crate::refuse_copy(path, &content, transaction_text, duplicate_lines)?;
         let update_request = UpdateRequest {
-            message: "updated content".to_string(),
-            content: encode(format!("{}\n{}", content, transaction_text)),
-            sha: file_content.sha,
+            message: message.to_string(),
+            content: encode(crate::upsert_entry(&content, transaction_text, marker)),
+            sha: Some(file_content.sha),
         };
 
-        let body = serde_json::to_string(&update_request)?;
-        let rb = self.client.put(url).body(body);
-        let response = rb.send()?;
+        let response = self
+            .client
+            .send_json(Method::PUT, &url, &update_request)
+            .instrument(info_span!("github.put", path = %path))
+            .await?;
         match response.status() {
-            StatusCode::OK | StatusCode::CREATED => {
-                info!(
-                    "Successfully created/updated file {} in repo {}.",
-                    transaction_year, self.repo
-                );
-                Ok(transaction_text)
-            }
-            _ => {
-                error!("Failed to save transaction!");
-                error!(
-                    "github api response status code was [{}]",
-                    response.status()
-                );
-                error!("github api response body was {}", response.text()?);
-                Err(anyhow!("Failed to save transaction!"))
-            }
+            StatusCode::OK | StatusCode::CREATED => Ok(()),
+            _ => Err(StoreError::from_http_response(
+                &response,
+                "Failed to save transaction!",
+            )),
         }
     }
-}
 
-impl GithubStore {
-    fn create_file(&self, path: &str) -> Result<()> {
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/contents/{}",
-            self.owner, self.repo, path
-        );
+    fn contents_url(&self, path: &str) -> String {
+        format!(
+            "{}/repos/{}/{}/contents/{}",
+            self.api_url, self.owner, self.repo, path
+        )
+    }
+
+    async fn get_file(&self, path: &str) -> Result<Option<FileContent>, StoreError> {
+        let response = self
+            .client
+            .get(&self.contents_url(path))
+            .instrument(info_span!("github.get", path = %path))
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(json(&response)?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(StoreError::from_http_response(
+                &response,
+                "Failed to get file content",
+            )),
+        }
+    }
+
+    async fn create_file(&self, path: &str, year: &str) -> Result<(), StoreError> {
+        let url = self.contents_url(path);
+        let header = crate::render_file_header(self.file_header.as_deref(), year);
         let mut body = HashMap::new();
         body.insert("message", format!("created file {}", path));
-        body.insert("content", "".into());
-        let response = self.client.put(&url).json(&body).send()?;
+        body.insert("content", encode(header));
+        let response = self.client.send_json(Method::PUT, &url, &body).await?;
         match response.status() {
             StatusCode::CREATED | StatusCode::OK => Ok(()),
-            _ => {
-                error!("Failed to create new file {}", path);
-                error!(
-                    "github api response status code was [{}]",
-                    response.status()
-                );
-                error!("github api response body was {}", response.text()?);
-                Err(anyhow!("Failed to create new file {}", path))
-            }
+            _ => Err(StoreError::from_http_response(
+                &response,
+                format!("Failed to create new file {}", path),
+            )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Answers every request with `status` and `body`, remembering the requests.
+    struct CannedClient {
+        status: StatusCode,
+        body: &'static str,
+        requests: Mutex<Vec<Request<Vec<u8>>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for CannedClient {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+            self.requests.lock().unwrap().push(request);
+            Ok(Response::builder()
+                .status(self.status)
+                .body(self.body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reads_files_through_any_http_client() {
+        let http = Arc::new(CannedClient {
+            status: StatusCode::OK,
+            body: r#"{"type": "file", "encoding": "base64", "size": 5, "name": "2021.bean",
+                "path": "2021.bean", "content": "aGVs\nbG8=\n", "sha": "abc", "url": "",
+                "git_url": "", "html_url": "", "download_url": "",
+                "_links": {"git": "", "self": "", "html": ""}}"#,
+            requests: Mutex::new(Vec::new()),
+        });
+        let token = Secret::new("secret-token".to_string());
+        let store = GithubStore::with_client(
+            http.clone(),
+            "https://ghe.example/api/v3/",
+            "liul85",
+            "ledger",
+            &token,
+        )
+        .unwrap();
+
+        assert_eq!(
+            store.read("2021.bean").await.unwrap().as_deref(),
+            Some("hello")
+        );
+        let requests = http.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].uri(),
+            "https://ghe.example/api/v3/repos/liul85/ledger/contents/2021.bean"
+        );
+        assert_eq!(
+            requests[0].headers()[header::AUTHORIZATION],
+            "token secret-token"
+        );
+        assert!(requests[0].headers()[header::AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn it_classifies_failed_responses() {
+        let http = Arc::new(CannedClient {
+            status: StatusCode::UNAUTHORIZED,
+            body: "{\"message\": \"Bad credentials\"}",
+            requests: Mutex::new(Vec::new()),
+        });
+        let token = Secret::new("expired".to_string());
+        let store =
+            GithubStore::with_client(http, DEFAULT_API_URL, "liul85", "ledger", &token).unwrap();
+        assert!(matches!(
+            store.read("2021.bean").await.unwrap_err(),
+            StoreError::Auth { .. }
+        ));
+    }
+
+    /// Answers requests with the next of `responses`, remembering the methods.
+    struct ScriptedClient {
+        responses: Mutex<Vec<(StatusCode, &'static str)>>,
+        methods: Mutex<Vec<Method>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for ScriptedClient {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+            self.methods.lock().unwrap().push(request.method().clone());
+            let (status, body) = self.responses.lock().unwrap().remove(0);
+            Ok(Response::builder()
+                .status(status)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_retries_saves_on_conflicts_and_server_errors() {
+        const FILE: &str = r#"{"type": "file", "encoding": "base64", "size": 0, "name": "2021.bean",
+            "path": "2021.bean", "content": "", "sha": "abc", "url": "", "git_url": "",
+            "html_url": "", "download_url": "", "_links": {"git": "", "self": "", "html": ""}}"#;
+        let http = Arc::new(ScriptedClient {
+            responses: Mutex::new(vec![
+                (StatusCode::OK, FILE),
+                (StatusCode::CONFLICT, "{}"),
+                (StatusCode::OK, FILE),
+                (StatusCode::BAD_GATEWAY, "{}"),
+                (StatusCode::OK, FILE),
+                (StatusCode::OK, "{}"),
+            ]),
+            methods: Mutex::new(Vec::new()),
+        });
+        let token = Secret::new("token".to_string());
+        let settings = beancount_core::settings::Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let transaction = beancount_core::parser::BeancountParser::new(settings)
+            .parse("2021-09-08 @KFC 12.40 cba > food")
+            .unwrap();
+
+        let store =
+            GithubStore::with_client(http.clone(), DEFAULT_API_URL, "liul85", "ledger", &token)
+                .unwrap()
+                .with_retries(3, Duration::ZERO);
+        assert!(store.save(transaction.clone()).await.is_ok());
+        assert_eq!(http.methods.lock().unwrap().len(), 6);
+
+        http.responses.lock().unwrap().extend([
+            (StatusCode::OK, FILE),
+            (StatusCode::CONFLICT, "{}"),
+            (StatusCode::OK, FILE),
+            (StatusCode::CONFLICT, "{}"),
+        ]);
+        let store = GithubStore::with_client(http, DEFAULT_API_URL, "liul85", "ledger", &token)
+            .unwrap()
+            .with_retries(2, Duration::ZERO);
+        assert!(matches!(
+            store.save(transaction).await,
+            Err(StoreError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn it_caps_the_wait_between_attempts() {
+        let http = Arc::new(CannedClient {
+            status: StatusCode::OK,
+            body: "{}",
+            requests: Mutex::new(Vec::new()),
+        });
+        let token = Secret::new("token".to_string());
+        let store = GithubStore::with_client(http, DEFAULT_API_URL, "liul85", "ledger", &token)
+            .unwrap()
+            .with_retries(u32::MAX, Duration::from_secs(u64::MAX / 2));
+        assert_eq!(store.attempts, MAX_ATTEMPTS);
+        assert_eq!(store.retry_delay(1), MAX_BACKOFF);
+        assert_eq!(store.retry_delay(40), MAX_BACKOFF);
+
+        let store = store.with_retries(5, DEFAULT_BACKOFF);
+        assert_eq!(store.retry_delay(1), Duration::from_millis(500));
+        assert_eq!(store.retry_delay(3), Duration::from_secs(2));
+        assert_eq!(store.retry_delay(8), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn it_splits_repository_with_optional_owner() {
+        assert_eq!(
+            split_repository("liul85", "acme/books"),
+            ("acme".to_string(), "books".to_string())
+        );
+        assert_eq!(
+            split_repository("liul85", "business"),
+            ("liul85".to_string(), "business".to_string())
+        );
+    }
+}
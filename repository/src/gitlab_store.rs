@@ -0,0 +1,302 @@
+use crate::error::StoreError;
+use crate::Store;
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{decode, encode};
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+use log::{error, info, warn};
+use reqwest::{header, Client, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use tracing::instrument;
+
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Stores the ledger in a GitLab repository, on gitlab.com or a self-managed
+/// instance, through the repository files API.
+///
+/// Updates name the `last_commit_id` of the file they were based on; when
+/// someone else committed to it in between GitLab rejects the update and it's
+/// rebuilt from the new content.
+pub struct GitLabStore {
+    /// `<api>/projects/<id>/repository/files`
+    files_url: String,
+    branch: String,
+    client: Client,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    commit_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct File {
+    content: String,
+    last_commit_id: String,
+}
+
+impl File {
+    fn text(&self) -> Result<String, StoreError> {
+        let decoded = decode(self.content.replace('\n', ""))?;
+        Ok(String::from_utf8_lossy(&decoded).into_owned())
+    }
+}
+
+impl GitLabStore {
+    pub fn new() -> Result<Self> {
+        let url = env::var("GITLAB_URL").unwrap_or_else(|_| "https://gitlab.com".into());
+        let project = env::var("GITLAB_PROJECT")?;
+        let token = Secret::from_env("GITLAB_TOKEN")?;
+        let branch = env::var("GITLAB_BRANCH").unwrap_or_else(|_| "main".into());
+
+        let mut headers = header::HeaderMap::new();
+        let mut private_token = header::HeaderValue::from_str(token.expose())?;
+        private_token.set_sensitive(true);
+        headers.insert("PRIVATE-TOKEN", private_token);
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .user_agent("beancount-automation/0.1.0")
+            .build()?;
+        Ok(GitLabStore {
+            files_url: format!(
+                "{}/api/v4/projects/{}/repository/files",
+                url.trim_end_matches('/'),
+                url_encode(&project)
+            ),
+            branch,
+            client,
+            file_header: None,
+            ledger_path: None,
+            commit_message: None,
+        })
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    fn file_url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.files_url,
+            url_encode(path.trim_start_matches('/'))
+        )
+    }
+
+    async fn get_file(&self, path: &str) -> Result<Option<File>, StoreError> {
+        let response = self
+            .client
+            .get(self.file_url(path))
+            .query(&[("ref", self.branch.as_str())])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(StoreError::from_response(response, "Failed to get file content").await),
+        }
+    }
+
+    /// Commits the content built from the current file content, retrying when
+    /// the file changed underneath us. `content` returning `None` deletes the
+    /// file.
+    async fn commit_with_retry<F>(
+        &self,
+        path: &str,
+        message: &str,
+        content: F,
+    ) -> Result<(), StoreError>
+    where
+        F: Fn(Option<String>) -> Result<Option<Vec<u8>>, StoreError>,
+    {
+        for _ in 0..MAX_ATTEMPTS {
+            let file = self.get_file(path).await?;
+            let current = file.as_ref().map(File::text).transpose()?;
+            let last_commit_id = file.map(|file| file.last_commit_id);
+            let (method, body) = match (content(current)?, last_commit_id) {
+                (Some(bytes), last_commit_id) => (
+                    if last_commit_id.is_some() {
+                        Method::PUT
+                    } else {
+                        Method::POST
+                    },
+                    commit_body(&self.branch, message, Some(&bytes), last_commit_id),
+                ),
+                (None, Some(last_commit_id)) => (
+                    Method::DELETE,
+                    commit_body(&self.branch, message, None, Some(last_commit_id)),
+                ),
+                (None, None) => return Err(StoreError::NotFound(path.into())),
+            };
+            let response = self
+                .client
+                .request(method, self.file_url(path))
+                .json(&body)
+                .send()
+                .await?;
+            match response.status() {
+                StatusCode::OK | StatusCode::CREATED | StatusCode::NO_CONTENT => {
+                    info!("Successfully committed file {} to {}.", path, self.branch);
+                    return Ok(());
+                }
+                StatusCode::BAD_REQUEST => {
+                    let body = response.text().await.unwrap_or_default();
+                    if !is_conflict(&body) {
+                        error!("Response body was {}", body);
+                        return Err(StoreError::Api {
+                            message: format!("Failed to commit file {}", path),
+                            status: StatusCode::BAD_REQUEST,
+                        });
+                    }
+                    warn!("file {} changed while committing, retrying", path);
+                }
+                _ => {
+                    return Err(StoreError::from_response(
+                        response,
+                        format!("Failed to commit file {}", path),
+                    )
+                    .await)
+                }
+            }
+        }
+
+        error!(
+            "Gave up committing {} after {} attempts",
+            path, MAX_ATTEMPTS
+        );
+        Err(StoreError::Conflict(format!(
+            "Failed to commit file {}",
+            path
+        )))
+    }
+}
+
+/// Percent-encodes everything but unreserved characters, as GitLab wants
+/// project paths and file paths in URLs, e.g. `2021.bean` as `2021%2Ebean`.
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn commit_body(
+    branch: &str,
+    message: &str,
+    bytes: Option<&[u8]>,
+    last_commit_id: Option<String>,
+) -> Value {
+    let mut body = json!({ "branch": branch, "commit_message": message });
+    if let Some(bytes) = bytes {
+        body["encoding"] = json!("base64");
+        body["content"] = json!(encode(bytes));
+    }
+    if let Some(last_commit_id) = last_commit_id {
+        body["last_commit_id"] = json!(last_commit_id);
+    }
+    body
+}
+
+/// GitLab answers 400 when the file was changed since `last_commit_id`, or
+/// created since we found it missing.
+fn is_conflict(body: &str) -> bool {
+    body.contains("has changed since") || body.contains("already exists")
+}
+
+#[async_trait]
+impl Store for GitLabStore {
+    #[instrument(name = "gitlab.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let message = transaction.commit_message(self.commit_message.as_deref());
+        let transaction_text = String::from(transaction);
+
+        self.commit_with_retry(&path, &message, |content| {
+            let content = content.unwrap_or_else(|| {
+                info!("file {} not found, will create the file", path);
+                crate::render_file_header(self.file_header.as_deref(), &year)
+            });
+            crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+            let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+            Ok(Some(content.into_bytes()))
+        })
+        .await?;
+        Ok(transaction_text)
+    }
+
+    #[instrument(name = "gitlab.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        self.get_file(path)
+            .await?
+            .as_ref()
+            .map(File::text)
+            .transpose()
+    }
+
+    #[instrument(name = "gitlab.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError> {
+        self.commit_with_retry(path, message, |_| Ok(Some(bytes.to_vec())))
+            .await
+    }
+
+    #[instrument(name = "gitlab.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError> {
+        self.commit_with_retry(path, message, |_| Ok(None)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_project_and_file_paths() {
+        assert_eq!(url_encode("liul85/beancount"), "liul85%2Fbeancount");
+        assert_eq!(url_encode("ledger/2021.bean"), "ledger%2F2021%2Ebean");
+        assert_eq!(url_encode("12345"), "12345");
+    }
+
+    #[test]
+    fn it_builds_commit_bodies() {
+        let body = commit_body("main", "updated content", Some(b"abc"), None);
+        assert_eq!(
+            body,
+            json!({ "branch": "main", "commit_message": "updated content", "encoding": "base64", "content": "YWJj" })
+        );
+        let body = commit_body("main", "removed", None, Some("9a1b".into()));
+        assert_eq!(
+            body,
+            json!({ "branch": "main", "commit_message": "removed", "last_commit_id": "9a1b" })
+        );
+        assert!(is_conflict("{\"message\":\"You are attempting to update a file that has changed since you started editing it.\"}"));
+        assert!(!is_conflict("{\"message\":\"branch is missing\"}"));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::error::StoreError;
+use async_trait::async_trait;
+use http::{Request, Response};
+
+/// Sends the requests of stores that talk to a web API, so their logic doesn't
+/// depend on reqwest and compiles to wasm, where the host supplies the client.
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError>;
+}
+
+/// Deserializes a JSON response body.
+#[cfg(feature = "github-contents")]
+pub(crate) fn json<T: serde::de::DeserializeOwned>(
+    response: &Response<Vec<u8>>,
+) -> Result<T, StoreError> {
+    Ok(serde_json::from_slice(response.body())?)
+}
+
+/// An [`HttpClient`] on reqwest, what native builds use.
+#[cfg(feature = "native-http")]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "native-http")]
+impl ReqwestClient {
+    pub fn new() -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent("beancount-automation/0.1.0")
+            .build()?;
+        Ok(ReqwestClient { client })
+    }
+}
+
+#[cfg(feature = "native-http")]
+#[async_trait]
+impl HttpClient for ReqwestClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+        let (parts, body) = request.into_parts();
+        let response = self
+            .client
+            .request(parts.method, parts.uri.to_string())
+            .headers(parts.headers)
+            .body(body)
+            .send()
+            .await?;
+        let mut builder = Response::builder().status(response.status());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = response.headers().clone();
+        }
+        let body = response.bytes().await?.to_vec();
+        builder.body(body).map_err(|e| StoreError::Other(e.into()))
+    }
+}
@@ -0,0 +1,109 @@
+use crate::Store;
+use anyhow::Result;
+use beancount_core::import::Statement;
+use beancount_core::ledger::Ledger;
+use beancount_core::settings::Settings;
+use log::info;
+
+/// Appends the rows of `statement` to their ledger files, leaving out those
+/// already there (see [`Statement::drop_known`]), with one write per file so the
+/// batch lands as a single commit per file. Returns the added entries; the
+/// dropped rows are added to `statement.skipped`.
+pub async fn commit_statement(
+    store: &dyn Store,
+    settings: &Settings,
+    statement: &mut Statement,
+    message: &str,
+) -> Result<Vec<String>> {
+    let template = settings.ledger_path.as_deref();
+    let mut paths: Vec<(String, String)> = statement
+        .rows
+        .iter()
+        .map(|row| {
+            (
+                row.transaction.year(),
+                row.transaction.ledger_path(template),
+            )
+        })
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut files = Vec::new();
+    for (year, path) in paths {
+        let content = store.read(&path).await?;
+        if let Some(content) = &content {
+            statement.drop_known(&Ledger::parse(&path, content));
+        }
+        files.push((year, path, content));
+    }
+
+    let mut added = Vec::new();
+    for (year, path, content) in files {
+        let entries: Vec<String> = statement
+            .rows
+            .iter()
+            .filter(|row| row.transaction.ledger_path(template) == path)
+            .map(|row| String::from(row.transaction.clone()))
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+        let mut content = content
+            .unwrap_or_else(|| crate::render_file_header(settings.file_header.as_deref(), &year));
+        for entry in entries.iter() {
+            content.push('\n');
+            content.push_str(entry);
+        }
+        store.write(&path, &content, message).await?;
+        info!("imported {} transactions to {}", entries.len(), path);
+        added.extend(entries);
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+    use beancount_core::import::{read_statement, StatementFormat};
+    use beancount_core::settings::ImportProfile;
+
+    #[tokio::test]
+    async fn it_appends_new_rows_to_the_files_of_their_years() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .default_from_account("cba")
+            .build()
+            .unwrap();
+        let profile = ImportProfile {
+            default_account: Some("food".into()),
+            ..ImportProfile::default()
+        };
+        let store = MemoryStore::new().with_file(
+            "2021.bean",
+            "2021-12-31 * \"KFC\" \"\"\n  Assets:CBA  -12.40 AUD\n  Expenses:Food\n",
+        );
+        let csv = "date,description,amount\n2021-12-31,KFC,-12.40\n2022-01-01,COLES,-3.00\n";
+        let mut statement =
+            read_statement(&settings, &profile, StatementFormat::Csv, csv.as_bytes()).unwrap();
+        let added = commit_statement(&store, &settings, &mut statement, "imported")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            added,
+            vec!["2022-01-01 * \"COLES\" \"\"\n  Assets:CBA        -3.00 AUD\n  Expenses:Food        3.00 AUD\n"]
+        );
+        assert_eq!(
+            statement.skipped,
+            vec!["line 2: KFC is already in the ledger"]
+        );
+        assert_eq!(store.file("2022.bean").unwrap(), format!("\n{}", added[0]));
+        assert!(store
+            .file("2021.bean")
+            .unwrap()
+            .ends_with("Expenses:Food\n"));
+    }
+}
@@ -1,8 +1,297 @@
-use anyhow::Result;
+use async_trait::async_trait;
+use beancount_core::duplicates::find_copy;
+use beancount_core::ledger::{includes, resolve_include, Ledger};
 use beancount_core::parser::Transaction;
+use chrono::NaiveDate;
+use error::StoreError;
+use log::warn;
+use std::collections::{HashMap, HashSet};
 
+pub mod account_discovery;
+pub mod aliases;
+#[cfg(feature = "azure")]
+pub mod azure_store;
+#[cfg(feature = "bank-feed")]
+pub mod bank_feed;
+pub mod chat_profiles;
+pub mod config_source;
+#[cfg(feature = "couchdb")]
+pub mod couchdb_store;
+pub mod dead_letter;
+pub mod duplicates;
+pub mod error;
+pub mod fs_store;
+#[cfg(feature = "git")]
+pub mod git_store;
+#[cfg(feature = "github-contents")]
+pub mod github_graphql_store;
+#[cfg(feature = "github-contents")]
 pub mod github_store;
+#[cfg(feature = "gitlab")]
+pub mod gitlab_store;
+pub mod http_client;
+pub mod importer;
+pub mod maintenance;
+#[cfg(any(test, feature = "test-util"))]
+pub mod memory_store;
+pub mod ocr;
+pub mod prices;
+pub mod rates;
+pub mod recent_updates;
+#[cfg(feature = "s3")]
+pub mod s3_store;
+pub mod scheduler;
+pub mod settings_cache;
+#[cfg(any(feature = "aws", feature = "s3"))]
+mod sigv4;
+pub mod splitwise;
+pub mod store_factory;
 
-pub trait Store {
-    fn save(&self, transaction: Transaction) -> Result<String>;
+pub use store_factory::StoreFactory;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn save(&self, transaction: Transaction) -> Result<String, StoreError> {
+        self.save_checked(transaction, 0).await
+    }
+
+    /// Saves `transaction` unless an entry among the last `duplicate_lines`
+    /// lines of its file looks the same, see [`find_copy`], in which case it
+    /// fails with [`StoreError::LikelyDuplicate`]. The file is read once
+    /// either way; `0` doesn't look.
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError>;
+
+    /// Returns the content of `path`, or `None` if the file doesn't exist.
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError>;
+
+    /// Creates or replaces `path` with `bytes`.
+    async fn write_bytes(&self, path: &str, bytes: &[u8], message: &str) -> Result<(), StoreError>;
+
+    async fn delete(&self, path: &str, message: &str) -> Result<(), StoreError>;
+
+    /// Creates or replaces `path` with `content`.
+    async fn write(&self, path: &str, content: &str, message: &str) -> Result<(), StoreError> {
+        self.write_bytes(path, content.as_bytes(), message).await
+    }
+
+    /// Removes the last transaction of the ledger file at `path` and returns
+    /// its text, `None` when the file has no transactions.
+    async fn delete_last(&self, path: &str) -> Result<Option<String>, StoreError> {
+        let content = match self.read(path).await? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        match split_last_entry(&content) {
+            Some((rest, entry)) => {
+                let header = entry.lines().next().unwrap_or_default().to_string();
+                self.write(path, &rest, &format!("removed {}", header))
+                    .await?;
+                Ok(Some(entry))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads `files` and, depth first, the files they include from `store`, see
+/// [`beancount_core::ledger::read_files`].
+pub async fn read_files(
+    store: &dyn Store,
+    files: &[String],
+) -> Result<Vec<(String, String)>, StoreError> {
+    read_files_from(store, files, HashMap::new()).await
+}
+
+/// Like [`read_files`], taking the files in `fetched` from there instead of
+/// reading them again.
+pub async fn read_files_from(
+    store: &dyn Store,
+    files: &[String],
+    mut fetched: HashMap<String, Option<String>>,
+) -> Result<Vec<(String, String)>, StoreError> {
+    let mut pending: Vec<String> = files.iter().rev().cloned().collect();
+    let mut seen = HashSet::new();
+    let mut contents = Vec::new();
+    while let Some(path) = pending.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        let content = match fetched.remove(&path) {
+            Some(content) => content,
+            None => store.read(&path).await?,
+        };
+        let content = match content {
+            Some(content) => content,
+            None => {
+                warn!("ledger file {} doesn't exist, skipping", path);
+                continue;
+            }
+        };
+        for include in includes(&content).into_iter().rev() {
+            if include.contains('*') {
+                warn!("skipping glob include {} in {}", include, path);
+                continue;
+            }
+            pending.push(resolve_include(&path, &include));
+        }
+        contents.push((path, content));
+    }
+    Ok(contents)
+}
+
+/// The ledger of `files` and the files they include, see [`read_files`].
+pub async fn load_ledger(store: &dyn Store, files: &[String]) -> Result<Ledger, StoreError> {
+    Ok(Ledger::from_files(&read_files(store, files).await?))
+}
+
+/// Writes `lines` below `content`, the current content of `file`, e.g. the
+/// directives of a command.
+pub async fn append_lines(
+    store: &dyn Store,
+    file: &str,
+    mut content: String,
+    lines: &[String],
+    message: &str,
+) -> Result<(), StoreError> {
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    store.write(file, &content, message).await
+}
+
+/// Renders the configured header for a new ledger file of `year`.
+pub(crate) fn render_file_header(template: Option<&str>, year: &str) -> String {
+    template
+        .map(|template| template.replace("{year}", year))
+        .unwrap_or_default()
+}
+
+/// Fails when the transaction `entry` looks like a copy of one in the last
+/// `lines` lines of `content`, the file at `path`.
+pub(crate) fn refuse_copy(
+    path: &str,
+    content: &str,
+    entry: &str,
+    lines: usize,
+) -> Result<(), StoreError> {
+    match find_copy(path, content, entry, lines) {
+        Some(copy) => Err(StoreError::LikelyDuplicate {
+            path: copy.file,
+            line: copy.line,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Adds `entry` to `content`, replacing the entry holding the `marker` line
+/// if there is one, e.g. the transaction of a Telegram message that was
+/// since edited.
+pub(crate) fn upsert_entry(content: &str, entry: &str, marker: Option<&str>) -> String {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let found = marker.and_then(|marker| {
+        lines
+            .iter()
+            .position(|line| line.trim_end_matches('\n') == marker)
+    });
+    let found = match found {
+        Some(found) => found,
+        None => return format!("{}\n{}", content, entry),
+    };
+    let is_posting = |line: &&str| line.starts_with(' ') || line.starts_with('\t');
+    let start = lines[..found]
+        .iter()
+        .rposition(|line| !is_posting(line))
+        .unwrap_or(0);
+    let end = found
+        + lines[found..]
+            .iter()
+            .position(|line| !is_posting(line))
+            .unwrap_or(lines.len() - found);
+    format!(
+        "{}{}{}",
+        lines[..start].concat(),
+        entry,
+        lines[end..].concat()
+    )
+}
+
+/// Splits the last transaction off `content`, with the blank line saving put
+/// before it, returning the rest of the content and the transaction.
+pub(crate) fn split_last_entry(content: &str) -> Option<(String, String)> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let start = lines.iter().rposition(|line| {
+        let mut words = line.split_whitespace();
+        let is_dated = words
+            .next()
+            .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok());
+        is_dated && matches!(words.next(), Some("*" | "!" | "txn"))
+    })?;
+    let end = start
+        + 1
+        + lines[start + 1..]
+            .iter()
+            .position(|line| !(line.starts_with(' ') || line.starts_with('\t')))
+            .unwrap_or(lines.len() - start - 1);
+    let before = match lines[..start].last() {
+        Some(line) if line.trim().is_empty() => start - 1,
+        _ => start,
+    };
+    Some((
+        format!("{}{}", lines[..before].concat(), lines[end..].concat()),
+        lines[start..end].concat(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_off_the_last_transaction() {
+        let content = "option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\" \"\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n\n2021-09-09 * \"Coles\" \"\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n";
+        let (rest, entry) = split_last_entry(content).unwrap();
+        assert_eq!(
+            rest,
+            "option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\" \"\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+        );
+        assert_eq!(
+            entry,
+            "2021-09-09 * \"Coles\" \"\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n"
+        );
+        assert_eq!(split_last_entry("option \"title\" \"2021\"\n"), None);
+    }
+
+    #[test]
+    fn it_replaces_the_entry_holding_the_marker() {
+        let marker = "  telegram_message: \"1/7\"";
+        let content = "option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\" \"\"\n  telegram_message: \"1/7\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n\n2021-09-09 * \"Coles\" \"\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n";
+        let entry = "2021-09-08 * \"KFC\" \"\"\n  telegram_message: \"1/7\"\n  Assets:CBA        -21.40 AUD\n  Expenses:Food        21.40 AUD\n";
+        assert_eq!(
+            upsert_entry(content, entry, Some(marker)),
+            content.replace("12.40", "21.40")
+        );
+        assert_eq!(
+            upsert_entry("", entry, Some("  telegram_message: \"1/8\"")),
+            format!("\n{}", entry)
+        );
+        assert_eq!(upsert_entry("", entry, None), format!("\n{}", entry));
+    }
+
+    #[test]
+    fn it_renders_file_header_with_year() {
+        let header = render_file_header(
+            Some("option \"title\" \"{year}\"\npushtag #y{year}\n"),
+            "2022",
+        );
+        assert_eq!(header, "option \"title\" \"2022\"\npushtag #y2022\n");
+        assert_eq!(render_file_header(None, "2022"), "");
+    }
 }
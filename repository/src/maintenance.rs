@@ -0,0 +1,81 @@
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::archive::{close_year, sort_year};
+use beancount_core::settings::Settings;
+use log::info;
+
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// Closes out a finished year: the year file is sorted, formatted and given closing
+/// balance assertions, then either rewritten in place or moved under `archive/`.
+/// A year split by month or account is closed file by file, with the balance
+/// assertions in the last one.
+///
+/// Returns the paths the closed ledger was written to.
+pub async fn archive_year(
+    store: &dyn Store,
+    settings: &Settings,
+    year: i32,
+    move_to_archive: bool,
+) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in settings.year_paths(&year.to_string()) {
+        if let Some(content) = store.read(&path).await? {
+            files.push((path, content));
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("no ledger file of {} exists", year));
+    }
+
+    let mut history = Vec::new();
+    let mut previous = year - 1;
+    loop {
+        let earlier = read_year(store, settings, previous).await?;
+        if earlier.is_empty() {
+            break;
+        }
+        history.splice(0..0, earlier);
+        previous -= 1;
+    }
+    info!(
+        "closing year {} with {} earlier ledger files",
+        year,
+        history.len()
+    );
+
+    let message = format!("closed year {}", year);
+    let last = files.len() - 1;
+    let mut written = Vec::new();
+    for (index, (path, content)) in files.iter().enumerate() {
+        let closed = if index == last {
+            history.extend(files[..last].iter().map(|(_, content)| content.clone()));
+            close_year(content, &history, year)
+        } else {
+            sort_year(content)
+        };
+        if move_to_archive {
+            let archive_path = format!("{}/{}", ARCHIVE_DIR, path);
+            store.write(&archive_path, &closed, &message).await?;
+            store.delete(path, &message).await?;
+            written.push(archive_path);
+        } else {
+            store.write(path, &closed, &message).await?;
+            written.push(path.clone());
+        }
+    }
+    Ok(written)
+}
+
+/// The content of every file of `year` found, in place or archived.
+async fn read_year(store: &dyn Store, settings: &Settings, year: i32) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    for path in settings.year_paths(&year.to_string()) {
+        let content = match store.read(&path).await? {
+            Some(content) => Some(content),
+            None => store.read(&format!("{}/{}", ARCHIVE_DIR, path)).await?,
+        };
+        found.extend(content);
+    }
+    Ok(found)
+}
@@ -0,0 +1,223 @@
+use crate::error::StoreError;
+use crate::Store;
+use async_trait::async_trait;
+use beancount_core::parser::Transaction;
+use http::StatusCode;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Failures a [`MemoryStore`] can be told to return, mirroring the GitHub
+/// contents API responses the real stores have to deal with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimulatedFailure {
+    NotFound,
+    Conflict,
+    ServerError,
+}
+
+impl fmt::Display for SimulatedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulatedFailure::NotFound => write!(f, "404 Not Found"),
+            SimulatedFailure::Conflict => write!(f, "409 Conflict"),
+            SimulatedFailure::ServerError => write!(f, "500 Internal Server Error"),
+        }
+    }
+}
+
+/// A `Store` that keeps files in memory and records every saved transaction, for
+/// tests that shouldn't stub HTTP.
+#[derive(Default)]
+pub struct MemoryStore {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+    saved: Mutex<Vec<String>>,
+    failures: Mutex<VecDeque<SimulatedFailure>>,
+    ledger_path: Option<String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    pub fn with_file(self, path: &str, content: &str) -> Self {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.into(), content.as_bytes().to_vec());
+        self
+    }
+
+    /// Makes the next store operation fail; calls queue up in order.
+    pub fn fail_next(&self, failure: SimulatedFailure) {
+        self.failures.lock().unwrap().push_back(failure);
+    }
+
+    /// Transaction texts returned by successful `save` calls, oldest first.
+    pub fn saved(&self) -> Vec<String> {
+        self.saved.lock().unwrap().clone()
+    }
+
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Maps a queued failure to the error a real store classifies it as.
+    fn check_failure(&self) -> Result<(), StoreError> {
+        let message = "simulated failure".to_string();
+        match self.failures.lock().unwrap().pop_front() {
+            Some(SimulatedFailure::NotFound) => Err(StoreError::Api {
+                message,
+                status: StatusCode::NOT_FOUND,
+            }),
+            Some(SimulatedFailure::Conflict) => Err(StoreError::Conflict(message)),
+            Some(SimulatedFailure::ServerError) => Err(StoreError::Api {
+                message,
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        self.check_failure()?;
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let transaction_text = String::from(transaction);
+        let content = self.file(&path).unwrap_or_default();
+        crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+        self.files.lock().unwrap().insert(
+            path,
+            crate::upsert_entry(&content, &transaction_text, marker.as_deref()).into_bytes(),
+        );
+        self.saved.lock().unwrap().push(transaction_text.clone());
+        Ok(transaction_text)
+    }
+
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        self.check_failure()?;
+        Ok(self.file(path))
+    }
+
+    async fn write_bytes(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        _message: &str,
+    ) -> Result<(), StoreError> {
+        self.check_failure()?;
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.into(), bytes.to_vec());
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str, _message: &str) -> Result<(), StoreError> {
+        self.check_failure()?;
+        match self.files.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound(path.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::maintenance::archive_year;
+    use beancount_core::settings::Settings;
+
+    fn settings(ledger_path: Option<&str>) -> Settings {
+        let builder = Settings::builder("AUD").account("cash", "Assets:Cash");
+        match ledger_path {
+            Some(template) => builder.ledger_path(template),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_returns_simulated_failures_in_order() {
+        let store = MemoryStore::new().with_file("2021.bean", "");
+        store.fail_next(SimulatedFailure::Conflict);
+        store.fail_next(SimulatedFailure::ServerError);
+
+        let error = store.read("2021.bean").await.unwrap_err();
+        assert!(matches!(error, StoreError::Conflict(_)));
+        assert!(error.is_retryable());
+        let error = store.read("2021.bean").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "simulated failure (500 Internal Server Error)"
+        );
+        assert_eq!(store.read("2021.bean").await.unwrap(), Some("".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_moves_archived_year_under_archive_dir() {
+        let store = MemoryStore::new().with_file(
+            "2021.bean",
+            "2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:Cash  -12.40 AUD\n  Expenses:Food\n",
+        );
+        let path = archive_year(&store, &settings(None), 2021, true)
+            .await
+            .unwrap();
+        assert_eq!(path, vec!["archive/2021.bean"]);
+        assert_eq!(store.paths(), vec!["archive/2021.bean"]);
+        assert!(store
+            .file("archive/2021.bean")
+            .unwrap()
+            .ends_with("2022-01-01 balance Assets:Cash -12.40 AUD\n"));
+    }
+
+    #[tokio::test]
+    async fn it_closes_every_file_of_a_year_split_by_month() {
+        let store = MemoryStore::new()
+            .with_file(
+                "ledger/2020/12.bean",
+                "2020-12-01 * \"Salary\"\n  Assets:Cash  100.00 AUD\n  Income:Salary\n",
+            )
+            .with_file(
+                "ledger/2021/03.bean",
+                "2021-03-08 * \"KFC\" \"hamburger\"\n  Assets:Cash  -12.40 AUD\n  Expenses:Food\n",
+            )
+            .with_file(
+                "ledger/2021/09.bean",
+                "2021-09-08 * \"KFC\" \"hamburger\"\n  Assets:Cash  -12.40 AUD\n  Expenses:Food\n",
+            );
+        let settings = settings(Some("ledger/{year}/{month}.bean"));
+        let paths = archive_year(&store, &settings, 2021, false).await.unwrap();
+        assert_eq!(paths, vec!["ledger/2021/03.bean", "ledger/2021/09.bean"]);
+        assert!(!store
+            .file("ledger/2021/03.bean")
+            .unwrap()
+            .contains("balance"));
+        assert!(store
+            .file("ledger/2021/09.bean")
+            .unwrap()
+            .ends_with("2022-01-01 balance Assets:Cash 75.20 AUD\n"));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::http_client::HttpClient;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::parser::Transaction;
+use beancount_core::secret::Secret;
+#[cfg(feature = "native-http")]
+use beancount_core::settings::OcrSettings;
+use http::{header, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Receipts waiting for a chat to confirm them, one file per photo, in the
+/// default ledger repository.
+pub const PENDING_DIR: &str = ".beancount-bot/receipts";
+
+/// An OCR API that takes an image as the request body and answers with the
+/// text it read as `{ "text": "..." }`.
+pub struct OcrClient {
+    http: Arc<dyn HttpClient>,
+    url: String,
+    api_key: Option<Secret<String>>,
+}
+
+#[derive(Deserialize)]
+struct Recognized {
+    text: String,
+}
+
+impl OcrClient {
+    pub fn new(http: Arc<dyn HttpClient>, url: &str, api_key: Option<Secret<String>>) -> Self {
+        OcrClient {
+            http,
+            url: url.into(),
+            api_key,
+        }
+    }
+
+    /// The API configured in `[ocr]`, reached with reqwest.
+    #[cfg(feature = "native-http")]
+    pub fn from_settings(settings: &OcrSettings) -> Result<Self> {
+        Ok(OcrClient::new(
+            Arc::new(crate::http_client::ReqwestClient::new()?),
+            &settings.url,
+            settings.api_key.clone(),
+        ))
+    }
+
+    /// The text of `image`, a JPEG as Telegram sends photos.
+    pub async fn recognize(&self, image: Vec<u8>) -> Result<String> {
+        let mut request =
+            Request::post(self.url.as_str()).header(header::CONTENT_TYPE, "image/jpeg");
+        if let Some(api_key) = &self.api_key {
+            request = request.header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", api_key.expose()),
+            );
+        }
+        let response = self.http.send(request.body(image)?).await?;
+        match response.status() {
+            StatusCode::OK => {
+                let recognized: Recognized = serde_json::from_slice(response.body())?;
+                Ok(recognized.text)
+            }
+            status => Err(anyhow!(
+                "the OCR API answered {}: {}",
+                status,
+                String::from_utf8_lossy(response.body())
+            )),
+        }
+    }
+}
+
+/// A transaction booked from a receipt photo, waiting for its chat to confirm
+/// it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pending {
+    /// `<chat_id>-<message_id>` of the photo.
+    pub id: String,
+    pub chat_id: u64,
+    pub transaction: Transaction,
+}
+
+/// The id of the receipt sent as `message_id` in `chat_id`.
+pub fn receipt_id(chat_id: u64, message_id: u64) -> String {
+    format!("{}-{}", chat_id, message_id)
+}
+
+fn path(id: &str) -> Result<String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return Err(anyhow!("{} isn't a receipt id", id));
+    }
+    Ok(format!("{}/{}.json", PENDING_DIR, id))
+}
+
+pub async fn save_pending(store: &dyn Store, pending: &Pending) -> Result<()> {
+    store
+        .write(
+            &path(&pending.id)?,
+            &serde_json::to_string_pretty(pending)?,
+            &format!("receipt {} awaits confirmation", pending.id),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn load_pending(store: &dyn Store, id: &str) -> Result<Option<Pending>> {
+    match store.read(&path(id)?).await? {
+        Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn remove_pending(store: &dyn Store, id: &str) -> Result<()> {
+    store
+        .delete(&path(id)?, &format!("receipt {} answered", id))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StoreError;
+    use crate::memory_store::MemoryStore;
+    use async_trait::async_trait;
+    use beancount_core::parser::BeancountParser;
+    use beancount_core::settings::Settings;
+    use http::Response;
+    use std::sync::Mutex;
+
+    /// Answers every request with `status` and `body`, remembering the
+    /// requests.
+    struct Canned {
+        status: StatusCode,
+        body: &'static str,
+        requests: Mutex<Vec<Request<Vec<u8>>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for Canned {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+            self.requests.lock().unwrap().push(request);
+            let mut response = Response::new(self.body.as_bytes().to_vec());
+            *response.status_mut() = self.status;
+            Ok(response)
+        }
+    }
+
+    fn client(status: StatusCode, body: &'static str) -> (Arc<Canned>, OcrClient) {
+        let http = Arc::new(Canned {
+            status,
+            body,
+            requests: Mutex::new(Vec::new()),
+        });
+        let client = OcrClient::new(
+            http.clone(),
+            "https://ocr.example.com/v1/text",
+            Some(Secret::new("key".to_string())),
+        );
+        (http, client)
+    }
+
+    #[tokio::test]
+    async fn it_posts_images_and_reads_the_text() {
+        let (http, ocr) = client(StatusCode::OK, r#"{"text": "COLES\nTOTAL 7.00"}"#);
+        assert_eq!(
+            ocr.recognize(vec![0xff, 0xd8]).await.unwrap(),
+            "COLES\nTOTAL 7.00"
+        );
+        {
+            let requests = http.requests.lock().unwrap();
+            assert_eq!(requests[0].method(), "POST");
+            assert_eq!(requests[0].headers()[header::AUTHORIZATION], "Bearer key");
+            assert_eq!(requests[0].body(), &vec![0xff, 0xd8]);
+        }
+
+        let (_, ocr) = client(StatusCode::PAYMENT_REQUIRED, "quota exceeded");
+        assert_eq!(
+            ocr.recognize(Vec::new()).await.unwrap_err().to_string(),
+            "the OCR API answered 402 Payment Required: quota exceeded"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_keeps_pending_receipts_until_answered() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("food", "Expenses:Food")
+            .build()
+            .unwrap();
+        let transaction = BeancountParser::new(settings)
+            .parse("2021-09-08 @Coles 7 cba > food")
+            .unwrap();
+        let store = MemoryStore::new();
+        let pending = Pending {
+            id: receipt_id(247673932, 290),
+            chat_id: 247673932,
+            transaction,
+        };
+        save_pending(&store, &pending).await.unwrap();
+        assert!(store
+            .read(".beancount-bot/receipts/247673932-290.json")
+            .await
+            .unwrap()
+            .is_some());
+        let loaded = load_pending(&store, "247673932-290")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.transaction.payee(), "Coles");
+
+        remove_pending(&store, "247673932-290").await.unwrap();
+        assert!(load_pending(&store, "247673932-290")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(load_pending(&store, "../secrets").await.is_err());
+    }
+}
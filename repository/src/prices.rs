@@ -0,0 +1,195 @@
+use crate::rates::{Rate, RateSource};
+use crate::{append_lines, Store};
+use anyhow::Result;
+use beancount_core::ledger::{Directive, Ledger};
+use beancount_core::settings::PriceSettings;
+use chrono::NaiveDate;
+use log::{info, warn};
+
+/// Fetches the price of every `[prices]` commodity on `date` in `currency` and
+/// appends them to the prices file as `price` directives, like bean-price.
+///
+/// Directives are dated the day the rate was published, and a commodity that
+/// already has a price of that day is skipped, so running it again, or on a
+/// weekend, doesn't duplicate entries. A commodity the source has no rate for
+/// is logged and skipped. Returns the added directives.
+pub async fn commit_prices(
+    store: &dyn Store,
+    source: &dyn RateSource,
+    prices: &PriceSettings,
+    currency: &str,
+    date: NaiveDate,
+) -> Result<Vec<String>> {
+    let content = store.read(&prices.file).await?.unwrap_or_default();
+    let ledger = Ledger::parse(&prices.file, &content);
+    let has_price = |date, commodity: &str| has_price(&ledger, date, commodity, currency);
+
+    let mut added: Vec<String> = Vec::new();
+    for commodity in prices.commodities.iter().filter(|c| *c != currency) {
+        let rate = match source.fetch(commodity, currency, date).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("No price of {} in {}: {}", commodity, currency, e);
+                continue;
+            }
+        };
+        if has_price(rate.date, commodity) {
+            info!("{} already has a price on {}", commodity, rate.date);
+            continue;
+        }
+        added.push(price_directive(commodity, &rate, currency));
+    }
+    if added.is_empty() {
+        return Ok(added);
+    }
+
+    append_lines(
+        store,
+        &prices.file,
+        content,
+        &added,
+        &format!("added {} prices on {}", added.len(), date),
+    )
+    .await?;
+    Ok(added)
+}
+
+/// Appends the `rate` a transaction was converted at, of `commodity` in
+/// `currency`, to the prices `file` as a `price` directive, unless the file
+/// has a price of that day already. Returns the directive when added.
+pub async fn record_price(
+    store: &dyn Store,
+    file: &str,
+    commodity: &str,
+    currency: &str,
+    rate: &Rate,
+) -> Result<Option<String>> {
+    let content = store.read(file).await?.unwrap_or_default();
+    if has_price(
+        &Ledger::parse(file, &content),
+        rate.date,
+        commodity,
+        currency,
+    ) {
+        return Ok(None);
+    }
+    let directive = price_directive(commodity, rate, currency);
+    append_lines(
+        store,
+        file,
+        content,
+        std::slice::from_ref(&directive),
+        &format!("added price of {} on {}", commodity, rate.date),
+    )
+    .await?;
+    Ok(Some(directive))
+}
+
+fn has_price(ledger: &Ledger, date: NaiveDate, commodity: &str, currency: &str) -> bool {
+    ledger.entries.iter().any(|entry| {
+        entry.date == date
+            && matches!(&entry.directive, Directive::Price { commodity: c, amount }
+                if c == commodity && amount.currency == currency)
+    })
+}
+
+fn price_directive(commodity: &str, rate: &Rate, currency: &str) -> String {
+    format!(
+        "{} price {} {} {}",
+        rate.date,
+        commodity,
+        format_price(rate.rate),
+        currency
+    )
+}
+
+/// Six decimals at most, without trailing zeros.
+fn format_price(price: f64) -> String {
+    let text = format!("{:.6}", price);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+    use crate::rates::Rate;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    /// USD and EUR rates published the Friday before the date asked for.
+    struct Friday;
+
+    #[async_trait]
+    impl RateSource for Friday {
+        fn name(&self) -> &'static str {
+            "friday"
+        }
+
+        async fn fetch(&self, from: &str, _to: &str, _date: NaiveDate) -> Result<Rate> {
+            let rate = match from {
+                "USD" => 1.3579,
+                "EUR" => 1.612345678,
+                _ => return Err(anyhow!("no rate")),
+            };
+            Ok(Rate {
+                rate,
+                date: NaiveDate::from_ymd_opt(2021, 9, 3).unwrap(),
+                source: self.name(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn it_appends_prices_once_per_day() {
+        let store = MemoryStore::new().with_file(
+            "prices.bean",
+            "2021-09-02 price USD 1.3601 AUD\n2021-09-03 price USD 1.3579 AUD",
+        );
+        let prices = PriceSettings {
+            commodities: vec!["USD".into(), "EUR".into(), "BTC".into(), "AUD".into()],
+            file: "prices.bean".into(),
+            currency: None,
+        };
+        let sunday = NaiveDate::from_ymd_opt(2021, 9, 5).unwrap();
+
+        let added = commit_prices(&store, &Friday, &prices, "AUD", sunday)
+            .await
+            .unwrap();
+        assert_eq!(added, vec!["2021-09-03 price EUR 1.612346 AUD"]);
+        assert_eq!(
+            store.file("prices.bean").unwrap(),
+            "2021-09-02 price USD 1.3601 AUD\n2021-09-03 price USD 1.3579 AUD\n2021-09-03 price EUR 1.612346 AUD\n"
+        );
+
+        assert!(commit_prices(&store, &Friday, &prices, "AUD", sunday)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_records_the_rate_of_a_conversion_once_per_day() {
+        let store = MemoryStore::new();
+        let rate = Friday
+            .fetch("USD", "AUD", NaiveDate::from_ymd_opt(2021, 9, 5).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            record_price(&store, "prices.bean", "USD", "AUD", &rate)
+                .await
+                .unwrap(),
+            Some("2021-09-03 price USD 1.3579 AUD".into())
+        );
+        assert_eq!(
+            record_price(&store, "prices.bean", "USD", "AUD", &rate)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            store.file("prices.bean").unwrap(),
+            "2021-09-03 price USD 1.3579 AUD\n"
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use crate::http_client::HttpClient;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use beancount_core::secret::Secret;
+use chrono::NaiveDate;
+use http::{Request, StatusCode};
+use log::info;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Rates kept before the cache starts over; a rate never changes once
+/// published, so entries don't expire otherwise.
+const MAX_CACHED_RATES: usize = 1000;
+
+/// How many units of one currency a unit of another was worth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub rate: f64,
+    /// The day the rate was published for, which can be before the one asked
+    /// for on weekends and holidays.
+    pub date: NaiveDate,
+    pub source: &'static str,
+}
+
+/// Somewhere exchange rates are published.
+#[async_trait]
+pub trait RateSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Units of `to` per unit of `from` on `date`.
+    async fn fetch(&self, from: &str, to: &str, date: NaiveDate) -> Result<Rate>;
+}
+
+/// The source configured in `[rates]`, reached with reqwest.
+/// `RATES_API_URL` replaces the provider's URL, e.g. for a mirror.
+#[cfg(feature = "native-http")]
+pub fn from_settings(
+    settings: &beancount_core::settings::RateSettings,
+) -> Result<Box<dyn RateSource>> {
+    use beancount_core::settings::RateProvider;
+
+    let http: Arc<dyn HttpClient> = Arc::new(crate::http_client::ReqwestClient::new()?);
+    let url = std::env::var("RATES_API_URL").ok();
+    Ok(match settings.provider {
+        RateProvider::Ecb => Box::new(Ecb::new(http, url.as_deref().unwrap_or(Ecb::API_URL))),
+        RateProvider::ExchangerateHost => {
+            let access_key = settings
+                .access_key
+                .clone()
+                .ok_or_else(|| anyhow!("exchangerate_host needs an access_key"))?;
+            Box::new(ExchangerateHost::new(
+                http,
+                url.as_deref().unwrap_or(ExchangerateHost::API_URL),
+                access_key,
+            ))
+        }
+        RateProvider::Rba => Box::new(Rba::new(http, url.as_deref().unwrap_or(Rba::FEED_URL))),
+    })
+}
+
+/// From and to currencies, and the day asked for.
+type RateKey = (String, String, NaiveDate);
+
+/// Remembers fetched rates by currency pair and day.
+pub struct RateCache {
+    rates: Mutex<Option<HashMap<RateKey, Rate>>>,
+}
+
+impl RateCache {
+    pub const fn new() -> Self {
+        RateCache {
+            rates: Mutex::new(None),
+        }
+    }
+
+    pub async fn get(
+        &self,
+        source: &dyn RateSource,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+    ) -> Result<Rate> {
+        let key = (from.to_string(), to.to_string(), date);
+        if let Some(rate) = self
+            .rates
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|rates| rates.get(&key))
+        {
+            return Ok(rate.clone());
+        }
+
+        let rate = source.fetch(from, to, date).await?;
+        info!(
+            "fetched {} {}/{} on {} from {}",
+            rate.rate, to, from, rate.date, rate.source
+        );
+        let mut rates = self.rates.lock().unwrap();
+        let rates = rates.get_or_insert_with(HashMap::new);
+        if rates.len() >= MAX_CACHED_RATES {
+            rates.clear();
+        }
+        rates.insert(key, rate.clone());
+        Ok(rate)
+    }
+}
+
+impl Default for RateCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn get(http: &dyn HttpClient, url: &str) -> Result<String> {
+    let request = Request::get(url).body(Vec::new())?;
+    let response = http.send(request).await?;
+    let body = String::from_utf8_lossy(response.body()).into_owned();
+    match response.status() {
+        StatusCode::OK => Ok(body),
+        status => Err(anyhow!("{} answered {}: {}", url, status, body)),
+    }
+}
+
+/// Euro foreign exchange reference rates of the European Central Bank, through
+/// the Frankfurter API. Rates of other pairs are crossed through the euro.
+pub struct Ecb {
+    http: Arc<dyn HttpClient>,
+    api_url: String,
+}
+
+#[derive(Deserialize)]
+struct EcbResponse {
+    date: NaiveDate,
+    rates: HashMap<String, f64>,
+}
+
+impl Ecb {
+    pub const API_URL: &'static str = "https://api.frankfurter.app";
+
+    pub fn new(http: Arc<dyn HttpClient>, api_url: &str) -> Self {
+        Ecb {
+            http,
+            api_url: api_url.trim_end_matches('/').into(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateSource for Ecb {
+    fn name(&self) -> &'static str {
+        "ecb"
+    }
+
+    async fn fetch(&self, from: &str, to: &str, date: NaiveDate) -> Result<Rate> {
+        let url = format!("{}/{}?from={}&to={}", self.api_url, date, from, to);
+        let response: EcbResponse = serde_json::from_str(&get(self.http.as_ref(), &url).await?)?;
+        let rate = response
+            .rates
+            .get(to)
+            .copied()
+            .ok_or_else(|| anyhow!("the ECB has no {}/{} rate", to, from))?;
+        Ok(Rate {
+            rate,
+            date: response.date,
+            source: self.name(),
+        })
+    }
+}
+
+/// exchangerate.host, which needs an access key.
+pub struct ExchangerateHost {
+    http: Arc<dyn HttpClient>,
+    api_url: String,
+    access_key: Secret<String>,
+}
+
+#[derive(Deserialize)]
+struct ExchangerateHostResponse {
+    #[serde(default)]
+    success: bool,
+    date: Option<NaiveDate>,
+    result: Option<f64>,
+    error: Option<serde_json::Value>,
+}
+
+impl ExchangerateHost {
+    pub const API_URL: &'static str = "https://api.exchangerate.host";
+
+    pub fn new(http: Arc<dyn HttpClient>, api_url: &str, access_key: Secret<String>) -> Self {
+        ExchangerateHost {
+            http,
+            api_url: api_url.trim_end_matches('/').into(),
+            access_key,
+        }
+    }
+}
+
+#[async_trait]
+impl RateSource for ExchangerateHost {
+    fn name(&self) -> &'static str {
+        "exchangerate_host"
+    }
+
+    async fn fetch(&self, from: &str, to: &str, date: NaiveDate) -> Result<Rate> {
+        let url = format!(
+            "{}/convert?from={}&to={}&amount=1&date={}&access_key={}",
+            self.api_url,
+            from,
+            to,
+            date,
+            self.access_key.expose()
+        );
+        let body = get(self.http.as_ref(), &url).await.map_err(|e| {
+            anyhow!(
+                "{}",
+                e.to_string()
+                    .replace(self.access_key.expose(), "[REDACTED]")
+            )
+        })?;
+        let response: ExchangerateHostResponse = serde_json::from_str(&body)?;
+        match (response.success, response.result) {
+            (true, Some(rate)) => Ok(Rate {
+                rate,
+                date: response.date.unwrap_or(date),
+                source: self.name(),
+            }),
+            _ => Err(anyhow!(
+                "exchangerate.host has no {}/{} rate: {}",
+                to,
+                from,
+                response.error.unwrap_or_default()
+            )),
+        }
+    }
+}
+
+/// The Reserve Bank of Australia's feed of the latest AUD rates. It only has
+/// the last published day, whatever the date asked for; other pairs are crossed
+/// through AUD.
+pub struct Rba {
+    http: Arc<dyn HttpClient>,
+    feed_url: String,
+}
+
+impl Rba {
+    pub const FEED_URL: &'static str = "https://www.rba.gov.au/rss/rss-cb-exchange-rates.xml";
+
+    pub fn new(http: Arc<dyn HttpClient>, feed_url: &str) -> Self {
+        Rba {
+            http,
+            feed_url: feed_url.into(),
+        }
+    }
+
+    /// Units of each currency per AUD, and the day they were published.
+    async fn aud_rates(&self) -> Result<(HashMap<String, f64>, NaiveDate)> {
+        let feed = get(self.http.as_ref(), &self.feed_url).await?;
+        let item_re = Regex::new(r"(?s)<item\b.*?</item>")?;
+        let field_re = Regex::new(
+            r"(?s)<cb:value[^>]*>([0-9.]+)</cb:value>.*?<cb:baseCurrency>AUD</cb:baseCurrency>.*?<cb:targetCurrency>([A-Z]{3})</cb:targetCurrency>.*?<cb:period>(\d{4}-\d{2}-\d{2})</cb:period>",
+        )?;
+        let mut rates = HashMap::new();
+        let mut published = None;
+        for item in item_re.find_iter(&feed) {
+            if let Some(captures) = field_re.captures(item.as_str()) {
+                rates.insert(captures[2].to_string(), captures[1].parse()?);
+                published = published.max(NaiveDate::parse_from_str(&captures[3], "%Y-%m-%d").ok());
+            }
+        }
+        let published = published.ok_or_else(|| anyhow!("the RBA feed has no AUD rates"))?;
+        rates.insert("AUD".into(), 1.0);
+        Ok((rates, published))
+    }
+}
+
+#[async_trait]
+impl RateSource for Rba {
+    fn name(&self) -> &'static str {
+        "rba"
+    }
+
+    async fn fetch(&self, from: &str, to: &str, _date: NaiveDate) -> Result<Rate> {
+        let (rates, published) = self.aud_rates().await?;
+        let per_aud = |currency: &str| {
+            rates
+                .get(currency)
+                .copied()
+                .ok_or_else(|| anyhow!("the RBA publishes no AUD/{} rate", currency))
+        };
+        Ok(Rate {
+            rate: per_aud(to)? / per_aud(from)?,
+            date: published,
+            source: self.name(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StoreError;
+    use http::Response;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers with `body` and counts requests.
+    struct Canned {
+        body: &'static str,
+        requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl HttpClient for Canned {
+        async fn send(&self, _request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Ok(Response::new(self.body.as_bytes().to_vec()))
+        }
+    }
+
+    fn canned(body: &'static str) -> Arc<Canned> {
+        Arc::new(Canned {
+            body,
+            requests: AtomicUsize::new(0),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_caches_rates_by_pair_and_day() {
+        let http = canned(
+            r#"{"amount": 1.0, "base": "USD", "date": "2021-09-03", "rates": {"AUD": 1.3451}}"#,
+        );
+        let ecb = Ecb::new(http.clone(), Ecb::API_URL);
+        let cache = RateCache::new();
+        let date = NaiveDate::from_ymd_opt(2021, 9, 5).unwrap();
+
+        let rate = cache.get(&ecb, "USD", "AUD", date).await.unwrap();
+        assert_eq!(
+            rate,
+            Rate {
+                rate: 1.3451,
+                date: NaiveDate::from_ymd_opt(2021, 9, 3).unwrap(),
+                source: "ecb",
+            }
+        );
+        cache.get(&ecb, "USD", "AUD", date).await.unwrap();
+        assert_eq!(http.requests.load(Ordering::SeqCst), 1);
+        assert!(cache.get(&ecb, "USD", "JPY", date).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_crosses_rba_rates_through_aud() {
+        let feed = r#"<rdf:RDF>
+<item rdf:about="https://www.rba.gov.au/statistics/frequency/exchange-rates.html#USD">
+  <cb:statistics><cb:exchangeRate>
+    <cb:observation><cb:value decimals="4">0.7400</cb:value></cb:observation>
+    <cb:baseCurrency>AUD</cb:baseCurrency>
+    <cb:targetCurrency>USD</cb:targetCurrency>
+    <cb:observationPeriod><cb:period>2021-09-08</cb:period></cb:observationPeriod>
+  </cb:exchangeRate></cb:statistics>
+</item>
+<item rdf:about="https://www.rba.gov.au/statistics/frequency/exchange-rates.html#JPY">
+  <cb:statistics><cb:exchangeRate>
+    <cb:observation><cb:value decimals="2">81.40</cb:value></cb:observation>
+    <cb:baseCurrency>AUD</cb:baseCurrency>
+    <cb:targetCurrency>JPY</cb:targetCurrency>
+    <cb:observationPeriod><cb:period>2021-09-08</cb:period></cb:observationPeriod>
+  </cb:exchangeRate></cb:statistics>
+</item>
+</rdf:RDF>"#;
+        let rba = Rba::new(canned(feed), Rba::FEED_URL);
+        let date = NaiveDate::from_ymd_opt(2021, 9, 1).unwrap();
+        assert_eq!(rba.fetch("AUD", "USD", date).await.unwrap().rate, 0.74);
+        assert_eq!(
+            rba.fetch("USD", "AUD", date).await.unwrap().rate,
+            1.0 / 0.74
+        );
+        let cross = rba.fetch("USD", "JPY", date).await.unwrap();
+        assert_eq!(cross.rate, 81.4 / 0.74);
+        assert_eq!(cross.date, NaiveDate::from_ymd_opt(2021, 9, 8).unwrap());
+        assert!(rba.fetch("EUR", "AUD", date).await.is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::Store;
+use anyhow::Result;
+
+/// Update ids a deployment handled last, kept across restarts so updates
+/// Telegram redelivers afterwards aren't saved twice.
+pub const RECENT_UPDATES_FILE: &str = ".beancount-bot/recent-updates.json";
+
+/// The ids saved by [`save`], oldest first.
+pub async fn load(store: &dyn Store) -> Result<Vec<u64>> {
+    match store.read(RECENT_UPDATES_FILE).await? {
+        Some(content) => Ok(serde_json::from_str(&content)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub async fn save(store: &dyn Store, update_ids: &[u64]) -> Result<()> {
+    store
+        .write(
+            RECENT_UPDATES_FILE,
+            &serde_json::to_string(update_ids)?,
+            &format!("kept {} recent update ids", update_ids.len()),
+        )
+        .await?;
+    Ok(())
+}
+
+/// A file per handled update holding its response, for deployments that don't
+/// live long enough to [`save`] the ids, see [`mark`].
+pub const MARKERS_DIR: &str = ".beancount-bot/updates";
+
+fn marker_path(update_id: u64) -> String {
+    format!("{}/{}", MARKERS_DIR, update_id)
+}
+
+/// Leaves a marker that `update_id` was answered with `response`.
+pub async fn mark(store: &dyn Store, update_id: u64, response: &str) -> Result<()> {
+    store
+        .write(
+            &marker_path(update_id),
+            response,
+            &format!("handled update {}", update_id),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The response of an update [`mark`] left a marker of.
+pub async fn marked(store: &dyn Store, update_id: u64) -> Result<Option<String>> {
+    Ok(store.read(&marker_path(update_id)).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_keeps_update_ids_in_order() {
+        let store = MemoryStore::new();
+        assert!(load(&store).await.unwrap().is_empty());
+        save(&store, &[459592837, 459592838]).await.unwrap();
+        assert_eq!(load(&store).await.unwrap(), vec![459592837, 459592838]);
+    }
+
+    #[tokio::test]
+    async fn it_marks_handled_updates_with_their_response() {
+        let store = MemoryStore::new();
+        assert_eq!(marked(&store, 459592837).await.unwrap(), None);
+        mark(&store, 459592837, "{\"method\":\"sendMessage\"}")
+            .await
+            .unwrap();
+        assert_eq!(
+            marked(&store, 459592837).await.unwrap().as_deref(),
+            Some("{\"method\":\"sendMessage\"}")
+        );
+        assert_eq!(marked(&store, 459592838).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::error::StoreError;
+use crate::sigv4::{sha256_hex, sign, uri_encode, Credentials};
+use crate::Store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use beancount_core::parser::Transaction;
+use chrono::Utc;
+use log::{error, info, warn};
+use reqwest::{header, Client, Method, Response, StatusCode, Url};
+use std::env;
+use tracing::instrument;
+
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Stores the ledger files as objects of an S3 bucket, or of an S3 compatible
+/// one such as MinIO or Cloudflare R2, for ledgers kept out of a Git host.
+///
+/// Saves send the `ETag` of the object they appended to as `If-Match`, or
+/// `If-None-Match: *` when creating it; when another save got in between the
+/// bucket answers 412 and the object is read again.
+pub struct S3Store {
+    endpoint: Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: Credentials,
+    client: Client,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+}
+
+/// What the object has to look like for a put to go through.
+enum Precondition<'a> {
+    Any,
+    Absent,
+    Matches(&'a str),
+}
+
+impl S3Store {
+    pub fn new() -> Result<Self> {
+        let bucket = env::var("S3_BUCKET")?;
+        let region = env::var("S3_REGION")
+            .or_else(|_| env::var("AWS_REGION"))
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))?;
+        let endpoint = env::var("S3_ENDPOINT")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+        let prefix = env::var("S3_PREFIX").unwrap_or_default();
+        let client = reqwest::Client::builder()
+            .user_agent("beancount-automation/0.1.0")
+            .build()?;
+        Ok(S3Store {
+            endpoint: Url::parse(&endpoint)?,
+            bucket,
+            prefix: prefix.trim_matches('/').into(),
+            region,
+            credentials: Credentials::from_env()?,
+            client,
+            file_header: None,
+            ledger_path: None,
+        })
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see [`Transaction::ledger_path`]. Defaults
+    /// to `{year}.bean`.
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+        precondition: Precondition<'_>,
+    ) -> Result<Response, StoreError> {
+        let key = object_key(&self.prefix, path);
+        let canonical_path = format!(
+            "/{}/{}",
+            uri_encode(&self.bucket, false),
+            uri_encode(&key, true)
+        );
+        let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(StoreError::Other(anyhow!(
+                    "S3 endpoint {} has no host",
+                    self.endpoint
+                )))
+            }
+        };
+        let now = Utc::now();
+        let payload_hash = sha256_hex(&body);
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.expose().clone()));
+        }
+        let authorization = sign(
+            &self.credentials.signing_params(&self.region, "s3", now),
+            method.as_str(),
+            &canonical_path,
+            &headers,
+            &payload_hash,
+        );
+
+        let url = format!(
+            "{}{}",
+            self.endpoint.as_str().trim_end_matches('/'),
+            canonical_path
+        );
+        let mut request = self.client.request(method, url).body(body);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, value);
+        }
+        request = match precondition {
+            Precondition::Any => request,
+            Precondition::Absent => request.header(header::IF_NONE_MATCH, "*"),
+            Precondition::Matches(etag) => request.header(header::IF_MATCH, etag),
+        };
+        Ok(request
+            .header(header::AUTHORIZATION, authorization)
+            .send()
+            .await?)
+    }
+
+    /// The content of `path` and its `ETag`.
+    async fn get_object(&self, path: &str) -> Result<Option<(String, String)>, StoreError> {
+        let response = self
+            .send(Method::GET, path, Vec::new(), Precondition::Any)
+            .await?;
+        match response.status() {
+            StatusCode::OK => {
+                let etag = response
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                let bytes = response.bytes().await?;
+                Ok(Some((String::from_utf8_lossy(&bytes).into_owned(), etag)))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(
+                StoreError::from_response(response, format!("Failed to get object {}", path)).await,
+            ),
+        }
+    }
+
+    async fn put_object(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        precondition: Precondition<'_>,
+    ) -> Result<(), StoreError> {
+        let response = self
+            .send(Method::PUT, path, bytes.to_vec(), precondition)
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            _ => Err(
+                StoreError::from_response(response, format!("Failed to put object {}", path)).await,
+            ),
+        }
+    }
+}
+
+/// The key of `path` under `prefix`, e.g. `ledger/2021.bean`.
+fn object_key(prefix: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    if prefix.is_empty() {
+        path.into()
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+#[async_trait]
+impl Store for S3Store {
+    #[instrument(name = "s3.save", skip_all, fields(date = transaction.date()))]
+    async fn save_checked(
+        &self,
+        transaction: Transaction,
+        duplicate_lines: usize,
+    ) -> Result<String, StoreError> {
+        let year = transaction.year();
+        let path = transaction.ledger_path(self.ledger_path.as_deref());
+        let marker = transaction.message_marker();
+        let transaction_text = String::from(transaction);
+
+        for _ in 0..MAX_ATTEMPTS {
+            let (content, precondition) = match self.get_object(&path).await? {
+                Some((content, etag)) => (content, Some(etag)),
+                None => {
+                    info!("file {} not found, will create the file", path);
+                    (
+                        crate::render_file_header(self.file_header.as_deref(), &year),
+                        None,
+                    )
+                }
+            };
+            crate::refuse_copy(&path, &content, &transaction_text, duplicate_lines)?;
+            let content = crate::upsert_entry(&content, &transaction_text, marker.as_deref());
+            let precondition = match &precondition {
+                Some(etag) => Precondition::Matches(etag),
+                None => Precondition::Absent,
+            };
+            match self
+                .put_object(&path, content.as_bytes(), precondition)
+                .await
+            {
+                Ok(()) => {
+                    info!("Successfully saved transaction to {}.", path);
+                    return Ok(transaction_text);
+                }
+                Err(StoreError::Conflict(_)) => {
+                    warn!("object {} changed while saving, retrying", path);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        error!("Gave up saving to {} after {} attempts", path, MAX_ATTEMPTS);
+        Err(StoreError::Conflict(format!(
+            "Failed to save transaction to {}",
+            path
+        )))
+    }
+
+    #[instrument(name = "s3.read", skip_all, fields(path = %path))]
+    async fn read(&self, path: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.get_object(path).await?.map(|(content, _)| content))
+    }
+
+    #[instrument(name = "s3.write_bytes", skip_all, fields(path = %path))]
+    async fn write_bytes(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        _message: &str,
+    ) -> Result<(), StoreError> {
+        self.put_object(path, bytes, Precondition::Any).await
+    }
+
+    #[instrument(name = "s3.delete", skip_all, fields(path = %path))]
+    async fn delete(&self, path: &str, _message: &str) -> Result<(), StoreError> {
+        // Deleting a missing object succeeds on S3, so look first.
+        if self.get_object(path).await?.is_none() {
+            return Err(StoreError::NotFound(path.into()));
+        }
+        let response = self
+            .send(Method::DELETE, path, Vec::new(), Precondition::Any)
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::NO_CONTENT => Ok(()),
+            _ => Err(StoreError::from_response(
+                response,
+                format!("Failed to delete object {}", path),
+            )
+            .await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keys_paths_under_the_prefix() {
+        assert_eq!(object_key("", "/2021.bean"), "2021.bean");
+        assert_eq!(
+            object_key("ledger", "documents/receipt.jpg"),
+            "ledger/documents/receipt.jpg"
+        );
+    }
+}
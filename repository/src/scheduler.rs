@@ -0,0 +1,93 @@
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::archive::split_entries;
+use beancount_core::parser::BeancountParser;
+use beancount_core::schedule::Schedule;
+use beancount_core::settings::Settings;
+use chrono::NaiveDate;
+use log::info;
+
+/// Posts the `[[recurring]]` transactions whose schedule matches `date`, dated
+/// `date` and tagged with `recurring: "<name>"` metadata.
+///
+/// Running it again for the same date skips transactions already in the ledger,
+/// so a retried or repeated job doesn't double-post. Returns the saved entries.
+pub async fn post_recurring(
+    store: &dyn Store,
+    settings: &Settings,
+    date: NaiveDate,
+) -> Result<Vec<String>> {
+    let parser = BeancountParser::new(settings.clone());
+    let date_text = date.format("%Y-%m-%d").to_string();
+
+    let mut saved = Vec::new();
+    for recurring in settings.recurring.iter() {
+        let schedule: Schedule = recurring.schedule.parse()?;
+        if !schedule.matches_date(date) {
+            continue;
+        }
+        let mut transaction = parser
+            .parse(&format!("{} {}", date_text, recurring.text))
+            .map_err(|e| anyhow!("recurring transaction {}: {}", recurring.name, e))?;
+
+        let ledger = store
+            .read(&transaction.ledger_path(settings.ledger_path.as_deref()))
+            .await?
+            .unwrap_or_default();
+        let (_, entries) = split_entries(&ledger);
+        let marker = format!("recurring: \"{}\"", recurring.name);
+        if entries
+            .iter()
+            .any(|entry| entry.date == date_text && entry.text.contains(&marker))
+        {
+            info!("{} was already posted on {}", recurring.name, date_text);
+            continue;
+        }
+
+        transaction.add_metadata("recurring", &recurring.name);
+        saved.push(store.save(transaction).await?);
+        info!(
+            "posted recurring transaction {} on {}",
+            recurring.name, date_text
+        );
+    }
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+    use beancount_core::settings::RecurringSettings;
+
+    #[tokio::test]
+    async fn it_posts_due_transactions_once() {
+        let settings = Settings::builder("AUD")
+            .account("cba", "Assets:CBA")
+            .account("rent", "Expenses:Rent")
+            .account("tv", "Expenses:TV")
+            .recurring(RecurringSettings {
+                name: "rent".into(),
+                schedule: "0 9 1 * *".into(),
+                text: "@Landlord rent 2000 cba > rent".into(),
+            })
+            .recurring(RecurringSettings {
+                name: "netflix".into(),
+                schedule: "0 9 15 * *".into(),
+                text: "@Netflix subscription 15 cba > tv".into(),
+            })
+            .build()
+            .unwrap();
+        let store = MemoryStore::new();
+        let date = NaiveDate::from_ymd(2021, 9, 1);
+
+        let saved = post_recurring(&store, &settings, date).await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].starts_with("2021-09-01 * \"Landlord\" \"rent\"\n  recurring: \"rent\"\n"));
+        assert!(post_recurring(&store, &settings, date)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.saved().len(), 1);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::clock::{Clock, SystemClock};
+use beancount_core::settings::{ConfigFormat, Settings};
+use chrono::{DateTime, Utc};
+use log::info;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const DEFAULT_CONFIG_FILE: &str = "bot-config.toml";
+
+/// A config document and its format, unparsed.
+pub type Document = (String, ConfigFormat);
+
+struct CachedSettings {
+    path: String,
+    base: Option<Document>,
+    loaded_at: DateTime<Utc>,
+    settings: Settings,
+}
+
+/// Settings read from a config file committed to the ledger repository, in YAML
+/// or JSON when the file extension says so and TOML otherwise.
+///
+/// Loaded settings are kept for the given TTL so warm invocations don't fetch
+/// the file on every message, while a new commit still takes effect without a
+/// redeploy.
+pub struct SettingsCache {
+    cached: Mutex<Option<CachedSettings>>,
+    isolated: bool,
+    clock: &'static dyn Clock,
+}
+
+impl SettingsCache {
+    pub const fn new() -> Self {
+        SettingsCache {
+            cached: Mutex::new(None),
+            isolated: false,
+            clock: &SystemClock,
+        }
+    }
+
+    /// A cache for files that are parsed with [`Settings::parse_isolated`], so
+    /// they can't read the deployment's env vars.
+    pub const fn isolated() -> Self {
+        SettingsCache {
+            cached: Mutex::new(None),
+            isolated: true,
+            clock: &SystemClock,
+        }
+    }
+
+    /// Ages entries with `clock` instead of the system time.
+    pub const fn with_clock(mut self, clock: &'static dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn get(&self, store: &dyn Store, path: &str, ttl: Duration) -> Result<Settings> {
+        self.get_over(store, None, path, ttl).await
+    }
+
+    /// Like [`SettingsCache::get`], with the file layered over `base`, e.g. the
+    /// `CONFIG` env var, see [`Settings::parse_layered`].
+    pub async fn get_over(
+        &self,
+        store: &dyn Store,
+        base: Option<&Document>,
+        path: &str,
+        ttl: Duration,
+    ) -> Result<Settings> {
+        if let Some(entry) = self.cached.lock().unwrap().as_ref() {
+            if entry.path == path
+                && entry.base.as_ref() == base
+                && is_fresh(entry.loaded_at, self.clock.now(), ttl)
+            {
+                return Ok(entry.settings.clone());
+            }
+        }
+        self.reload_over(store, base, path).await
+    }
+
+    /// Reads the file again regardless of the TTL. The cached settings are only
+    /// replaced once the new ones loaded and validated, so a broken commit keeps
+    /// the bot running on the previous config.
+    pub async fn reload(&self, store: &dyn Store, path: &str) -> Result<Settings> {
+        self.reload_over(store, None, path).await
+    }
+
+    /// Like [`SettingsCache::reload`], with the file layered over `base`.
+    pub async fn reload_over(
+        &self,
+        store: &dyn Store,
+        base: Option<&Document>,
+        path: &str,
+    ) -> Result<Settings> {
+        let settings = fetch(store, base, path, self.isolated).await?;
+        *self.cached.lock().unwrap() = Some(CachedSettings {
+            path: path.into(),
+            base: base.cloned(),
+            loaded_at: self.clock.now(),
+            settings: settings.clone(),
+        });
+        Ok(settings)
+    }
+
+    /// Forces the next `get` to read the file again.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+/// Whether an entry loaded at `loaded_at` is younger than `ttl`. An entry from
+/// the future, after the clock was set back, counts as expired.
+pub(crate) fn is_fresh(loaded_at: DateTime<Utc>, now: DateTime<Utc>, ttl: Duration) -> bool {
+    now.signed_duration_since(loaded_at)
+        .to_std()
+        .is_ok_and(|age| age < ttl)
+}
+
+async fn fetch(
+    store: &dyn Store,
+    base: Option<&Document>,
+    path: &str,
+    isolated: bool,
+) -> Result<Settings> {
+    let content = match store.read(path).await? {
+        Some(v) => v,
+        None => return Err(anyhow!("config file {} doesn't exist", path)),
+    };
+    let format = ConfigFormat::from_path(path).unwrap_or(ConfigFormat::Toml);
+    let settings = match base {
+        // Isolated caches are for tenants, which have no base document.
+        _ if isolated => Settings::parse_isolated(&content, format)?,
+        Some((base, base_format)) => {
+            Settings::parse_layered(&[(base, *base_format), (&content, format)])?
+        }
+        None => Settings::parse(&content, format)?,
+    };
+    info!("loaded settings from {}", path);
+    Ok(settings)
+}
+
+impl Default for SettingsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn it_serves_cached_settings_until_ttl_expires() {
+        let store = MemoryStore::new().with_file(
+            DEFAULT_CONFIG_FILE,
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n",
+        );
+        let cache = SettingsCache::new();
+        let ttl = Duration::from_secs(60);
+        assert_eq!(
+            cache
+                .get(&store, DEFAULT_CONFIG_FILE, ttl)
+                .await
+                .unwrap()
+                .currency,
+            "AUD"
+        );
+
+        store
+            .write(
+                DEFAULT_CONFIG_FILE,
+                "currency = \"USD\"\n[accounts]\ncba = \"Assets:CBA\"\n",
+                "changed currency",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            cache
+                .get(&store, DEFAULT_CONFIG_FILE, ttl)
+                .await
+                .unwrap()
+                .currency,
+            "AUD"
+        );
+        assert_eq!(
+            cache
+                .get(&store, DEFAULT_CONFIG_FILE, Duration::ZERO)
+                .await
+                .unwrap()
+                .currency,
+            "USD"
+        );
+
+        cache.invalidate();
+        assert!(cache
+            .get(&MemoryStore::new(), DEFAULT_CONFIG_FILE, ttl)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_ages_entries_with_its_clock() {
+        use beancount_core::clock::FixedClock;
+        use chrono::TimeZone;
+
+        let loaded_at = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let ttl = Duration::from_secs(60);
+        assert!(is_fresh(
+            loaded_at,
+            loaded_at + chrono::Duration::seconds(59),
+            ttl
+        ));
+        assert!(!is_fresh(
+            loaded_at,
+            loaded_at + chrono::Duration::seconds(60),
+            ttl
+        ));
+        assert!(!is_fresh(
+            loaded_at,
+            loaded_at - chrono::Duration::seconds(1),
+            ttl
+        ));
+
+        static CLOCK: FixedClock = FixedClock(chrono::MIN_DATETIME);
+        let store = MemoryStore::new().with_file(
+            DEFAULT_CONFIG_FILE,
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n",
+        );
+        let cache = SettingsCache::new().with_clock(&CLOCK);
+        cache.get(&store, DEFAULT_CONFIG_FILE, ttl).await.unwrap();
+        store
+            .delete(DEFAULT_CONFIG_FILE, "removed config")
+            .await
+            .unwrap();
+        // the clock never moves, so the entry never expires
+        assert!(cache.get(&store, DEFAULT_CONFIG_FILE, ttl).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_layers_the_file_over_a_base_document() {
+        let store = MemoryStore::new().with_file(
+            DEFAULT_CONFIG_FILE,
+            "[accounts]\nfood = \"Expenses:Food\"\n",
+        );
+        let base: Document = (
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n".into(),
+            ConfigFormat::Toml,
+        );
+        let cache = SettingsCache::new();
+        let ttl = Duration::from_secs(60);
+        let settings = cache
+            .get_over(&store, Some(&base), DEFAULT_CONFIG_FILE, ttl)
+            .await
+            .unwrap();
+        assert_eq!(settings.currency, "AUD");
+        assert_eq!(settings.accounts.len(), 2);
+
+        // Without the base the file alone lacks a currency.
+        assert!(cache.get(&store, DEFAULT_CONFIG_FILE, ttl).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_keeps_previous_settings_when_reload_fails() {
+        let store = MemoryStore::new().with_file(
+            DEFAULT_CONFIG_FILE,
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n",
+        );
+        let cache = SettingsCache::new();
+        let ttl = Duration::from_secs(60);
+        cache.get(&store, DEFAULT_CONFIG_FILE, ttl).await.unwrap();
+
+        store
+            .write(DEFAULT_CONFIG_FILE, "currency = \"AUDD\"\n", "broke config")
+            .await
+            .unwrap();
+        assert!(cache.reload(&store, DEFAULT_CONFIG_FILE).await.is_err());
+        assert_eq!(
+            cache
+                .get(&store, DEFAULT_CONFIG_FILE, ttl)
+                .await
+                .unwrap()
+                .currency,
+            "AUD"
+        );
+
+        store
+            .write(
+                DEFAULT_CONFIG_FILE,
+                "currency = \"USD\"\n[accounts]\ncba = \"Assets:CBA\"\n",
+                "fixed config",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            cache
+                .reload(&store, DEFAULT_CONFIG_FILE)
+                .await
+                .unwrap()
+                .currency,
+            "USD"
+        );
+        assert_eq!(
+            cache
+                .get(&store, DEFAULT_CONFIG_FILE, ttl)
+                .await
+                .unwrap()
+                .currency,
+            "USD"
+        );
+    }
+}
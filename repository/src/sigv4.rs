@@ -0,0 +1,188 @@
+use anyhow::Result;
+use beancount_core::secret::Secret;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::env;
+
+/// AWS credentials from the environment, e.g. of a Lambda execution role.
+pub(crate) struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: Secret<String>,
+    pub session_token: Option<Secret<String>>,
+}
+
+impl Credentials {
+    pub fn from_env() -> Result<Self> {
+        Ok(Credentials {
+            access_key_id: env::var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: Secret::from_env("AWS_SECRET_ACCESS_KEY")?,
+            session_token: Secret::from_env("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    pub fn signing_params<'a>(
+        &'a self,
+        region: &'a str,
+        service: &'a str,
+        time: DateTime<Utc>,
+    ) -> SigningParams<'a> {
+        SigningParams {
+            access_key_id: &self.access_key_id,
+            secret_access_key: self.secret_access_key.expose(),
+            region,
+            service,
+            time,
+        }
+    }
+}
+
+pub(crate) struct SigningParams<'a> {
+    pub access_key_id: &'a str,
+    pub secret_access_key: &'a str,
+    pub region: &'a str,
+    pub service: &'a str,
+    pub time: DateTime<Utc>,
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_access_key).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// `value` percent-encoded the way canonical requests want it, keeping `/`
+/// when it separates path segments.
+pub(crate) fn uri_encode(value: &str, keep_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slash => "/".into(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Signature Version 4 `Authorization` header for a request without a query
+/// string to the already encoded `path`. `headers` must be lowercase, sorted,
+/// and include `host` and `x-amz-date`.
+pub(crate) fn sign(
+    params: &SigningParams,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    payload_hash: &str,
+) -> String {
+    let amz_date = params.time.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = params.time.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/{}/aws4_request", date, params.region, params.service);
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    let key = signing_key(
+        params.secret_access_key,
+        &date,
+        params.region,
+        params.service,
+    );
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        params.access_key_id,
+        scope,
+        signed_headers,
+        hex(&hmac(&key, &string_to_sign))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn it_derives_documented_signing_key() {
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn it_signs_with_scope_and_signed_headers() {
+        let params = SigningParams {
+            access_key_id: "AKIDEXAMPLE",
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            region: "ap-southeast-2",
+            service: "ssm",
+            time: Utc.ymd(2022, 8, 14).and_hms(10, 0, 0),
+        };
+        let headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", "ssm.ap-southeast-2.amazonaws.com".to_string()),
+            ("x-amz-date", "20220814T100000Z".to_string()),
+            ("x-amz-target", "AmazonSSM.GetParameter".to_string()),
+        ];
+        let sign_body =
+            |body: &str| sign(&params, "POST", "/", &headers, &sha256_hex(body.as_bytes()));
+        let authorization = sign_body("{}");
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20220814/ap-southeast-2/ssm/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-target, Signature="
+        ));
+        assert_eq!(authorization, sign_body("{}"));
+        assert_ne!(authorization, sign_body("{ }"));
+        assert_ne!(
+            authorization,
+            sign(&params, "GET", "/", &headers, &sha256_hex(b"{}"))
+        );
+    }
+
+    #[test]
+    fn it_encodes_paths_keeping_segments() {
+        assert_eq!(
+            uri_encode("ledger/2021 Q1+.bean", true),
+            "ledger/2021%20Q1%2B.bean"
+        );
+        assert_eq!(uri_encode("a/b~c", false), "a%2Fb~c");
+    }
+}
@@ -0,0 +1,256 @@
+use crate::http_client::HttpClient;
+use crate::Store;
+use anyhow::{anyhow, Result};
+use beancount_core::secret::Secret;
+use beancount_core::settings::Settings;
+use beancount_core::splitwise::{book, SplitwiseExpense};
+use chrono::NaiveDate;
+use http::{header, Request, StatusCode};
+use log::info;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The Splitwise API, with an API key of your own app from
+/// <https://secure.splitwise.com/apps>.
+pub struct Splitwise {
+    http: Arc<dyn HttpClient>,
+    api_url: String,
+    api_key: Secret<String>,
+}
+
+#[derive(Deserialize)]
+struct CurrentUser {
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Expenses {
+    expenses: Vec<SplitwiseExpense>,
+}
+
+impl Splitwise {
+    pub const API_URL: &'static str = "https://secure.splitwise.com/api/v3.0";
+
+    pub fn new(http: Arc<dyn HttpClient>, api_url: &str, api_key: Secret<String>) -> Self {
+        Splitwise {
+            http,
+            api_url: api_url.trim_end_matches('/').into(),
+            api_key,
+        }
+    }
+
+    /// Reaches Splitwise with reqwest and `SPLITWISE_API_KEY`.
+    /// `SPLITWISE_API_URL` points it at another server, e.g. in tests.
+    #[cfg(feature = "native-http")]
+    pub fn from_env() -> Result<Self> {
+        let url = std::env::var("SPLITWISE_API_URL").unwrap_or_else(|_| Self::API_URL.into());
+        Ok(Splitwise::new(
+            Arc::new(crate::http_client::ReqwestClient::new()?),
+            &url,
+            Secret::from_env("SPLITWISE_API_KEY")?,
+        ))
+    }
+
+    /// The id of the user the API key belongs to.
+    pub async fn current_user(&self) -> Result<u64> {
+        let user: CurrentUser = serde_json::from_str(&self.get("get_current_user").await?)?;
+        Ok(user.user.id)
+    }
+
+    /// Every expense of the user's groups and friends dated after `date`.
+    pub async fn expenses(&self, dated_after: NaiveDate) -> Result<Vec<SplitwiseExpense>> {
+        let path = format!("get_expenses?dated_after={}&limit=0", dated_after);
+        let expenses: Expenses = serde_json::from_str(&self.get(&path).await?)?;
+        Ok(expenses.expenses)
+    }
+
+    async fn get(&self, path: &str) -> Result<String> {
+        let request = Request::get(format!("{}/{}", self.api_url, path))
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.api_key.expose()),
+            )
+            .body(Vec::new())?;
+        let response = self.http.send(request).await?;
+        let body = String::from_utf8_lossy(response.body()).into_owned();
+        match response.status() {
+            StatusCode::OK => Ok(body),
+            status => Err(anyhow!(
+                "Splitwise answered {} to {}: {}",
+                status,
+                path,
+                body
+            )),
+        }
+    }
+}
+
+/// Books the shares `user_id` owes of `expenses` (see [`book`]) and appends
+/// those not in the ledger yet, found by their `splitwise_id`, to the ledger
+/// files of their transactions, one write per file. Returns the added entries.
+pub async fn commit_expenses(
+    store: &dyn Store,
+    settings: &Settings,
+    expenses: &[SplitwiseExpense],
+    user_id: u64,
+) -> Result<Vec<String>> {
+    let splitwise = settings
+        .splitwise
+        .as_ref()
+        .ok_or_else(|| anyhow!("[splitwise] isn't configured"))?;
+    let mut files: BTreeMap<(String, String), Vec<(&SplitwiseExpense, String)>> = BTreeMap::new();
+    for expense in expenses {
+        if let Some(transaction) = book(settings, splitwise, expense, user_id)? {
+            let path = transaction.ledger_path(settings.ledger_path.as_deref());
+            files
+                .entry((transaction.year(), path))
+                .or_default()
+                .push((expense, String::from(transaction)));
+        }
+    }
+
+    let mut added = Vec::new();
+    for ((year, path), entries) in files {
+        let content = store.read(&path).await?;
+        let entries: Vec<String> = entries
+            .into_iter()
+            .filter(|(expense, _)| {
+                !content
+                    .as_ref()
+                    .is_some_and(|content| content.contains(&expense.marker()))
+            })
+            .map(|(_, entry)| entry)
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+        let mut content = content
+            .unwrap_or_else(|| crate::render_file_header(settings.file_header.as_deref(), &year));
+        for entry in entries.iter() {
+            content.push('\n');
+            content.push_str(entry);
+        }
+        store
+            .write(
+                &path,
+                &content,
+                &format!("booked {} Splitwise expenses", entries.len()),
+            )
+            .await?;
+        info!("booked {} Splitwise expenses to {}", entries.len(), path);
+        added.extend(entries);
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StoreError;
+    use crate::memory_store::MemoryStore;
+    use async_trait::async_trait;
+    use beancount_core::settings::SplitwiseSettings;
+    use http::Response;
+    use std::sync::Mutex;
+
+    /// Answers with the body of the first route the URL contains, remembering
+    /// the requests.
+    struct Canned {
+        routes: Vec<(&'static str, &'static str)>,
+        requests: Mutex<Vec<Request<Vec<u8>>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for Canned {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StoreError> {
+            let url = request.uri().to_string();
+            self.requests.lock().unwrap().push(request);
+            let body = self
+                .routes
+                .iter()
+                .find(|(route, _)| url.contains(route))
+                .map(|(_, body)| *body)
+                .unwrap_or_default();
+            Ok(Response::new(body.as_bytes().to_vec()))
+        }
+    }
+
+    const EXPENSES: &str = r#"{"expenses": [
+        { "id": 1, "group_id": 7, "description": "Dinner", "cost": "90.0", "currency_code": "AUD", "date": "2021-12-31T09:30:00Z", "payment": false, "deleted_at": null, "category": { "name": "Dining out" },
+          "users": [{ "user_id": 101, "paid_share": "90.0", "owed_share": "30.0" }, { "user_id": 202, "paid_share": "0.0", "owed_share": "60.0" }] },
+        { "id": 2, "group_id": 7, "description": "Taxi", "cost": "20.0", "currency_code": "AUD", "date": "2022-01-01T01:00:00Z", "payment": false, "deleted_at": null, "category": { "name": "Taxi" },
+          "users": [{ "user_id": 101, "paid_share": "0.0", "owed_share": "10.0" }, { "user_id": 202, "paid_share": "20.0", "owed_share": "10.0" }] },
+        { "id": 3, "group_id": 7, "description": "Payment", "cost": "60.0", "currency_code": "AUD", "date": "2022-01-02T01:00:00Z", "payment": true, "deleted_at": null,
+          "users": [{ "user_id": 101, "paid_share": "0.0", "owed_share": "60.0" }, { "user_id": 202, "paid_share": "60.0", "owed_share": "0.0" }] }
+    ]}"#;
+
+    #[tokio::test]
+    async fn it_books_new_shares_once() {
+        let http = Arc::new(Canned {
+            routes: vec![
+                (
+                    "get_current_user",
+                    r#"{"user": {"id": 101, "first_name": "Liang"}}"#,
+                ),
+                ("get_expenses", EXPENSES),
+            ],
+            requests: Mutex::new(Vec::new()),
+        });
+        let splitwise = Splitwise::new(
+            http.clone(),
+            "https://splitwise.test/api/v3.0/",
+            Secret::new("key".to_string()),
+        );
+        assert_eq!(splitwise.current_user().await.unwrap(), 101);
+        let expenses = splitwise
+            .expenses(NaiveDate::from_ymd_opt(2021, 12, 1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(expenses.len(), 3);
+        {
+            let requests = http.requests.lock().unwrap();
+            assert_eq!(
+                requests[1].uri(),
+                "https://splitwise.test/api/v3.0/get_expenses?dated_after=2021-12-01&limit=0"
+            );
+            assert_eq!(requests[1].headers()[header::AUTHORIZATION], "Bearer key");
+        }
+
+        let settings = Settings::builder("AUD")
+            .account("splitwise", "Liabilities:Splitwise")
+            .account("food", "Expenses:Food")
+            .account("transport", "Expenses:Transport")
+            .splitwise(SplitwiseSettings {
+                account: "splitwise".into(),
+                default_account: Some("transport".into()),
+                categories: [("Dining out".to_string(), "food".to_string())].into(),
+                ..SplitwiseSettings::default()
+            })
+            .build()
+            .unwrap();
+        let store = MemoryStore::new().with_file("2021.bean", "option \"title\" \"2021\"\n");
+        let added = commit_expenses(&store, &settings, &expenses, 101)
+            .await
+            .unwrap();
+        assert_eq!(added.len(), 2);
+        assert!(store
+            .file("2021.bean")
+            .unwrap()
+            .contains("splitwise_id: \"1\"\n  splitwise_url: \"https://secure.splitwise.com/#/expenses/1\"\n  Liabilities:Splitwise        -30.00 AUD\n  Expenses:Food        30.00 AUD\n"));
+        assert!(store
+            .file("2022.bean")
+            .unwrap()
+            .contains("Expenses:Transport        10.00 AUD"));
+
+        assert!(commit_expenses(&store, &settings, &expenses, 101)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+#[cfg(feature = "azure")]
+use crate::azure_store::AzureDevOpsStore;
+#[cfg(feature = "couchdb")]
+use crate::couchdb_store::CouchDbStore;
+use crate::fs_store::FsStore;
+#[cfg(feature = "git")]
+use crate::git_store::GitStore;
+#[cfg(feature = "github")]
+use crate::github_graphql_store::GithubGraphqlStore;
+#[cfg(feature = "github")]
+use crate::github_store::GithubStore;
+#[cfg(feature = "gitlab")]
+use crate::gitlab_store::GitLabStore;
+#[cfg(feature = "s3")]
+use crate::s3_store::S3Store;
+use crate::Store;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "github")]
+use beancount_core::secret::Secret;
+use std::env;
+
+/// Builds the store backend named by `STORE_BACKEND`, GitHub when it isn't
+/// set, from the env vars that backend reads.
+///
+/// A new backend only needs an arm in [`StoreFactory::build`] and a Cargo
+/// feature; the code using stores stays the same.
+#[derive(Debug, Clone, Default)]
+pub struct StoreFactory {
+    backend: Option<String>,
+    file_header: Option<String>,
+    ledger_path: Option<String>,
+    #[cfg_attr(
+        not(any(
+            feature = "github",
+            feature = "azure",
+            feature = "gitlab",
+            feature = "git"
+        )),
+        allow(dead_code)
+    )]
+    commit_message: Option<String>,
+    repository: Option<String>,
+    #[cfg(feature = "github")]
+    github: Option<GithubRepo>,
+}
+
+/// A GitHub repository and the token to reach it with.
+#[cfg(feature = "github")]
+#[derive(Debug, Clone)]
+struct GithubRepo {
+    owner: String,
+    repo: String,
+    token: Secret<String>,
+}
+
+impl StoreFactory {
+    /// The store of the default ledger, without settings applied.
+    pub fn from_env() -> Result<Box<dyn Store>> {
+        StoreFactory::new().build()
+    }
+
+    pub fn new() -> Self {
+        StoreFactory {
+            backend: env::var("STORE_BACKEND").ok(),
+            ..StoreFactory::default()
+        }
+    }
+
+    pub fn with_backend(mut self, backend: Option<String>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_file_header(mut self, file_header: Option<String>) -> Self {
+        self.file_header = file_header;
+        self
+    }
+
+    /// Path template of ledger files, see
+    /// [`beancount_core::parser::Transaction::ledger_path`].
+    pub fn with_ledger_path(mut self, ledger_path: Option<String>) -> Self {
+        self.ledger_path = ledger_path;
+        self
+    }
+
+    /// Commit message template of saved transactions, see
+    /// [`beancount_core::parser::Transaction::commit_message`].
+    pub fn with_commit_message(mut self, commit_message: Option<String>) -> Self {
+        self.commit_message = commit_message;
+        self
+    }
+
+    /// `owner/repo` of the ledger, for the GitHub backends.
+    pub fn with_repository(mut self, repository: Option<String>) -> Self {
+        self.repository = repository;
+        self
+    }
+
+    /// Reaches `owner/repo` on GitHub with `token` instead of the `GITHUB_*`
+    /// env vars, e.g. a tenant's ledger; the backend is GitHub then.
+    #[cfg(feature = "github")]
+    pub fn with_github_repo(mut self, owner: &str, repo: &str, token: &Secret<String>) -> Self {
+        self.backend = Some("github".into());
+        self.github = Some(GithubRepo {
+            owner: owner.into(),
+            repo: repo.into(),
+            token: token.clone(),
+        });
+        self
+    }
+
+    /// Fails rather than ignore a setting the backend can't honour.
+    pub fn build(self) -> Result<Box<dyn Store>> {
+        let backend = self.backend.as_deref();
+        if let (Some(backend), Some(repository)) = (backend, &self.repository) {
+            if backend != "github" {
+                return Err(anyhow!(
+                    "the {} store backend can't write to repository {}",
+                    backend,
+                    repository
+                ));
+            }
+        }
+        if backend == Some("couchdb") && (self.file_header.is_some() || self.ledger_path.is_some())
+        {
+            return Err(anyhow!(
+                "the couchdb store backend keeps transactions as documents, it has no file_header or ledger_path"
+            ));
+        }
+        // Only the backends built in take a commit message or a repository.
+        #[allow(unused_variables)]
+        let StoreFactory {
+            file_header,
+            ledger_path,
+            commit_message,
+            repository,
+            ..
+        } = self;
+        match backend {
+            #[cfg(feature = "azure")]
+            Some("azure") => Ok(Box::new(
+                AzureDevOpsStore::new()?
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path)
+                    .with_commit_message(commit_message),
+            )),
+            #[cfg(feature = "gitlab")]
+            Some("gitlab") => Ok(Box::new(
+                GitLabStore::new()?
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path)
+                    .with_commit_message(commit_message),
+            )),
+            #[cfg(feature = "git")]
+            Some("git") => Ok(Box::new(
+                GitStore::new()?
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path)
+                    .with_commit_message(commit_message),
+            )),
+            #[cfg(feature = "couchdb")]
+            Some("couchdb") => Ok(Box::new(CouchDbStore::new()?)),
+            #[cfg(feature = "s3")]
+            Some("s3") => Ok(Box::new(
+                S3Store::new()?
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path),
+            )),
+            Some("fs") => Ok(Box::new(
+                FsStore::new()?
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path),
+            )),
+            #[cfg(feature = "github")]
+            Some("github") | None => match (env::var("GITHUB_API").as_deref(), self.github) {
+                (Ok("graphql"), github) => Ok(Box::new(
+                    match github {
+                        Some(github) => GithubGraphqlStore::for_repo(
+                            &github.owner,
+                            &github.repo,
+                            &github.token,
+                        )?,
+                        None => GithubGraphqlStore::new()?,
+                    }
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path)
+                    .with_commit_message(commit_message)
+                    .with_repository(repository.as_deref()),
+                )),
+                (_, github) => Ok(Box::new(
+                    match github {
+                        Some(github) => {
+                            GithubStore::for_repo(&github.owner, &github.repo, &github.token)?
+                        }
+                        None => GithubStore::new()?,
+                    }
+                    .with_file_header(file_header)
+                    .with_ledger_path(ledger_path)
+                    .with_commit_message(commit_message)
+                    .with_repository(repository.as_deref()),
+                )),
+            },
+            #[cfg(not(feature = "github"))]
+            None => Err(anyhow!("STORE_BACKEND env not set!")),
+            Some(backend) => Err(anyhow!(
+                "unknown store backend {}, or it wasn't enabled at build time",
+                backend
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_builds_the_named_backend() {
+        let dir = env::temp_dir().join(format!("store-factory-{}", std::process::id()));
+        env::set_var("LEDGER_DIR", &dir);
+        let store = StoreFactory::default()
+            .with_backend(Some("fs".into()))
+            .build()
+            .unwrap();
+        store
+            .write("ledger/2021.bean", "", "created")
+            .await
+            .unwrap();
+        assert!(dir.join("ledger/2021.bean").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let error = StoreFactory::default()
+            .with_backend(Some("dropbox".into()))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "unknown store backend dropbox, or it wasn't enabled at build time"
+        );
+    }
+
+    #[test]
+    fn it_refuses_settings_the_backend_cant_honour() {
+        let error = StoreFactory::default()
+            .with_backend(Some("fs".into()))
+            .with_repository(Some("liul85/ledger".into()))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the fs store backend can't write to repository liul85/ledger"
+        );
+        let error = StoreFactory::default()
+            .with_backend(Some("couchdb".into()))
+            .with_ledger_path(Some("ledger/{year}.bean".into()))
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("no file_header or ledger_path"));
+    }
+}
@@ -0,0 +1,19 @@
+use lambda_http::lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+
+/// AWS Lambda entry point for EventBridge schedules. A rule whose input is
+/// `{"job": "<name>"}` runs that job; any other event runs the jobs due this
+/// minute, for a `rate(1 minute)` rule.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+    run(service_fn(handle)).await
+}
+
+async fn handle(event: LambdaEvent<Value>) -> Result<Vec<String>, Error> {
+    let outcomes = match event.payload["job"].as_str() {
+        Some(name) => vec![beancount::run_job(name).await?],
+        None => beancount::run_due_jobs().await?,
+    };
+    Ok(outcomes)
+}
@@ -0,0 +1,36 @@
+use lambda_http::http::{header, StatusCode};
+use lambda_http::{run, service_fn, Body, Error, Request, Response};
+
+/// AWS Lambda entry point for API Gateway and Function URL events, built with
+/// `cargo build --release --no-default-features --features lambda`.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init();
+    run(service_fn(webhook)).await
+}
+
+async fn webhook(request: Request) -> Result<Response<Body>, Error> {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let secret_token = request
+        .headers()
+        .get(beancount::SECRET_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let body =
+        match beancount::validate_request(content_type, secret_token, request.body().as_ref()) {
+            Ok(body) => body,
+            Err(rejection) => {
+                return Ok(Response::builder()
+                    .status(rejection.status())
+                    .header("Content-Type", "text/plain")
+                    .body(rejection.to_string().into())?)
+            }
+        };
+    let response = beancount::handle_serverless_update(None, body).await?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(response.into())?)
+}
@@ -0,0 +1,420 @@
+//! The axum app of the standalone server, shared by its `server` binary and
+//! other long-running hosts such as Shuttle.
+#![cfg(feature = "http")]
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use beancount::Handled;
+use log::{error, info, warn};
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{task, time};
+use tracing::{instrument, Instrument, Span};
+
+/// Runs the jobs due at the start of every minute. Only one replica should run
+/// it, since reminders and reports would be sent once per replica.
+pub async fn run_scheduler() {
+    loop {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        time::sleep(Duration::from_secs(60 - seconds % 60)).await;
+        match beancount::run_due_jobs().await {
+            Ok(outcomes) => outcomes.iter().for_each(|outcome| info!("{}", outcome)),
+            Err(e) => error!("Scheduled jobs failed: {}", e),
+        }
+    }
+}
+
+/// Waits for the transactions still being saved in the background, for up to
+/// `SHUTDOWN_TIMEOUT_SECONDS` (20 by default), then hands what's left over to
+/// `beancount::flush_state`. Call it once the server stopped taking requests.
+pub async fn drain() {
+    let timeout = env::var("SHUTDOWN_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20);
+    let deadline = time::Instant::now() + Duration::from_secs(timeout);
+    while beancount::saves_in_flight() > 0 && time::Instant::now() < deadline {
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    let left = beancount::saves_in_flight();
+    if left > 0 {
+        warn!(
+            "{} saves didn't finish in {}s, dead-lettering them",
+            left, timeout
+        );
+    }
+    match beancount::flush_state().await {
+        Ok(()) => info!("state flushed"),
+        Err(e) => error!("Failed to flush state: {}", e),
+    }
+}
+
+/// Seconds, from a cached settings hit to a slow GitHub commit.
+pub const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+pub fn app(metrics: PrometheusHandle) -> Router {
+    let router = Router::new()
+        .route("/", post(webhook))
+        .route("/webhook", post(webhook))
+        .route("/webhook/:bot", post(bot_webhook))
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(ready))
+        .route("/jobs/:name", post(job))
+        .route("/admin/:name", get(admin))
+        .route("/api/entries", get(|h, q| dashboard("entries", h, q)))
+        .route("/api/balances", get(|h, q| dashboard("balances", h, q)))
+        .route(
+            "/api/report/monthly",
+            get(|h, q| dashboard("report/monthly", h, q)),
+        )
+        .route("/metrics", get(move || async move { metrics.render() }));
+    #[cfg(feature = "bank-feed")]
+    let router = router.route("/webhooks/:provider", post(bank_webhook));
+    #[cfg(feature = "slack")]
+    let router = router.route("/slack", post(slack));
+    #[cfg(feature = "discord")]
+    let router = router.route("/discord", post(discord));
+    router.layer(DefaultBodyLimit::max(beancount::MAX_BODY_BYTES))
+}
+
+/// Transactions pushed by a bank, signed in `X-Up-Authenticity-Signature` by Up
+/// and in `X-Signature` by other feeds, see `beancount::bank_webhook`.
+#[cfg(feature = "bank-feed")]
+#[instrument(name = "bank_webhook", skip_all, fields(provider = %provider))]
+async fn bank_webhook(
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, String) {
+    let signature = ["x-up-authenticity-signature", "x-signature"]
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|v| v.to_str().ok()))
+        .map(String::from);
+    match beancount::bank_webhook(&provider, signature.as_deref(), &body).await {
+        Ok(outcome) => (StatusCode::OK, outcome),
+        Err(e) => {
+            error!("Bank webhook failed: {}", e);
+            (StatusCode::from_u16(e.status()).unwrap(), e.to_string())
+        }
+    }
+}
+
+/// Events and slash commands of a Slack app, see `beancount::slack_request`.
+#[cfg(feature = "slack")]
+#[instrument(name = "slack", skip_all)]
+async fn slack(
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, [(header::HeaderName, &'static str); 1], String) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let signature = header(beancount::SLACK_SIGNATURE_HEADER);
+    let timestamp = header(beancount::SLACK_TIMESTAMP_HEADER);
+    let retry = headers.contains_key(beancount::SLACK_RETRY_HEADER);
+    let handled =
+        beancount::slack_request(signature.as_deref(), timestamp.as_deref(), retry, &body).await;
+    match handled {
+        Ok(response) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            response,
+        ),
+        Err(e) => {
+            error!("Slack request failed: {}", e);
+            (
+                StatusCode::from_u16(e.status()).unwrap(),
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            )
+        }
+    }
+}
+
+/// Interactions of a Discord application, see `beancount::discord_request`.
+#[cfg(feature = "discord")]
+#[instrument(name = "discord", skip_all)]
+async fn discord(
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, [(header::HeaderName, &'static str); 1], String) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let signature = header(beancount::DISCORD_SIGNATURE_HEADER);
+    let timestamp = header(beancount::DISCORD_TIMESTAMP_HEADER);
+    match beancount::discord_request(signature.as_deref(), timestamp.as_deref(), &body).await {
+        Ok(response) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            response,
+        ),
+        Err(e) => {
+            error!("Discord interaction failed: {}", e);
+            (
+                StatusCode::from_u16(e.status()).unwrap(),
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            )
+        }
+    }
+}
+
+#[instrument(name = "webhook", skip_all)]
+async fn webhook(headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+    handle(None, headers, body).await
+}
+
+/// The webhook of a tenant's own bot, at `/webhook/<bot id>`.
+#[instrument(name = "webhook", skip_all, fields(bot = %bot))]
+async fn bot_webhook(
+    Path(bot): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    handle(Some(bot), headers, body).await
+}
+
+async fn handle(
+    bot: Option<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, [(header::HeaderName, &'static str); 1], String) {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let secret_token = headers
+        .get(beancount::SECRET_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let body = match beancount::validate_request(content_type, secret_token, &body) {
+        Ok(body) => body.to_string(),
+        Err(rejection) => {
+            warn!("Rejected request: {}", rejection);
+            return (
+                StatusCode::from_u16(rejection.status()).unwrap(),
+                [(header::CONTENT_TYPE, "text/plain")],
+                rejection.to_string(),
+            );
+        }
+    };
+    let fast_ack = env::var("FAST_ACK").is_ok_and(|v| v == "true");
+    let handled = match (fast_ack, bot) {
+        (true, bot) => beancount::handle_update_deferred(bot.as_deref(), &body).await,
+        (false, Some(bot)) => beancount::handle_bot_update(&bot, &body)
+            .await
+            .map(Handled::from),
+        (false, None) => beancount::handle_update(&body).await.map(Handled::from),
+    };
+    match handled {
+        Ok(handled) => {
+            // The save outlives the request; `drain` waits for it on shutdown.
+            if let Some(pending) = handled.pending {
+                task::spawn(pending.run().instrument(Span::current()));
+            }
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/json")],
+                handled.response,
+            )
+        }
+        Err(e) => {
+            error!("Failed to handle update: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            )
+        }
+    }
+}
+
+/// Runs a job by name for an external cron, e.g. a Kubernetes CronJob, which
+/// must send `Authorization: Bearer <CRON_SECRET>`.
+async fn job(Path(name): Path<String>, headers: HeaderMap) -> (StatusCode, String) {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !beancount::is_cron_authorized(authorization) {
+        return (StatusCode::UNAUTHORIZED, "unauthorized".into());
+    }
+    match beancount::run_job(&name).await {
+        Ok(outcome) => (StatusCode::OK, outcome),
+        Err(e) => {
+            error!("Job failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+/// Bot API diagnostics for `Authorization: Bearer <ADMIN_TOKEN>`, see
+/// `beancount::admin_report`.
+async fn admin(Path(name): Path<String>, headers: HeaderMap) -> impl IntoResponse {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !beancount::is_admin_authorized(authorization) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "unauthorized".to_string(),
+        );
+    }
+    match beancount::admin_report(&name).await {
+        Ok(Some(report)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            report,
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "not found".into(),
+        ),
+        Err(e) => {
+            error!("Admin endpoint failed: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            )
+        }
+    }
+}
+
+/// The read-only dashboard API for `Authorization: Bearer <API_TOKEN>`, see
+/// `beancount::dashboard_api`.
+async fn dashboard(
+    name: &'static str,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !beancount::is_api_authorized(authorization) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "unauthorized".to_string(),
+        );
+    }
+    match beancount::dashboard_api(name, &query).await {
+        Ok(Some(json)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            json,
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "not found".into(),
+        ),
+        Err(e) => {
+            error!("Dashboard API failed: {}", e);
+            (
+                StatusCode::from_u16(e.status()).unwrap(),
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            )
+        }
+    }
+}
+
+async fn ready() -> (StatusCode, String) {
+    match beancount::check_ready().await {
+        Ok(()) => (StatusCode::OK, "ok".into()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn it_answers_health_checks_metrics_and_malformed_requests() {
+        let metrics = PrometheusBuilder::new().build_recorder().handle();
+        let response = app(metrics.clone())
+            .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app(metrics.clone())
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app(metrics.clone())
+            .oneshot(Request::post("/jobs/report").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app(metrics.clone())
+            .oneshot(Request::get("/admin/me").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app(metrics.clone())
+            .oneshot(
+                Request::get("/api/balances?date=2021-12-31")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app(metrics.clone())
+            .oneshot(
+                Request::post("/webhook")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from("{"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app(metrics.clone())
+            .oneshot(Request::post("/webhook").body(Body::from("{")).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = app(metrics)
+            .oneshot(
+                Request::post("/webhook")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(vec![b' '; beancount::MAX_BODY_BYTES + 1]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+#[cfg(feature = "otel")]
+use log::error;
+use log::info;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use server::{app, drain, run_scheduler, LATENCY_BUCKETS};
+use std::env;
+use tokio::signal;
+
+/// Runs the bot as a long-lived HTTP server instead of a Vercel function, for a
+/// VPS, docker-compose or a Kubernetes deployment. Configuration is the same set
+/// of env vars, plus `CONFIG_PATH` for a config file on local disk.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let telemetry = init_telemetry()?;
+    let address = format!(
+        "{}:{}",
+        env::var("HOST").unwrap_or_else(|_| "0.0.0.0".into()),
+        env::var("PORT").unwrap_or_else(|_| "8080".into())
+    );
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    info!("listening on {}", address);
+    let metrics = PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)?
+        .install_recorder()?;
+    if env::var("RUN_SCHEDULER").is_ok_and(|v| v == "true") {
+        info!("running scheduled jobs");
+        tokio::spawn(run_scheduler());
+    }
+    axum::serve(listener, app(metrics))
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    drain().await;
+    info!("server stopped");
+    telemetry.shutdown();
+    Ok(())
+}
+
+/// Keeps the OTLP exporter alive so buffered spans can be flushed on exit.
+struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Telemetry {
+    fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                error!("Failed to flush spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Logs and spans go to stderr, filtered by `RUST_LOG`. Built with the `otel`
+/// feature and with `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also exported
+/// over OTLP, e.g. to Jaeger or Honeycomb.
+fn init_telemetry() -> Result<Telemetry> {
+    use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer());
+
+    #[cfg(feature = "otel")]
+    if env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok() {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::KeyValue;
+        use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .build()?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "beancount-bot",
+            )]))
+            .build();
+        registry
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("beancount-bot")))
+            .try_init()?;
+        return Ok(Telemetry {
+            provider: Some(provider),
+        });
+    }
+
+    registry.try_init()?;
+    Ok(Telemetry {
+        #[cfg(feature = "otel")]
+        provider: None,
+    })
+}
+
+/// Resolves on Ctrl-C or SIGTERM, after which in-flight updates finish before
+/// the server exits, so `docker stop` doesn't drop a transaction half saved.
+async fn shutdown_signal() {
+    let interrupt = async {
+        signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    info!("shutting down, waiting for in-flight requests");
+}
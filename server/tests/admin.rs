@@ -0,0 +1,80 @@
+//! Admin endpoints against a stubbed Telegram Bot API and GitHub contents API.
+
+use serde_json::{json, Value};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn it_proxies_bot_api_and_reports_recent_errors() {
+    let server = MockServer::start().await;
+    env::set_var("GITHUB_API_URL", server.uri());
+    env::set_var("TELEGRAM_API_URL", server.uri());
+    env::set_var("TELEGRAM_BOT_TOKEN", "123456:test");
+    env::set_var("GITHUB_TOKEN", "test-token");
+    env::set_var("GITHUB_OWNER", "liul85");
+    env::set_var("GITHUB_REPO", "beancount");
+    env::set_var("ADMIN_TOKEN", "admin-token");
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n",
+    );
+    let webhook_info = json!({
+        "ok": true,
+        "result": { "url": "https://bot.example.com/webhook", "pending_update_count": 3, "last_error_message": "Wrong response from the webhook: 500 Internal Server Error" }
+    });
+    Mock::given(method("GET"))
+        .and(path("/bot123456:test/getWebhookInfo"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(webhook_info.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/bot123456:test/getMe"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repos/liul85/beancount/contents/2021.bean"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    assert!(beancount::is_admin_authorized(Some("Bearer admin-token")));
+    assert!(!beancount::is_admin_authorized(Some("Bearer nope")));
+
+    let report = beancount::admin_report("webhook-info")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&report).unwrap(),
+        webhook_info
+    );
+    let error = beancount::admin_report("me").await.unwrap_err();
+    assert_eq!(error.to_string(), "Failed to call getMe: 401 Unauthorized");
+    assert!(beancount::admin_report("nope").await.unwrap().is_none());
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let body = json!({
+        "update_id": 459592837,
+        "message": {
+            "message_id": 7,
+            "from": { "id": 247673932, "is_bot": false, "first_name": "Liang", "username": "liul85", "language_code": "en" },
+            "chat": { "id": 247673932, "first_name": "Liang", "username": "liul85", "type": "private" },
+            "date": now,
+            "text": "2021-09-08 @KFC hamburger 12.40 AUD cba > food",
+        }
+    })
+    .to_string();
+    beancount::handle_update(&body).await.unwrap_err();
+
+    let report = beancount::admin_report("errors").await.unwrap().unwrap();
+    let report: Value = serde_json::from_str(&report).unwrap();
+    assert_eq!(report["total"], 1);
+    assert_eq!(report["by_kind"]["api"], 1);
+    assert_eq!(report["recent"][0]["kind"], "api");
+}
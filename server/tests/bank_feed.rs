@@ -0,0 +1,164 @@
+//! Up webhooks booked against a stubbed Up API, GitHub contents API and
+//! Telegram Bot API, then confirmed with an inline keyboard button.
+#![cfg(feature = "bank-feed")]
+
+mod common;
+
+use common::{decoded, file_content, requests, LEDGER};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::env;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const PENDING: &str = "/repos/liul85/beancount/contents/.beancount-bot/bank-feed/tx-1.json";
+
+fn sign(body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"up-secret").unwrap();
+    mac.update(body.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[tokio::test]
+async fn it_asks_before_booking_up_transactions() {
+    let server = common::github().await;
+    common::telegram(&server);
+    env::set_var("UP_API_URL", server.uri());
+    env::set_var("UP_API_TOKEN", "up:yeah:test");
+    env::set_var("UP_WEBHOOK_SECRET", "up-secret");
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\nup = \"Assets:Up\"\nfood = \"Expenses:Food\"\n[bank_feed]\ndefault_account = \"food\"\nchat_id = 42\n[bank_feed.accounts]\nacc-1 = \"up\"\n",
+    );
+    Mock::given(method("GET"))
+        .and(path("/transactions/tx-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {
+                "type": "transactions",
+                "id": "tx-1",
+                "attributes": {
+                    "description": "KFC",
+                    "rawText": "KFC SYDNEY",
+                    "amount": { "currencyCode": "AUD", "value": "-12.40" },
+                    "createdAt": "2021-09-08T12:30:00+10:00"
+                },
+                "relationships": {
+                    "account": { "data": { "type": "accounts", "id": "acc-1" } },
+                    "category": { "data": null }
+                }
+            }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(file_content("option \"title\" \"2021\"\n", "def")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(404))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    for url in [PENDING, LEDGER] {
+        Mock::given(method("PUT"))
+            .and(path(url))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("DELETE"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    for method_name in ["sendMessage", "editMessageText"] {
+        Mock::given(method("POST"))
+            .and(path(format!("/bot123456:test/{}", method_name)))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "ok": true, "result": {} })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let event = json!({
+        "data": {
+            "type": "webhook-events",
+            "id": "ev-1",
+            "attributes": { "eventType": "TRANSACTION_CREATED" },
+            "relationships": { "transaction": { "data": { "type": "transactions", "id": "tx-1" } } }
+        }
+    })
+    .to_string();
+    let error = beancount::bank_webhook("up", Some("00"), event.as_bytes())
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), 401);
+
+    let signature = sign(&event);
+    let outcome = beancount::bank_webhook("up", Some(&signature), event.as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(outcome, "asked chat 42 about tx-1");
+
+    let prompt = &requests(&server, "POST", "/bot123456:test/sendMessage").await[0];
+    assert_eq!(prompt["chat_id"], 42);
+    assert_eq!(
+        prompt["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        "bank:save:tx-1"
+    );
+    let pending = &requests(&server, "PUT", PENDING).await[0];
+    let pending =
+        String::from_utf8(base64::decode(pending["content"].as_str().unwrap()).unwrap()).unwrap();
+    Mock::given(method("GET"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(&pending, "abc")))
+        .mount(&server)
+        .await;
+
+    let tap = json!({
+        "update_id": 459593200,
+        "callback_query": {
+            "id": "1063732217806040",
+            "from": { "id": 42, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "message": {
+                "message_id": 301,
+                "from": { "id": 123456, "is_bot": true, "first_name": "beancount", "username": "beancount_bot" },
+                "chat": { "id": 42, "first_name": "Liang", "username": "liul85", "type": "private" },
+                "date": 1631068200,
+                "text": "New bank transaction"
+            },
+            "chat_instance": "-5093712373",
+            "data": "bank:save:tx-1"
+        }
+    })
+    .to_string();
+    let response = beancount::handle_update(&tap).await.unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["method"], "answerCallbackQuery");
+    assert_eq!(response["text"], "Saved");
+
+    let ledger = decoded(&requests(&server, "PUT", LEDGER).await[0]);
+    assert!(ledger.contains(
+        "2021-09-08 * \"KFC\" \"\"\n  bank_id: \"tx-1\"\n  Assets:Up        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+    ));
+    let edit = &requests(&server, "POST", "/bot123456:test/editMessageText").await[0];
+    assert_eq!(edit["message_id"], 301);
+    assert!(edit["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("2021-09-08 * \"KFC\""));
+}
@@ -0,0 +1,60 @@
+//! Stubs and helpers shared by the end-to-end tests, which each use a part.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::env;
+use wiremock::MockServer;
+
+pub const LEDGER: &str = "/repos/liul85/beancount/contents/2021.bean";
+
+/// Starts the stub server and points the GitHub store at it; tests set their
+/// own `CONFIG`.
+pub async fn github() -> MockServer {
+    let server = MockServer::start().await;
+    env::set_var("GITHUB_API_URL", server.uri());
+    env::set_var("GITHUB_TOKEN", "test-token");
+    env::set_var("GITHUB_OWNER", "liul85");
+    env::set_var("GITHUB_REPO", "beancount");
+    server
+}
+
+/// Points the Bot API client at the stub server too.
+pub fn telegram(server: &MockServer) {
+    env::set_var("TELEGRAM_API_URL", server.uri());
+    env::set_var("TELEGRAM_BOT_TOKEN", "123456:test");
+}
+
+/// A GitHub contents API answer with `content` as the file.
+pub fn file_content(content: &str, sha: &str) -> Value {
+    json!({
+        "type": "file",
+        "encoding": "base64",
+        "size": content.len(),
+        "name": "",
+        "path": "",
+        "content": base64::encode(content),
+        "sha": sha,
+        "url": "",
+        "git_url": "",
+        "html_url": "",
+        "download_url": "",
+        "_links": { "git": "", "self": "", "html": "" },
+    })
+}
+
+/// The JSON bodies of the `verb` requests to `url` the stub server received.
+pub async fn requests(server: &MockServer, verb: &str, url: &str) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == verb && request.url.path() == url)
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+/// The file content of a GitHub contents API `PUT` body.
+pub fn decoded(body: &Value) -> String {
+    String::from_utf8(base64::decode(body["content"].as_str().unwrap()).unwrap()).unwrap()
+}
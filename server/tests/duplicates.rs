@@ -0,0 +1,111 @@
+//! A transaction sent again, held back as a likely duplicate of the entry
+//! already in the ledger until it's saved anyway with an inline keyboard
+//! button, against a stubbed GitHub contents API and Telegram Bot API.
+
+mod common;
+
+use common::{decoded, file_content, requests, LEDGER};
+use serde_json::{json, Value};
+use std::env;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const PENDING: &str = "/repos/liul85/beancount/contents/.beancount-bot/duplicates/247673932-8.json";
+
+#[tokio::test]
+async fn it_asks_before_saving_a_likely_duplicate() {
+    let server = common::github().await;
+    common::telegram(&server);
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n[reply]\nmonth_to_date = true\n",
+    );
+    let ledger = "2021-09-08 * \"KFC\" \"hamburger\"\n  telegram_message: \"247673932/7\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n";
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(ledger, "abc")))
+        .mount(&server)
+        .await;
+    for url in [PENDING, LEDGER] {
+        Mock::given(method("PUT"))
+            .and(path(url))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("DELETE"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/editMessageText"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true, "result": {} })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let update = json!({
+        "update_id": 459593300,
+        "message": {
+            "message_id": 8,
+            "from": { "id": 247673932, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "chat": { "id": 247673932, "first_name": "Liang", "username": "liul85", "type": "private" },
+            "date": 1631068200,
+            "text": "2021-09-08 @KFC hamburger 12.40 AUD cba > food"
+        }
+    })
+    .to_string();
+    let response = beancount::handle_update(&update).await.unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["method"], "sendMessage");
+    assert!(response["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Looks like a duplicate of 2021.bean:1, save anyway?\n2021-09-08 * \"KFC\""));
+    assert_eq!(
+        response["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        "duplicate:save:247673932-8"
+    );
+    assert!(requests(&server, "PUT", LEDGER).await.is_empty());
+
+    let pending = decoded(&requests(&server, "PUT", PENDING).await[0]);
+    Mock::given(method("GET"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(&pending, "def")))
+        .mount(&server)
+        .await;
+
+    let tap = json!({
+        "update_id": 459593301,
+        "callback_query": {
+            "id": "1063732217806042",
+            "from": { "id": 247673932, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "message": {
+                "message_id": 9,
+                "from": { "id": 123456, "is_bot": true, "first_name": "beancount", "username": "beancount_bot" },
+                "chat": { "id": 247673932, "first_name": "Liang", "username": "liul85", "type": "private" },
+                "date": 1631068201,
+                "text": "Looks like a duplicate of 2021.bean:1, save anyway?"
+            },
+            "chat_instance": "-5093712373",
+            "data": "duplicate:save:247673932-8"
+        }
+    })
+    .to_string();
+    let response = beancount::handle_update(&tap).await.unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["method"], "answerCallbackQuery");
+    assert_eq!(response["text"], "Saved");
+
+    let saved = decoded(&requests(&server, "PUT", LEDGER).await[0]);
+    assert!(saved.starts_with(ledger));
+    assert!(saved.contains("telegram_message: \"247673932/8\""));
+    let edit = &requests(&server, "POST", "/bot123456:test/editMessageText").await[0];
+    assert_eq!(edit["message_id"], 9);
+    let edit = edit["text"].as_str().unwrap();
+    assert!(edit.starts_with("2021-09-08 * \"KFC\""));
+    assert!(edit.ends_with("Month to date: 12.40 AUD on Expenses:Food"));
+}
@@ -0,0 +1,54 @@
+//! Scheduled jobs run against a stubbed GitHub contents API and Telegram Bot
+//! API.
+
+use serde_json::{json, Value};
+use std::env;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn it_sends_reminders_and_monthly_reports() {
+    let server = MockServer::start().await;
+    env::set_var("GITHUB_API_URL", server.uri());
+    env::set_var("TELEGRAM_API_URL", server.uri());
+    env::set_var("TELEGRAM_BOT_TOKEN", "123456:test");
+    env::set_var("GITHUB_TOKEN", "test-token");
+    env::set_var("GITHUB_OWNER", "liul85");
+    env::set_var("GITHUB_REPO", "beancount");
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\n[[jobs]]\nname = \"receipts\"\nkind = \"reminder\"\nschedule = \"0 21 * * *\"\nchat_id = 42\ntext = \"Any receipts today?\"\n[[jobs]]\nname = \"report\"\nkind = \"monthly_report\"\nschedule = \"0 9 1 * *\"\nchat_id = 42\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/sendMessage"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let outcome = beancount::run_job("receipts").await.unwrap();
+    assert_eq!(outcome, "receipts: reminded chat 42");
+
+    // The ledger of last month's year doesn't exist, so the report is empty.
+    let outcome = beancount::run_job("report").await.unwrap();
+    assert!(outcome.starts_with("report: sent the "));
+
+    let messages: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    assert_eq!(messages[0]["chat_id"], 42);
+    assert_eq!(messages[0]["text"], "Any receipts today?");
+    assert_eq!(messages[1]["parse_mode"], "MarkdownV2");
+    assert!(messages[1]["text"]
+        .as_str()
+        .unwrap()
+        .contains("No expenses or income in"));
+
+    let error = beancount::run_job("missing").await.unwrap_err();
+    assert_eq!(error.to_string(), "job missing doesn't exist");
+}
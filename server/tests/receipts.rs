@@ -0,0 +1,146 @@
+//! A receipt photo read with a stubbed OCR API and booked against a stubbed
+//! GitHub contents API and Telegram Bot API, then confirmed with an inline
+//! keyboard button.
+
+mod common;
+
+use common::{decoded, file_content, requests, LEDGER};
+use serde_json::{json, Value};
+use std::env;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const PENDING: &str = "/repos/liul85/beancount/contents/.beancount-bot/receipts/42-290.json";
+
+#[tokio::test]
+async fn it_books_receipt_photos_once_confirmed() {
+    let server = common::github().await;
+    common::telegram(&server);
+    env::set_var(
+        "CONFIG",
+        format!(
+            "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n[ocr]\nurl = \"{}/ocr\"\napi_key = \"ocr-key\"\ndefault_account = \"food\"\n",
+            server.uri()
+        ),
+    );
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/getFile"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            json!({ "ok": true, "result": { "file_id": "AgACAgUAAxkBAAIBIn", "file_path": "photos/file_1.jpg" } }),
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/file/bot123456:test/photos/file_1.jpg"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0xff, 0xd8, 0xff]))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/ocr"))
+        .and(header("Authorization", "Bearer ocr-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "text": "TAX INVOICE\nKFC Carlton\n2 PC MEAL  12.40\nTOTAL  $12.40\n08/09/2021 12:40"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(file_content("option \"title\" \"2021\"\n", "def")),
+        )
+        .mount(&server)
+        .await;
+    for url in [PENDING, LEDGER] {
+        Mock::given(method("PUT"))
+            .and(path(url))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("DELETE"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/editMessageText"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true, "result": {} })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let photo = json!({
+        "update_id": 459593150,
+        "message": {
+            "message_id": 290,
+            "from": { "id": 42, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "chat": { "id": 42, "first_name": "Liang", "username": "liul85", "type": "private" },
+            "date": 1631068200,
+            "photo": [
+                { "file_id": "AgACAgUAAxkBAAIBIm", "file_unique_id": "AQADsK0x", "file_size": 1402, "width": 67, "height": 90 },
+                { "file_id": "AgACAgUAAxkBAAIBIn", "file_unique_id": "AQADsK0y", "file_size": 98304, "width": 960, "height": 1280 }
+            ],
+            "caption": "cba"
+        }
+    })
+    .to_string();
+    let response = beancount::handle_update(&photo).await.unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["method"], "sendMessage");
+    assert_eq!(
+        response["text"],
+        "Receipt read as:\n2021-09-08 * \"KFC Carlton\" \"\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+    );
+    assert_eq!(
+        response["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        "receipt:save:42-290"
+    );
+    let getfile = &requests(&server, "POST", "/bot123456:test/getFile").await[0];
+    assert_eq!(getfile["file_id"], "AgACAgUAAxkBAAIBIn");
+
+    let pending = &requests(&server, "PUT", PENDING).await[0];
+    let pending =
+        String::from_utf8(base64::decode(pending["content"].as_str().unwrap()).unwrap()).unwrap();
+    Mock::given(method("GET"))
+        .and(path(PENDING))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(&pending, "abc")))
+        .mount(&server)
+        .await;
+
+    let tap = json!({
+        "update_id": 459593151,
+        "callback_query": {
+            "id": "1063732217806041",
+            "from": { "id": 42, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "message": {
+                "message_id": 291,
+                "from": { "id": 123456, "is_bot": true, "first_name": "beancount", "username": "beancount_bot" },
+                "chat": { "id": 42, "first_name": "Liang", "username": "liul85", "type": "private" },
+                "date": 1631068201,
+                "text": "Receipt read as:"
+            },
+            "chat_instance": "-5093712373",
+            "data": "receipt:save:42-290"
+        }
+    })
+    .to_string();
+    let response = beancount::handle_update(&tap).await.unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["method"], "answerCallbackQuery");
+    assert_eq!(response["text"], "Saved");
+
+    let ledger = decoded(&requests(&server, "PUT", LEDGER).await[0]);
+    assert!(ledger.contains(
+        "2021-09-08 * \"KFC Carlton\" \"\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+    ));
+    let edit = &requests(&server, "POST", "/bot123456:test/editMessageText").await[0];
+    assert_eq!(edit["message_id"], 291);
+    assert!(edit["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("2021-09-08 * \"KFC Carlton\""));
+}
@@ -0,0 +1,58 @@
+//! Recurring transactions posted against a stubbed GitHub contents API, with
+//! the summary sent through a stubbed Telegram Bot API.
+
+mod common;
+
+use common::file_content;
+use serde_json::{json, Value};
+use std::env;
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn it_posts_due_recurring_transactions_and_tells_the_chat() {
+    let server = MockServer::start().await;
+    env::set_var("GITHUB_API_URL", server.uri());
+    env::set_var("TELEGRAM_API_URL", server.uri());
+    env::set_var("TELEGRAM_BOT_TOKEN", "123456:test");
+    env::set_var("GITHUB_TOKEN", "test-token");
+    env::set_var("GITHUB_OWNER", "liul85");
+    env::set_var("GITHUB_REPO", "beancount");
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nsubscriptions = \"Expenses:Subscriptions\"\n[[recurring]]\nname = \"netflix\"\nschedule = \"0 9 * * *\"\ntext = \"@Netflix 16.99 cba > subscriptions\"\n[[jobs]]\nname = \"recurring\"\nkind = \"recurring\"\nschedule = \"0 9 * * *\"\nchat_id = 42\n",
+    );
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/repos/liul85/beancount/contents/\d{4}\.bean$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/repos/liul85/beancount/contents/\d{4}\.bean$"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/sendMessage"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let outcomes = beancount::run_recurring().await.unwrap();
+    assert_eq!(outcomes, vec!["recurring: posted 1 recurring transactions"]);
+
+    let message: Value = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .find(|request| request.method.as_str() == "POST")
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .unwrap();
+    assert_eq!(message["chat_id"], 42);
+    let text = message["text"].as_str().unwrap();
+    assert!(text.starts_with("Posted 1 recurring transactions:\n\n"));
+    assert!(text.contains("\"Netflix\" \"\"\n  recurring: \"netflix\"\n"));
+}
@@ -0,0 +1,627 @@
+//! End-to-end runs of the webhook handler against a stubbed GitHub contents
+//! API, fed with Telegram update JSON as the webhook receives it.
+
+mod common;
+
+use common::{decoded, file_content, requests, LEDGER};
+use serde_json::{json, Value};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use wiremock::matchers::{header, method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const RECENT_UPDATES: &str = r"/contents/\.beancount-bot/recent-updates\.json$";
+
+/// The handler is configured through env vars, so tests take turns.
+static ENV: Mutex<()> = Mutex::const_new(());
+
+/// The handler answers an update id it has seen with the earlier response, so
+/// each update gets its own.
+static UPDATE_ID: AtomicU64 = AtomicU64::new(459592837);
+
+fn update(text: &str) -> String {
+    update_sent_at(
+        text,
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    )
+}
+
+fn update_sent_at(text: &str, date: u64) -> String {
+    json!({
+        "update_id": UPDATE_ID.fetch_add(1, Ordering::SeqCst),
+        "message": {
+            "message_id": 7,
+            "from": { "id": 247673932, "is_bot": false, "first_name": "Liang", "username": "liul85", "language_code": "en" },
+            "chat": { "id": 247673932, "first_name": "Liang", "username": "liul85", "type": "private" },
+            "date": date,
+            "text": text,
+        }
+    })
+    .to_string()
+}
+
+fn update_id(body: &str) -> u64 {
+    serde_json::from_str::<Value>(body).unwrap()["update_id"]
+        .as_u64()
+        .unwrap()
+}
+
+fn dead_letter(update_id: u64) -> String {
+    format!(
+        "/repos/liul85/beancount/contents/.beancount-bot/dead-letter/{}.json",
+        update_id
+    )
+}
+
+async fn github() -> MockServer {
+    let server = common::github().await;
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n",
+    );
+    server
+}
+
+async fn handle(body: String) -> anyhow::Result<String> {
+    beancount::handle_update(&body).await
+}
+
+fn reply_text(response: &str) -> String {
+    let response: Value = serde_json::from_str(response).unwrap();
+    assert_eq!(response["method"], "sendMessage");
+    assert_eq!(response["chat_id"], 247673932);
+    assert_eq!(response["reply_to_message_id"], 7);
+    response["text"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn it_appends_transaction_to_existing_ledger() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(file_content("option \"title\" \"2021\"\n", "abc")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = handle(update("2021-09-08 @KFC hamburger 12.40 AUD cba > food"))
+        .await
+        .unwrap();
+    assert!(reply_text(&response).contains("KFC"));
+
+    let puts = requests(&server, "PUT", LEDGER).await;
+    assert_eq!(puts[0]["sha"], "abc");
+    assert_eq!(
+        decoded(&puts[0]),
+        "option \"title\" \"2021\"\n\n2021-09-08 * \"KFC\" \"hamburger\"\n  telegram_message: \"247673932/7\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n"
+    );
+}
+
+#[tokio::test]
+async fn it_replaces_the_entry_of_an_edited_message() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(
+            "\n2021-09-08 * \"KFC\" \"hamburger\"\n  telegram_message: \"247673932/7\"\n  Assets:CBA        -12.40 AUD\n  Expenses:Food        12.40 AUD\n\n2021-09-09 * \"Coles\" \"\"\n  telegram_message: \"247673932/8\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n",
+            "abc",
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut body: Value =
+        serde_json::from_str(&update("2021-09-08 @KFC hamburger 21.40 cba > food")).unwrap();
+    body["edited_message"] = body["message"].take();
+    body.as_object_mut().unwrap().remove("message");
+    handle(body.to_string()).await.unwrap();
+
+    assert_eq!(
+        decoded(&requests(&server, "PUT", LEDGER).await[0]),
+        "\n2021-09-08 * \"KFC\" \"hamburger\"\n  telegram_message: \"247673932/7\"\n  Assets:CBA        -21.40 AUD\n  Expenses:Food        21.40 AUD\n\n2021-09-09 * \"Coles\" \"\"\n  telegram_message: \"247673932/8\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n"
+    );
+}
+
+#[tokio::test]
+async fn it_converts_amounts_in_another_currency_at_the_ecb_rate() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    env::set_var("RATES_API_URL", server.uri());
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n[rates]\nprovider = \"ecb\"\n",
+    );
+    Mock::given(method("GET"))
+        .and(path("/2021-09-08"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "amount": 1.0,
+            "base": "USD",
+            "date": "2021-09-08",
+            "rates": { "AUD": 1.3579 },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let response = handle(update("2021-09-08 @Steam games 20 USD cba > food")).await;
+    env::remove_var("RATES_API_URL");
+    assert!(reply_text(&response.unwrap()).contains("Steam"));
+    assert_eq!(
+        decoded(&requests(&server, "PUT", LEDGER).await[0]),
+        "\n2021-09-08 * \"Steam\" \"games\"\n  fx_rate: \"1.3579 AUD/USD\"\n  fx_source: \"ecb 2021-09-08\"\n  telegram_message: \"247673932/7\"\n  Assets:CBA        -27.16 AUD\n  Expenses:Food        20.00 USD @@ 27.16 AUD\n"
+    );
+}
+
+#[tokio::test]
+async fn it_imports_a_csv_statement_sent_as_a_document() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    common::telegram(&server);
+    env::set_var(
+        "CONFIG",
+        "currency = \"AUD\"\n[accounts]\ncba = \"Assets:CBA\"\nfood = \"Expenses:Food\"\n[import_profiles.cba]\naccount = \"cba\"\ndefault_account = \"food\"\ndate_format = \"%d/%m/%Y\"\ncolumns = { date = \"Date\", description = \"Description\", amount = \"Amount\" }\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/getFile"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "result": { "file_id": "BQAC", "file_path": "documents/file_1.csv" },
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/file/bot123456:test/documents/file_1.csv"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "Date,Description,Amount\n08/09/2021,KFC,-12.40\n09/09/2021,COLES,-30.00\n",
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(
+            "2021-09-08 * \"KFC\" \"\"\n  Assets:CBA  -12.40 AUD\n  Expenses:Food\n",
+            "abc",
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut body: Value = serde_json::from_str(&update("")).unwrap();
+    body["message"]["document"] =
+        json!({ "file_id": "BQAC", "file_name": "statement.csv", "mime_type": "text/csv" });
+    body["message"]["caption"] = json!("cba");
+    let response = handle(body.to_string()).await;
+    env::remove_var("TELEGRAM_API_URL");
+    assert_eq!(
+        reply_text(&response.unwrap()),
+        "Imported 1 transactions, skipped 1\nline 2: KFC is already in the ledger"
+    );
+    assert!(decoded(&requests(&server, "PUT", LEDGER).await[0]).ends_with(
+        "Expenses:Food\n\n2021-09-09 * \"COLES\" \"\"\n  Assets:CBA        -30.00 AUD\n  Expenses:Food        30.00 AUD\n"
+    ));
+}
+
+#[tokio::test]
+async fn it_creates_ledger_of_a_new_year() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(404))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "new")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let response = handle(update("2021-09-08 @KFC hamburger 12.40 AUD cba > food"))
+        .await
+        .unwrap();
+    assert!(reply_text(&response).contains("KFC"));
+
+    let puts = requests(&server, "PUT", LEDGER).await;
+    assert_eq!(puts[0]["message"], "created file 2021.bean");
+    assert!(puts[0].get("sha").is_none());
+    assert_eq!(puts[1]["sha"], "new");
+    assert!(decoded(&puts[1]).contains("2021-09-08 * \"KFC\" \"hamburger\""));
+}
+
+#[tokio::test]
+async fn it_fails_the_update_so_telegram_retries_on_conflict_and_server_errors() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "stale")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(409))
+        .mount(&server)
+        .await;
+
+    let error = handle(update("2021-09-08 @KFC hamburger 12.40 AUD cba > food"))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("changed at the same time"));
+
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let error = handle(update("2021-09-08 @KFC hamburger 12.40 AUD cba > food"))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("500 Internal Server Error"));
+}
+
+#[tokio::test]
+async fn it_dead_letters_updates_that_fail_for_good() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    let body = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    let id = update_id(&body);
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(dead_letter(id)))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let text = reply_text(&handle(body.clone()).await.unwrap());
+    assert!(text.contains("check GITHUB_TOKEN"));
+    assert!(text.contains(&format!("/replay {}", id)));
+    // Telegram redelivering it gets the same answer without a second attempt.
+    assert_eq!(reply_text(&handle(body).await.unwrap()), text);
+
+    // A transient error stops being retried once the message is old.
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let body = update_sent_at("2021-09-08 @KFC hamburger 12.40 AUD cba > food", 1631506802);
+    let id = update_id(&body);
+    Mock::given(method("PUT"))
+        .and(path(dead_letter(id)))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&server)
+        .await;
+    let text = reply_text(&handle(body).await.unwrap());
+    assert!(text.contains(&format!("/replay {}", id)));
+}
+
+#[tokio::test]
+async fn it_replies_to_invalid_input_without_touching_the_ledger() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+
+    let response = handle(update("2021-09-08 @KFC hamburger 12.40 AUD nope > food"))
+        .await
+        .unwrap();
+    assert!(reply_text(&response).contains("Failed to parse input"));
+    assert!(requests(&server, "PUT", LEDGER).await.is_empty());
+}
+
+#[tokio::test]
+async fn it_serves_tenants_from_their_own_repository() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    env::set_var(
+        "TENANTS",
+        "[tenants.alice]\nchat_ids = [247673932]\ngithub_owner = \"alice\"\ngithub_repo = \"ledger\"\ngithub_token = \"alice-token\"\nconfig = \"currency = \\\"AUD\\\"\\n[accounts]\\ncba = \\\"Assets:CBA\\\"\\nfood = \\\"Expenses:Food\\\"\\n\"\n",
+    );
+    Mock::given(method("GET"))
+        .and(path("/repos/alice/ledger/contents/2021.bean"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/repos/alice/ledger/contents/2021.bean"))
+        .and(header("Authorization", "token alice-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let body = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    let response = handle(body.clone()).await.unwrap();
+    assert!(reply_text(&response).contains("KFC"));
+    // Telegram redelivering the update gets the same answer without saving it twice.
+    assert_eq!(handle(body.clone()).await.unwrap(), response);
+    let response = handle(body.replace("247673932", "1")).await.unwrap();
+    assert_eq!(response, "chat 1 isn't registered to a tenant");
+    assert!(requests(&server, "PUT", LEDGER).await.is_empty());
+
+    env::remove_var("TENANTS");
+}
+
+#[tokio::test]
+async fn it_answers_redeliveries_from_the_marker_another_instance_left() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"/contents/\.beancount-bot/updates/\d+$"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let body = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    let response = beancount::handle_serverless_update(None, &body)
+        .await
+        .unwrap();
+    assert!(reply_text(&response).contains("KFC"));
+    let marker = format!(
+        "/repos/liul85/beancount/contents/.beancount-bot/updates/{}",
+        update_id(&body)
+    );
+    let marked = requests(&server, "PUT", &marker).await;
+    assert_eq!(decoded(&marked[0]), response);
+
+    // This instance never saw the update, another one handled it.
+    let redelivered = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/repos/liul85/beancount/contents/.beancount-bot/updates/{}",
+            update_id(&redelivered)
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content(&response, "def")))
+        .mount(&server)
+        .await;
+    assert_eq!(
+        beancount::handle_serverless_update(None, &redelivered)
+            .await
+            .unwrap(),
+        response
+    );
+}
+
+#[tokio::test]
+async fn it_routes_button_taps_to_the_tenant_of_the_chat() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    env::set_var(
+        "TENANTS",
+        "[tenants.alice]\nchat_ids = [247673932]\ngithub_owner = \"alice\"\ngithub_repo = \"ledger\"\ngithub_token = \"alice-token\"\nconfig = \"currency = \\\"AUD\\\"\\n\"\n",
+    );
+    let pending = "/repos/alice/ledger/contents/.beancount-bot/duplicates/247673932-8.json";
+    Mock::given(method("GET"))
+        .and(path(pending))
+        .and(header("Authorization", "token alice-token"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let tap = json!({
+        "update_id": UPDATE_ID.fetch_add(1, Ordering::SeqCst),
+        "callback_query": {
+            "id": "1063732217806042",
+            "from": { "id": 247673932, "is_bot": false, "first_name": "Liang", "username": "liul85" },
+            "message": {
+                "message_id": 9,
+                "from": { "id": 123456, "is_bot": true, "first_name": "beancount", "username": "beancount_bot" },
+                "chat": { "id": 247673932, "first_name": "Liang", "username": "liul85", "type": "private" },
+                "date": 1631068201,
+                "text": "Looks like a duplicate of 2021.bean:1, save anyway?"
+            },
+            "chat_instance": "-5093712373",
+            "data": "duplicate:skip:247673932-8"
+        }
+    })
+    .to_string();
+    let response: Value = serde_json::from_str(&handle(tap.clone()).await.unwrap()).unwrap();
+    assert_eq!(response["method"], "answerCallbackQuery");
+    assert_eq!(response["text"], "Already answered");
+    let response = handle(tap.replace("247673932", "1")).await.unwrap();
+    assert_eq!(response, "chat 1 isn't registered to a tenant");
+
+    env::remove_var("TENANTS");
+}
+
+#[tokio::test]
+async fn it_acknowledges_first_and_edits_the_reply_once_saved() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    common::telegram(&server);
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/sendMessage"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "ok": true, "result": { "message_id": 99 } })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/editMessageText"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let body = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    let handled = beancount::handle_update_deferred(None, &body)
+        .await
+        .unwrap();
+    assert_eq!(handled.response, "saving in the background");
+    assert!(requests(&server, "PUT", LEDGER).await.is_empty());
+
+    let pending = handled.pending.unwrap();
+    pending.run().await;
+    assert_eq!(requests(&server, "PUT", LEDGER).await.len(), 1);
+
+    let telegram: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.url.path().starts_with("/bot"))
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    assert_eq!(telegram[0]["text"], "Parsed ✓, saving…");
+    assert_eq!(telegram[0]["reply_to_message_id"], 7);
+    assert_eq!(telegram[1]["message_id"], 99);
+    assert!(telegram[1]["text"].as_str().unwrap().contains("KFC"));
+
+    env::remove_var("TELEGRAM_API_URL");
+}
+
+#[tokio::test]
+async fn it_dead_letters_saves_still_running_at_shutdown() {
+    let _env = ENV.lock().await;
+    let server = github().await;
+    common::telegram(&server);
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/sendMessage"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "ok": true, "result": { "message_id": 99 } })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/bot123456:test/editMessageText"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ok": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("", "abc")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(LEDGER))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let body = update("2021-09-08 @KFC hamburger 12.40 AUD cba > food");
+    let id = update_id(&body);
+    Mock::given(method("PUT"))
+        .and(path(dead_letter(id)))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(dead_letter(id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(file_content("{}", "letter")))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(dead_letter(id)))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // The ids of handled updates are kept too.
+    Mock::given(method("PUT"))
+        .and(path_regex(RECENT_UPDATES))
+        .respond_with(ResponseTemplate::new(201))
+        .mount(&server)
+        .await;
+
+    let pending = beancount::handle_update_deferred(None, &body)
+        .await
+        .unwrap()
+        .pending
+        .unwrap();
+    assert_eq!(beancount::saves_in_flight(), 1);
+
+    beancount::flush_state().await.unwrap();
+    assert_eq!(beancount::saves_in_flight(), 0);
+    let edit: Value = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.url.path().ends_with("/editMessageText"))
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .next()
+        .unwrap();
+    assert!(edit["text"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("/replay {}", id)));
+
+    // The save finishing after all removes the dead letter again.
+    pending.run().await;
+
+    env::remove_var("TELEGRAM_API_URL");
+}
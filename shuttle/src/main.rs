@@ -0,0 +1,27 @@
+use log::info;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use server::{app, run_scheduler, LATENCY_BUCKETS};
+use shuttle_axum::ShuttleAxum;
+use shuttle_runtime::{CustomError, SecretStore};
+use std::env;
+
+/// Runs the standalone server's app on Shuttle with `shuttle deploy`. Every
+/// entry of `Secrets.toml` becomes the env var of the same name, so it takes
+/// the same configuration as the other deployments. The instance is long-lived,
+/// so the settings cache, tenant limits and error stats are shared by all
+/// requests, and `FAST_ACK` and `RUN_SCHEDULER` work as on the server.
+#[shuttle_runtime::main]
+async fn main(#[shuttle_runtime::Secrets] secrets: SecretStore) -> ShuttleAxum {
+    for (key, value) in secrets {
+        env::set_var(key, value);
+    }
+    let metrics = PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)
+        .and_then(|builder| builder.install_recorder())
+        .map_err(CustomError::new)?;
+    if env::var("RUN_SCHEDULER").is_ok_and(|v| v == "true") {
+        info!("running scheduled jobs");
+        tokio::spawn(run_scheduler());
+    }
+    Ok(app(metrics).into())
+}